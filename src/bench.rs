@@ -0,0 +1,113 @@
+// Concurrent TFTP downloads against running server, to tell whether a
+// change to transfer handling made it faster or slower.
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use futures_util::future;
+
+use crate::tftp::{self, client};
+
+const RETRY_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub server_ip: Ipv4Addr,
+    pub file: String,
+    pub clients: usize,
+    pub block_size: Option<u32>,
+    pub window_size: Option<u16>,
+}
+
+pub async fn tftp(settings: Settings) -> anyhow::Result<()> {
+    let server = SocketAddr::from((settings.server_ip, tftp::SERVER_PORT));
+    let start = Instant::now();
+    let results = future::join_all((0..settings.clients).map(|_| {
+        client::download(
+            server,
+            &settings.file,
+            settings.block_size,
+            settings.window_size,
+            RETRY_TIMEOUT,
+        )
+    }))
+    .await;
+    let wall = start.elapsed();
+
+    let mut downloads = Vec::new();
+    for (i, result) in results.into_iter().enumerate() {
+        match result {
+            Ok(x) => {
+                println!(
+                    "client {}: {} bytes in {} ms ({}/s), first block after {} ms, \
+                     blksize {} windowsize {}, {} timeout(s), {} out of order",
+                    i,
+                    x.bytes,
+                    x.elapsed.as_millis(),
+                    throughput(x.bytes, x.elapsed),
+                    x.first_block.as_millis(),
+                    x.block_size,
+                    x.window_size,
+                    x.timeouts,
+                    x.out_of_order
+                );
+                downloads.push(x);
+            }
+            Err(e) => println!("client {}: failed: {:#}", i, e),
+        }
+    }
+    if downloads.is_empty() {
+        bail!("all {} download(s) failed", settings.clients);
+    }
+
+    let bytes = downloads.iter().map(|x| x.bytes).sum::<u64>();
+    let blocks = downloads.iter().map(|x| x.blocks).sum::<u64>();
+    let lost = downloads
+        .iter()
+        .map(|x| x.timeouts + x.out_of_order)
+        .sum::<u64>();
+    println!();
+    println!(
+        "{} of {} download(s) completed in {} ms",
+        downloads.len(),
+        settings.clients,
+        wall.as_millis()
+    );
+    println!("throughput: {}/s", throughput(bytes, wall));
+    println!(
+        "loss: {} of {} block(s) ({:.2}%)",
+        lost,
+        blocks,
+        lost as f64 * 100.0 / blocks.max(1) as f64
+    );
+    println!(
+        "first block latency: {}",
+        percentiles(downloads.iter().map(|x| x.first_block).collect())
+    );
+    println!(
+        "download time: {}",
+        percentiles(downloads.iter().map(|x| x.elapsed).collect())
+    );
+
+    if downloads.len() != settings.clients {
+        bail!("{} download(s) failed", settings.clients - downloads.len());
+    }
+
+    Ok(())
+}
+
+fn throughput(bytes: u64, elapsed: Duration) -> String {
+    let per_second = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+    format!("{:.2} MiB", per_second / (1024.0 * 1024.0))
+}
+
+fn percentiles(mut durations: Vec<Duration>) -> String {
+    durations.sort();
+    let at = |p: usize| durations[(durations.len() - 1) * p / 100].as_secs_f64() * 1000.0;
+    format!(
+        "min {:.1} ms, p50 {:.1} ms, p95 {:.1} ms, max {:.1} ms",
+        at(0),
+        at(50),
+        at(95),
+        at(100)
+    )
+}
@@ -0,0 +1,235 @@
+// BINL as spoken by RIS era Windows setup. Once text mode setup is loaded
+// over TFTP it asks boot server which driver to load for network card it
+// booted from, and aborts when nobody answers. Only these network card
+// queries are answered, OSChooser screens and authentication are not.
+//
+// Every message starts with signature, \x81 for requests and \x82 for
+// replies followed by three letter type, and little endian length of
+// what follows.
+use std::net::SocketAddr;
+
+use byteorder::{ByteOrder, LittleEndian};
+use tokio::net::UdpSocket;
+
+use crate::config::BinlDriver;
+use crate::dhcp::id::Mac;
+
+pub const BINL_PORT: u16 = crate::dhcp::BOOT_SERVER_PORT;
+
+const HEADER_SIZE: usize = 8;
+const NCQ: &[u8; 4] = b"\x81NCQ";
+const NCR: &[u8; 4] = b"\x82NCR";
+
+// network card query, offsets from start of message
+const NCQ_MAC: usize = 0x08;
+const NCQ_VENDOR: usize = 0x24;
+const NCQ_DEVICE: usize = 0x26;
+const NCQ_SUBSYSTEM: usize = 0x28;
+const NCQ_SIZE: usize = 0x2c;
+
+const NCR_OK: u32 = 0;
+// STATUS_UNSUCCESSFUL, setup reports card as unsupported
+const NCR_NOT_FOUND: u32 = 0xc000_0001;
+// fixed part of reply: result, type, three string offsets and
+// length of parameters
+const NCR_FIXED_SIZE: usize = 6 * 4;
+// PCI
+const BUS_TYPE: u32 = 5;
+
+pub async fn start(options: &super::Options) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind((options.server_ip(), BINL_PORT)).await?;
+    let drivers = options.config.binl_drivers.clone();
+
+    debug!("server starting");
+
+    let mut buf = [0u8; 1500];
+    loop {
+        let (n, client) = socket.recv_from(&mut buf).await?;
+        if let Some(reply) = handle(&drivers, &buf[..n], client) {
+            socket.send_to(&reply, client).await?;
+        }
+    }
+}
+
+fn handle(drivers: &[BinlDriver], message: &[u8], client: SocketAddr) -> Option<Vec<u8>> {
+    let signature = message.get(..4)?;
+    if signature != NCQ {
+        debug!(
+            "ignoring {} from {}",
+            String::from_utf8_lossy(signature),
+            client
+        );
+        return None;
+    }
+    if message.len() < NCQ_SIZE {
+        debug!("truncated NCQ from {}", client);
+        return None;
+    }
+
+    let mut mac = [0u8; 16];
+    mac[..6].copy_from_slice(&message[NCQ_MAC..NCQ_MAC + 6]);
+    let mac = Mac::from(mac);
+    let vendor = LittleEndian::read_u16(&message[NCQ_VENDOR..]);
+    let device = LittleEndian::read_u16(&message[NCQ_DEVICE..]);
+    let subsystem = LittleEndian::read_u32(&message[NCQ_SUBSYSTEM..]);
+
+    let driver = find_driver(drivers, vendor, device, subsystem);
+    match driver {
+        Some(x) => info!(
+            "{} with card {:04x}:{:04x} gets driver {}",
+            mac, vendor, device, x.driver
+        ),
+        None => warn!(
+            "{} asked for driver of unknown card {:04x}:{:04x} subsystem {:08x}",
+            mac, vendor, device, subsystem
+        ),
+    }
+
+    Some(encode_ncr(driver, vendor, device))
+}
+
+// driver for exact subsystem wins over one matching any
+fn find_driver(
+    drivers: &[BinlDriver],
+    vendor: u16,
+    device: u16,
+    subsystem: u32,
+) -> Option<&BinlDriver> {
+    let candidates = || {
+        drivers
+            .iter()
+            .filter(move |x| x.vendor == vendor && x.device == device)
+    };
+    candidates()
+        .find(|x| x.subsystem == Some(subsystem))
+        .or_else(|| candidates().find(|x| x.subsystem.is_none()))
+}
+
+// strings are NUL terminated UTF-16, parameters are name, type
+// (1 for numbers, 2 for strings) and value triplets ending with empty name
+fn encode_ncr(driver: Option<&BinlDriver>, vendor: u16, device: u16) -> Vec<u8> {
+    let driver = match driver {
+        Some(x) => x,
+        None => {
+            let mut reply = NCR.to_vec();
+            reply.extend_from_slice(&4u32.to_le_bytes());
+            reply.extend_from_slice(&NCR_NOT_FOUND.to_le_bytes());
+            return reply;
+        }
+    };
+
+    let hardware_id = utf16(&format!("PCI\\VEN_{:04X}&DEV_{:04X}", vendor, device));
+    let driver_name = utf16(&driver.driver);
+    let service = utf16(&driver.service);
+    let mut parameters = Vec::new();
+    for (name, kind, value) in [
+        (
+            "Description",
+            "2",
+            driver.description.clone().unwrap_or_default(),
+        ),
+        ("Characteristics", "1", "132".to_string()),
+        ("BusType", "1", BUS_TYPE.to_string()),
+    ]
+    .iter()
+    {
+        for x in [name, kind, value.as_str()].iter() {
+            parameters.extend_from_slice(&utf16(x));
+        }
+    }
+    parameters.extend_from_slice(&utf16(""));
+
+    let hardware_id_offset = HEADER_SIZE + NCR_FIXED_SIZE;
+    let driver_offset = hardware_id_offset + hardware_id.len();
+    let service_offset = driver_offset + driver_name.len();
+    let parameters_offset = service_offset + service.len();
+
+    let mut reply = NCR.to_vec();
+    let length = parameters_offset + parameters.len() - HEADER_SIZE;
+    for x in [
+        length as u32,
+        NCR_OK,
+        2,
+        hardware_id_offset as u32,
+        driver_offset as u32,
+        service_offset as u32,
+        parameters.len() as u32,
+    ]
+    .iter()
+    {
+        reply.extend_from_slice(&x.to_le_bytes());
+    }
+    reply.extend_from_slice(&hardware_id);
+    reply.extend_from_slice(&driver_name);
+    reply.extend_from_slice(&service);
+    reply.extend_from_slice(&parameters);
+
+    reply
+}
+
+fn utf16(s: &str) -> Vec<u8> {
+    s.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(|x| x.to_le_bytes().to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn driver(subsystem: Option<u32>, name: &str) -> BinlDriver {
+        BinlDriver {
+            vendor: 0x8086,
+            device: 0x100e,
+            subsystem,
+            driver: name.to_string(),
+            service: "E1000".to_string(),
+            description: None,
+        }
+    }
+
+    fn ncq(vendor: u16, device: u16, subsystem: u32) -> Vec<u8> {
+        let mut message = vec![0u8; NCQ_SIZE];
+        message[..4].copy_from_slice(NCQ);
+        LittleEndian::write_u32(&mut message[4..], (NCQ_SIZE - HEADER_SIZE) as u32);
+        message[NCQ_MAC..NCQ_MAC + 6].copy_from_slice(&[0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+        LittleEndian::write_u16(&mut message[NCQ_VENDOR..], vendor);
+        LittleEndian::write_u16(&mut message[NCQ_DEVICE..], device);
+        LittleEndian::write_u32(&mut message[NCQ_SUBSYSTEM..], subsystem);
+        message
+    }
+
+    #[test]
+    fn test_ncq() {
+        let drivers = [
+            driver(None, "e1000325.sys"),
+            driver(Some(0x001e8086), "e1000oem.sys"),
+        ];
+        let client = SocketAddr::from(([10, 0, 0, 100], 1024));
+
+        let reply = handle(&drivers, &ncq(0x8086, 0x100e, 0x001e8086), client).unwrap();
+        assert_eq!(&reply[..4], NCR);
+        assert_eq!(
+            LittleEndian::read_u32(&reply[4..]) as usize,
+            reply.len() - 8
+        );
+        assert_eq!(LittleEndian::read_u32(&reply[8..]), NCR_OK);
+        let driver_offset = LittleEndian::read_u32(&reply[20..]) as usize;
+        let name = utf16("e1000oem.sys");
+        assert_eq!(&reply[driver_offset..driver_offset + name.len()], &name[..]);
+        let hardware_id = utf16("PCI\\VEN_8086&DEV_100E");
+        assert_eq!(&reply[32..32 + hardware_id.len()], &hardware_id[..]);
+
+        let reply = handle(&drivers, &ncq(0x8086, 0x100e, 0), client).unwrap();
+        let driver_offset = LittleEndian::read_u32(&reply[20..]) as usize;
+        let name = utf16("e1000325.sys");
+        assert_eq!(&reply[driver_offset..driver_offset + name.len()], &name[..]);
+
+        let reply = handle(&drivers, &ncq(0x10ec, 0x8139, 0), client).unwrap();
+        assert_eq!(LittleEndian::read_u32(&reply[8..]), NCR_NOT_FOUND);
+
+        assert!(handle(&drivers, &ncq(0x8086, 0x100e, 0)[..16], client).is_none());
+        assert!(handle(&drivers, b"\x81RQU\0\0\0\0", client).is_none());
+    }
+}
@@ -0,0 +1,602 @@
+// Boot loader configuration generated from profiles, served over TFTP and
+// HTTP in place of files missing from root, so menus never have to be
+// written by hand. Only profiles with a kernel are listed, followed by
+// images found in image directory.
+//
+// Every subdirectory of image directory holding a kernel is an image named
+// after it, e.g. debian/vmlinuz and debian/initrd.gz, with optional kernel
+// command line in debian/cmdline. Directory is scanned on every request, so
+// images dropped in or removed show up in the very next menu.
+//
+// Hosts provisioned according to inventory get configuration that leaves
+// loader for next boot device instead, so machines still booting from
+// network first are not reinstalled.
+use std::fmt::Write;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::{Config, Profile};
+use crate::dhcp::id::Mac;
+use crate::inventory::{Inventory, State};
+
+const PXELINUX_DIR: &str = "pxelinux.cfg/";
+// per-MAC variants are grub.cfg-01-aa-bb-cc-dd-ee-ff
+const GRUB_CONFIG: &str = "grub.cfg";
+// chainloaded by iPXE from profile template or loader's embedded script
+const IPXE_MENU: &str = "menu.ipxe";
+// for iPXE based boot files of provisioned hosts, see --local-boot-file
+const IPXE_LOCAL: &str = "local.ipxe";
+// ARP hardware type prefix of per-MAC file names
+const ETHERNET_PREFIX: &str = "01-";
+// same for every loader so menus behave alike
+const MENU_TIMEOUT_SECS: u32 = 5;
+// file name prefixes, signatures next to them are skipped
+const KERNEL_NAMES: &[&str] = &["vmlinuz", "bzImage", "linux", "kernel"];
+const INITRD_NAMES: &[&str] = &["initrd", "initramfs"];
+const CMDLINE_FILE: &str = "cmdline";
+
+// also served to iPXE chainloading profile scripts
+pub const IPXE_LOCAL_BOOT: &str = "#!ipxe\nexit\n";
+const PXELINUX_LOCAL_BOOT: &str = "# generated for provisioned host, do not edit\n\
+                                   DEFAULT local\nLABEL local\n  LOCALBOOT 0\n";
+// returns to firmware, which moves on to next boot entry
+const GRUB_LOCAL_BOOT: &str = "# generated for provisioned host, do not edit\nexit\n";
+
+#[derive(Debug, Clone)]
+pub struct Generator {
+    config: Config,
+    server_ip: Ipv4Addr,
+    nbd_port: u16,
+    images: Option<ImageDir>,
+    inventory: Inventory,
+}
+
+#[derive(Debug, Clone)]
+struct ImageDir {
+    path: PathBuf,
+    // path relative to TFTP root, with trailing slash unless empty
+    prefix: String,
+}
+
+// menu entry, paths are relative to TFTP root
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    name: String,
+    kernel: String,
+    initrd: Option<String>,
+    cmdline: String,
+}
+
+impl Generator {
+    pub fn new(options: &crate::Options, inventory: &Inventory) -> Self {
+        // image directory is verified to be under root on startup
+        let images = match (options.tftp_root.as_deref(), options.image_dir.as_deref()) {
+            (Some(root), Some(path)) => path.strip_prefix(root).ok().map(|prefix| {
+                let prefix = prefix.to_string_lossy().replace('\\', "/");
+                ImageDir {
+                    path: path.to_path_buf(),
+                    prefix: if prefix.is_empty() {
+                        prefix
+                    } else {
+                        prefix + "/"
+                    },
+                }
+            }),
+            _ => None,
+        };
+
+        Self {
+            config: options.config.clone(),
+            server_ip: options.server_ip(),
+            nbd_port: options.nbd_port,
+            images,
+            inventory: Arc::clone(inventory),
+        }
+    }
+
+    // path as requested by client, loaders prepend directory they were
+    // loaded from, e.g. bios/pxelinux.cfg/default or boot/grub/grub.cfg
+    pub fn generate(&self, path: &str) -> Option<String> {
+        if let Some(i) = path.rfind(PXELINUX_DIR) {
+            return match &path[i + PXELINUX_DIR.len()..] {
+                "default" => self.pxelinux(None),
+                name => {
+                    let mac = parse_mac(name)?;
+                    if self.boots_locally(&mac) {
+                        return Some(PXELINUX_LOCAL_BOOT.to_string());
+                    }
+                    self.pxelinux(Some(self.select_by_mac(&mac)?))
+                }
+            };
+        }
+
+        let file_name = path.rsplit('/').next()?;
+        match file_name {
+            IPXE_MENU => return self.ipxe(),
+            IPXE_LOCAL => return Some(IPXE_LOCAL_BOOT.to_string()),
+            _ => (),
+        }
+
+        match file_name.strip_prefix(GRUB_CONFIG)? {
+            "" => self.grub(None),
+            name => {
+                let mac = parse_mac(name.strip_prefix('-')?)?;
+                if self.boots_locally(&mac) {
+                    return Some(GRUB_LOCAL_BOOT.to_string());
+                }
+                self.grub(Some(self.select_by_mac(&mac)?))
+            }
+        }
+    }
+
+    // clients whose profile has no kernel are ignored
+    // so that loader moves on to default configuration
+    fn select_by_mac(&self, mac: &Mac) -> Option<&str> {
+        self.config
+            .select_profile(mac, None, None, None, None)
+            .filter(|x| x.kernel.is_some())
+            .map(|x| x.name.as_str())
+    }
+
+    fn boots_locally(&self, mac: &Mac) -> bool {
+        matches!(
+            self.inventory.lock().unwrap().get(mac),
+            Some(x) if x.state == State::Provisioned
+        )
+    }
+
+    // bootable profiles followed by images not shadowed by one of them
+    fn entries(&self) -> Vec<Entry> {
+        let mut entries = self
+            .config
+            .profiles
+            .iter()
+            .filter_map(|profile| {
+                Some(Entry {
+                    name: profile.name.clone(),
+                    kernel: profile.kernel.clone()?,
+                    initrd: profile.initrd.clone(),
+                    cmdline: self.cmdline(profile),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(images) = self.images.as_ref() {
+            for image in scan_images(&images.path) {
+                if entries.iter().any(|x| x.name == image.name) {
+                    continue;
+                }
+                let path = |file: &str| format!("{}{}/{}", images.prefix, image.name, file);
+                entries.push(Entry {
+                    kernel: path(&image.kernel),
+                    initrd: image.initrd.as_deref().map(path),
+                    cmdline: image
+                        .cmdline
+                        .replace("{{server_ip}}", &self.server_ip.to_string())
+                        .replace("{{profile}}", &image.name),
+                    name: image.name,
+                });
+            }
+        }
+
+        entries
+    }
+
+    // all entries, default one first
+    fn menu(&self, default: Option<&str>) -> Option<Vec<Entry>> {
+        let mut entries = self.entries();
+        let index = match default {
+            Some(name) => entries.iter().position(|x| x.name == name)?,
+            None if entries.is_empty() => return None,
+            None => 0,
+        };
+        let default = entries.remove(index);
+        entries.insert(0, default);
+        Some(entries)
+    }
+
+    fn pxelinux(&self, default: Option<&str>) -> Option<String> {
+        let menu = self.menu(default)?;
+
+        let mut out = String::new();
+        writeln!(out, "# generated from profiles, do not edit").unwrap();
+        writeln!(out, "DEFAULT {}", menu[0].name).unwrap();
+        writeln!(out, "PROMPT 1").unwrap();
+        writeln!(out, "TIMEOUT {}", MENU_TIMEOUT_SECS * 10).unwrap();
+
+        for entry in menu {
+            writeln!(out).unwrap();
+            writeln!(out, "LABEL {}", entry.name).unwrap();
+            writeln!(out, "  KERNEL {}", entry.kernel).unwrap();
+            if let Some(initrd) = entry.initrd.as_deref() {
+                writeln!(out, "  INITRD {}", initrd).unwrap();
+            }
+            if !entry.cmdline.is_empty() {
+                writeln!(out, "  APPEND {}", entry.cmdline).unwrap();
+            }
+        }
+
+        Some(out)
+    }
+
+    // Paths are relative to script, so iPXE fetches files over whichever
+    // protocol script came from. EFI kernels find initrd by its name.
+    fn ipxe(&self) -> Option<String> {
+        let menu = self.menu(None)?;
+
+        let mut out = String::new();
+        writeln!(out, "#!ipxe").unwrap();
+        writeln!(out, "# generated from profiles, do not edit").unwrap();
+        writeln!(out, ":start").unwrap();
+        writeln!(out, "menu Boot menu").unwrap();
+        for entry in menu.iter() {
+            writeln!(out, "item {0} {0}", entry.name).unwrap();
+        }
+        writeln!(out, "item --gap").unwrap();
+        writeln!(out, "item local Boot from local disk").unwrap();
+        writeln!(
+            out,
+            "choose --default {} --timeout {} target || goto local",
+            menu[0].name,
+            MENU_TIMEOUT_SECS * 1000
+        )
+        .unwrap();
+        writeln!(out, "goto ${{target}}").unwrap();
+
+        for entry in menu.iter() {
+            writeln!(out).unwrap();
+            writeln!(out, ":{}", entry.name).unwrap();
+            write!(out, "kernel /{}", entry.kernel.trim_start_matches('/')).unwrap();
+            if let Some(initrd) = entry.initrd.as_deref() {
+                write!(out, " initrd={}", initrd.rsplit('/').next().unwrap()).unwrap();
+            }
+            if !entry.cmdline.is_empty() {
+                write!(out, " {}", entry.cmdline).unwrap();
+            }
+            writeln!(out).unwrap();
+            if let Some(initrd) = entry.initrd.as_deref() {
+                writeln!(out, "initrd /{}", initrd.trim_start_matches('/')).unwrap();
+            }
+            writeln!(out, "boot || goto start").unwrap();
+        }
+
+        writeln!(out).unwrap();
+        writeln!(out, ":local").unwrap();
+        writeln!(out, "exit").unwrap();
+
+        Some(out)
+    }
+
+    // GRUB sets root to TFTP server it was loaded from, so paths
+    // relative to TFTP root only need leading slash
+    fn grub(&self, default: Option<&str>) -> Option<String> {
+        let menu = self.menu(default)?;
+
+        let mut out = String::new();
+        writeln!(out, "# generated from profiles, do not edit").unwrap();
+        writeln!(out, "set default=\"{}\"", menu[0].name).unwrap();
+        writeln!(out, "set timeout={}", MENU_TIMEOUT_SECS).unwrap();
+
+        for entry in menu {
+            writeln!(out).unwrap();
+            writeln!(out, "menuentry \"{0}\" --id \"{0}\" {{", entry.name).unwrap();
+            write!(out, "    linux /{}", entry.kernel.trim_start_matches('/')).unwrap();
+            if !entry.cmdline.is_empty() {
+                write!(out, " {}", entry.cmdline).unwrap();
+            }
+            writeln!(out).unwrap();
+            if let Some(initrd) = entry.initrd.as_deref() {
+                writeln!(out, "    initrd /{}", initrd.trim_start_matches('/')).unwrap();
+            }
+            writeln!(out, "}}").unwrap();
+        }
+
+        Some(out)
+    }
+
+    fn cmdline(&self, profile: &Profile) -> String {
+        let root_path = profile
+            .root_path(self.server_ip, self.nbd_port)
+            .unwrap_or_default();
+
+        profile
+            .cmdline
+            .as_deref()
+            .unwrap_or_default()
+            .replace("{{server_ip}}", &self.server_ip.to_string())
+            .replace("{{profile}}", &profile.name)
+            .replace("{{root_path}}", &root_path)
+    }
+}
+
+// name is 01-aa-bb-cc-dd-ee-ff
+fn parse_mac(name: &str) -> Option<Mac> {
+    name.strip_prefix(ETHERNET_PREFIX)?.parse().ok()
+}
+
+#[derive(Debug)]
+struct Image {
+    name: String,
+    // file names within image directory
+    kernel: String,
+    initrd: Option<String>,
+    cmdline: String,
+}
+
+// unreadable directories and ones without kernel are skipped,
+// sorted by name so that menus do not reorder between requests
+fn scan_images(dir: &Path) -> Vec<Image> {
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("cannot scan image directory {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let mut images = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') || !entry.path().is_dir() {
+                return None;
+            }
+            let mut files = fs::read_dir(entry.path())
+                .ok()?
+                .filter_map(|x| x.ok()?.file_name().into_string().ok())
+                .filter(|x| !x.ends_with(crate::signature::SIGNATURE_SUFFIX))
+                .collect::<Vec<_>>();
+            files.sort();
+            let find = |prefixes: &[&str]| {
+                prefixes
+                    .iter()
+                    .find_map(|prefix| files.iter().find(|x| x.starts_with(prefix)).cloned())
+            };
+
+            Some(Image {
+                kernel: find(KERNEL_NAMES)?,
+                initrd: find(INITRD_NAMES),
+                cmdline: fs::read_to_string(entry.path().join(CMDLINE_FILE))
+                    .map(|x| x.trim().to_string())
+                    .unwrap_or_default(),
+                name,
+            })
+        })
+        .collect::<Vec<_>>();
+    images.sort_by(|a, b| a.name.cmp(&b.name));
+
+    images
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pxelinux() {
+        let config: Config = toml::from_str(
+            r#"
+            [[profile]]
+            name = "debian"
+            boot_file = "pxelinux.0"
+            kernel = "debian/linux"
+            initrd = "debian/initrd.gz"
+            root_path = "10.0.0.2:/srv/nfsroot"
+            cmdline = "root=/dev/nfs nfsroot={{root_path}} ip=dhcp"
+
+            [[profile]]
+            name = "rescue"
+            boot_file = "pxelinux.0"
+            kernel = "rescue/vmlinuz"
+
+            [[profile]]
+            name = "uefi"
+            boot_file = "ipxe.efi"
+
+            [[selector]]
+            profile = "rescue"
+            mac = "52:54:00:12:34:56"
+
+            [[selector]]
+            profile = "uefi"
+            mac = "52:54:00:ab:cd:ef"
+            "#,
+        )
+        .unwrap();
+        config.verify().unwrap();
+
+        let generator = Generator {
+            config,
+            server_ip: Ipv4Addr::new(10, 0, 0, 1),
+            nbd_port: 10809,
+            images: None,
+            inventory: Inventory::default(),
+        };
+
+        let default = generator.generate("pxelinux.cfg/default").unwrap();
+        assert_eq!(
+            default,
+            "# generated from profiles, do not edit\n\
+             DEFAULT debian\nPROMPT 1\nTIMEOUT 50\n\n\
+             LABEL debian\n  KERNEL debian/linux\n  INITRD debian/initrd.gz\n\
+             \x20 APPEND root=/dev/nfs nfsroot=10.0.0.2:/srv/nfsroot ip=dhcp\n\n\
+             LABEL rescue\n  KERNEL rescue/vmlinuz\n"
+        );
+
+        let host = generator
+            .generate("bios/pxelinux.cfg/01-52-54-00-12-34-56")
+            .unwrap();
+        assert!(host.contains("DEFAULT rescue\n"));
+        assert!(host.contains("LABEL debian\n"));
+
+        // profile without kernel and unknown client fall back to default
+        assert!(generator
+            .generate("pxelinux.cfg/01-52-54-00-ab-cd-ef")
+            .is_none());
+        assert!(generator
+            .generate("pxelinux.cfg/01-52-54-00-00-00-01")
+            .is_none());
+        assert!(generator.generate("pxelinux.cfg/C0A80001").is_none());
+        assert!(generator.generate("debian/linux").is_none());
+    }
+
+    #[test]
+    fn test_grub() {
+        let config: Config = toml::from_str(
+            r#"
+            [[profile]]
+            name = "debian"
+            boot_file = "grubx64.efi"
+            kernel = "debian/linux"
+            initrd = "debian/initrd.gz"
+            cmdline = "ip=dhcp"
+
+            [[profile]]
+            name = "rescue"
+            boot_file = "grubx64.efi"
+            kernel = "/rescue/vmlinuz"
+
+            [[selector]]
+            profile = "rescue"
+            mac = "52:54:00:12:34:56"
+            "#,
+        )
+        .unwrap();
+
+        let generator = Generator {
+            config,
+            server_ip: Ipv4Addr::new(10, 0, 0, 1),
+            nbd_port: 10809,
+            images: None,
+            inventory: Inventory::default(),
+        };
+
+        assert_eq!(
+            generator.generate("boot/grub/grub.cfg").unwrap(),
+            "# generated from profiles, do not edit\n\
+             set default=\"debian\"\nset timeout=5\n\n\
+             menuentry \"debian\" --id \"debian\" {\n\
+             \x20   linux /debian/linux ip=dhcp\n\
+             \x20   initrd /debian/initrd.gz\n}\n\n\
+             menuentry \"rescue\" --id \"rescue\" {\n\
+             \x20   linux /rescue/vmlinuz\n}\n"
+        );
+
+        let host = generator
+            .generate("grub/grub.cfg-01-52-54-00-12-34-56")
+            .unwrap();
+        assert!(host.contains("set default=\"rescue\"\n"));
+        assert!(generator
+            .generate("grub/grub.cfg-01-52-54-00-00-00-01")
+            .is_none());
+        assert!(generator.generate("grub/grub.cfg-0A000001").is_none());
+    }
+
+    #[test]
+    fn test_local_boot() {
+        let config: Config = toml::from_str(
+            r#"
+            [[profile]]
+            name = "debian"
+            boot_file = "pxelinux.0"
+            kernel = "debian/linux"
+            "#,
+        )
+        .unwrap();
+        let mac = "52:54:00:12:34:56".parse().unwrap();
+        let inventory = Inventory::default();
+        let mut store = inventory.lock().unwrap();
+        store.set_state(mac, State::Installing, None).unwrap();
+        store.provisioned(&mac).unwrap();
+        drop(store);
+        let generator = Generator {
+            config,
+            server_ip: Ipv4Addr::new(10, 0, 0, 1),
+            nbd_port: 10809,
+            images: None,
+            inventory,
+        };
+
+        assert_eq!(
+            generator
+                .generate("pxelinux.cfg/01-52-54-00-12-34-56")
+                .unwrap(),
+            PXELINUX_LOCAL_BOOT
+        );
+        assert_eq!(
+            generator.generate("grub.cfg-01-52-54-00-12-34-56").unwrap(),
+            GRUB_LOCAL_BOOT
+        );
+        assert_eq!(generator.generate("local.ipxe").unwrap(), IPXE_LOCAL_BOOT);
+        assert!(generator
+            .generate("pxelinux.cfg/default")
+            .unwrap()
+            .contains("LABEL debian\n"));
+    }
+
+    #[test]
+    fn test_images() {
+        let root = std::env::temp_dir().join(format!("pxe-bootcfg-{}", std::process::id()));
+        let dir = root.join("images");
+        for (file, data) in [
+            ("debian/vmlinuz", ""),
+            ("debian/initrd.gz", ""),
+            ("debian/initrd.gz.sig", ""),
+            ("debian/cmdline", "ip=dhcp server={{server_ip}}\n"),
+            ("alpine/vmlinuz-lts", ""),
+            ("alpine/initramfs-lts", ""),
+            ("rescue/vmlinuz", ""),
+            ("empty/README", ""),
+        ] {
+            fs::create_dir_all(dir.join(file).parent().unwrap()).unwrap();
+            fs::write(dir.join(file), data).unwrap();
+        }
+
+        let config: Config = toml::from_str(
+            r#"
+            [[profile]]
+            name = "rescue"
+            boot_file = "ipxe.efi"
+            kernel = "rescue/bzImage"
+            "#,
+        )
+        .unwrap();
+        let generator = Generator {
+            config,
+            server_ip: Ipv4Addr::new(10, 0, 0, 1),
+            nbd_port: 10809,
+            images: Some(ImageDir {
+                path: dir.clone(),
+                prefix: "images/".to_string(),
+            }),
+            inventory: Inventory::default(),
+        };
+
+        assert_eq!(
+            generator.generate("menu.ipxe").unwrap(),
+            "#!ipxe\n# generated from profiles, do not edit\n:start\nmenu Boot menu\n\
+             item rescue rescue\nitem alpine alpine\nitem debian debian\n\
+             item --gap\nitem local Boot from local disk\n\
+             choose --default rescue --timeout 5000 target || goto local\n\
+             goto ${target}\n\n\
+             :rescue\nkernel /rescue/bzImage\nboot || goto start\n\n\
+             :alpine\nkernel /images/alpine/vmlinuz-lts initrd=initramfs-lts\n\
+             initrd /images/alpine/initramfs-lts\nboot || goto start\n\n\
+             :debian\nkernel /images/debian/vmlinuz initrd=initrd.gz ip=dhcp server=10.0.0.1\n\
+             initrd /images/debian/initrd.gz\nboot || goto start\n\n\
+             :local\nexit\n"
+        );
+        assert!(generator
+            .generate("pxelinux.cfg/default")
+            .unwrap()
+            .contains("LABEL debian\n  KERNEL images/debian/vmlinuz\n"));
+
+        // removed images disappear from next menu
+        fs::remove_dir_all(dir.join("debian")).unwrap();
+        assert!(!generator.generate("menu.ipxe").unwrap().contains("debian"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
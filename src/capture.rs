@@ -0,0 +1,279 @@
+// Debug capture of DHCP, TFTP and HTTP traffic handled by the server into
+// pcap file, written from the server's own point of view so it needs no
+// extra privileges. Payloads are exact, IP, UDP and TCP headers are
+// synthesized. HTTP messages are rebuilt from parsed requests and responses,
+// headers added by hyper itself are not captured.
+#[cfg(feature = "http")]
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use tokio::net::UdpSocket;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const SNAPLEN: u32 = 65535;
+// packets start with IPv4 header
+const LINKTYPE_RAW: u32 = 101;
+
+#[cfg(feature = "http")]
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+#[cfg(feature = "http")]
+const TCP_PSH_ACK: u8 = 0x18;
+// keeps segments within IPv4 total length
+#[cfg(feature = "http")]
+const MAX_SEGMENT: usize = 65000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+
+struct Capture {
+    file: File,
+    path: String,
+    ip_id: u16,
+    // next sequence number of each TCP direction
+    #[cfg(feature = "http")]
+    tcp_seq: HashMap<(SocketAddrV4, SocketAddrV4), u32>,
+}
+
+pub fn open(path: &Path) -> anyhow::Result<()> {
+    let mut file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+
+    let mut header = Vec::with_capacity(24);
+    header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    // timezone and timestamp accuracy
+    header.extend_from_slice(&[0u8; 8]);
+    header.extend_from_slice(&SNAPLEN.to_le_bytes());
+    header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    file.write_all(&header)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    *CAPTURE.lock().unwrap() = Some(Capture {
+        file,
+        path: path.display().to_string(),
+        ip_id: 0,
+        #[cfg(feature = "http")]
+        tcp_seq: HashMap::new(),
+    });
+    ENABLED.store(true, Ordering::Relaxed);
+
+    Ok(())
+}
+
+#[inline]
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn udp(source: SocketAddr, destination: SocketAddr, payload: &[u8]) {
+    if !enabled() {
+        return;
+    }
+    if let (SocketAddr::V4(source), SocketAddr::V4(destination)) = (source, destination) {
+        let mut datagram = Vec::with_capacity(8 + payload.len());
+        datagram.extend_from_slice(&source.port().to_be_bytes());
+        datagram.extend_from_slice(&destination.port().to_be_bytes());
+        datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        // zero checksum means none for UDP over IPv4
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+
+        write(source, destination, PROTO_UDP, datagram, |_, _| ());
+    }
+}
+
+// datagram sent on connected socket
+pub fn udp_sent(socket: &UdpSocket, payload: &[u8]) {
+    if enabled() {
+        if let (Ok(local), Ok(peer)) = (socket.local_addr(), socket.peer_addr()) {
+            udp(local, peer, payload);
+        }
+    }
+}
+
+// datagram received on connected socket
+pub fn udp_received(socket: &UdpSocket, payload: &[u8]) {
+    if enabled() {
+        if let (Ok(local), Ok(peer)) = (socket.local_addr(), socket.peer_addr()) {
+            udp(peer, local, payload);
+        }
+    }
+}
+
+// stream data, sequence numbers continue where previous segment
+// in the same direction ended, connection setup is not captured
+#[cfg(feature = "http")]
+pub fn tcp(source: SocketAddr, destination: SocketAddr, payload: &[u8]) {
+    if !enabled() {
+        return;
+    }
+    if let (SocketAddr::V4(source), SocketAddr::V4(destination)) = (source, destination) {
+        for chunk in payload.chunks(MAX_SEGMENT) {
+            let mut segment = Vec::with_capacity(20 + chunk.len());
+            segment.extend_from_slice(&source.port().to_be_bytes());
+            segment.extend_from_slice(&destination.port().to_be_bytes());
+            // sequence and acknowledgment numbers are filled in under lock
+            segment.extend_from_slice(&[0u8; 8]);
+            segment.push(5 << 4);
+            segment.push(TCP_PSH_ACK);
+            segment.extend_from_slice(&u16::MAX.to_be_bytes());
+            // checksum and urgent pointer
+            segment.extend_from_slice(&[0u8; 4]);
+            segment.extend_from_slice(chunk);
+
+            write(
+                source,
+                destination,
+                PROTO_TCP,
+                segment,
+                |capture, segment| {
+                    let seq = capture.next_seq(source, destination, chunk.len() as u32);
+                    let ack = capture.next_seq(destination, source, 0);
+                    segment[4..8].copy_from_slice(&seq.to_be_bytes());
+                    segment[8..12].copy_from_slice(&ack.to_be_bytes());
+                },
+            );
+        }
+    }
+}
+
+fn write(
+    source: SocketAddrV4,
+    destination: SocketAddrV4,
+    protocol: u8,
+    mut transport: Vec<u8>,
+    prepare: impl FnOnce(&mut Capture, &mut [u8]),
+) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let mut guard = CAPTURE.lock().unwrap();
+    let capture = match guard.as_mut() {
+        Some(capture) => capture,
+        None => return,
+    };
+    prepare(capture, &mut transport);
+    capture.ip_id = capture.ip_id.wrapping_add(1);
+    let packet = ipv4_packet(source, destination, protocol, capture.ip_id, &transport);
+
+    let mut record = Vec::with_capacity(16 + packet.len());
+    record.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+    record.extend_from_slice(&timestamp.subsec_micros().to_le_bytes());
+    record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    record.extend_from_slice(&packet);
+
+    if let Err(e) = capture.file.write_all(&record) {
+        error!("capture to {} stopped: {}", capture.path, e);
+        *guard = None;
+        ENABLED.store(false, Ordering::Relaxed);
+    }
+}
+
+// Forgets sequence numbers of connection when dropped, it is kept by
+// whatever lives as long as connection does.
+#[cfg(feature = "http")]
+pub struct TcpConnection {
+    local: SocketAddr,
+    peer: SocketAddr,
+}
+
+#[cfg(feature = "http")]
+impl TcpConnection {
+    pub fn new(local: SocketAddr, peer: SocketAddr) -> Self {
+        Self { local, peer }
+    }
+}
+
+#[cfg(feature = "http")]
+impl Drop for TcpConnection {
+    fn drop(&mut self) {
+        if !enabled() {
+            return;
+        }
+        if let (SocketAddr::V4(local), SocketAddr::V4(peer)) = (self.local, self.peer) {
+            if let Some(capture) = CAPTURE.lock().unwrap().as_mut() {
+                capture.tcp_seq.remove(&(local, peer));
+                capture.tcp_seq.remove(&(peer, local));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl Capture {
+    // returns current sequence number and advances it by len
+    fn next_seq(&mut self, source: SocketAddrV4, destination: SocketAddrV4, len: u32) -> u32 {
+        let seq = self.tcp_seq.entry((source, destination)).or_insert(1);
+        let current = *seq;
+        *seq = seq.wrapping_add(len);
+        current
+    }
+}
+
+fn ipv4_packet(
+    source: SocketAddrV4,
+    destination: SocketAddrV4,
+    protocol: u8,
+    id: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(20 + payload.len());
+    packet.push(0x45);
+    packet.push(0);
+    packet.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    // don't fragment
+    packet.extend_from_slice(&0x4000u16.to_be_bytes());
+    packet.push(64);
+    packet.push(protocol);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&source.ip().octets());
+    packet.extend_from_slice(&destination.ip().octets());
+
+    let checksum = !packet
+        .chunks(2)
+        .map(|x| u16::from_be_bytes([x[0], x[1]]) as u32)
+        .fold(0u32, |sum, x| {
+            let sum = sum + x;
+            (sum & 0xffff) + (sum >> 16)
+        }) as u16;
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    packet.extend_from_slice(payload);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_ipv4_packet() {
+        let packet = ipv4_packet(
+            SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 1), 67),
+            SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 199), 68),
+            PROTO_UDP,
+            0,
+            &[0u8; 0x73 - 20],
+        );
+        assert_eq!(
+            &packet[..20],
+            &[
+                0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8,
+                0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7
+            ]
+        );
+    }
+}
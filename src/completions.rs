@@ -0,0 +1,47 @@
+use std::io;
+use std::str::FromStr;
+
+use clap::IntoApp;
+use clap_generate::generate;
+use clap_generate::generators::{Bash, Elvish, Fish, PowerShell, Zsh};
+
+use crate::Options;
+
+const BIN_NAME: &str = env!("CARGO_PKG_NAME");
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Elvish,
+    Fish,
+    Powershell,
+    Zsh,
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Self::Bash),
+            "elvish" => Ok(Self::Elvish),
+            "fish" => Ok(Self::Fish),
+            "powershell" => Ok(Self::Powershell),
+            "zsh" => Ok(Self::Zsh),
+            _ => bail!("unsupported shell \"{}\"", s),
+        }
+    }
+}
+
+pub fn print(shell: Shell) {
+    let mut app = Options::into_app();
+    let out = &mut io::stdout();
+
+    match shell {
+        Shell::Bash => generate::<Bash, _>(&mut app, BIN_NAME, out),
+        Shell::Elvish => generate::<Elvish, _>(&mut app, BIN_NAME, out),
+        Shell::Fish => generate::<Fish, _>(&mut app, BIN_NAME, out),
+        Shell::Powershell => generate::<PowerShell, _>(&mut app, BIN_NAME, out),
+        Shell::Zsh => generate::<Zsh, _>(&mut app, BIN_NAME, out),
+    }
+}
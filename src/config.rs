@@ -0,0 +1,1510 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::dhcp::allocation::Allocation;
+use crate::dhcp::id::Mac;
+use crate::dhcp::packet::RelayAgentInfo;
+use crate::iputil::{Ipv4AddrAndMask, Ipv4Range};
+use crate::units::HumanDuration;
+
+// Configuration file, complements command line options.
+//
+// [[profile]]
+// name = "uefi"
+// boot_file = "ipxe.efi"
+// ipxe_template = "/srv/pxe/uefi.ipxe"
+// nbd_export = "debian"
+// kernel = "debian/linux"
+// initrd = "debian/initrd.gz"
+// cmdline = "root={{root_path}} ip=dhcp"
+// user_data = "/srv/pxe/cloud-config.yaml"
+//
+// [profile.iscsi]
+// server = "10.0.0.5"
+// target = "iqn.2021-01.lab:disk1"
+//
+// [[profile]]
+// name = "signed"
+// kernel = "signed/vmlinuz"
+//
+// [profile.secure_boot]
+// directory = "signed"
+//
+// [[profile.option]]
+// code = 252
+// value = "http://10.0.0.1/wpad.dat"
+//
+// [[selector]]
+// profile = "uefi"
+// arch = 7
+//
+// [[selector.option]]
+// code = 224
+// hex = "01:02:03:04"
+//
+// [[option]]
+// code = 42
+// hex = "0a000001"
+//
+// [[option]]
+// code = 4
+// type = "ip"
+// value = "10.0.0.1, 10.0.0.2"
+//
+// [[vendor_class]]
+// match = "udhcp"
+// boot_file = "udhcp/boot.scr"
+//
+// [[vendor_class]]
+// match = "PXEClient"
+// lease_time = "10m"
+//
+// [[vendor_class.option]]
+// code = 224
+// value = "embedded"
+//
+// [[nbd_export]]
+// name = "debian"
+// path = "/srv/images/debian.img"
+//
+// [[dns_record]]
+// name = "mirror.lab"
+// ip = "10.0.0.2"
+//
+// [hooks]
+// boot_file = "/usr/local/lib/pxe/choose-boot-file"
+// lease = "/usr/local/lib/pxe/allow-lease"
+// tftp_path = "/usr/local/lib/pxe/rewrite-path"
+// lease_event = "/usr/local/lib/pxe/register-host"
+// lease_event_url = "https://inventory.lab/api/dhcp-events"
+//
+// [[pool]]
+// range = "10.0.2.100-10.0.2.200/24"
+// router = "10.0.2.1"
+// profile = "uefi"
+// root_path = "10.0.2.1:/srv/nfsroot"
+//
+// [[instance]]
+// name = "lab1"
+// server_ip = "10.0.1.1"
+// dhcp_range = "10.0.1.100-10.0.1.200/24"
+//
+// [[instance]]
+// name = "lab2"
+// interface = "eth2"
+// dhcp_range = "10.0.3.100-10.0.3.200/24"
+// dhcp_allocation = "hash"
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // sent to every client, profile and selector options take precedence
+    #[serde(default, rename = "option")]
+    pub options: Vec<ExtraOption>,
+
+    #[serde(default, rename = "profile")]
+    pub profiles: Vec<Profile>,
+
+    // evaluated in order, first matching selector wins
+    // clients not matched by any selector boot the loader given on command line
+    #[serde(default, rename = "selector")]
+    pub selectors: Vec<Selector>,
+
+    // for clients not matched by any selector, first matching one wins,
+    // shared by all instances
+    #[serde(default, rename = "vendor_class")]
+    pub vendor_classes: Vec<VendorClassPolicy>,
+
+    // fixed addresses, never offered to other clients, with instances
+    // each one has its own as they belong to its DHCP range
+    #[serde(default, rename = "reservation")]
+    pub reservations: Vec<Reservation>,
+
+    // subnets behind DHCP relays, with instances each one has its own
+    #[serde(default, rename = "pool")]
+    pub pools: Vec<Pool>,
+
+    // read-only images served over NBD, shared by all instances
+    #[serde(default, rename = "nbd_export")]
+    pub nbd_exports: Vec<NbdExport>,
+
+    // static records served by --dns, shared by all instances
+    #[serde(default, rename = "dns_record")]
+    pub dns_records: Vec<DnsRecord>,
+
+    // network card drivers announced by --binl, shared by all instances
+    #[serde(default, rename = "binl_driver")]
+    pub binl_drivers: Vec<BinlDriver>,
+
+    // external programs consulted at decision points, shared by all instances
+    #[serde(default)]
+    pub hooks: Hooks,
+
+    // independent sets of servers, each one overrides command line options
+    // when empty single instance is started from command line options
+    #[serde(default, rename = "instance")]
+    pub instances: Vec<Instance>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub name: String,
+    // path relative to TFTP root, sent to client as is
+    // may be left out when secure_boot provides loader for client
+    #[serde(default)]
+    pub boot_file: String,
+    // TFTP server to boot from, defaults to our own address
+    pub next_server: Option<Ipv4Addr>,
+    // extra options appended to OFFER/ACK
+    #[serde(default, rename = "option")]
+    pub options: Vec<ExtraOption>,
+    // iPXE script served over HTTP as /profiles/<name>.ipxe
+    pub ipxe_template: Option<PathBuf>,
+    // NBD export mounted as root, available to templates as {{nbd_root}}
+    pub nbd_export: Option<String>,
+    // sent in option 17, e.g. 10.0.0.2:/srv/nfsroot
+    pub root_path: Option<String>,
+    // SAN disk, sent in option 17 and booted with sanboot
+    // when profile has no iPXE template
+    pub iscsi: Option<IscsiTarget>,
+    // listed in generated loader menus, paths relative to TFTP root
+    pub kernel: Option<String>,
+    pub initrd: Option<String>,
+    // may refer to {{server_ip}}, {{profile}} and {{root_path}}
+    pub cmdline: Option<String>,
+    // UEFI clients boot signed shim and GRUB from given directory,
+    // see secureboot module
+    pub secure_boot: Option<SecureBoot>,
+    // cloud-init user data and Ignition config served to clients of profile,
+    // templates like ipxe_template, see http::metadata module
+    pub user_data: Option<PathBuf>,
+    pub ignition: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecureBoot {
+    // relative to TFTP root, holds shimx64.efi and grubx64.efi
+    // and their aa64 counterparts
+    pub directory: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IscsiTarget {
+    pub server: Ipv4Addr,
+    // defaults to 3260 when not given
+    pub port: Option<u16>,
+    pub lun: Option<u32>,
+    // IQN of target
+    pub target: String,
+}
+
+// DHCP option of any code, given either as value of its type or as raw
+// bytes in hex, optionally separated by colons
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtraOption {
+    pub code: u8,
+    #[serde(default, rename = "type")]
+    pub kind: OptionType,
+    pub value: Option<String>,
+    pub hex: Option<String>,
+}
+
+// how value of option is encoded, numbers are big endian
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionType {
+    #[default]
+    String,
+    // comma separated addresses
+    Ip,
+    U8,
+    U16,
+    U32,
+    // true or false, one byte
+    Bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Selector {
+    pub profile: String,
+    // client system architecture (DHCP option 93)
+    pub arch: Option<u16>,
+    // prefix of vendor class identifier (DHCP option 60)
+    pub vendor_class: Option<String>,
+    pub mac: Option<Mac>,
+    // SMBIOS UUID of client (DHCP option 97)
+    pub uuid: Option<String>,
+    // relay agent information (DHCP option 82) in printed form,
+    // e.g. eth0/1:100 or 00:04:00:64:01:07, see RelayId
+    pub circuit_id: Option<String>,
+    pub remote_id: Option<String>,
+    // extra options for matched clients, override those of profile
+    #[serde(default, rename = "option")]
+    pub options: Vec<ExtraOption>,
+}
+
+// Boot file and options for clients sending given vendor class
+// identifier (DHCP option 60)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VendorClassPolicy {
+    // prefix of vendor class, e.g. PXEClient:Arch:00007 or udhcp
+    #[serde(rename = "match")]
+    pub prefix: String,
+    // path relative to TFTP root, loader is sent when not given
+    pub boot_file: Option<String>,
+    // instead of --dhcp-lease-time, for every client of class including
+    // those matched by selector, e.g. 10m for PXE ROMs
+    pub lease_time: Option<HumanDuration>,
+    #[serde(default, rename = "option")]
+    pub options: Vec<ExtraOption>,
+}
+
+// Client is identified by exactly one of MAC, client identifier and UUID.
+// Address may lie outside of DHCP range, but not outside of its subnet.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Reservation {
+    pub ip: Ipv4Addr,
+    pub mac: Option<Mac>,
+    // DHCP option 61 as hex, colons allowed, e.g. 01:52:54:00:12:34:56
+    pub client_id: Option<String>,
+    // DHCP option 97, e.g. 4c4c4544-0042-3610-8057-b4c04f4d3732
+    pub uuid: Option<String>,
+    // sent instead of boot file from profile selection
+    pub boot_file: Option<String>,
+    // option 17, takes precedence over those of profile and pool
+    pub root_path: Option<String>,
+}
+
+// Subnet served through DHCP relay, chosen for relayed requests whose relay
+// agent address (giaddr) it contains. Clients on local segment keep getting
+// addresses from dhcp_range.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pool {
+    // e.g. 10.0.2.100-10.0.2.200/24
+    pub range: Ipv4Range,
+    // sent in option 3, relay agent address when not given
+    pub router: Option<Ipv4Addr>,
+    // boots clients not matched by any selector instead of loader
+    pub profile: Option<String>,
+    // option 17 for clients whose profile has none, e.g. 10.0.2.1:/srv/nfsroot
+    pub root_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NbdExport {
+    pub name: String,
+    // image file or block device
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsRecord {
+    pub name: String,
+    pub ip: Ipv4Addr,
+}
+
+// Windows driver of PCI network card, IDs are best written in hex,
+// e.g. vendor = 0x8086
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BinlDriver {
+    pub vendor: u16,
+    pub device: u16,
+    // matches any subsystem when left out
+    pub subsystem: Option<u32>,
+    // e.g. e1000325.sys
+    pub driver: String,
+    // e.g. E1000
+    pub service: String,
+    pub description: Option<String>,
+}
+
+// executables, see hooks module for protocol
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    pub boot_file: Option<PathBuf>,
+    pub lease: Option<PathBuf>,
+    pub tftp_path: Option<PathBuf>,
+    // told about offers, acks, renewals, releases and expiries
+    pub lease_event: Option<PathBuf>,
+    // same events POSTed as JSON
+    pub lease_event_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Instance {
+    pub name: String,
+    pub server_ip: Option<Ipv4Addr>,
+    // network interface, provides server IP and DHCP subnet when not given
+    pub interface: Option<String>,
+    pub dhcp_range: Option<Ipv4Range>,
+    // e.g. 12h
+    pub dhcp_lease_time: Option<HumanDuration>,
+    // first-free or hash, see dhcp::allocation
+    pub dhcp_allocation: Option<Allocation>,
+    // DNS servers announced to clients instead of global ones
+    #[serde(default)]
+    pub dhcp_dns: Vec<Ipv4Addr>,
+    pub dhcp_domain: Option<String>,
+    #[serde(default)]
+    pub dhcp_search: Vec<String>,
+    #[serde(default)]
+    pub dhcp_ntp: Vec<Ipv4Addr>,
+    pub tftp_root: Option<PathBuf>,
+    pub loader: Option<PathBuf>,
+    // sent instead of loader and profile boot files to clients running iPXE
+    pub ipxe_boot_file: Option<String>,
+    // TFTP server on another host
+    pub next_server: Option<Ipv4Addr>,
+    pub tftp_server_name: Option<String>,
+    pub http_port: Option<u16>,
+    // leases kept across restarts, each instance needs its own
+    pub lease_file: Option<PathBuf>,
+    // SQLite database instead of lease file
+    pub lease_db: Option<PathBuf>,
+    // replace global --mac-allow and --mac-deny
+    pub mac_allow: Option<PathBuf>,
+    pub mac_deny: Option<PathBuf>,
+    // only clients from these subnets may fetch files over TFTP and HTTP,
+    // any client when empty
+    #[serde(default)]
+    pub client_subnets: Vec<Ipv4AddrAndMask>,
+    // user:password required from HTTP clients, iPXE templates put it
+    // in URLs as {{http_auth}}
+    pub http_credentials: Option<String>,
+    // global profiles and selectors are not inherited, so that labs
+    // sharing a server cannot boot each other's images
+    #[serde(default)]
+    pub isolated: bool,
+    #[serde(default)]
+    pub no_dhcp: bool,
+    // only boot files for PXE clients, addresses come from another server
+    #[serde(default)]
+    pub proxy_dhcp: bool,
+    // addresses only for PXE clients, others are left to another server
+    #[serde(default)]
+    pub pxe_only: bool,
+    // two message exchange for clients asking for it
+    #[serde(default)]
+    pub rapid_commit: bool,
+    #[serde(default)]
+    pub no_tftp: bool,
+    #[serde(default, rename = "profile")]
+    pub profiles: Vec<Profile>,
+    #[serde(default, rename = "selector")]
+    pub selectors: Vec<Selector>,
+    #[serde(default, rename = "reservation")]
+    pub reservations: Vec<Reservation>,
+    #[serde(default, rename = "pool")]
+    pub pools: Vec<Pool>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        // validated together with command line options, see Config::verify_into
+        toml::from_str(&data).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    // collects all problems instead of stopping at the first one
+    pub fn verify(&self) -> Result<(), Diagnostics> {
+        let mut diagnostics = Diagnostics::default();
+        self.verify_into(&mut diagnostics);
+        diagnostics.into_result()
+    }
+
+    pub fn verify_into(&self, diagnostics: &mut Diagnostics) {
+        verify_options("", &self.options, diagnostics);
+        verify_profiles("", &self.profiles, &self.nbd_exports, diagnostics);
+        verify_selectors("", &self.selectors, &[&self.profiles], diagnostics);
+        for (i, policy) in self.vendor_classes.iter().enumerate() {
+            let path = format!("vendor_class[{}]", i);
+            if policy.prefix.is_empty() {
+                diagnostics.error(format!("{}.match", path), "must not be empty");
+            }
+            if let Some(lease_time) = policy.lease_time {
+                let secs = lease_time.get().as_secs();
+                if secs < 1 || secs >= u32::MAX.into() {
+                    diagnostics.error(
+                        format!("{}.lease_time", path),
+                        format!("{} is out of range", lease_time),
+                    );
+                }
+            }
+            verify_options(&path, &policy.options, diagnostics);
+        }
+        verify_reservations("", &self.reservations, diagnostics);
+        if !self.instances.is_empty() && !self.reservations.is_empty() {
+            diagnostics.error(
+                "reservation",
+                "not used with instances, reserve addresses in instance.reservation",
+            );
+        }
+        verify_pools("", &self.pools, &[&self.profiles], diagnostics);
+        if !self.instances.is_empty() && !self.pools.is_empty() {
+            diagnostics.error("pool", "not used with instances, use instance.pool");
+        }
+
+        for (i, export) in self.nbd_exports.iter().enumerate() {
+            let path = format!("nbd_export[{}]", i);
+
+            if self.nbd_exports[..i].iter().any(|x| x.name == export.name) {
+                diagnostics.error(
+                    format!("{}.name", path),
+                    format!("export {} defined more than once", export.name),
+                );
+            }
+
+            if let Err(e) = fs::File::open(&export.path) {
+                diagnostics.error(
+                    format!("{}.path", path),
+                    format!("cannot open {}: {}", export.path.display(), e),
+                );
+            }
+        }
+
+        for (i, record) in self.dns_records.iter().enumerate() {
+            let name = record.name.trim_end_matches('.');
+            if name.is_empty() || name.split('.').any(|x| x.is_empty() || x.len() > 63) {
+                diagnostics.error(
+                    format!("dns_record[{}].name", i),
+                    format!("invalid name \"{}\"", record.name),
+                );
+            }
+        }
+
+        for (i, driver) in self.binl_drivers.iter().enumerate() {
+            for (field, value) in [("driver", &driver.driver), ("service", &driver.service)].iter()
+            {
+                if value.is_empty() {
+                    diagnostics.error(format!("binl_driver[{}].{}", i, field), "must not be empty");
+                }
+            }
+        }
+
+        for (name, hook) in [
+            ("boot_file", &self.hooks.boot_file),
+            ("lease", &self.hooks.lease),
+            ("tftp_path", &self.hooks.tftp_path),
+            ("lease_event", &self.hooks.lease_event),
+        ]
+        .iter()
+        {
+            if let Some(hook) = hook {
+                if let Err(e) = fs::metadata(hook) {
+                    diagnostics.error(
+                        format!("hooks.{}", name),
+                        format!("cannot run {}: {}", hook.display(), e),
+                    );
+                }
+            }
+        }
+
+        if let Some(url) = self.hooks.lease_event_url.as_deref() {
+            if cfg!(not(feature = "fetch")) {
+                diagnostics.error("hooks.lease_event_url", "requires fetch feature");
+            } else if !url.starts_with("http://") && !url.starts_with("https://") {
+                diagnostics.error("hooks.lease_event_url", format!("{} is not HTTP URL", url));
+            }
+        }
+
+        for (i, instance) in self.instances.iter().enumerate() {
+            let path = format!("instance[{}]", i);
+
+            if self.instances[..i].iter().any(|x| x.name == instance.name) {
+                diagnostics.error(
+                    format!("{}.name", path),
+                    format!("instance {} defined more than once", instance.name),
+                );
+            }
+
+            if let Some(range) = instance.dhcp_range {
+                if instance.no_dhcp {
+                    diagnostics.error(format!("{}.dhcp_range", path), "set together with no_dhcp");
+                }
+
+                for (j, other) in self.instances[..i].iter().enumerate() {
+                    if matches!(other.dhcp_range, Some(r) if r.overlaps(&range)) {
+                        diagnostics.error(
+                            format!("{}.dhcp_range", path),
+                            format!("{} overlaps with instance[{}] ({})", range, j, other.name),
+                        );
+                    }
+                }
+            }
+
+            if let Some(lease_file) = instance.lease_file.as_ref() {
+                if let Some(j) = self.instances[..i]
+                    .iter()
+                    .position(|x| x.lease_file.as_ref() == Some(lease_file))
+                {
+                    diagnostics.error(
+                        format!("{}.lease_file", path),
+                        format!("{} already used by instance[{}]", lease_file.display(), j),
+                    );
+                }
+            }
+            if let Some(lease_db) = instance.lease_db.as_ref() {
+                if cfg!(not(feature = "sqlite")) {
+                    diagnostics.error(format!("{}.lease_db", path), "requires sqlite feature");
+                } else if instance.lease_file.is_some() {
+                    diagnostics.error(format!("{}.lease_db", path), "set together with lease_file");
+                } else if let Some(j) = self.instances[..i]
+                    .iter()
+                    .position(|x| x.lease_db.as_ref() == Some(lease_db))
+                {
+                    diagnostics.error(
+                        format!("{}.lease_db", path),
+                        format!("{} already used by instance[{}]", lease_db.display(), j),
+                    );
+                }
+            }
+
+            if instance.no_tftp && instance.loader.is_some() {
+                diagnostics.error(format!("{}.loader", path), "set together with no_tftp");
+            }
+
+            let prefix = format!("{}.", path);
+            verify_profiles(&prefix, &instance.profiles, &self.nbd_exports, diagnostics);
+            let global: &[Profile] = if instance.isolated {
+                &[]
+            } else {
+                &self.profiles
+            };
+            verify_selectors(
+                &prefix,
+                &instance.selectors,
+                &[&instance.profiles, global],
+                diagnostics,
+            );
+            verify_reservations(&prefix, &instance.reservations, diagnostics);
+            verify_pools(
+                &prefix,
+                &instance.pools,
+                &[&instance.profiles, global],
+                diagnostics,
+            );
+        }
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn select_profile(
+        &self,
+        mac: &Mac,
+        uuid: Option<&str>,
+        arch: Option<u16>,
+        vendor_class: Option<&str>,
+        relay: Option<&RelayAgentInfo>,
+    ) -> Option<&Profile> {
+        self.select(mac, uuid, arch, vendor_class, relay)
+            .map(|(_, profile)| profile)
+    }
+
+    pub fn vendor_class_policy(&self, vendor_class: Option<&str>) -> Option<&VendorClassPolicy> {
+        let vendor_class = vendor_class?;
+        self.vendor_classes
+            .iter()
+            .find(|x| vendor_class.starts_with(&x.prefix))
+    }
+
+    // matching selector together with its profile
+    pub fn select(
+        &self,
+        mac: &Mac,
+        uuid: Option<&str>,
+        arch: Option<u16>,
+        vendor_class: Option<&str>,
+        relay: Option<&RelayAgentInfo>,
+    ) -> Option<(&Selector, &Profile)> {
+        self.selectors
+            .iter()
+            .find(|s| s.matches(mac, uuid, arch, vendor_class, relay))
+            .and_then(|s| Some((s, self.profile(&s.profile)?)))
+    }
+}
+
+// prefix is prepended to field paths, e.g. "instance[0]."
+fn verify_profiles(
+    prefix: &str,
+    profiles: &[Profile],
+    nbd_exports: &[NbdExport],
+    diagnostics: &mut Diagnostics,
+) {
+    for (i, profile) in profiles.iter().enumerate() {
+        let path = format!("{}profile[{}]", prefix, i);
+
+        if profiles[..i].iter().any(|p| p.name == profile.name) {
+            diagnostics.error(
+                format!("{}.name", path),
+                format!("profile {} defined more than once", profile.name),
+            );
+        }
+
+        if profile.boot_file.is_empty() && profile.secure_boot.is_none() {
+            diagnostics.error(format!("{}.boot_file", path), "must not be empty");
+        }
+
+        verify_options(&path, &profile.options, diagnostics);
+
+        for (field, template) in [
+            ("ipxe_template", profile.ipxe_template.as_deref()),
+            ("user_data", profile.user_data.as_deref()),
+            ("ignition", profile.ignition.as_deref()),
+        ] {
+            let template = match template {
+                Some(x) => x,
+                None => continue,
+            };
+            if let Err(e) = fs::metadata(template) {
+                diagnostics.error(
+                    format!("{}.{}", path, field),
+                    format!("cannot read {}: {}", template.display(), e),
+                );
+            }
+        }
+
+        if profile.kernel.is_none() {
+            if profile.initrd.is_some() {
+                diagnostics.error(format!("{}.initrd", path), "set without kernel");
+            }
+            if profile.cmdline.is_some() {
+                diagnostics.error(format!("{}.cmdline", path), "set without kernel");
+            }
+        }
+
+        if profile.root_path.is_some() && profile.iscsi.is_some() {
+            diagnostics.error(format!("{}.root_path", path), "set together with iscsi");
+        }
+        verify_root_path(&path, profile.root_path.as_deref(), diagnostics);
+        // that of NBD export for longest server address and port it may get
+        let longest = profile.root_path(Ipv4Addr::new(255, 255, 255, 255), u16::MAX);
+        if profile.root_path.is_none() && matches!(longest, Some(x) if x.len() > 255) {
+            let field = match profile.iscsi {
+                Some(_) => "iscsi",
+                None => "nbd_export",
+            };
+            diagnostics.error(
+                format!("{}.{}", path, field),
+                "root path made of it is longer than 255 bytes",
+            );
+        }
+
+        if let Some(iscsi) = profile.iscsi.as_ref() {
+            if iscsi.target.is_empty() || iscsi.target.contains(char::is_whitespace) {
+                diagnostics.error(
+                    format!("{}.iscsi.target", path),
+                    format!("invalid target name \"{}\"", iscsi.target),
+                );
+            }
+        }
+
+        if let Some(export) = profile.nbd_export.as_deref() {
+            if !nbd_exports.iter().any(|x| x.name == export) {
+                diagnostics.error(
+                    format!("{}.nbd_export", path),
+                    format!("unknown export {}", export),
+                );
+            }
+        }
+    }
+}
+
+// profiles are searched in order, selector may refer to any of them
+fn verify_selectors(
+    prefix: &str,
+    selectors: &[Selector],
+    profiles: &[&[Profile]],
+    diagnostics: &mut Diagnostics,
+) {
+    for (i, selector) in selectors.iter().enumerate() {
+        verify_options(
+            &format!("{}selector[{}]", prefix, i),
+            &selector.options,
+            diagnostics,
+        );
+        if !profiles
+            .iter()
+            .any(|p| p.iter().any(|p| p.name == selector.profile))
+        {
+            diagnostics.error(
+                format!("{}selector[{}].profile", prefix, i),
+                format!("unknown profile {}", selector.profile),
+            );
+        }
+        if let Some(uuid) = selector.uuid.as_deref().filter(|x| !valid_uuid(x)) {
+            diagnostics.error(
+                format!("{}selector[{}].uuid", prefix, i),
+                format!("invalid UUID {}", uuid),
+            );
+        }
+    }
+}
+
+// in form printed by Packet::client_uuid, either case
+fn valid_uuid(uuid: &str) -> bool {
+    uuid.len() == 36
+        && uuid.char_indices().all(|(i, x)| match i {
+            8 | 13 | 18 | 23 => x == '-',
+            _ => x.is_ascii_hexdigit(),
+        })
+}
+
+fn verify_reservations(prefix: &str, reservations: &[Reservation], diagnostics: &mut Diagnostics) {
+    for (i, reservation) in reservations.iter().enumerate() {
+        let path = format!("{}reservation[{}]", prefix, i);
+
+        let identities = |client_id: bool| {
+            [
+                reservation.mac.is_some(),
+                client_id,
+                reservation.uuid.is_some(),
+            ]
+            .iter()
+            .filter(|x| **x)
+            .count()
+        };
+        match reservation.client_id() {
+            Err(e) => diagnostics.error(format!("{}.client_id", path), e),
+            Ok(x) if identities(x.is_some()) != 1 => diagnostics.error(
+                path.clone(),
+                "exactly one of mac, client_id and uuid must be given",
+            ),
+            Ok(Some(x)) if x.is_empty() => {
+                diagnostics.error(format!("{}.client_id", path), "must not be empty")
+            }
+            _ => (),
+        }
+        if let Some(uuid) = reservation.uuid.as_deref().filter(|x| !valid_uuid(x)) {
+            diagnostics.error(format!("{}.uuid", path), format!("invalid UUID {}", uuid));
+        }
+        verify_root_path(&path, reservation.root_path.as_deref(), diagnostics);
+
+        let earlier = &reservations[..i];
+        if earlier.iter().any(|x| x.ip == reservation.ip) {
+            diagnostics.error(
+                format!("{}.ip", path),
+                format!("{} reserved more than once", reservation.ip),
+            );
+        }
+        let client_id = reservation.client_id().ok().flatten();
+        if earlier.iter().any(|x| {
+            (x.mac.is_some() && x.mac == reservation.mac)
+                || (client_id.is_some() && x.client_id().ok().flatten() == client_id)
+                || matches!((&x.uuid, &reservation.uuid), (Some(a), Some(b)) if a.eq_ignore_ascii_case(b))
+        }) {
+            diagnostics.error(
+                path,
+                format!("{} has more than one reservation", reservation.client()),
+            );
+        }
+    }
+}
+
+// Relayed request picks pool by subnet, so subnets must not overlap.
+// Overlap with dhcp_range is checked together with command line options.
+fn verify_pools(
+    prefix: &str,
+    pools: &[Pool],
+    profiles: &[&[Profile]],
+    diagnostics: &mut Diagnostics,
+) {
+    for (i, pool) in pools.iter().enumerate() {
+        let path = format!("{}pool[{}]", prefix, i);
+        let subnet = pool.range.subnet();
+
+        if let Some(other) = pools[..i].iter().find(|x| {
+            x.range.subnet().contains(subnet.address())
+                || subnet.contains(x.range.subnet().address())
+        }) {
+            diagnostics.error(
+                format!("{}.range", path),
+                format!("{} overlaps with {}", pool.range, other.range),
+            );
+        }
+        if let Some(router) = pool.router.filter(|x| !subnet.contains(*x)) {
+            diagnostics.error(
+                format!("{}.router", path),
+                format!("{} is outside of {}", router, subnet),
+            );
+        }
+        if let Some(profile) = pool.profile.as_deref() {
+            if !profiles.iter().any(|p| p.iter().any(|p| p.name == profile)) {
+                diagnostics.error(
+                    format!("{}.profile", path),
+                    format!("unknown profile {}", profile),
+                );
+            }
+        }
+        verify_root_path(&path, pool.root_path.as_deref(), diagnostics);
+    }
+}
+
+// sent as is in option 17
+fn verify_root_path(path: &str, root_path: Option<&str>, diagnostics: &mut Diagnostics) {
+    match root_path {
+        Some("") => diagnostics.error(format!("{}.root_path", path), "must not be empty"),
+        Some(x) if x.len() > 255 => {
+            diagnostics.error(format!("{}.root_path", path), "longer than 255 bytes")
+        }
+        _ => (),
+    }
+}
+
+// path is that of owner of options, empty for global ones
+fn verify_options(path: &str, options: &[ExtraOption], diagnostics: &mut Diagnostics) {
+    for (i, option) in options.iter().enumerate() {
+        let path = match path {
+            "" => format!("option[{}]", i),
+            _ => format!("{}.option[{}]", path, i),
+        };
+
+        // pad, end and message type are managed by the server itself
+        if matches!(option.code, 0 | 53 | 255) {
+            diagnostics.error(
+                format!("{}.code", path),
+                format!("option {} cannot be overridden", option.code),
+            );
+        }
+        match option.data() {
+            Ok(data) if data.len() > u8::MAX as usize => diagnostics.error(
+                path,
+                format!("{} bytes do not fit into single option", data.len()),
+            ),
+            Ok(_) => (),
+            Err(e) => diagnostics.error(path, e),
+        }
+    }
+}
+
+impl ExtraOption {
+    pub fn data(&self) -> anyhow::Result<Vec<u8>> {
+        match (self.value.as_deref(), self.hex.as_deref()) {
+            (Some(value), None) => self.kind.encode(value),
+            (None, Some(_)) if self.kind != OptionType::String => {
+                bail!("type applies to value only")
+            }
+            (None, Some(hex)) => crate::signature::from_hex(&hex.replace(':', "")),
+            _ => bail!("exactly one of value and hex must be given"),
+        }
+    }
+}
+
+impl OptionType {
+    fn encode(self, value: &str) -> anyhow::Result<Vec<u8>> {
+        let number = || {
+            value
+                .trim()
+                .parse::<u32>()
+                .with_context(|| format!("invalid number {}", value))
+        };
+        let data = match self {
+            Self::String => value.as_bytes().to_vec(),
+            Self::Ip => {
+                let mut data = Vec::new();
+                for x in value.split(',').map(str::trim) {
+                    let ip: Ipv4Addr = x
+                        .parse()
+                        .with_context(|| format!("invalid IP address {}", x))?;
+                    data.extend_from_slice(&ip.octets());
+                }
+                data
+            }
+            Self::U8 => u8::try_from(number()?)
+                .context("number does not fit into u8")?
+                .to_be_bytes()
+                .to_vec(),
+            Self::U16 => u16::try_from(number()?)
+                .context("number does not fit into u16")?
+                .to_be_bytes()
+                .to_vec(),
+            Self::U32 => number()?.to_be_bytes().to_vec(),
+            Self::Bool => match value.trim() {
+                "true" => vec![1],
+                "false" => vec![0],
+                _ => bail!("invalid bool {}, expected true or false", value),
+            },
+        };
+        Ok(data)
+    }
+}
+
+impl Reservation {
+    pub fn client_id(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        self.client_id
+            .as_deref()
+            .map(|x| crate::signature::from_hex(&x.replace(':', "")))
+            .transpose()
+    }
+
+    // client_id is DHCP option 61 sent by client, empty when missing
+    pub fn matches(&self, mac: &Mac, client_id: &[u8], uuid: Option<&str>) -> bool {
+        match (self.mac.as_ref(), self.client_id(), self.uuid.as_deref()) {
+            (Some(x), _, _) => x == mac,
+            (None, Ok(Some(x)), _) => x == client_id,
+            (None, Ok(None), Some(x)) => matches!(uuid, Some(u) if u.eq_ignore_ascii_case(x)),
+            _ => false,
+        }
+    }
+
+    pub fn client(&self) -> String {
+        match (
+            self.mac.as_ref(),
+            self.client_id.as_deref(),
+            self.uuid.as_deref(),
+        ) {
+            (Some(mac), _, _) => mac.to_string(),
+            (None, Some(client_id), _) => format!("client id {}", client_id),
+            (None, None, Some(uuid)) => format!("UUID {}", uuid),
+            (None, None, None) => "nobody".to_string(),
+        }
+    }
+}
+
+impl Profile {
+    // explicit root path wins over iSCSI target and NBD export
+    pub fn root_path(&self, server_ip: Ipv4Addr, nbd_port: u16) -> Option<String> {
+        if let Some(root_path) = self.root_path.as_ref() {
+            return Some(root_path.clone());
+        }
+        if let Some(iscsi) = self.iscsi.as_ref() {
+            return Some(iscsi.to_string());
+        }
+
+        self.nbd_export
+            .as_deref()
+            .map(|x| crate::nbd::root_path(server_ip, nbd_port, x))
+    }
+}
+
+// RFC 4173 iscsi:<server>:<protocol>:<port>:<LUN>:<target>, also understood
+// by iPXE sanboot, empty fields take their defaults
+impl fmt::Display for IscsiTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "iscsi:{}::", self.server)?;
+        if let Some(port) = self.port {
+            write!(f, "{}", port)?;
+        }
+        f.write_str(":")?;
+        if let Some(lun) = self.lun {
+            write!(f, "{:x}", lun)?;
+        }
+        write!(f, ":{}", self.target)
+    }
+}
+
+impl Selector {
+    fn matches(
+        &self,
+        mac: &Mac,
+        uuid: Option<&str>,
+        arch: Option<u16>,
+        vendor_class: Option<&str>,
+        relay: Option<&RelayAgentInfo>,
+    ) -> bool {
+        if let Some(m) = self.mac.as_ref() {
+            if m != mac {
+                return false;
+            }
+        }
+
+        if let Some(u) = self.uuid.as_deref() {
+            if !matches!(uuid, Some(x) if x.eq_ignore_ascii_case(u)) {
+                return false;
+            }
+        }
+
+        if let Some(a) = self.arch {
+            if arch != Some(a) {
+                return false;
+            }
+        }
+
+        if let Some(prefix) = self.vendor_class.as_deref() {
+            if !matches!(vendor_class, Some(v) if v.starts_with(prefix)) {
+                return false;
+            }
+        }
+
+        for (expected, id) in [
+            (&self.circuit_id, relay.and_then(|x| x.circuit_id.as_ref())),
+            (&self.remote_id, relay.and_then(|x| x.remote_id.as_ref())),
+        ] {
+            if let Some(expected) = expected {
+                if !matches!(id, Some(id) if id.to_string() == *expected) {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+// Validation errors, each one with path of offending field,
+// e.g. instance[1].dhcp_range or --tftp-root.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    errors: Vec<(String, String)>,
+}
+
+impl Diagnostics {
+    pub fn error(&mut self, path: impl Into<String>, message: impl fmt::Display) {
+        self.errors.push((path.into(), message.to_string()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.errors.len() == 1 {
+            write!(f, "configuration error:")?;
+        } else {
+            write!(f, "{} configuration errors:", self.errors.len())?;
+        }
+        for (path, message) in self.errors.iter() {
+            write!(f, "\n  {}: {}", path, message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, RelayAgentInfo};
+
+    #[test]
+    fn test_verify_reports_all_errors() {
+        let config: Config = toml::from_str(
+            r#"
+            [[profile]]
+            name = "bios"
+            boot_file = "undionly.kpxe"
+
+            [[profile]]
+            name = "bios"
+            boot_file = ""
+
+            [[selector]]
+            profile = "uefi"
+
+            [hooks]
+            lease_event_url = "inventory.lab/api/dhcp-events"
+
+            [[instance]]
+            name = "lab1"
+            server_ip = "10.0.1.1"
+            dhcp_range = "10.0.1.100-10.0.1.200/24"
+
+            [[instance.pool]]
+            range = "10.0.2.100-10.0.2.200/24"
+            router = "10.0.3.1"
+
+            [[instance.pool]]
+            range = "10.0.2.2-10.0.2.14/28"
+            profile = "uefi"
+
+            [[instance]]
+            name = "lab2"
+            dhcp_range = "10.0.1.150-10.0.1.250/24"
+            no_dhcp = true
+
+            [[instance.selector]]
+            profile = "bios"
+            "#,
+        )
+        .unwrap();
+
+        let paths: Vec<_> = config
+            .verify()
+            .unwrap_err()
+            .errors
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "profile[1].name",
+                "profile[1].boot_file",
+                "selector[0].profile",
+                "hooks.lease_event_url",
+                "instance[0].pool[0].router",
+                "instance[0].pool[1].range",
+                "instance[0].pool[1].profile",
+                "instance[1].dhcp_range",
+                "instance[1].dhcp_range",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vendor_class_policy() {
+        let config: Config = toml::from_str(
+            r#"
+            [[vendor_class]]
+            match = "PXEClient:Arch:00007"
+            boot_file = "ipxe.efi"
+
+            [[vendor_class]]
+            match = "udhcp"
+
+            [[vendor_class.option]]
+            code = 224
+            value = "embedded"
+
+            [[vendor_class]]
+            match = ""
+
+            [[vendor_class]]
+            match = "PXEClient"
+            lease_time = "0s"
+            "#,
+        )
+        .unwrap();
+
+        let find = |x| config.vendor_class_policy(x).map(|x| x.prefix.as_str());
+        assert_eq!(
+            find(Some("PXEClient:Arch:00007:UNDI:003016")),
+            Some("PXEClient:Arch:00007")
+        );
+        assert_eq!(find(Some("udhcp 1.30.1")), Some("udhcp"));
+        assert_eq!(find(None), None);
+
+        let errors = config.verify().unwrap_err().errors;
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].0, "vendor_class[2].match");
+        assert_eq!(errors[1].0, "vendor_class[3].lease_time");
+    }
+
+    #[test]
+    fn test_options() {
+        let config: Config = toml::from_str(
+            r#"
+            [[option]]
+            code = 42
+            hex = "0a:00:00:01"
+
+            [[option]]
+            code = 43
+            hex = "0a0"
+
+            [[profile]]
+            name = "bios"
+            boot_file = "undionly.kpxe"
+
+            [[profile.option]]
+            code = 53
+            value = "1"
+
+            [[selector]]
+            profile = "bios"
+
+            [[selector.option]]
+            code = 252
+            value = "wpad.dat"
+            hex = "00"
+
+            [[option]]
+            code = 4
+            type = "ip"
+            value = "10.0.0.1, 10.0.0.2"
+
+            [[option]]
+            code = 23
+            type = "u8"
+            value = "64"
+
+            [[option]]
+            code = 24
+            type = "u32"
+            value = "600"
+
+            [[option]]
+            code = 19
+            type = "bool"
+            value = "false"
+
+            [[option]]
+            code = 22
+            type = "u16"
+            value = "65536"
+
+            [[option]]
+            code = 28
+            type = "ip"
+            hex = "0a0000ff"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.options[0].data().unwrap(), [10, 0, 0, 1]);
+        assert_eq!(
+            config.options[2].data().unwrap(),
+            [10, 0, 0, 1, 10, 0, 0, 2]
+        );
+        assert_eq!(config.options[3].data().unwrap(), [64]);
+        assert_eq!(config.options[4].data().unwrap(), [0, 0, 2, 0x58]);
+        assert_eq!(config.options[5].data().unwrap(), [0]);
+        assert_eq!(
+            config.selectors[0].options[0].value.as_deref(),
+            Some("wpad.dat")
+        );
+        let paths: Vec<_> = config
+            .verify()
+            .unwrap_err()
+            .errors
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "option[1]",
+                "option[6]",
+                "option[7]",
+                "profile[0].option[0].code",
+                "selector[0].option[0]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reservations() {
+        let config: Config = toml::from_str(
+            r#"
+            [[reservation]]
+            ip = "10.0.0.5"
+            mac = "52:54:00:12:34:56"
+            boot_file = "ipxe.efi"
+
+            [[reservation]]
+            ip = "10.0.0.6"
+            client_id = "01:52:54:00:aa:bb:cc"
+
+            [[reservation]]
+            ip = "10.0.0.5"
+            mac = "52:54:00:12:34:56"
+            client_id = "01"
+
+            [[reservation]]
+            ip = "10.0.0.7"
+            client_id = "0152540"
+
+            [[reservation]]
+            ip = "10.0.0.8"
+            uuid = "4C4C4544-0042-3610-8057-B4C04F4D3732"
+
+            [[reservation]]
+            ip = "10.0.0.9"
+            uuid = "4c4c4544"
+            "#,
+        )
+        .unwrap();
+
+        let mac = "52:54:00:12:34:56".parse().unwrap();
+        let other_mac = "52:54:00:aa:bb:cc".parse().unwrap();
+        assert!(config.reservations[0].matches(&mac, &[], None));
+        assert!(!config.reservations[0].matches(&other_mac, &[], None));
+        assert!(config.reservations[1].matches(&mac, &[1, 0x52, 0x54, 0, 0xaa, 0xbb, 0xcc], None));
+        assert!(!config.reservations[1].matches(&other_mac, &[], None));
+        let uuid = "4c4c4544-0042-3610-8057-b4c04f4d3732";
+        assert!(config.reservations[4].matches(&other_mac, &[], Some(uuid)));
+        assert!(!config.reservations[4].matches(&mac, &[], None));
+
+        let paths: Vec<_> = config
+            .verify()
+            .unwrap_err()
+            .errors
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "reservation[2]",
+                "reservation[2].ip",
+                "reservation[2]",
+                "reservation[3].client_id",
+                "reservation[5].uuid",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_profile() {
+        let config: Config = toml::from_str(
+            r#"
+            [[profile]]
+            name = "bios"
+            boot_file = "undionly.kpxe"
+
+            [[profile]]
+            name = "uefi"
+            boot_file = "ipxe.efi"
+            next_server = "10.0.0.2"
+
+            [[selector]]
+            profile = "uefi"
+            mac = "52:54:00:12:34:56"
+
+            [[selector]]
+            profile = "uefi"
+            arch = 7
+            vendor_class = "PXEClient"
+
+            [[selector]]
+            profile = "bios"
+            circuit_id = "eth0/1:5"
+
+            [[selector]]
+            profile = "bios"
+            arch = 0
+            "#,
+        )
+        .unwrap();
+        config.verify().unwrap();
+
+        let mac = "52:54:00:12:34:56".parse().unwrap();
+        let other_mac = "52-54-00-AA-BB-CC".parse().unwrap();
+
+        assert_eq!(
+            config
+                .select_profile(&mac, None, None, None, None)
+                .unwrap()
+                .name,
+            "uefi"
+        );
+        assert_eq!(
+            config
+                .select_profile(
+                    &other_mac,
+                    None,
+                    Some(7),
+                    Some("PXEClient:Arch:00007"),
+                    None
+                )
+                .unwrap()
+                .name,
+            "uefi"
+        );
+        assert!(config
+            .select_profile(&other_mac, None, Some(7), None, None)
+            .is_none());
+        assert_eq!(
+            config
+                .select_profile(&other_mac, None, Some(0), None, None)
+                .unwrap()
+                .name,
+            "bios"
+        );
+
+        let relay = RelayAgentInfo::parse(b"\x01\x08eth0/1:5").unwrap();
+        assert_eq!(
+            config
+                .select_profile(&other_mac, None, Some(7), None, Some(&relay))
+                .unwrap()
+                .name,
+            "bios"
+        );
+        let relay = RelayAgentInfo::parse(b"\x01\x08eth0/1:6").unwrap();
+        assert!(config
+            .select_profile(&other_mac, None, Some(7), None, Some(&relay))
+            .is_none());
+    }
+
+    #[test]
+    fn test_root_path() {
+        let config: Config = toml::from_str(
+            r#"
+            [[profile]]
+            name = "san"
+            boot_file = "ipxe.efi"
+
+            [profile.iscsi]
+            server = "10.0.0.5"
+            lun = 10
+            target = "iqn.2021-01.lab:disk1"
+
+            [[profile]]
+            name = "nfs"
+            boot_file = "ipxe.efi"
+            root_path = "10.0.0.2:/srv/nfsroot"
+            "#,
+        )
+        .unwrap();
+        config.verify().unwrap();
+
+        let mut long = config.clone();
+        long.profiles[0].iscsi.as_mut().unwrap().target = "x".repeat(240);
+        let errors = long.verify().unwrap_err().errors;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "profile[0].iscsi");
+
+        let server_ip = "10.0.0.1".parse().unwrap();
+        assert_eq!(
+            config.profile("san").unwrap().root_path(server_ip, 10809),
+            Some("iscsi:10.0.0.5:::a:iqn.2021-01.lab:disk1".to_string())
+        );
+        assert_eq!(
+            config.profile("nfs").unwrap().root_path(server_ip, 10809),
+            Some("10.0.0.2:/srv/nfsroot".to_string())
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+            [[reservation]]
+            ip = "10.0.0.5"
+            mac = "52:54:00:12:34:56"
+            root_path = ""
+
+            [[pool]]
+            range = "10.0.2.100-10.0.2.200/24"
+            root_path = "10.0.2.1:/srv/nfsroot"
+            "#,
+        )
+        .unwrap();
+        let paths: Vec<_> = config
+            .verify()
+            .unwrap_err()
+            .errors
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, ["reservation[0].root_path"]);
+    }
+}
@@ -0,0 +1,736 @@
+// Control socket used by `ctl` subcommand to inspect and manage running server.
+//
+// Protocol is line based, client sends single command line,
+// server writes plain text response and closes connection.
+// Commands taking secret get it on following line, so that it stays out
+// of arguments and may contain spaces.
+// Failed commands are answered with line starting with "error: ".
+// Unix sockets are not available on Windows, the control socket is not either.
+#![cfg_attr(not(unix), allow(dead_code))]
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::io::ErrorKind;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::watch;
+
+use crate::config::Config;
+use crate::dhcp::id::Mac;
+use crate::dhcp::{self, LeaseKey};
+use crate::inventory::{self, Bmc, Inventory, State};
+#[cfg(target_os = "linux")]
+use crate::netif;
+use crate::netif::LinkState;
+use crate::registry::Registry;
+use crate::sessions::Sessions;
+use crate::stats::{self, Stats};
+use crate::tftp::Transfers;
+use crate::Options;
+
+pub const DEFAULT_SOCKET_PATH: &str = "/run/pxeserver.sock";
+
+const ERROR_PREFIX: &str = "error: ";
+// clients send whole command right after connecting
+#[cfg(unix)]
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// runtime handles of single server instance
+pub struct Instance {
+    pub name: Option<String>,
+    pub interface: Option<String>,
+    // absent when interface is not monitored
+    pub link: Option<watch::Receiver<LinkState>>,
+    pub dhcp: Option<dhcp::Handle>,
+    pub transfers: Transfers,
+    pub sessions: Sessions,
+    pub stats: Arc<Stats>,
+}
+
+// State of all instances at once as JSON, polled by `top` subcommand
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub instances: Vec<InstanceSnapshot>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct InstanceSnapshot {
+    pub name: Option<String>,
+    pub leases: Vec<LeaseSnapshot>,
+    pub transfers: Vec<TransferSnapshot>,
+    // most recently updated first
+    pub sessions: Vec<SessionSnapshot>,
+    pub counters: Counters,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaseSnapshot {
+    pub ip: Ipv4Addr,
+    pub client: String,
+    // seconds, None for offers
+    pub expires_in: Option<u64>,
+    #[serde(default)]
+    pub hostname: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferSnapshot {
+    pub client: String,
+    pub file: String,
+    pub size: Option<u64>,
+    pub sent: u64,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub mac: String,
+    pub ip: Option<Ipv4Addr>,
+    pub stage: String,
+    pub profile: Option<String>,
+    pub last_file: Option<String>,
+    pub last_log: Option<String>,
+    pub idle_secs: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Counters {
+    pub dhcp_offers: u64,
+    pub dhcp_acks: u64,
+    pub dhcp_naks: u64,
+    // missing in snapshots of older servers
+    #[serde(default)]
+    pub dhcp_declines: u64,
+    pub dhcp_pool_size: u64,
+    pub tftp_completed: u64,
+    pub tftp_failed: u64,
+    pub tftp_bytes: u64,
+    #[serde(default)]
+    pub tftp_datagrams: u64,
+    #[serde(default)]
+    pub tftp_send_calls: u64,
+    pub http_completed: u64,
+    pub http_failed: u64,
+    pub http_bytes: u64,
+}
+
+#[cfg(unix)]
+pub async fn serve(
+    options: Arc<Options>,
+    instances: Arc<Vec<Instance>>,
+    inventory: Inventory,
+) -> anyhow::Result<()> {
+    let path = options
+        .control_socket
+        .as_deref()
+        .expect("control socket not set");
+
+    // stale socket left by previous run prevents binding
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("failed to remove {}", path.display()))
+        }
+        _ => (),
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))?;
+
+    debug!("control socket listening on {}", path.display());
+
+    // slow command or stuck client does not hold up others
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let options = Arc::clone(&options);
+        let instances = Arc::clone(&instances);
+        let inventory = Arc::clone(&inventory);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &options, &instances, &inventory).await {
+                warn!("control connection failed: {:#}", e);
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn serve(
+    _options: Arc<Options>,
+    _instances: Arc<Vec<Instance>>,
+    _inventory: Inventory,
+) -> anyhow::Result<()> {
+    bail!("control socket is not supported on this platform")
+}
+
+#[cfg(unix)]
+async fn handle_connection(
+    stream: UnixStream,
+    options: &Options,
+    instances: &[Instance],
+    inventory: &Inventory,
+) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    let mut secret = String::new();
+    tokio::time::timeout(READ_TIMEOUT, async {
+        reader.read_line(&mut line).await?;
+        if takes_secret(&line) {
+            reader.read_line(&mut secret).await?;
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await
+    .context("timed out reading command")??;
+    debug!("control command: {}", line.trim());
+    let secret = secret.trim_end_matches(&['\r', '\n'][..]);
+
+    let response = match execute(line.trim(), secret, options, instances, inventory).await {
+        Ok(response) => response,
+        Err(e) => format!("{}{:#}\n", ERROR_PREFIX, e),
+    };
+    writer.write_all(response.as_bytes()).await?;
+
+    Ok(())
+}
+
+// BMC password follows when address and user are given
+fn takes_secret(line: &str) -> bool {
+    let mut args = line.split_whitespace();
+    args.next() == Some("bmc") && args.nth(1).is_some()
+}
+
+async fn execute(
+    line: &str,
+    secret: &str,
+    options: &Options,
+    instances: &[Instance],
+    inventory: &Inventory,
+) -> anyhow::Result<String> {
+    let mut args = line.split_whitespace();
+    let mut out = String::new();
+
+    match args.next() {
+        Some("leases") => {
+            for instance in instances {
+                if let Some(handle) = instance.dhcp.as_ref() {
+                    for lease in handle.leases().await? {
+                        let state = match lease.remaining {
+                            Some(remaining) => format!("expires in {} s", remaining.as_secs()),
+                            None => "offered".to_string(),
+                        };
+                        out += &format!(
+                            "{}{} {} {}{}{}\n",
+                            instance_prefix(instance),
+                            lease.ip,
+                            lease.client,
+                            state,
+                            lease
+                                .hostname
+                                .map_or(String::new(), |x| format!(", hostname {}", x)),
+                            lease
+                                .conflict
+                                .map_or(String::new(), |x| format!(", conflict with {}", x))
+                        );
+                    }
+                }
+            }
+        }
+        Some("transfers") => {
+            let now = Instant::now();
+            for instance in instances {
+                for (tid, transfer) in instance.transfers.lock().unwrap().iter() {
+                    let size = match transfer.size {
+                        Some(size) => size.to_string(),
+                        None => "?".to_string(),
+                    };
+                    out += &format!(
+                        "{}{} {} {} {}/{} B, {} s\n",
+                        instance_prefix(instance),
+                        tid,
+                        transfer.client,
+                        transfer.file,
+                        transfer.sent,
+                        size,
+                        now.duration_since(transfer.started).as_secs()
+                    );
+                }
+            }
+        }
+        Some("sessions") => {
+            let now = Instant::now();
+            for instance in instances {
+                for (mac, session) in instance.sessions.lock().unwrap().iter() {
+                    let ip = match session.ip {
+                        Some(ip) => ip.to_string(),
+                        None => "-".to_string(),
+                    };
+                    out += &format!(
+                        "{}{} {} {}, profile {}, last file {}, {} s\n",
+                        instance_prefix(instance),
+                        mac,
+                        ip,
+                        session.stage,
+                        session.profile.as_deref().unwrap_or("-"),
+                        session.last_file.as_deref().unwrap_or("-"),
+                        now.duration_since(session.started).as_secs()
+                    );
+                }
+            }
+        }
+        Some("clients") => {
+            let filter = args.next();
+            for client in inventory
+                .lock()
+                .unwrap()
+                .clients()
+                .filter(|x| match filter {
+                    Some(filter) => x.matches(filter),
+                    None => true,
+                })
+            {
+                let field = |x: &Option<String>| x.clone().unwrap_or_else(|| "-".to_string());
+                out += &format!(
+                    "{} {}, profile {}, ip {}, hostname {}, uuid {}, arch {}, vendor {}, {} boot(s), first {}, last {}\n",
+                    client.mac,
+                    client.state,
+                    field(&client.profile),
+                    field(&client.ip.map(|x| x.to_string())),
+                    field(&client.hostname),
+                    field(&client.uuid),
+                    field(&client.arch.map(|x| x.to_string())),
+                    field(&client.vendor_class),
+                    client.boots,
+                    format_time(client.first_boot),
+                    format_time(client.last_boot)
+                );
+            }
+        }
+        Some("logs") => {
+            let mac: Mac = args
+                .next()
+                .ok_or_else(|| anyhow!("expected MAC address"))?
+                .parse()?;
+            let mut found = false;
+            for instance in instances {
+                if let Some(session) = instance.sessions.lock().unwrap().get(&mac) {
+                    found = true;
+                    for (time, line) in session.log.iter() {
+                        out += &format!("{} {}\n", humantime::format_rfc3339_seconds(*time), line);
+                    }
+                }
+            }
+            if !found {
+                bail!("no boot session of {}", mac);
+            }
+        }
+        Some("bmc") => {
+            let mac: Mac = args
+                .next()
+                .ok_or_else(|| anyhow!("expected MAC address"))?
+                .parse()?;
+            let bmc = match (args.next(), args.next()) {
+                (Some(address), Some(username)) => {
+                    let mut bmc = Bmc {
+                        address: address.to_string(),
+                        username: username.to_string(),
+                        password: secret.to_string(),
+                        protocol: Default::default(),
+                        insecure: false,
+                    };
+                    for flag in args.by_ref() {
+                        match flag {
+                            "insecure" => bmc.insecure = true,
+                            protocol => bmc.protocol = protocol.parse()?,
+                        }
+                    }
+                    Some(bmc)
+                }
+                (None, _) => None,
+                _ => bail!("expected address and user"),
+            };
+            match bmc.as_ref() {
+                Some(x) => {
+                    out += &format!(
+                        "{} managed through {} over {}\n",
+                        mac, x.address, x.protocol
+                    )
+                }
+                None => out += &format!("{} has no BMC\n", mac),
+            }
+            inventory.lock().unwrap().set_bmc(mac, bmc);
+            inventory::flush(inventory).await?;
+        }
+        Some("boot-once") => {
+            let mac: Mac = args
+                .next()
+                .ok_or_else(|| anyhow!("expected MAC address"))?
+                .parse()?;
+            let profile = args.next().map(str::to_string);
+            out += &format!(
+                "{} boots {} until provisioned\n",
+                mac,
+                profile.as_deref().unwrap_or("selected profile")
+            );
+            inventory
+                .lock()
+                .unwrap()
+                .set_state(mac, State::Installing, profile)?;
+            inventory::flush(inventory).await?;
+        }
+        Some("state") => {
+            let mac: Mac = args
+                .next()
+                .ok_or_else(|| anyhow!("expected MAC address"))?
+                .parse()?;
+            let state: State = args
+                .next()
+                .ok_or_else(|| anyhow!("expected state"))?
+                .parse()?;
+            let profile = args.next().map(str::to_string);
+            let previous = inventory.lock().unwrap().set_state(mac, state, profile)?;
+            inventory::flush(inventory).await?;
+            out += &format!("{} is {}, was {}\n", mac, state, previous);
+        }
+        Some("provisioned") => {
+            let mac: Mac = args
+                .next()
+                .ok_or_else(|| anyhow!("expected MAC address"))?
+                .parse()?;
+            if !inventory.lock().unwrap().provisioned(&mac)? {
+                bail!("{} is not installing", mac);
+            }
+            inventory::flush(inventory).await?;
+            out += &format!("{} boots from local disk\n", mac);
+        }
+        Some("boot-normal") => {
+            let mac: Mac = args
+                .next()
+                .ok_or_else(|| anyhow!("expected MAC address"))?
+                .parse()?;
+            inventory
+                .lock()
+                .unwrap()
+                .set_state(mac, State::Discovered, None)?;
+            inventory::flush(inventory).await?;
+            out += &format!("{} boots selected profile\n", mac);
+        }
+        Some("expire-lease") => {
+            let key: LeaseKey = args
+                .next()
+                .ok_or_else(|| anyhow!("expected IP or MAC address"))?
+                .parse()?;
+
+            let mut removed = 0;
+            for handle in instances.iter().filter_map(|x| x.dhcp.as_ref()) {
+                removed += handle.expire(key).await?;
+            }
+            if removed == 0 {
+                bail!("no such lease");
+            }
+            out += &format!("{} lease(s) expired\n", removed);
+        }
+        Some("reload") => {
+            reload(options, instances).await?;
+            out += "profiles and selectors reloaded\n";
+        }
+        Some("versions") => {
+            for artifact in open_registry(options)?.artifacts()? {
+                out += &format!(
+                    "{} current {}, previous {}, versions {}\n",
+                    artifact.name,
+                    artifact.current.as_deref().unwrap_or("-"),
+                    artifact.previous.as_deref().unwrap_or("-"),
+                    artifact.versions.join(" ")
+                );
+            }
+        }
+        Some("switch") => {
+            let name = args
+                .next()
+                .ok_or_else(|| anyhow!("expected artifact name"))?;
+            let version = args.next().ok_or_else(|| anyhow!("expected version"))?;
+            let previous = open_registry(options)?.switch(name, version)?;
+            out += &format!(
+                "{} switched to {}, was {}\n",
+                name,
+                version,
+                previous.as_deref().unwrap_or("-")
+            );
+        }
+        Some("rollback") => {
+            let name = args
+                .next()
+                .ok_or_else(|| anyhow!("expected artifact name"))?;
+            let version = open_registry(options)?.rollback(name)?;
+            out += &format!("{} rolled back to {}\n", name, version);
+        }
+        #[cfg(target_os = "linux")]
+        Some("arp") => {
+            let ip: Ipv4Addr = args
+                .next()
+                .ok_or_else(|| anyhow!("expected IP address"))?
+                .parse()?;
+            match netif::lookup_neighbor(ip)? {
+                Some(neighbor) => out += &format!("{}\n", neighbor),
+                None => bail!("{} not in neighbor table", ip),
+            }
+        }
+        Some("status") => {
+            for instance in instances {
+                out += &instance_prefix(instance);
+                match (instance.interface.as_deref(), instance.link.as_ref()) {
+                    (Some(interface), Some(link)) => {
+                        let state = *link.borrow();
+                        out += &format!(
+                            "{}, {} {}\n",
+                            if state.is_usable() {
+                                "serving"
+                            } else {
+                                "paused"
+                            },
+                            interface,
+                            state
+                        );
+                    }
+                    _ => out += "serving\n",
+                }
+            }
+        }
+        Some("snapshot") => {
+            out = serde_json::to_string(&snapshot(instances).await?)?;
+            out.push('\n');
+        }
+        Some("stats") => {
+            for instance in instances {
+                let (leases, offers) = match instance.dhcp.as_ref() {
+                    Some(handle) => {
+                        let leases = handle.leases().await?;
+                        let offers = leases.iter().filter(|x| x.remaining.is_none()).count();
+                        (leases.len() - offers, offers)
+                    }
+                    None => (0, 0),
+                };
+                out += &format!(
+                    "{}{} leases, {} pending offers, {} active transfers\n{}{}\n",
+                    instance_prefix(instance),
+                    leases,
+                    offers,
+                    instance.transfers.lock().unwrap().len(),
+                    instance_prefix(instance),
+                    instance.stats
+                );
+            }
+        }
+        Some(command) => bail!("unknown command {}", command),
+        None => bail!("empty command"),
+    }
+
+    Ok(out)
+}
+
+// rereads configuration file and hands new profiles and selectors to DHCP servers
+async fn snapshot(instances: &[Instance]) -> anyhow::Result<Snapshot> {
+    let now = Instant::now();
+    let mut snapshot = Snapshot::default();
+    for instance in instances {
+        let leases = match instance.dhcp.as_ref() {
+            Some(handle) => handle.leases().await?,
+            None => Vec::new(),
+        };
+        let mut sessions = instance
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(mac, session)| (session.updated, mac.to_string(), session.clone()))
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|x| std::cmp::Reverse(x.0));
+        let stats = &instance.stats;
+
+        snapshot.instances.push(InstanceSnapshot {
+            name: instance.name.clone(),
+            leases: leases
+                .into_iter()
+                .map(|x| LeaseSnapshot {
+                    ip: x.ip,
+                    client: x.client,
+                    expires_in: x.remaining.map(|x| x.as_secs()),
+                    hostname: x.hostname,
+                })
+                .collect(),
+            transfers: instance
+                .transfers
+                .lock()
+                .unwrap()
+                .values()
+                .map(|x| TransferSnapshot {
+                    client: x.client.to_string(),
+                    file: x.file.clone(),
+                    size: x.size,
+                    sent: x.sent,
+                    elapsed_ms: now.duration_since(x.started).as_millis() as u64,
+                })
+                .collect(),
+            sessions: sessions
+                .into_iter()
+                .map(|(updated, mac, session)| SessionSnapshot {
+                    mac,
+                    ip: session.ip,
+                    stage: session.stage.to_string(),
+                    profile: session.profile,
+                    last_file: session.last_file,
+                    last_log: session.log.back().map(|(_, line)| line.clone()),
+                    idle_secs: now.duration_since(updated).as_secs(),
+                })
+                .collect(),
+            counters: Counters {
+                dhcp_offers: stats::get(&stats.dhcp_offers),
+                dhcp_acks: stats::get(&stats.dhcp_acks),
+                dhcp_naks: stats::get(&stats.dhcp_naks),
+                dhcp_declines: stats::get(&stats.dhcp_declines),
+                dhcp_pool_size: stats::get(&stats.dhcp_pool_size),
+                tftp_completed: stats::get(&stats.tftp.completed),
+                tftp_failed: stats::get(&stats.tftp.failed),
+                tftp_bytes: stats::get(&stats.tftp.bytes),
+                tftp_datagrams: stats::get(&stats.tftp_datagrams),
+                tftp_send_calls: stats::get(&stats.tftp_send_calls),
+                http_completed: stats::get(&stats.http.completed),
+                http_failed: stats::get(&stats.http.failed),
+                http_bytes: stats::get(&stats.http.bytes),
+            },
+        });
+    }
+
+    Ok(snapshot)
+}
+
+fn open_registry(options: &Options) -> anyhow::Result<Registry> {
+    Registry::open(
+        options
+            .registry
+            .as_deref()
+            .ok_or_else(|| anyhow!("server runs without --registry"))?,
+    )
+}
+
+async fn reload(options: &Options, instances: &[Instance]) -> anyhow::Result<()> {
+    let path = options
+        .config_file
+        .as_deref()
+        .ok_or_else(|| anyhow!("no configuration file given"))?;
+    let config = Config::load(path)?;
+    config.verify()?;
+
+    for instance in instances {
+        let handle = match instance.dhcp.as_ref() {
+            Some(handle) => handle,
+            None => continue,
+        };
+
+        let config = match instance.name.as_deref() {
+            Some(name) => {
+                let found = config
+                    .instances
+                    .iter()
+                    .find(|x| x.name == name)
+                    .ok_or_else(|| anyhow!("instance {} no longer exists", name))?;
+                let mut options = options.clone();
+                options.config = config.clone();
+                options.with_instance(found).config
+            }
+            None => config.clone(),
+        };
+        handle.set_config(config).await?;
+    }
+
+    Ok(())
+}
+
+// seconds since Unix epoch as stored in inventory
+fn format_time(secs: u64) -> String {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs)).to_string()
+}
+
+fn instance_prefix(instance: &Instance) -> String {
+    match instance.name.as_deref() {
+        Some(name) => format!("{}: ", name),
+        None => String::new(),
+    }
+}
+
+pub async fn run_ctl(
+    path: &Path,
+    command: &[String],
+    password_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut command = command.join(" ");
+    if takes_secret(&command) {
+        let password = match password_file {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?,
+            None => {
+                let mut line = String::new();
+                std::io::stdin()
+                    .read_line(&mut line)
+                    .context("failed to read password from stdin")?;
+                line
+            }
+        };
+        command += "\n";
+        command += password.lines().next().unwrap_or_default();
+    }
+    print!("{}", request(path, &command).await?);
+    Ok(())
+}
+
+#[cfg(unix)]
+pub async fn request(path: &Path, command: &str) -> anyhow::Result<String> {
+    let mut stream = UnixStream::connect(path)
+        .await
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+
+    stream
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+    stream.shutdown().await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    match response.strip_prefix(ERROR_PREFIX) {
+        Some(e) => bail!("{}", e.trim_end()),
+        None => Ok(response),
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn request(_path: &Path, _command: &str) -> anyhow::Result<String> {
+    bail!("control socket is not supported on this platform")
+}
+
+// server is listening on control socket, stale socket file refuses
+#[cfg(unix)]
+pub async fn answers(path: &Path) -> bool {
+    UnixStream::connect(path).await.is_ok()
+}
+
+#[cfg(not(unix))]
+pub async fn answers(_path: &Path) -> bool {
+    false
+}
+
+pub fn socket_path(options: &Options) -> PathBuf {
+    options
+        .control_socket
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET_PATH))
+}
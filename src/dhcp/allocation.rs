@@ -0,0 +1,105 @@
+// How free address is picked from pool for client without one. First free
+// gives out addresses in order, hash starts at position derived from client
+// identifier and goes on linearly from there, so that same machine keeps
+// landing on same address across lab runs as long as it is free. Hash of
+// hardware address is used when there is one, so PXE ROM and operating
+// system with DUID start at same place.
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use super::id::ClientId;
+
+// FNV-1a, stable across builds unlike std hashers
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Allocation {
+    FirstFree,
+    Hash,
+}
+
+impl Allocation {
+    // position in pool of given size scan starts at
+    pub fn offset(&self, client_id: &ClientId, size: u64) -> u64 {
+        match self {
+            Self::FirstFree => 0,
+            Self::Hash if size == 0 => 0,
+            Self::Hash => {
+                let key = client_id.hardware_address().unwrap_or(&client_id.ext);
+                fnv1a(key) % size
+            }
+        }
+    }
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(FNV_OFFSET_BASIS, |hash, &x| {
+        (hash ^ u64::from(x)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+impl FromStr for Allocation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-free" => Ok(Self::FirstFree),
+            "hash" => Ok(Self::Hash),
+            _ => bail!("invalid allocation \"{}\", expected first-free or hash", s),
+        }
+    }
+}
+
+impl TryFrom<String> for Allocation {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::FirstFree => write!(f, "first-free"),
+            Self::Hash => write!(f, "hash"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_offset() {
+        let client = |mac: &str, ext: &[u8]| ClientId {
+            mac: mac.parse().unwrap(),
+            ext: ext.to_vec(),
+        };
+        let pxe = client("52:54:00:12:34:56", &[]);
+        let os = client("52:54:00:12:34:56", &[1, 0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+        let other = client("52:54:00:12:34:57", &[]);
+
+        // must not change between builds
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(
+            Allocation::Hash.offset(&pxe, 100),
+            Allocation::Hash.offset(&os, 100)
+        );
+        assert_ne!(
+            Allocation::Hash.offset(&pxe, 1000),
+            Allocation::Hash.offset(&other, 1000)
+        );
+        assert!(Allocation::Hash.offset(&other, 10) < 10);
+        assert_eq!(Allocation::Hash.offset(&other, 0), 0);
+        assert_eq!(Allocation::FirstFree.offset(&other, 10), 0);
+        assert_eq!("hash".parse::<Allocation>().unwrap(), Allocation::Hash);
+        assert!("random".parse::<Allocation>().is_err());
+    }
+}
@@ -0,0 +1,82 @@
+// Client system architecture types of DHCP option 93 (RFC 4578, IANA
+// "Processor Architecture Types"), known by name on command line.
+use std::fmt;
+use std::str::FromStr;
+
+const NAMES: &[(&str, u16)] = &[
+    ("bios", 0),
+    ("efi-ia32", 6),
+    ("efi-x64", 7),
+    ("efi-bc", 9),
+    ("efi-arm32", 10),
+    ("efi-arm64", 11),
+    ("efi-x64-http", 16),
+    ("efi-arm64-http", 19),
+];
+
+// decimal, hex with 0x prefix or name from NAMES
+pub fn parse(s: &str) -> anyhow::Result<u16> {
+    if let Some(&(_, arch)) = NAMES.iter().find(|(name, _)| *name == s) {
+        return Ok(arch);
+    }
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| anyhow!("invalid architecture \"{}\"", s))
+}
+
+// boot file offered to clients of given architecture not matched by
+// any selector, e.g. efi-x64=ipxe.efi
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchLoader {
+    pub arch: u16,
+    // relative to TFTP root, sent as is
+    pub file: String,
+}
+
+impl FromStr for ArchLoader {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (arch, file) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected <architecture>=<boot file>"))?;
+        if file.is_empty() {
+            bail!("boot file must not be empty");
+        }
+        Ok(Self {
+            arch: parse(arch)?,
+            file: file.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ArchLoader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match NAMES.iter().find(|(_, arch)| *arch == self.arch) {
+            Some((name, _)) => write!(f, "{}={}", name, self.file),
+            None => write!(f, "{}={}", self.arch, self.file),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arch_loader() {
+        let loader: ArchLoader = "efi-x64=ipxe.efi".parse().unwrap();
+        assert_eq!(loader.arch, 7);
+        assert_eq!(loader.to_string(), "efi-x64=ipxe.efi");
+        assert_eq!("0x0b=arm/ipxe.efi".parse::<ArchLoader>().unwrap().arch, 11);
+        assert_eq!(
+            "0=undionly.kpxe".parse::<ArchLoader>().unwrap().to_string(),
+            "bios=undionly.kpxe"
+        );
+        assert!("x64=ipxe.efi".parse::<ArchLoader>().is_err());
+        assert!("7=".parse::<ArchLoader>().is_err());
+        assert!("ipxe.efi".parse::<ArchLoader>().is_err());
+    }
+}
@@ -0,0 +1,146 @@
+// Address is checked once more after it was acknowledged, catching hosts
+// that have it configured statically but did not answer probe or were
+// off when it was made. ARP probe (RFC 5227 section 2.1.1) carries no
+// sender address, so unlike gratuitous ARP announcing it on behalf of
+// client it leaves neighbour caches alone. Host owning address answers
+// it and so does client once it configured address, only other MACs
+// count as conflict.
+use std::io;
+use std::net::Ipv4Addr;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use nix::libc;
+use nix::net::if_::if_nametoindex;
+use nix::sys::socket::{bind, recv, sendto, socket, AddressFamily, MsgFlags, SockFlag, SockType};
+use tokio::io::unix::AsyncFd;
+
+use super::id::Mac;
+use super::raw::{link_address, Fd, BROADCAST_MAC};
+
+const ARP_LEN: usize = 28;
+const HTYPE_ETHERNET: u16 = 1;
+const PTYPE_IPV4: u16 = 0x0800;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+
+// MAC of host other than client that answered for address, None when
+// nobody did in given time
+pub async fn other_owner(
+    interface: &str,
+    ip: Ipv4Addr,
+    client: &Mac,
+    timeout: Duration,
+) -> io::Result<Option<Mac>> {
+    let ifindex = if_nametoindex(interface)?;
+    let own_mac = interface_mac(interface)?;
+    // link layer header is stripped and added by kernel
+    let fd = Fd(socket(
+        AddressFamily::Packet,
+        SockType::Datagram,
+        SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+        None,
+    )?);
+    bind(fd.0, &link_address(ifindex, libc::ETH_P_ARP, [0; 6]))?;
+    let fd = AsyncFd::new(fd)?;
+
+    let destination = link_address(ifindex, libc::ETH_P_ARP, BROADCAST_MAC);
+    sendto(
+        fd.as_raw_fd(),
+        &probe(own_mac, ip),
+        &destination,
+        MsgFlags::empty(),
+    )?;
+
+    let mut buf = [0u8; 128];
+    let answer = tokio::time::timeout(timeout, async {
+        loop {
+            let mut guard = fd.readable().await?;
+            let n = match guard.try_io(|fd| {
+                recv(fd.as_raw_fd(), &mut buf, MsgFlags::empty()).map_err(io::Error::from)
+            }) {
+                Ok(result) => result?,
+                Err(_would_block) => continue,
+            };
+            match sender(&buf[..n]) {
+                Some((mac, sender_ip))
+                    if sender_ip == ip && mac != own_mac && mac[..] != client.get_raw()[..6] =>
+                {
+                    let mut raw = [0u8; 16];
+                    raw[..6].copy_from_slice(&mac);
+                    return Ok::<_, io::Error>(Mac::from(raw));
+                }
+                _ => (),
+            }
+        }
+    })
+    .await;
+
+    match answer {
+        Ok(result) => result.map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+fn interface_mac(interface: &str) -> io::Result<[u8; 6]> {
+    let text = std::fs::read_to_string(format!("/sys/class/net/{}/address", interface))?;
+    let mac: Mac = text
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid MAC of interface"))?;
+    let mut raw = [0u8; 6];
+    raw.copy_from_slice(&mac.get_raw()[..6]);
+    Ok(raw)
+}
+
+fn probe(mac: [u8; 6], ip: Ipv4Addr) -> Vec<u8> {
+    let mut message = Vec::with_capacity(ARP_LEN);
+    message.extend_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+    message.extend_from_slice(&PTYPE_IPV4.to_be_bytes());
+    message.extend_from_slice(&[6, 4]);
+    message.extend_from_slice(&OP_REQUEST.to_be_bytes());
+    message.extend_from_slice(&mac);
+    message.extend_from_slice(&Ipv4Addr::UNSPECIFIED.octets());
+    message.extend_from_slice(&[0; 6]);
+    message.extend_from_slice(&ip.octets());
+    message
+}
+
+// sender of Ethernet ARP request or reply, requests of other hosts
+// announcing address count as answer too
+fn sender(message: &[u8]) -> Option<([u8; 6], Ipv4Addr)> {
+    if message.len() < ARP_LEN
+        || message[0..2] != HTYPE_ETHERNET.to_be_bytes()
+        || message[2..4] != PTYPE_IPV4.to_be_bytes()
+        || message[4..6] != [6, 4]
+        || ![OP_REQUEST, OP_REPLY].contains(&u16::from_be_bytes([message[6], message[7]]))
+    {
+        return None;
+    }
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&message[8..14]);
+    let ip = Ipv4Addr::new(message[14], message[15], message[16], message[17]);
+    Some((mac, ip)).filter(|(_, ip)| !ip.is_unspecified())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_and_sender() {
+        let mac = [0x52, 0x54, 0, 0x12, 0x34, 0x56];
+        let ip = Ipv4Addr::new(10, 0, 0, 100);
+        let message = probe(mac, ip);
+        assert_eq!(message.len(), ARP_LEN);
+        assert_eq!(&message[24..28], &[10, 0, 0, 100]);
+        // probes of other hosts are not answers
+        assert_eq!(sender(&message), None);
+
+        let mut reply = message.clone();
+        reply[6..8].copy_from_slice(&OP_REPLY.to_be_bytes());
+        reply[14..18].copy_from_slice(&ip.octets());
+        assert_eq!(sender(&reply), Some((mac, ip)));
+        assert_eq!(sender(&reply[..20]), None);
+    }
+}
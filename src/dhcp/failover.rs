@@ -0,0 +1,183 @@
+// Two servers serving same segment as primary/secondary pair. Pool is split,
+// primary offers from lower half and secondary from upper one, so they never
+// offer same address, and every binding made by either is sent to the other.
+// Clients bound by server that went down keep their addresses when they
+// come back through the other one, and restarted server learns what was
+// handed out meanwhile as soon as pair is connected again.
+//
+// Primary listens on port of peer address, secondary connects to it. Both
+// sides send all their bindings once connected, then every new binding as
+// line of JSON.
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+
+use super::Handle;
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Primary,
+    Secondary,
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "primary" => Ok(Self::Primary),
+            "secondary" => Ok(Self::Secondary),
+            _ => bail!(
+                "invalid failover role \"{}\", expected primary or secondary",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Primary => write!(f, "primary"),
+            Self::Secondary => write!(f, "secondary"),
+        }
+    }
+}
+
+impl Role {
+    // host parts of addresses this side offers from,
+    // pool must have at least two addresses
+    pub fn split(&self, start: u32, end: u32) -> (u32, u32) {
+        let middle = start + (end - start) / 2;
+        match self {
+            Self::Primary => (start, middle),
+            Self::Secondary => (middle + 1, end),
+        }
+    }
+}
+
+// zero remaining time releases address
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Binding {
+    pub ip: Ipv4Addr,
+    pub mac: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub client_id: Vec<u8>,
+    pub remaining: u64,
+}
+
+pub async fn run(role: Role, server_ip: Ipv4Addr, peer: SocketAddr, handle: Handle) {
+    match role {
+        Role::Primary => {
+            let listener = match TcpListener::bind((server_ip, peer.port())).await {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("failover listener: {}", e);
+                    return;
+                }
+            };
+            loop {
+                let (stream, from) = match listener.accept().await {
+                    Ok(x) => x,
+                    Err(e) => {
+                        error!("failover listener: {}", e);
+                        continue;
+                    }
+                };
+                if from.ip() != peer.ip() {
+                    warn!("refused failover connection from {}", from);
+                    continue;
+                }
+                session(stream, peer, &handle).await;
+            }
+        }
+        Role::Secondary => loop {
+            match connect(server_ip, peer).await {
+                Ok(stream) => session(stream, peer, &handle).await,
+                Err(e) => debug!("failover peer {} unreachable: {}", peer, e),
+            }
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        },
+    }
+}
+
+// from server address, which is what primary accepts
+async fn connect(server_ip: Ipv4Addr, peer: SocketAddr) -> std::io::Result<TcpStream> {
+    let socket = TcpSocket::new_v4()?;
+    socket.bind(SocketAddr::from((server_ip, 0)))?;
+    socket.connect(peer).await
+}
+
+async fn session(stream: TcpStream, peer: SocketAddr, handle: &Handle) {
+    info!("failover peer {} connected", peer);
+    match exchange(stream, handle).await {
+        Ok(()) => warn!("failover peer {} disconnected", peer),
+        Err(e) => warn!("failover peer {} disconnected: {:#}", peer, e),
+    }
+}
+
+async fn exchange(stream: TcpStream, handle: &Handle) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    // subscribed before taking snapshot so nothing is missed in between
+    let mut updates = handle.updates.subscribe();
+    let mut full_sync = true;
+
+    loop {
+        if full_sync {
+            let bindings = handle.bindings().await?;
+            debug!("sending {} binding(s) to failover peer", bindings.len());
+            for binding in bindings {
+                send(&mut writer, &binding).await?;
+            }
+            full_sync = false;
+        }
+
+        tokio::select! {
+            line = lines.next_line() => match line? {
+                Some(line) => {
+                    let binding = serde_json::from_str(&line)
+                        .map_err(|e| anyhow!("invalid binding from peer: {}", e))?;
+                    handle.import(binding).await?;
+                }
+                None => return Ok(()),
+            },
+            update = updates.recv() => match update {
+                Ok(binding) => send(&mut writer, &binding).await?,
+                Err(RecvError::Lagged(_)) => full_sync = true,
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+async fn send(
+    writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    binding: &Binding,
+) -> anyhow::Result<()> {
+    let mut line = serde_json::to_string(binding)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(Role::Primary.split(100, 110), (100, 105));
+        assert_eq!(Role::Secondary.split(100, 110), (106, 110));
+        assert_eq!(Role::Primary.split(100, 101), (100, 100));
+        assert_eq!(Role::Secondary.split(100, 101), (101, 101));
+    }
+}
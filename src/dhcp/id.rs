@@ -1,7 +1,12 @@
+use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize, Serializer};
 
 #[repr(transparent)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
 pub struct Mac([u8; 16]);
 
 impl Mac {
@@ -16,6 +21,44 @@ impl From<[u8; 16]> for Mac {
     }
 }
 
+// accepts aa:bb:cc:dd:ee:ff and aa-bb-cc-dd-ee-ff
+impl FromStr for Mac {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut raw = [0u8; 16];
+        let mut n = 0;
+
+        for x in s.split(&[':', '-'][..]) {
+            if n == 6 || x.len() != 2 {
+                bail!("invalid MAC address {}", s);
+            }
+            raw[n] = u8::from_str_radix(x, 16).map_err(|_| anyhow!("invalid MAC address {}", s))?;
+            n += 1;
+        }
+
+        if n != 6 {
+            bail!("invalid MAC address {}", s);
+        }
+
+        Ok(Self(raw))
+    }
+}
+
+impl TryFrom<String> for Mac {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Serialize for Mac {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl fmt::Display for Mac {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for (i, &x) in self.0.iter().take(6).enumerate() {
@@ -30,6 +73,15 @@ impl fmt::Display for Mac {
     }
 }
 
+// Ethernet in client identifiers (RFC 2132 section 9.14) and DUIDs
+const HTYPE_ETHERNET: u8 = 1;
+// identifier made of IAID and DUID (RFC 4361 section 6.1)
+const TYPE_IAID_DUID: u8 = 255;
+// DUIDs based on link-layer address plus time and link-layer address alone
+// (RFC 8415 sections 11.2 and 11.4)
+const DUID_LLT: u16 = 1;
+const DUID_LL: u16 = 3;
+
 // Combined hardware address and DHCP client ID
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClientId {
@@ -37,6 +89,46 @@ pub struct ClientId {
     pub ext: Vec<u8>,
 }
 
+impl ClientId {
+    // Hardware address identifier is derived from, MAC of client without
+    // one, None for DUIDs not based on link-layer address.
+    pub fn hardware_address(&self) -> Option<&[u8]> {
+        match self.ext.as_slice() {
+            [] => Some(&self.mac.0[..6]),
+            [HTYPE_ETHERNET, mac @ ..] if mac.len() == 6 => Some(mac),
+            [TYPE_IAID_DUID, _, _, _, _, duid @ ..] if duid.len() >= 4 => {
+                let (kind, htype) = (
+                    u16::from_be_bytes([duid[0], duid[1]]),
+                    u16::from_be_bytes([duid[2], duid[3]]),
+                );
+                let address = match kind {
+                    DUID_LLT => duid.get(8..)?,
+                    DUID_LL => &duid[4..],
+                    _ => return None,
+                };
+                Some(address).filter(|x| htype == u16::from(HTYPE_ETHERNET) && x.len() == 6)
+            }
+            _ => None,
+        }
+    }
+
+    // Machine booting over PXE is identified by MAC, operating system it
+    // boots may use DUID (RFC 4361) instead, both share lease. Identifier
+    // derived from MAC stands for any client with that MAC, as PXE ROM and
+    // operating system share network card.
+    pub fn is_same_client(&self, other: &ClientId) -> bool {
+        if self == other {
+            return true;
+        }
+        match (self.hardware_address(), other.hardware_address()) {
+            (Some(x), Some(y)) => x == y,
+            (Some(x), None) => x == &other.mac.0[..6],
+            (None, Some(y)) => y == &self.mac.0[..6],
+            (None, None) => false,
+        }
+    }
+}
+
 impl fmt::Display for ClientId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.ext.is_empty() {
@@ -50,3 +142,40 @@ impl fmt::Display for ClientId {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_client() {
+        let mac: Mac = "52:54:00:12:34:56".parse().unwrap();
+        let client = |ext: &[u8]| ClientId {
+            mac,
+            ext: ext.to_vec(),
+        };
+        let pxe = client(&[]);
+        let hardware = client(&[1, 0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+        // IAID, DUID-LL and DUID-EN
+        let duid_ll = client(&[255, 0, 0, 0, 1, 0, 3, 0, 1, 0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+        let duid_en = client(&[255, 0, 0, 0, 1, 0, 2, 0, 0, 0xab, 0x11, 1, 2, 3]);
+        let other_en = client(&[255, 0, 0, 0, 1, 0, 2, 0, 0, 0xab, 0x11, 4, 5, 6]);
+
+        assert_eq!(duid_ll.hardware_address(), Some(&mac.0[..6]));
+        assert_eq!(duid_en.hardware_address(), None);
+        for x in [&hardware, &duid_ll, &duid_en].iter() {
+            assert!(pxe.is_same_client(x));
+            assert!(x.is_same_client(&pxe));
+        }
+        assert!(hardware.is_same_client(&duid_ll));
+        assert!(!duid_en.is_same_client(&other_en));
+
+        let other_mac = ClientId {
+            mac: "52:54:00:12:34:57".parse().unwrap(),
+            ext: Vec::new(),
+        };
+        assert!(!other_mac.is_same_client(&pxe));
+        assert!(!other_mac.is_same_client(&duid_en));
+        assert!(!other_mac.is_same_client(&duid_ll));
+    }
+}
@@ -0,0 +1,230 @@
+// Leases kept in SQLite database, one row per lease with same fields as
+// lease file. Server keeps working on leases in memory, database mirrors
+// them so it can be queried any time while server runs, e.g.
+// sqlite3 leases.db "select ip, mac, hostname from leases". Writes are
+// done by own thread, which upserts and deletes only rows that changed
+// since last write, so packets are not held up by disk.
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::Context;
+use rusqlite::{params, Connection};
+
+use super::lease_file::StoredLease;
+
+const SCHEMA: &str = "
+    create table if not exists leases (
+        ip text primary key,
+        mac text not null,
+        client_id text not null default '',
+        hostname text,
+        -- seconds since Unix epoch
+        expires integer not null
+    )";
+
+pub struct LeaseDb {
+    path: PathBuf,
+    connection: Arc<Mutex<Connection>>,
+    // dropped first so writer finishes queued leases before it is joined
+    sender: Option<mpsc::Sender<Vec<StoredLease>>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl LeaseDb {
+    // missing database is created
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)
+            .and_then(|x| x.execute_batch(SCHEMA).map(|_| x))
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let connection = Arc::new(Mutex::new(connection));
+        let (sender, receiver) = mpsc::channel();
+        let writer = Writer {
+            path: path.to_path_buf(),
+            connection: Arc::clone(&connection),
+            saved: None,
+        };
+        let writer = thread::Builder::new()
+            .name("lease-db".to_string())
+            .spawn(move || writer.run(receiver))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            connection,
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load(&self) -> anyhow::Result<Vec<StoredLease>> {
+        read(&self.connection.lock().unwrap())
+            .with_context(|| format!("failed to read {}", self.path.display()))
+    }
+
+    // leases are queued for writer, failures to write are logged by it
+    pub fn save(&self, leases: Vec<StoredLease>) -> anyhow::Result<()> {
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(leases)
+            .map_err(|_| anyhow!("writer of {} stopped", self.path.display()))
+    }
+}
+
+impl Drop for LeaseDb {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+struct Writer {
+    path: PathBuf,
+    connection: Arc<Mutex<Connection>>,
+    // rows as last written, read from database before first write
+    saved: Option<HashMap<Ipv4Addr, StoredLease>>,
+}
+
+impl Writer {
+    fn run(mut self, receiver: mpsc::Receiver<Vec<StoredLease>>) {
+        while let Ok(leases) = receiver.recv() {
+            // only latest leases matter when several got queued meanwhile
+            let leases = receiver.try_iter().last().unwrap_or(leases);
+            if let Err(e) = self.write(leases) {
+                warn!("failed to save leases: {:#}", e);
+            }
+        }
+    }
+
+    fn write(&mut self, leases: Vec<StoredLease>) -> anyhow::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let saved = match self.saved.take() {
+            Some(saved) => saved,
+            None => match read(&transaction) {
+                Ok(rows) => rows.into_iter().map(|x| (x.ip, x)).collect(),
+                // unreadable rows are replaced altogether
+                Err(_) => {
+                    transaction.execute("delete from leases", [])?;
+                    HashMap::new()
+                }
+            },
+        };
+
+        let mut written = HashMap::with_capacity(leases.len());
+        for lease in leases {
+            match saved.get(&lease.ip) {
+                Some(row) if !changed(row, &lease) => {
+                    written.insert(row.ip, row.clone());
+                }
+                _ => {
+                    transaction.execute(
+                        "insert or replace into leases (ip, mac, client_id, hostname, expires)
+                         values (?1, ?2, ?3, ?4, ?5)",
+                        params![
+                            lease.ip.to_string(),
+                            lease.mac.to_string(),
+                            lease.client_id,
+                            lease.hostname,
+                            lease.expires as i64
+                        ],
+                    )?;
+                    written.insert(lease.ip, lease);
+                }
+            }
+        }
+        for ip in saved.keys().filter(|x| !written.contains_key(x)) {
+            transaction.execute("delete from leases where ip = ?1", [ip.to_string()])?;
+        }
+        transaction
+            .commit()
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        self.saved = Some(written);
+        Ok(())
+    }
+}
+
+// expiry is worked out from remaining time of lease on every save and may
+// move by a second without lease being renewed
+fn changed(row: &StoredLease, lease: &StoredLease) -> bool {
+    row.mac != lease.mac
+        || row.client_id != lease.client_id
+        || row.hostname != lease.hostname
+        || row.expires.abs_diff(lease.expires) > 1
+}
+
+fn read(connection: &Connection) -> anyhow::Result<Vec<StoredLease>> {
+    let mut statement =
+        connection.prepare("select ip, mac, client_id, hostname, expires from leases")?;
+    let rows = statement.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get::<_, i64>(4)?,
+        ))
+    })?;
+    rows.map(|row| {
+        let (ip, mac, client_id, hostname, expires) = row?;
+        Ok(StoredLease {
+            ip: ip.parse().with_context(|| format!("invalid IP {}", ip))?,
+            mac: mac
+                .parse()
+                .map_err(|_| anyhow!("invalid MAC {} of {}", mac, ip))?,
+            client_id,
+            hostname,
+            expires: expires.max(0) as u64,
+        })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("pxe-leases-{}.db", std::process::id()));
+        let mac = "52:54:00:12:34:56".parse().unwrap();
+        let leases = vec![
+            StoredLease {
+                hostname: Some("node1".to_string()),
+                ..StoredLease::new(
+                    Ipv4Addr::new(10, 0, 0, 100),
+                    mac,
+                    &[],
+                    Duration::from_secs(3600),
+                )
+            },
+            StoredLease::new(
+                Ipv4Addr::new(10, 0, 0, 101),
+                mac,
+                &[1, 0x52, 0x54, 0, 0x12, 0x34, 0x56],
+                Duration::from_secs(60),
+            ),
+        ];
+
+        let renamed = StoredLease {
+            hostname: Some("node2".to_string()),
+            ..leases[1].clone()
+        };
+
+        let db = LeaseDb::open(&path).unwrap();
+        db.save(leases.clone()).unwrap();
+        db.save(vec![renamed.clone()]).unwrap();
+        drop(db);
+        let loaded = LeaseDb::open(&path).unwrap().load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, vec![renamed]);
+    }
+}
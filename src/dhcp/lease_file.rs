@@ -0,0 +1,120 @@
+// Leases kept across restarts, so that addresses still in use are not
+// offered again. Whole file is rewritten whenever leases change, new one
+// replaces old only once complete. Expirations are wall clock time, time
+// server was down counts against leases.
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::id::Mac;
+use crate::fsutil;
+use crate::signature::{from_hex, to_hex};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredLease {
+    pub ip: Ipv4Addr,
+    pub mac: Mac,
+    // DHCP option 61 as hex, empty when client sent none
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub client_id: String,
+    // option 12 of client, restored for DNS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    // seconds since Unix epoch
+    pub expires: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LeaseFile {
+    #[serde(default)]
+    leases: Vec<StoredLease>,
+}
+
+impl StoredLease {
+    pub fn new(ip: Ipv4Addr, mac: Mac, client_id: &[u8], remaining: Duration) -> Self {
+        Self {
+            ip,
+            mac,
+            client_id: to_hex(client_id),
+            hostname: None,
+            expires: unix_time() + remaining.as_secs(),
+        }
+    }
+
+    pub fn client_id(&self) -> anyhow::Result<Vec<u8>> {
+        from_hex(&self.client_id)
+    }
+
+    // zero once expired
+    pub fn remaining(&self) -> Duration {
+        Duration::from_secs(self.expires.saturating_sub(unix_time()))
+    }
+}
+
+// missing file is created on first change
+pub fn load(path: &Path) -> anyhow::Result<Vec<StoredLease>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let data =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let file: LeaseFile = serde_json::from_str(&data)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok(file.leases)
+}
+
+pub fn save(path: &Path, leases: Vec<StoredLease>) -> anyhow::Result<()> {
+    let mut data = serde_json::to_string_pretty(&LeaseFile { leases })?;
+    data.push('\n');
+    fsutil::replace(path, data.as_bytes(), false)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("pxe-leases-{}.json", std::process::id()));
+        let mac: Mac = "52:54:00:12:34:56".parse().unwrap();
+        let leases = vec![
+            StoredLease {
+                hostname: Some("node1".to_string()),
+                ..StoredLease::new(
+                    Ipv4Addr::new(10, 0, 0, 100),
+                    mac,
+                    &[],
+                    Duration::from_secs(3600),
+                )
+            },
+            StoredLease::new(
+                Ipv4Addr::new(10, 0, 0, 101),
+                mac,
+                &[1, 0x52, 0x54],
+                Duration::ZERO,
+            ),
+        ];
+
+        assert!(load(&path).unwrap().is_empty());
+        save(&path, leases.clone()).unwrap();
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded, leases);
+        assert!(loaded[0].remaining() > Duration::from_secs(3590));
+        assert_eq!(loaded[1].remaining(), Duration::ZERO);
+        assert_eq!(loaded[1].client_id().unwrap(), [1, 0x52, 0x54]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,488 @@
+// Leases of other DHCP servers, so that moving to this one does not hand
+// out addresses still in use and moving back keeps clients where they are.
+// Import merges leases into lease file or database of server, which must
+// not be running as it rewrites them, export writes active leases out.
+//
+// dnsmasq keeps one lease per line:
+//   <expiry> <MAC> <IP> <hostname or *> <client ID or *>
+// with expiry in seconds since Unix epoch, 0 for leases that never expire.
+//
+// ISC dhcpd appends lease blocks, later ones replace earlier ones of same
+// address, times are UTC:
+//   lease 10.0.0.100 {
+//     ends 4 2021/05/20 22:00:00;
+//     binding state active;
+//     hardware ethernet 52:54:00:12:34:56;
+//     uid "\001RT\000\0224V";
+//     client-hostname "node1";
+//   }
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Context;
+
+use super::id::Mac;
+use super::lease_file::{unix_time, StoredLease};
+use super::lease_store::LeaseStore;
+use super::INFINITE_LEASE;
+use crate::signature::{from_hex, to_hex};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LeaseFormat {
+    Dnsmasq,
+    Isc,
+}
+
+impl FromStr for LeaseFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dnsmasq" => Ok(Self::Dnsmasq),
+            "isc" => Ok(Self::Isc),
+            _ => bail!("invalid lease format \"{}\", expected dnsmasq or isc", s),
+        }
+    }
+}
+
+impl fmt::Display for LeaseFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Dnsmasq => write!(f, "dnsmasq"),
+            Self::Isc => write!(f, "isc"),
+        }
+    }
+}
+
+pub fn import(options: &crate::Options, format: LeaseFormat, path: &Path) -> anyhow::Result<()> {
+    let store = LeaseStore::open(options)?;
+    if !store.is_persistent() {
+        bail!("--lease-file or --lease-db is required to import leases");
+    }
+    let data =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let imported = match format {
+        LeaseFormat::Dnsmasq => parse_dnsmasq(&data),
+        LeaseFormat::Isc => parse_isc(&data),
+    }
+    .with_context(|| format!("failed to parse {}", path.display()))?;
+
+    // imported leases replace stored ones of same address
+    let now = unix_time();
+    let mut leases: BTreeMap<_, _> = store.load()?.into_iter().map(|x| (x.ip, x)).collect();
+    let count = imported.len();
+    leases.extend(imported.into_iter().map(|x| (x.ip, x)));
+    leases.retain(|_, x| x.expires > now);
+    store.save(leases.into_values().collect())?;
+    println!("imported {} lease(s) into {}", count, store);
+    Ok(())
+}
+
+// standard output without path
+pub fn export(
+    options: &crate::Options,
+    format: LeaseFormat,
+    path: Option<&Path>,
+) -> anyhow::Result<()> {
+    let store = LeaseStore::open(options)?;
+    if !store.is_persistent() {
+        bail!("--lease-file or --lease-db is required to export leases");
+    }
+    let now = unix_time();
+    let leases: Vec<_> = store
+        .load()?
+        .into_iter()
+        .filter(|x| x.expires > now)
+        .collect();
+    let data = match format {
+        LeaseFormat::Dnsmasq => format_dnsmasq(&leases),
+        LeaseFormat::Isc => format_isc(&leases),
+    };
+    match path {
+        Some(path) => {
+            fs::write(path, data).with_context(|| format!("failed to write {}", path.display()))
+        }
+        None => {
+            print!("{}", data);
+            Ok(())
+        }
+    }
+}
+
+// DHCPv6 leases follow duid line, they are skipped
+fn parse_dnsmasq(data: &str) -> anyhow::Result<Vec<StoredLease>> {
+    let mut leases = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [] => continue,
+            ["duid", ..] => continue,
+            [_, _, ip, ..] if ip.contains(':') => continue,
+            [expiry, mac, ip, hostname, client_id, ..] => {
+                let parse = || -> anyhow::Result<StoredLease> {
+                    let expires = match expiry.parse()? {
+                        0 => unix_time() + INFINITE_LEASE.as_secs(),
+                        x => x,
+                    };
+                    Ok(StoredLease {
+                        ip: ip.parse()?,
+                        mac: mac.parse()?,
+                        client_id: match *client_id {
+                            "*" => String::new(),
+                            x => to_hex(&from_hex(&x.replace(':', ""))?),
+                        },
+                        hostname: Some(hostname.to_string()).filter(|x| x != "*"),
+                        expires,
+                    })
+                };
+                leases.push(parse().with_context(|| format!("line {}", i + 1))?);
+            }
+            _ => bail!("line {}: expected 5 fields", i + 1),
+        }
+    }
+    Ok(leases)
+}
+
+fn format_dnsmasq(leases: &[StoredLease]) -> String {
+    let mut out = String::new();
+    for lease in leases.iter() {
+        out += &format!(
+            "{} {} {} {} {}\n",
+            lease.expires,
+            lease.mac.to_string().to_lowercase(),
+            lease.ip,
+            lease.hostname.as_deref().unwrap_or("*"),
+            match lease.client_id.as_str() {
+                "" => "*".to_string(),
+                x => colon_hex(x),
+            }
+        );
+    }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    // unescaped
+    Str(Vec<u8>),
+    Open,
+    Close,
+    Semicolon,
+}
+
+fn tokenize(data: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            x if x.is_whitespace() => (),
+            '#' => while matches!(chars.next(), Some(x) if x != '\n') {},
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            ';' => tokens.push(Token::Semicolon),
+            '"' => {
+                let mut value = Vec::new();
+                loop {
+                    match chars.next() {
+                        None => bail!("unterminated string"),
+                        Some('"') => break,
+                        // octal escapes are written for bytes outside ASCII
+                        Some('\\') => match chars.next() {
+                            Some(x @ '0'..='7') => {
+                                let mut n = x.to_digit(8).unwrap();
+                                for _ in 0..2 {
+                                    if let Some(d) = chars.peek().and_then(|x| x.to_digit(8)) {
+                                        n = n * 8 + d;
+                                        chars.next();
+                                    }
+                                }
+                                value.push(n as u8);
+                            }
+                            Some('n') => value.push(b'\n'),
+                            Some('t') => value.push(b'\t'),
+                            Some(x) => value.extend(x.to_string().bytes()),
+                            None => bail!("unterminated string"),
+                        },
+                        Some(x) => value.extend(x.to_string().bytes()),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            x => {
+                let mut word = x.to_string();
+                while let Some(&x) = chars.peek() {
+                    if x.is_whitespace() || matches!(x, '{' | '}' | ';' | '"') {
+                        break;
+                    }
+                    word.push(x);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// Only active leases that did not end yet are taken, statements other than
+// those describing lease are skipped along with blocks they open.
+fn parse_isc(data: &str) -> anyhow::Result<Vec<StoredLease>> {
+    let tokens = tokenize(data)?;
+    let mut leases = BTreeMap::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match (&tokens[i], tokens.get(i + 1), tokens.get(i + 2)) {
+            (Token::Word(x), Some(Token::Word(ip)), Some(Token::Open)) if x == "lease" => {
+                let ip: Ipv4Addr = match ip.parse() {
+                    Ok(x) => x,
+                    // IPv6 lease
+                    Err(_) => {
+                        i = skip_block(&tokens, i + 2);
+                        continue;
+                    }
+                };
+                let end = block_end(&tokens, i + 2)?;
+                let lease = parse_isc_lease(ip, &tokens[i + 3..end])
+                    .with_context(|| format!("lease {}", ip))?;
+                match lease {
+                    Some(lease) => leases.insert(ip, lease),
+                    None => leases.remove(&ip),
+                };
+                i = end + 1;
+            }
+            _ => i = skip_statement(&tokens, i),
+        }
+    }
+    let now = unix_time();
+    Ok(leases.into_values().filter(|x| x.expires > now).collect())
+}
+
+// index of brace closing block opened at given index
+fn block_end(tokens: &[Token], open: usize) -> anyhow::Result<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token {
+            Token::Open => depth += 1,
+            Token::Close if depth == 1 => return Ok(i),
+            Token::Close => depth -= 1,
+            _ => (),
+        }
+    }
+    bail!("unterminated block")
+}
+
+fn skip_block(tokens: &[Token], open: usize) -> usize {
+    block_end(tokens, open).map_or(tokens.len(), |x| x + 1)
+}
+
+// index past statement, or past block if statement opens one
+fn skip_statement(tokens: &[Token], start: usize) -> usize {
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::Semicolon | Token::Close => return i + 1,
+            Token::Open => return skip_block(tokens, i),
+            _ => (),
+        }
+    }
+    tokens.len()
+}
+
+// None for lease that is not active
+fn parse_isc_lease(ip: Ipv4Addr, tokens: &[Token]) -> anyhow::Result<Option<StoredLease>> {
+    let mut mac = None;
+    let mut client_id = String::new();
+    let mut hostname = None;
+    let mut expires = None;
+    let mut active = true;
+    for statement in tokens.split(|x| *x == Token::Semicolon) {
+        let words: Vec<_> = statement
+            .iter()
+            .map(|x| match x {
+                Token::Word(x) => x.as_str(),
+                _ => "",
+            })
+            .collect();
+        match (words.as_slice(), statement) {
+            (["ends", "never"], _) => expires = Some(unix_time() + INFINITE_LEASE.as_secs()),
+            (["ends", "epoch", x], _) => expires = Some(x.parse()?),
+            (["ends", _, date, time], _) => expires = Some(parse_isc_time(date, time)?),
+            (["binding", "state", state], _) => active = *state == "active",
+            (["hardware", "ethernet", x], _) => mac = Some(parse_isc_mac(x)?),
+            (["uid", x], [_, Token::Word(_)]) => {
+                client_id = to_hex(&parse_isc_mac_bytes(x)?);
+            }
+            (_, [Token::Word(x), Token::Str(uid)]) if x == "uid" => client_id = to_hex(uid),
+            (_, [Token::Word(x), Token::Str(name)]) if x == "client-hostname" => {
+                hostname = Some(String::from_utf8_lossy(name).into_owned());
+            }
+            _ => (),
+        }
+    }
+    if !active {
+        return Ok(None);
+    }
+    Ok(Some(StoredLease {
+        ip,
+        mac: mac.ok_or_else(|| anyhow!("no hardware ethernet"))?,
+        client_id,
+        hostname,
+        expires: expires.ok_or_else(|| anyhow!("no ends"))?,
+    }))
+}
+
+fn format_isc(leases: &[StoredLease]) -> String {
+    let mut out = String::new();
+    for lease in leases.iter() {
+        out += &format!("lease {} {{\n", lease.ip);
+        out += &format!("  ends {};\n", format_isc_time(lease.expires));
+        out += "  binding state active;\n";
+        out += &format!(
+            "  hardware ethernet {};\n",
+            lease.mac.to_string().to_lowercase()
+        );
+        if !lease.client_id.is_empty() {
+            out += &format!("  uid {};\n", colon_hex(&lease.client_id));
+        }
+        if let Some(hostname) = lease.hostname.as_deref() {
+            out += &format!(
+                "  client-hostname \"{}\";\n",
+                hostname.replace('\\', "\\\\").replace('"', "\\\"")
+            );
+        }
+        out += "}\n";
+    }
+    out
+}
+
+// e.g. 2021/05/20 22:00:00 in UTC
+fn parse_isc_time(date: &str, time: &str) -> anyhow::Result<u64> {
+    let time = humantime::parse_rfc3339_weak(&format!("{} {}", date.replace('/', "-"), time))
+        .map_err(|e| anyhow!("invalid time {} {}: {}", date, time, e))?;
+    Ok(time.duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+// weekday (0 is Sunday) followed by date and time
+fn format_isc_time(secs: u64) -> String {
+    let time = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs));
+    // 1970-01-01 was Thursday
+    let weekday = (secs / (24 * 60 * 60) + 4) % 7;
+    let time = time.to_string();
+    format!(
+        "{} {} {}",
+        weekday,
+        time[..10].replace('-', "/"),
+        &time[11..19]
+    )
+}
+
+// dhcpd leaves out leading zeros, e.g. 52:54:0:12:34:56
+fn parse_isc_mac_bytes(s: &str) -> anyhow::Result<Vec<u8>> {
+    s.split(':')
+        .map(|x| u8::from_str_radix(x, 16).map_err(|_| anyhow!("invalid hex {}", s)))
+        .collect()
+}
+
+fn parse_isc_mac(s: &str) -> anyhow::Result<Mac> {
+    let bytes = parse_isc_mac_bytes(s)?;
+    let text: Vec<_> = bytes.iter().map(|x| format!("{:02x}", x)).collect();
+    text.join(":").parse()
+}
+
+// 01:52:54:00:12:34:56 for hex without separators
+fn colon_hex(hex: &str) -> String {
+    let pairs: Vec<_> = hex
+        .as_bytes()
+        .chunks(2)
+        .map(|x| String::from_utf8_lossy(x))
+        .collect();
+    pairs.join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dnsmasq() {
+        let future = unix_time() + 3600;
+        let data = format!(
+            "{} 52:54:00:12:34:56 10.0.0.100 node1 01:52:54:00:12:34:56\n\
+             0 52:54:00:12:34:57 10.0.0.101 * *\n\
+             duid 00:01:00:01:2a:2b:2c:2d:52:54:00:12:34:56\n\
+             {} 1234 fd00::10 node1 00:01:00:01\n",
+            future, future
+        );
+        let leases = parse_dnsmasq(&data).unwrap();
+        assert_eq!(leases.len(), 2);
+        assert_eq!(leases[0].client_id, "01525400123456");
+        assert_eq!(leases[0].hostname.as_deref(), Some("node1"));
+        assert_eq!(leases[0].expires, future);
+        assert_eq!(leases[1].hostname, None);
+        assert!(leases[1].remaining() > Duration::from_secs(300 * 24 * 60 * 60));
+
+        assert_eq!(
+            format_dnsmasq(&leases[..1]),
+            format!(
+                "{} 52:54:00:12:34:56 10.0.0.100 node1 01:52:54:00:12:34:56\n",
+                future
+            )
+        );
+        assert!(parse_dnsmasq("0 52:54:00:12:34:56 10.0.0.100\n").is_err());
+    }
+
+    #[test]
+    fn test_isc() {
+        let data = r#"
+            # The format of this file is documented in the dhcpd.leases(5) manual page.
+            authoring-byte-order little-endian;
+            server-duid "\000\001\000\001";
+
+            lease 10.0.0.100 {
+              starts 4 2021/05/20 10:00:00;
+              ends 4 2021/05/20 22:00:00;
+              binding state free;
+              hardware ethernet 52:54:0:12:34:56;
+            }
+            lease 10.0.0.100 {
+              ends never;
+              binding state active;
+              next binding state free;
+              hardware ethernet 52:54:0:12:34:56;
+              uid "\001RT\000\0224V";
+              client-hostname "node1";
+            }
+            lease 10.0.0.101 {
+              ends 4 2021/05/20 22:00:00;
+              binding state active;
+              hardware ethernet 52:54:00:12:34:57;
+            }
+            lease 10.0.0.102 {
+              ends never;
+              hardware ethernet 52:54:00:12:34:58;
+              uid 01:52:54:00:12:34:58;
+            }
+            "#;
+        let leases = parse_isc(data).unwrap();
+        assert_eq!(leases.len(), 2);
+        assert_eq!(leases[0].ip, Ipv4Addr::new(10, 0, 0, 100));
+        assert_eq!(leases[0].mac, "52:54:00:12:34:56".parse().unwrap());
+        assert_eq!(leases[0].client_id, "01525400123456");
+        assert_eq!(leases[0].hostname.as_deref(), Some("node1"));
+        assert_eq!(leases[1].client_id, "01525400123458");
+
+        assert_eq!(
+            parse_isc_time("2021/05/20", "22:00:00").unwrap(),
+            1621548000
+        );
+        assert_eq!(format_isc_time(1621548000), "4 2021/05/20 22:00:00");
+        let exported = format_isc(&leases);
+        assert!(exported.contains("  uid 01:52:54:00:12:34:56;\n"));
+        assert_eq!(parse_isc(&exported).unwrap(), leases);
+    }
+}
@@ -0,0 +1,63 @@
+// Where leases are kept across restarts. Server works on leases in memory
+// and hands over all of them whenever they change. Without lease file or
+// database they are gone with the server.
+use std::fmt;
+use std::path::PathBuf;
+
+#[cfg(feature = "sqlite")]
+use super::lease_db::LeaseDb;
+use super::lease_file::{self, StoredLease};
+
+pub enum LeaseStore {
+    Memory,
+    File(PathBuf),
+    #[cfg(feature = "sqlite")]
+    Sqlite(LeaseDb),
+}
+
+impl LeaseStore {
+    // lease file and database are validated not to be given together
+    pub fn open(options: &crate::Options) -> anyhow::Result<Self> {
+        #[cfg(feature = "sqlite")]
+        if let Some(path) = options.lease_db.as_deref() {
+            return LeaseDb::open(path).map(Self::Sqlite);
+        }
+        Ok(match options.lease_file.clone() {
+            Some(path) => Self::File(path),
+            None => Self::Memory,
+        })
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        !matches!(self, Self::Memory)
+    }
+
+    pub fn load(&self) -> anyhow::Result<Vec<StoredLease>> {
+        match self {
+            Self::Memory => Ok(Vec::new()),
+            Self::File(path) => lease_file::load(path),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(db) => db.load(),
+        }
+    }
+
+    pub fn save(&self, leases: Vec<StoredLease>) -> anyhow::Result<()> {
+        match self {
+            Self::Memory => Ok(()),
+            Self::File(path) => lease_file::save(path, leases),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(db) => db.save(leases),
+        }
+    }
+}
+
+impl fmt::Display for LeaseStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Memory => write!(f, "memory"),
+            Self::File(path) => path.display().fmt(f),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(db) => db.path().display().fmt(f),
+        }
+    }
+}
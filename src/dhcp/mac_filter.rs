@@ -0,0 +1,129 @@
+// Allow and deny lists of clients served over DHCP. Files hold one entry
+// per line, either full MAC or OUI (e.g. 52:54:00) matching every card of
+// its vendor, text after # is comment. Denied clients are ignored even if
+// allowed, with allow list given nobody else is served. Lists are read
+// again on reload, list that fails to load keeps previous contents.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context;
+
+use super::id::Mac;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MacList {
+    // prefixes of raw MAC, 3 bytes for OUI, 6 for MAC
+    entries: Vec<Vec<u8>>,
+}
+
+impl MacList {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?
+            .parse()
+            .with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn contains(&self, mac: &Mac) -> bool {
+        self.entries.iter().any(|x| mac.get_raw().starts_with(x))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl FromStr for MacList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (text, len) = match line.split(&[':', '-'][..]).count() {
+                3 => (format!("{}:00:00:00", line), 3),
+                _ => (line.to_string(), 6),
+            };
+            let mac: Mac = text
+                .parse()
+                .map_err(|_| anyhow!("line {}: invalid MAC or OUI {}", i + 1, line))?;
+            entries.push(mac.get_raw()[..len].to_vec());
+        }
+        Ok(Self { entries })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MacFilter {
+    allow: Option<(PathBuf, MacList)>,
+    deny: Option<(PathBuf, MacList)>,
+}
+
+impl MacFilter {
+    pub fn load(allow: Option<&Path>, deny: Option<&Path>) -> anyhow::Result<Self> {
+        let load = |path: Option<&Path>| {
+            path.map(|x| MacList::load(x).map(|list| (x.to_path_buf(), list)))
+                .transpose()
+        };
+        Ok(Self {
+            allow: load(allow)?,
+            deny: load(deny)?,
+        })
+    }
+
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        for list in [&mut self.allow, &mut self.deny].iter_mut() {
+            if let Some((path, entries)) = list.as_mut() {
+                *entries = MacList::load(path)?;
+                info!("{} reloaded, {} entries", path.display(), entries.len());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn permits(&self, mac: &Mac) -> bool {
+        if matches!(&self.deny, Some((_, x)) if x.contains(mac)) {
+            return false;
+        }
+        match &self.allow {
+            Some((_, x)) => x.contains(mac),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mac_list() {
+        let allow: MacList = "# lab machines\n52:54:00\n\n00-1b-21-0a-0b-0c # spare\n"
+            .parse()
+            .unwrap();
+        let deny: MacList = "52:54:00:00:00:66".parse().unwrap();
+        let filter = MacFilter {
+            allow: Some((PathBuf::new(), allow)),
+            deny: Some((PathBuf::new(), deny)),
+        };
+
+        for (mac, permitted) in [
+            ("52:54:00:12:34:56", true),
+            ("00:1b:21:0a:0b:0c", true),
+            ("00:1b:21:0a:0b:0d", false),
+            ("52:54:00:00:00:66", false),
+        ]
+        .iter()
+        {
+            assert_eq!(filter.permits(&mac.parse().unwrap()), *permitted, "{}", mac);
+        }
+
+        assert!("52:54".parse::<MacList>().is_err());
+        assert!("52:54:00:zz".parse::<MacList>().is_err());
+        assert!(MacFilter::default().permits(&"52:54:00:12:34:56".parse().unwrap()));
+    }
+}
@@ -1,105 +1,645 @@
 use std::collections::BTreeMap;
+use std::io;
 use std::mem::MaybeUninit;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use futures_util::task::{Context, Poll};
 use tokio::io::ReadBuf;
 use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
 use tokio_stream::{Stream, StreamExt};
+use tracing::Instrument;
 
+use crate::capture;
+use crate::config::{Config, ExtraOption, Profile, Reservation};
 use crate::dhcp::id::Mac;
-use crate::Ipv4AddrAndMask;
+use crate::dns::{LeaseName, LeaseNames};
+use crate::hooks::{self, LeaseEvent, LeaseEvents};
+use crate::inventory::{self, Inventory, State};
+use crate::iputil::Ipv4AddrAndMask;
+use crate::sessions::{self, Sessions};
+use crate::stats::{self, Stats};
+use allocation::Allocation;
 pub use error::{Error, Result};
+use failover::Binding;
 use id::ClientId;
+use lease_store::LeaseStore;
+use mac_filter::MacFilter;
 use packet::{
     options::{
-        DhcpOption, MessageType, DHCP_CLIENT_IDENTIFIER, DHCP_LEASE_TIME, DHCP_MESSAGE_TYPE,
-        DHCP_MTU, DHCP_REQUESTED_IP, DHCP_SERVER_ID, DHCP_SUBNET_MASK, DHCP_TFTP_SERVER_NAME,
+        DhcpOption, MessageType, DHCP_BOOT_FILE_NAME, DHCP_CLIENT_IDENTIFIER,
+        DHCP_CLIENT_MACHINE_IDENTIFIER, DHCP_DNS_SERVER, DHCP_DOMAIN_NAME, DHCP_DOMAIN_SEARCH,
+        DHCP_LEASE_TIME, DHCP_MESSAGE_TYPE, DHCP_MTU, DHCP_NTP_SERVERS, DHCP_RAPID_COMMIT,
+        DHCP_REBINDING_TIME, DHCP_RELAY_AGENT_INFORMATION, DHCP_RENEWAL_TIME, DHCP_REQUESTED_IP,
+        DHCP_ROOT_PATH, DHCP_ROUTER_IP, DHCP_SERVER_ID, DHCP_SUBNET_MASK, DHCP_TFTP_SERVER_NAME,
+        DHCP_VENDOR_CLASS_IDENTIFIER, DHCP_VENDOR_SPECIFIC,
     },
-    BootpMessageType, Packet,
+    pxe::{self, VendorOptions},
+    BootpMessageType, Packet, BROADCAST_FLAG,
 };
+use transport::Transport;
+pub use transport::{BOOT_SERVER_PORT, CLIENT_PORT, SERVER_PORT};
 
+pub mod allocation;
+pub mod arch;
+#[cfg(target_os = "linux")]
+mod arp;
 mod error;
-mod id;
-mod packet;
+pub mod failover;
+pub mod id;
+#[cfg(feature = "sqlite")]
+mod lease_db;
+mod lease_file;
+pub mod lease_formats;
+mod lease_store;
+pub mod mac_filter;
+pub mod packet;
+mod pktinfo;
+mod probe;
+#[cfg(target_os = "linux")]
+mod raw;
+mod transport;
 
+// addresses probed for single DISCOVER, see find_unused_ip_address
+const MAX_PROBES: usize = 3;
+// receive buffer size used when interface MTU is unknown
 const MAX_PACKET_SIZE: usize = 1024;
+// every DHCP client must accept 576 byte datagrams (RFC 2131)
+const MIN_PACKET_SIZE: usize = 576 - IP_UDP_HEADER_LEN;
+const IP_UDP_HEADER_LEN: usize = 20 + 8;
+const PXE_MENU_PROMPT: &str = "Press F8 for boot menu";
+// how often expired leases, offers and quarantines are dropped
+const REAP_INTERVAL: Duration = Duration::from_secs(10);
+// leases that never expire are kept for this long
+const INFINITE_LEASE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
 
 pub async fn start(
     options: &super::Options,
-    server_ip: Ipv4Addr,
-    dhcp_ip_start: Ipv4Addr,
-    dhcp_ip_end: Ipv4Addr,
-    dhcp_subnet: Ipv4AddrAndMask,
-) {
-    let socket = UdpSocket::bind((server_ip, 67)).await.unwrap();
-    socket.set_broadcast(true).unwrap();
+    handle: &Handle,
+    stats: &Arc<Stats>,
+    lease_names: &LeaseNames,
+    sessions: &Sessions,
+    inventory: &Inventory,
+) -> Result<()> {
+    let server_ip = options.server_ip();
+    #[cfg(target_os = "linux")]
+    let socket = match options.interface.as_deref() {
+        Some(interface) if options.raw_socket => {
+            Transport::Raw(raw::RawSocket::open(interface, server_ip)?)
+        }
+        // validated, without --interface it is the one owning server IP
+        _ if options.dhcp_bind_any => Transport::Pktinfo(pktinfo::PktinfoSocket::open(
+            options.interface.as_deref(),
+            server_ip,
+        )?),
+        Some(interface) => bind_interface_udp(interface)?,
+        None => bind_udp(server_ip).await?,
+    };
+    #[cfg(not(target_os = "linux"))]
+    let socket = match options.dhcp_bind_any {
+        true => Transport::Pktinfo(pktinfo::PktinfoSocket::open(
+            options.interface.as_deref(),
+            server_ip,
+        )?),
+        false => bind_udp(server_ip).await?,
+    };
+    let boot_socket = match options.boot_server {
+        true => Some(UdpSocket::bind((server_ip, BOOT_SERVER_PORT)).await?),
+        false => None,
+    };
 
-    let mask = dhcp_subnet.mask_raw();
+    new_server(options, handle, stats, lease_names, sessions, inventory)?
+        .start(socket, boot_socket, &mut *handle.commands.lock().await)
+        .await;
+
+    Ok(())
+}
 
-    let ip_range_start = Into::<u32>::into(dhcp_ip_start) & !mask;
-    let ip_range_end = Into::<u32>::into(dhcp_ip_end) & !mask;
-    let ip_range_size = ip_range_end - ip_range_start + 1;
+// server is built without sockets, those are bound by start
+fn new_server(
+    options: &super::Options,
+    handle: &Handle,
+    stats: &Arc<Stats>,
+    lease_names: &LeaseNames,
+    sessions: &Sessions,
+    inventory: &Inventory,
+) -> Result<Server> {
+    let server_ip = options.server_ip();
+    let proxy = options.proxy_dhcp;
+    // validated before server is started, proxy has no pool
+    let (dhcp_ip_start, dhcp_ip_end, dhcp_subnet) = match proxy {
+        false => (
+            options.dhcp_ip_start.unwrap(),
+            options.dhcp_ip_end.unwrap(),
+            options.dhcp_subnet.unwrap(),
+        ),
+        true => (
+            Ipv4Addr::new(0, 0, 0, 1),
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4AddrAndMask::network_of(server_ip, 32),
+        ),
+    };
 
-    let broadcast_ip = Ipv4Addr::from(Into::<u32>::into(dhcp_subnet.address()) | !mask);
+    #[cfg(target_os = "linux")]
+    let unicast_replies = options.unicast_replies;
+    #[cfg(not(target_os = "linux"))]
+    let unicast_replies = false;
+
+    let mut pools = vec![Pool::new(dhcp_subnet, dhcp_ip_start, dhcp_ip_end)];
+    pools.extend(options.config.pools.iter().map(|x| Pool {
+        router: x.router,
+        profile: x.profile.clone(),
+        root_path: x.root_path.clone(),
+        ..Pool::new(x.range.subnet(), x.range.start(), x.range.end())
+    }));
+    if let Some(role) = options.failover_role {
+        for pool in pools.iter_mut() {
+            let (start, end) = role.split(pool.range_start, pool.range_end);
+            pool.range_start = start;
+            pool.range_end = end;
+        }
+        debug!("failover {}, offering from own half of pools", role);
+    }
+
+    // subnet of main DHCP server is not known to proxy
+    let broadcast_ip = match proxy {
+        false => Ipv4Addr::from(Into::<u32>::into(dhcp_subnet.address()) | !dhcp_subnet.mask_raw()),
+        true => Ipv4Addr::BROADCAST,
+    };
 
     debug!("server starting");
     debug!("server ip: {}", server_ip);
-    debug!("server subnet: {}", dhcp_subnet);
-    debug!(
-        "IP range: {} - {} ({} IP addresses available)",
-        ip_range_start, ip_range_end, ip_range_size
-    );
+    for pool in pools.iter() {
+        debug!(
+            "subnet {}, IP range: {} - {} ({} IP addresses available)",
+            pool.subnet,
+            pool.range_start,
+            pool.range_end,
+            pool.size()
+        );
+    }
     debug!("broadcast address: {}", broadcast_ip);
 
-    Server {
-        leases: BTreeMap::new(),
+    stats
+        .dhcp_pool_size
+        .store(pools.iter().map(Pool::size).sum(), Ordering::Relaxed);
+
+    let invalid = |e: anyhow::Error| io::Error::new(io::ErrorKind::InvalidData, format!("{:#}", e));
+    let lease_store = LeaseStore::open(options).map_err(invalid)?;
+
+    let mut leases = BTreeMap::new();
+    if lease_store.is_persistent() {
+        let now = Instant::now();
+        for lease in lease_store.load().map_err(invalid)? {
+            let remaining = lease.remaining();
+            if remaining > Duration::ZERO {
+                let client_id = ClientId {
+                    mac: lease.mac,
+                    ext: lease.client_id().map_err(invalid)?,
+                };
+                leases.insert(lease.ip, (client_id, 0, now, remaining));
+                if let Some(hostname) = lease.hostname {
+                    lease_names.lock().unwrap().insert(
+                        lease.ip,
+                        LeaseName {
+                            hostname,
+                            expires: now + remaining,
+                        },
+                    );
+                }
+            }
+        }
+        info!("restored {} lease(s) from {}", leases.len(), lease_store);
+    }
+
+    // validated, without --interface it is the one owning server IP
+    #[cfg(target_os = "linux")]
+    let arp_check = match options.arp_check {
+        true => options
+            .interface
+            .clone()
+            .or_else(|| {
+                crate::netif::NetworkInterface::with_address(server_ip)
+                    .ok()
+                    .flatten()
+                    .map(|x| x.name().to_string())
+            })
+            .map(|x| (x, options.ping_timeout.get())),
+        false => None,
+    };
+
+    let mac_filter = MacFilter::load(options.mac_allow.as_deref(), options.mac_deny.as_deref())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:#}", e)))?;
+
+    Ok(Server {
+        proxy,
+        mac_filter,
+        pxe_only: options.pxe_only,
+        rapid_commit: options.rapid_commit,
+        allocation: options.dhcp_allocation,
+        leases,
+        conflicts: BTreeMap::new(),
+        quarantine: options.decline_quarantine.get(),
+        probe_timeout: Some(options.ping_timeout.get()).filter(|_| options.ping_check),
+        #[cfg(target_os = "linux")]
+        arp_check,
+        lease_conflicts: BTreeMap::new(),
+        #[cfg(target_os = "linux")]
+        commands: handle.sender.clone(),
+        lease_store,
+        leases_changed: false,
         pending: BTreeMap::new(),
-        subnet_mask: dhcp_subnet.mask(),
-        subnet_mask_width: dhcp_subnet.mask_width(),
-        subnet: dhcp_subnet.address(),
+        offer_timeout: options.offer_timeout.get(),
+        pools,
         broadcast_ip,
-        ip_range_start,
-        ip_range_end,
+        unicast_replies,
         server_ip: server_ip,
-        tftp_loader_path: crate::tftp::loader_path_to_relative(
-            options.loader.as_path(),
-            options.tftp_root.as_deref(),
-        ),
-        lease_duration_secs: 3600,
+        tftp_loader_path: options.loader.as_deref().map(|loader| {
+            crate::tftp::loader_path_to_relative(loader, options.tftp_root.as_deref())
+        }),
+        arch_loaders: options.arch_loader.clone(),
+        // 255 would wait for user forever
+        pxe_menu_timeout: Some(options.pxe_menu_timeout.get().as_secs().min(254) as u8)
+            .filter(|_| options.pxe_menu),
+        ipxe_boot_file: options.ipxe_boot_file.clone(),
+        next_server: options.next_server.unwrap_or(server_ip),
+        tftp_server_name: options.tftp_server_name.clone(),
+        local_boot_file: options.local_boot_file.clone(),
+        // validated to fit
+        default_lease_secs: options.dhcp_lease_time.get().as_secs() as u32,
         mtu: options.mtu,
+        // own DNS server unless others were given
+        dns_servers: if !options.dhcp_dns.is_empty() {
+            options.dhcp_dns.clone()
+        } else if options.dns {
+            vec![server_ip]
+        } else {
+            Vec::new()
+        },
+        domain_name: options.dhcp_domain.clone(),
+        domain_search: options.dhcp_search.clone(),
+        ntp_servers: options.dhcp_ntp.clone(),
+        nbd_port: options.nbd_port,
+        max_packet_size: options.interface_mtu.map_or(MAX_PACKET_SIZE, |mtu| {
+            (mtu as usize)
+                .saturating_sub(IP_UDP_HEADER_LEN)
+                .max(MIN_PACKET_SIZE)
+        }),
+        config: options.config.clone(),
+        stats: Arc::clone(stats),
+        lease_names: Arc::clone(lease_names),
+        sessions: Arc::clone(sessions),
+        inventory: Arc::clone(inventory),
+        updates: handle.updates.clone(),
+        lease_events: LeaseEvents::start(),
+    })
+}
+
+async fn bind_udp(server_ip: Ipv4Addr) -> Result<Transport> {
+    let socket = UdpSocket::bind((server_ip, SERVER_PORT)).await?;
+    socket.set_broadcast(true)?;
+
+    Ok(Transport::Udp(socket))
+}
+
+// Broadcasts reach only sockets bound to wildcard address, bound to
+// interface they do not see those of other networks, each instance
+// serving its own.
+#[cfg(target_os = "linux")]
+fn bind_interface_udp(interface: &str) -> Result<Transport> {
+    let socket = crate::sockutil::bind_udp_to_device(
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, SERVER_PORT)),
+        interface,
+    )?;
+    socket.set_broadcast(true)?;
+
+    Ok(Transport::Udp(socket))
+}
+
+// Lets other parts of the program inspect and manage running DHCP server.
+// Survives server restarts, commands sent while server is down are handled
+// once it is up again.
+#[derive(Debug, Clone)]
+pub struct Handle {
+    sender: mpsc::Sender<Command>,
+    commands: Arc<Mutex<mpsc::Receiver<Command>>>,
+    // bindings made and released by server, for failover peer
+    updates: broadcast::Sender<Binding>,
+}
+
+enum Command {
+    Leases(oneshot::Sender<Vec<Lease>>),
+    Reservations(oneshot::Sender<Vec<Reservation>>),
+    Expire(LeaseKey, oneshot::Sender<usize>),
+    // boxed, configuration is much larger than other commands
+    SetConfig(Box<Config>),
+    Bindings(oneshot::Sender<Vec<Binding>>),
+    Import(Binding),
+    // another host answered ARP for address bound to client, see arp module
+    #[cfg(target_os = "linux")]
+    Conflict(Ipv4Addr, ClientId, Mac),
+}
+
+#[derive(Debug, Clone)]
+pub struct Lease {
+    pub ip: Ipv4Addr,
+    pub client: String,
+    pub mac: Mac,
+    // None for offers not yet accepted by client
+    pub remaining: Option<Duration>,
+    // sent by client in option 12 when it was bound
+    pub hostname: Option<String>,
+    // other host found using address after it was bound
+    pub conflict: Option<Mac>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum LeaseKey {
+    Ip(Ipv4Addr),
+    Mac(Mac),
+}
+
+impl std::str::FromStr for LeaseKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(ip) = s.parse() {
+            Ok(Self::Ip(ip))
+        } else {
+            s.parse().map(Self::Mac)
+        }
+    }
+}
+
+impl LeaseKey {
+    fn matches(&self, ip: &Ipv4Addr, client_id: &ClientId) -> bool {
+        match self {
+            Self::Ip(x) => x == ip,
+            Self::Mac(x) => *x == client_id.mac,
+        }
+    }
+}
+
+impl Handle {
+    pub fn new() -> Self {
+        let (sender, commands) = mpsc::channel(16);
+        Self {
+            sender,
+            commands: Arc::new(Mutex::new(commands)),
+            updates: broadcast::channel(256).0,
+        }
+    }
+
+    pub async fn leases(&self) -> anyhow::Result<Vec<Lease>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::Leases(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    // those of current configuration, which may have been reloaded
+    pub async fn reservations(&self) -> anyhow::Result<Vec<Reservation>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::Reservations(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    // MAC of client currently holding lease of address
+    #[cfg(feature = "http")]
+    pub async fn client_of(&self, ip: Ipv4Addr) -> anyhow::Result<Option<Mac>> {
+        Ok(self
+            .leases()
+            .await?
+            .into_iter()
+            .find(|x| x.ip == ip && matches!(x.remaining, Some(r) if r > Duration::ZERO))
+            .map(|x| x.mac))
+    }
+
+    // returns number of removed leases and pending offers
+    pub async fn expire(&self, key: LeaseKey) -> anyhow::Result<usize> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::Expire(key, tx)).await?;
+        Ok(rx.await?)
+    }
+
+    pub async fn set_config(&self, config: Config) -> anyhow::Result<()> {
+        self.send(Command::SetConfig(Box::new(config))).await
+    }
+
+    // active leases
+    pub async fn bindings(&self) -> anyhow::Result<Vec<Binding>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::Bindings(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    // binding made by failover peer
+    pub async fn import(&self, binding: Binding) -> anyhow::Result<()> {
+        self.send(Command::Import(binding)).await
+    }
+
+    async fn send(&self, command: Command) -> anyhow::Result<()> {
+        self.sender
+            .send(command)
+            .await
+            .map_err(|_| anyhow!("DHCP server is not running"))
     }
-    .start(socket)
-    .await
 }
 
 struct Server {
+    // answers PXE clients only, addresses come from another DHCP server
+    proxy: bool,
+    // clients ignored unless allowed, see mac_filter module
+    mac_filter: MacFilter,
+    // clients not identifying themselves as PXE are ignored
+    pxe_only: bool,
+    // DISCOVER asking for it is acknowledged without OFFER
+    rapid_commit: bool,
+    allocation: Allocation,
     leases: BTreeMap<Ipv4Addr, (ClientId, u32, Instant, Duration)>,
-    pending: BTreeMap<Ipv4Addr, (ClientId, u32)>,
-    subnet_mask: Ipv4Addr,
-    subnet_mask_width: u8,
-    subnet: Ipv4Addr,
+    // declined addresses, not offered until given time
+    conflicts: BTreeMap<Ipv4Addr, Instant>,
+    quarantine: Duration,
+    // ICMP echo and ARP wait, None when addresses are not probed
+    probe_timeout: Option<Duration>,
+    // interface and wait of ARP check after ACK, None when bound
+    // addresses are not checked
+    #[cfg(target_os = "linux")]
+    arp_check: Option<(String, Duration)>,
+    // bound addresses other host answered ARP for, with its MAC
+    lease_conflicts: BTreeMap<Ipv4Addr, Mac>,
+    // for results of checks running alongside server
+    #[cfg(target_os = "linux")]
+    commands: mpsc::Sender<Command>,
+    // leases are kept in memory only when None
+    lease_store: LeaseStore,
+    // saved once packet or command that changed them is handled
+    leases_changed: bool,
+    // client, xid and time of offer
+    pending: BTreeMap<Ipv4Addr, (ClientId, u32, Instant)>,
+    offer_timeout: Duration,
+    // that of DHCP range first, followed by those for relayed clients
+    pools: Vec<Pool>,
     broadcast_ip: Ipv4Addr,
-    ip_range_start: u32,
-    ip_range_end: u32,
+    // replies to clients without address go to their MAC, see send_reply
+    unicast_replies: bool,
     server_ip: Ipv4Addr,
-    tftp_loader_path: String,
-    lease_duration_secs: u32,
+    // unmatched clients get no boot file when no loader was given
+    tftp_loader_path: Option<String>,
+    // take precedence over loader for clients of their architecture
+    arch_loaders: Vec<arch::ArchLoader>,
+    // seconds boot menu waits for user, no menu is offered when None
+    pxe_menu_timeout: Option<u8>,
+    // for iPXE loaded from our boot file, which would load it again
+    ipxe_boot_file: Option<String>,
+    // TFTP server, this one unless boot files live elsewhere
+    next_server: Ipv4Addr,
+    // option 66 instead of next server address
+    tftp_server_name: Option<String>,
+    // for hosts marked for local boot, whose firmware stops
+    // when offered no boot file instead of trying next device
+    local_boot_file: Option<String>,
+    // for clients whose vendor class sets no lease time
+    default_lease_secs: u32,
     mtu: Option<u16>,
+    // announced in option 6 in order of preference, our own DNS
+    // responder when it runs and no others were given
+    dns_servers: Vec<Ipv4Addr>,
+    domain_name: Option<String>,
+    domain_search: Vec<String>,
+    ntp_servers: Vec<Ipv4Addr>,
+    // for root paths of profiles booting from NBD export
+    nbd_port: u16,
+    // largest datagram that fits into single frame on server interface
+    max_packet_size: usize,
+    config: Config,
+    stats: Arc<Stats>,
+    lease_names: LeaseNames,
+    sessions: Sessions,
+    inventory: Inventory,
+    updates: broadcast::Sender<Binding>,
+    lease_events: LeaseEvents,
+}
+
+// Addresses of single subnet, see config::Pool. Range is kept as host
+// parts of its first and last address.
+#[derive(Clone)]
+struct Pool {
+    subnet: Ipv4AddrAndMask,
+    range_start: u32,
+    range_end: u32,
+    router: Option<Ipv4Addr>,
+    profile: Option<String>,
+    root_path: Option<String>,
+}
+
+impl Pool {
+    fn new(subnet: Ipv4AddrAndMask, start: Ipv4Addr, end: Ipv4Addr) -> Self {
+        let mask = subnet.mask_raw();
+        Self {
+            subnet,
+            range_start: Into::<u32>::into(start) & !mask,
+            range_end: Into::<u32>::into(end) & !mask,
+            router: None,
+            profile: None,
+            root_path: None,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        (self.range_end + 1).saturating_sub(self.range_start).into()
+    }
+
+    fn contains(&self, ip: Ipv4Addr) -> bool {
+        let host = Into::<u32>::into(ip) & !self.subnet.mask_raw();
+        self.subnet.contains(ip) && (self.range_start..=self.range_end).contains(&host)
+    }
+
+    // all addresses of range starting at given position, wrapping around
+    fn addresses_from(&self, offset: u64) -> impl Iterator<Item = Ipv4Addr> {
+        let network = Into::<u32>::into(self.subnet.address());
+        let (start, size) = (u64::from(self.range_start), self.size());
+        (0..size).map(move |n| Ipv4Addr::from(network | (start + (offset + n) % size) as u32))
+    }
+}
+
+// boot parameters selected for particular client
+struct BootParams<'a> {
+    file: Option<String>,
+    next_server: Ipv4Addr,
+    // of profile followed by those of selector, later ones win
+    options: Vec<&'a ExtraOption>,
+    root_path: Option<String>,
+    profile: Option<&'a Profile>,
 }
 
 impl Server {
-    async fn start(mut self, socket: UdpSocket) {
-        let mut stream = PacketStream { socket: &socket };
-        while let Some(packet) = stream.next().await {
+    async fn start(
+        mut self,
+        socket: Transport,
+        boot_socket: Option<UdpSocket>,
+        commands: &mut mpsc::Receiver<Command>,
+    ) {
+        let mut stream = PacketStream {
+            socket: &socket,
+            buf: vec![MaybeUninit::uninit(); self.max_packet_size],
+        };
+        let mut boot_buf = vec![0u8; self.max_packet_size];
+        let mut reap = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            let packet = tokio::select! {
+                packet = stream.next() => match packet {
+                    Some(packet) => packet,
+                    None => break,
+                },
+                Some(command) = commands.recv() => {
+                    self.handle_command(command);
+                    self.save_leases();
+                    continue;
+                }
+                Some(received) = recv_optional(boot_socket.as_ref(), &mut boot_buf) => {
+                    match received {
+                        Ok((n, client)) => {
+                            let data = boot_buf[..n].to_vec();
+                            self.process_boot_request(&data, client, boot_socket.as_ref().unwrap())
+                                .await
+                        }
+                        Err(e) => error!("boot server: {}", e),
+                    }
+                    continue;
+                }
+                _ = reap.tick() => {
+                    self.reap();
+                    self.update_lease_count();
+                    self.save_leases();
+                    continue;
+                }
+            };
+
             error!("processing packet");
             match packet {
                 Ok(packet) if packet.bootp_message_type == BootpMessageType::Request => {
-                    if let Err(e) = self.process_packet(packet, &socket).await {
-                        error!("{}", e);
+                    let span = info_span!(
+                        "dhcp",
+                        xid = %format_args!("{:08x}", packet.xid),
+                        mac = %packet.mac,
+                        relay = tracing::field::Empty
+                    );
+                    if let Some(relay) = packet.relay_agent_info() {
+                        span.record("relay", tracing::field::display(relay));
+                    }
+                    if let Err(e) = self
+                        .process_packet(packet, &socket)
+                        .instrument(span.clone())
+                        .await
+                    {
+                        error!(parent: &span, "{}", e);
                     }
+                    self.update_lease_count();
+                    self.save_leases();
                 }
                 Ok(packet) => error!("dropped {} packet", packet.bootp_message_type),
                 Err(e) => error!("{}", e),
@@ -107,35 +647,331 @@ impl Server {
         }
     }
 
-    async fn process_packet(&mut self, packet: Packet, socket: &UdpSocket) -> anyhow::Result<()> {
-        if self.filter_packet(&packet) {
-            return Ok(());
+    fn update_lease_name(&self, packet: &Packet, ip: Ipv4Addr) {
+        let mut lease_names = self.lease_names.lock().unwrap();
+        match packet.hostname() {
+            Some(hostname) => {
+                lease_names.insert(
+                    ip,
+                    LeaseName {
+                        hostname,
+                        expires: Instant::now()
+                            + Duration::from_secs(self.lease_duration_secs(packet).into()),
+                    },
+                );
+            }
+            None => {
+                lease_names.remove(&ip);
+            }
         }
+    }
 
-        let client_id_opt =
-            packet
-                .options
-                .get(&DHCP_CLIENT_IDENTIFIER)
-                .cloned()
-                .map_or(Vec::new(), |x| {
-                    if let DhcpOption::ByteArray(x) = x {
-                        x
-                    } else {
-                        Vec::new()
+    // boot is set for DISCOVER, REQUEST only fills in details
+    fn record_client(&self, packet: &Packet, boot: bool, ip: Option<Ipv4Addr>) {
+        let client = inventory::Client {
+            ip,
+            uuid: packet.client_uuid(),
+            arch: packet.client_arch(),
+            vendor_class: packet.vendor_class(),
+            hostname: packet.hostname(),
+            profile: self.select_boot(packet).profile.map(|x| x.name.clone()),
+            ..inventory::Client::new(packet.mac)
+        };
+        self.inventory.lock().unwrap().record(client, boot);
+    }
+
+    // inventory keeps address of client only while it is leased to it
+    fn forget_client_ip(&self, client_id: &ClientId, ip: Ipv4Addr) {
+        self.inventory.lock().unwrap().forget_ip(&client_id.mac, ip);
+    }
+
+    fn save_leases(&mut self) {
+        if !self.leases_changed || !self.lease_store.is_persistent() {
+            return;
+        }
+        self.leases_changed = false;
+
+        let now = Instant::now();
+        let lease_names = self.lease_names.lock().unwrap();
+        let leases = self
+            .leases
+            .iter()
+            .filter_map(|(&ip, (client_id, _, allocation_time, lease_duration))| {
+                let remaining = (*allocation_time + *lease_duration).saturating_duration_since(now);
+                Some(lease_file::StoredLease {
+                    hostname: lease_names.get(&ip).map(|x| x.hostname.clone()),
+                    ..lease_file::StoredLease::new(ip, client_id.mac, &client_id.ext, remaining)
+                })
+                .filter(|_| remaining > Duration::ZERO)
+            })
+            .collect();
+        drop(lease_names);
+        if let Err(e) = self.lease_store.save(leases) {
+            warn!("failed to save leases: {:#}", e);
+        }
+    }
+
+    // Expired entries are otherwise dropped only when their address is
+    // about to be given out again, so lease list and DNS names of clients
+    // that are gone would stay around forever.
+    fn reap(&mut self) {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.leases
+            .retain(|&ip, (client_id, _, allocation_time, lease_duration)| {
+                let keep = now.duration_since(*allocation_time) <= *lease_duration;
+                if !keep {
+                    expired.push((ip, client_id.clone()));
+                    info!("lease of {} to {} expired", ip, client_id);
+                }
+                keep
+            });
+        if !expired.is_empty() {
+            self.leases_changed = true;
+            let mut lease_names = self.lease_names.lock().unwrap();
+            for (ip, client_id) in expired.iter() {
+                let hostname = lease_names.remove(ip).map(|x| x.hostname);
+                self.lease_event(LeaseEvent::Expiry, *ip, client_id, hostname);
+                self.forget_client_ip(client_id, *ip);
+            }
+        }
+
+        let leases = &self.leases;
+        self.lease_conflicts.retain(|ip, _| leases.contains_key(ip));
+
+        self.expire_offers();
+        self.conflicts.retain(|ip, until| {
+            let keep = now < *until;
+            if !keep {
+                debug!("quarantine of {} is over", ip);
+            }
+            keep
+        });
+    }
+
+    fn update_lease_count(&self) {
+        let now = Instant::now();
+        let active = self
+            .leases
+            .values()
+            .filter(|(_, _, allocation_time, lease_duration)| {
+                now.duration_since(*allocation_time) <= *lease_duration
+            })
+            .count();
+        self.stats
+            .dhcp_leases
+            .store(active as u64, Ordering::Relaxed);
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Leases(reply) => {
+                self.expire_offers();
+                let now = Instant::now();
+                let lease_names = self.lease_names.lock().unwrap();
+                let leases = self
+                    .leases
+                    .iter()
+                    .map(
+                        |(&ip, (client_id, _, allocation_time, lease_duration))| Lease {
+                            ip,
+                            client: client_id.to_string(),
+                            mac: client_id.mac,
+                            remaining: Some(
+                                (*allocation_time + *lease_duration).saturating_duration_since(now),
+                            ),
+                            hostname: lease_names.get(&ip).map(|x| x.hostname.clone()),
+                            conflict: self.lease_conflicts.get(&ip).copied(),
+                        },
+                    )
+                    .chain(self.pending.iter().map(|(&ip, (client_id, _, _))| Lease {
+                        ip,
+                        client: client_id.to_string(),
+                        mac: client_id.mac,
+                        remaining: None,
+                        hostname: None,
+                        conflict: None,
+                    }))
+                    .collect();
+                let _ = reply.send(leases);
+            }
+            Command::Reservations(reply) => {
+                let _ = reply.send(self.config.reservations.clone());
+            }
+            Command::Expire(key, reply) => {
+                let before = self.leases.len() + self.pending.len();
+                for (&ip, (c, _, _, _)) in self.leases.iter() {
+                    if key.matches(&ip, c) {
+                        self.publish(ip, c, Duration::ZERO);
+                        let hostname = self
+                            .lease_names
+                            .lock()
+                            .unwrap()
+                            .get(&ip)
+                            .map(|x| x.hostname.clone());
+                        self.lease_event(LeaseEvent::Expiry, ip, c, hostname);
+                        self.forget_client_ip(c, ip);
                     }
-                });
+                }
+                self.leases.retain(|ip, (c, _, _, _)| !key.matches(ip, c));
+                self.leases_changed = true;
+                self.pending.retain(|ip, (c, _, _)| !key.matches(ip, c));
+                // conflict resolved by hand
+                let quarantined = self.conflicts.len();
+                self.conflicts
+                    .retain(|ip, _| !matches!(key, LeaseKey::Ip(x) if x == *ip));
+                let leases = &self.leases;
+                self.lease_names
+                    .lock()
+                    .unwrap()
+                    .retain(|ip, _| leases.contains_key(ip));
+                self.lease_conflicts.retain(|ip, _| leases.contains_key(ip));
+                let removed = before - self.leases.len() - self.pending.len()
+                    + (quarantined - self.conflicts.len());
+                info!("expired {} lease(s) on request", removed);
+                self.update_lease_count();
+                let _ = reply.send(removed);
+            }
+            Command::SetConfig(config) => {
+                info!("profiles and selectors reloaded");
+                self.config = *config;
+                if let Err(e) = self.mac_filter.reload() {
+                    warn!("failed to reload MAC lists: {:#}", e);
+                }
+            }
+            Command::Bindings(reply) => {
+                let now = Instant::now();
+                let bindings = self
+                    .leases
+                    .iter()
+                    .filter_map(|(&ip, (client_id, _, allocation_time, lease_duration))| {
+                        let remaining =
+                            (*allocation_time + *lease_duration).saturating_duration_since(now);
+                        Some(binding(ip, client_id, remaining)).filter(|x| x.remaining > 0)
+                    })
+                    .collect();
+                let _ = reply.send(bindings);
+            }
+            Command::Import(binding) => {
+                if let Err(e) = self.import(binding) {
+                    warn!("ignored binding from failover peer: {:#}", e);
+                }
+                self.update_lease_count();
+            }
+            #[cfg(target_os = "linux")]
+            Command::Conflict(ip, client_id, mac) => {
+                // lease may have ended meanwhile
+                if !matches!(self.leases.get(&ip), Some((c, _, _, _)) if c.is_same_client(&client_id))
+                {
+                    return;
+                }
+                warn!(
+                    "ADDRESS CONFLICT: {} bound to {} is also used by {}, quarantined for {}",
+                    ip,
+                    client_id,
+                    mac,
+                    humantime::format_duration(self.quarantine)
+                );
+                self.lease_conflicts.insert(ip, mac);
+                self.conflicts.insert(ip, Instant::now() + self.quarantine);
+            }
+        }
+    }
+
+    fn import(&mut self, binding: Binding) -> anyhow::Result<()> {
         let client_id = ClientId {
-            mac: packet.mac,
-            ext: client_id_opt,
+            mac: binding.mac.parse()?,
+            ext: binding.client_id,
         };
+        let ip = binding.ip;
+
+        if binding.remaining == 0 {
+            if matches!(self.leases.get(&ip), Some((c, _, _, _)) if c.is_same_client(&client_id)) {
+                debug!("{} released by failover peer", ip);
+                self.leases.remove(&ip);
+                self.leases_changed = true;
+            }
+            return Ok(());
+        }
+
+        // addresses from own half of pool are bound only by us
+        let own = self.pools.iter().any(|x| x.contains(ip));
+        let now = Instant::now();
+        if let Some((c, _, allocation_time, lease_duration)) = self.leases.get(&ip) {
+            if own
+                && !c.is_same_client(&client_id)
+                && now.duration_since(*allocation_time) <= *lease_duration
+            {
+                bail!("{} is bound to {}, not {}", ip, c, client_id);
+            }
+        }
+
+        debug!("{} bound to {} by failover peer", ip, client_id);
+        self.leases
+            .retain(|&x, (c, _, _, _)| x == ip || !c.is_same_client(&client_id));
+        self.pending
+            .retain(|&x, (c, _, _)| x != ip && !c.is_same_client(&client_id));
+        self.leases.insert(
+            ip,
+            (client_id, 0, now, Duration::from_secs(binding.remaining)),
+        );
+        self.leases_changed = true;
+        Ok(())
+    }
+
+    // nobody listens unless failover is configured
+    fn publish(&self, ip: Ipv4Addr, client_id: &ClientId, remaining: Duration) {
+        let _ = self.updates.send(binding(ip, client_id, remaining));
+    }
+
+    fn lease_event(
+        &self,
+        event: LeaseEvent,
+        ip: Ipv4Addr,
+        client_id: &ClientId,
+        hostname: Option<String>,
+    ) {
+        self.lease_events.send(
+            &self.config.hooks,
+            event,
+            client_id.mac,
+            ip,
+            hostname,
+            &client_id.ext,
+        );
+    }
+
+    async fn process_packet(&mut self, packet: Packet, socket: &Transport) -> anyhow::Result<()> {
+        if self.filter_packet(&packet) {
+            return Ok(());
+        }
+
+        let client_id = client_id(&packet);
+
+        if self.proxy {
+            return self.process_proxy_packet(&packet, &client_id, socket).await;
+        }
 
         match packet.options.get(&DHCP_MESSAGE_TYPE) {
             Some(DhcpOption::MessageType(_t @ MessageType::Discover)) => {
                 debug!("discover from {}", client_id);
-                self.offer_ip_address(&packet, &client_id, socket).await;
+                sessions::discovered(&self.sessions, packet.mac);
+                self.record_client(&packet, true, None);
+                if self.rapid_commit && packet.options.contains_key(&DHCP_RAPID_COMMIT) {
+                    self.commit_ip_address(&packet, &client_id, socket).await;
+                } else {
+                    self.offer_ip_address(&packet, &client_id, socket).await;
+                }
 
                 Ok(())
             }
+            Some(DhcpOption::MessageType(_t @ MessageType::Request))
+                if !packet.ciaddr.is_unspecified()
+                    && !packet.options.contains_key(&DHCP_REQUESTED_IP) =>
+            {
+                self.renew(&packet, &client_id, socket).await;
+                Ok(())
+            }
             Some(DhcpOption::MessageType(_t @ MessageType::Request)) => {
                 if let Some(DhcpOption::Ipv4Addr(requested_ip)) =
                     packet.options.get(&DHCP_REQUESTED_IP)
@@ -144,46 +980,23 @@ impl Server {
                         packet.options.get(&DHCP_SERVER_ID)
                     {
                         if *server_ip == self.server_ip {
-                            if let Some((c, _)) = self.pending.get(requested_ip) {
-                                if *c == client_id {
-                                    self.send_ack(
-                                        &socket,
-                                        &client_id,
-                                        packet.xid,
-                                        *requested_ip,
-                                        packet.mac,
-                                    )
-                                    .await;
-                                    self.pending.remove_entry(requested_ip);
-                                    self.leases.insert(
-                                        *requested_ip,
-                                        (
-                                            client_id.clone(),
-                                            packet.xid,
-                                            Instant::now(),
-                                            Duration::from_secs(Into::<u64>::into(
-                                                self.lease_duration_secs,
-                                            )),
-                                        ),
-                                    );
-                                    info!(
-                                        "{}/{} bound to {}",
-                                        requested_ip, self.subnet_mask_width, client_id
-                                    );
-                                } else {
-                                    self.send_nak(&socket, packet.xid, &client_id, packet.mac)
+                            if let Some((c, _, _)) = self.pending.get(requested_ip) {
+                                if c.is_same_client(&client_id) {
+                                    self.send_ack(&socket, &client_id, &packet, *requested_ip)
                                         .await;
+                                    self.bind(&packet, &client_id, *requested_ip, LeaseEvent::Ack);
+                                } else {
+                                    self.send_nak(&socket, &client_id, &packet).await;
                                 }
                             } else {
-                                self.send_nak(&socket, packet.xid, &client_id, packet.mac)
-                                    .await;
+                                self.send_nak(&socket, &client_id, &packet).await;
                             }
                         } else {
                             // client requests IP from another DHCP server
                             // this automatically declines our offer
                             // see RFC 2131 section 3.1.4
-                            if let Some((c, _)) = self.pending.get(requested_ip) {
-                                if *c == client_id {
+                            if let Some((c, _, _)) = self.pending.get(requested_ip) {
+                                if c.is_same_client(&client_id) {
                                     self.pending.remove(requested_ip);
                                 }
                             }
@@ -197,51 +1010,963 @@ impl Server {
                     bail!("missing/invalid requested ip option")
                 }
             }
+            Some(DhcpOption::MessageType(MessageType::Decline)) => {
+                self.decline(&packet, &client_id)
+            }
+            Some(DhcpOption::MessageType(MessageType::Release)) => {
+                self.release(&packet, &client_id);
+                Ok(())
+            }
+            Some(DhcpOption::MessageType(MessageType::Inform)) => {
+                debug!("inform from {} at {}", client_id, packet.ciaddr);
+                self.record_client(&packet, false, Some(packet.ciaddr));
+                self.send_inform_ack(socket, &client_id, &packet).await;
+                Ok(())
+            }
             Some(DhcpOption::MessageType(t)) => bail!("unhandled message type {}", t),
-            _ => bail!("message type not set"),
+            _ => {
+                self.process_bootp(&packet, &client_id, socket).await;
+                Ok(())
+            }
+        }
+    }
+
+    // Client in RENEWING or REBINDING state asks to extend lease of address
+    // it has in ciaddr (RFC 2131 section 4.3.2). Address moved to another
+    // client, out of served subnets or no longer meant for client because of
+    // reservation is NAKed, so that client starts over. Lease of other server
+    // is none of our business, rebinding client broadcasts to all of them.
+    async fn renew(&mut self, packet: &Packet, client_id: &ClientId, socket: &Transport) {
+        let ip = packet.ciaddr;
+        let now = Instant::now();
+        let (owner, expired) = match (self.leases.get(&ip), self.pending.get(&ip)) {
+            (Some((c, _, allocation_time, lease_duration)), _) => (
+                c.is_same_client(client_id),
+                now.duration_since(*allocation_time) > *lease_duration,
+            ),
+            // lease is gone and address offered to another client
+            (None, Some((c, _, _))) if !c.is_same_client(client_id) => (false, false),
+            _ => {
+                debug!("no lease of {} to renew for {}", ip, client_id);
+                return;
+            }
+        };
+
+        let reservations = &self.config.reservations;
+        let uuid = packet.client_uuid();
+        let uuid = uuid.as_deref();
+        let valid = owner
+            && !expired
+            && self.pool_of(ip).is_some()
+            && !reserved_for_other(reservations, ip, client_id, uuid)
+            && reservation(reservations, client_id, uuid)
+                .filter(|x| x.ip != ip && self.pool_of(x.ip).is_some())
+                .is_none();
+        if valid {
+            debug!("renewing lease of {} for {}", ip, client_id);
+            self.send_ack(socket, client_id, packet, ip).await;
+            self.bind(packet, client_id, ip, LeaseEvent::Renew);
+        } else {
+            match expired {
+                true => info!(
+                    "refused renewal of expired lease of {} for {}",
+                    ip, client_id
+                ),
+                false => info!("refused renewal of {} for {}", ip, client_id),
+            }
+            self.send_nak(socket, client_id, packet).await;
+        }
+    }
+
+    // Client gives up address in ciaddr, it gets no reply (RFC 2131
+    // section 4.4.6). Others cannot release it.
+    fn release(&mut self, packet: &Packet, client_id: &ClientId) {
+        let ip = packet.ciaddr;
+        if !matches!(self.leases.get(&ip), Some((c, _, _, _)) if c.is_same_client(client_id)) {
+            debug!("ignored release of {} not leased to {}", ip, client_id);
+            return;
+        }
+        self.leases.remove(&ip);
+        self.leases_changed = true;
+        self.publish(ip, client_id, Duration::ZERO);
+        let hostname = self
+            .lease_names
+            .lock()
+            .unwrap()
+            .remove(&ip)
+            .map(|x| x.hostname);
+        info!("{} released by {}", ip, client_id);
+        self.lease_event(LeaseEvent::Release, ip, client_id, hostname);
+        self.forget_client_ip(client_id, ip);
+    }
+
+    // Client found address it got already in use, typically by ARP probe
+    // (RFC 2131 section 3.1.5), it is most likely configured statically on
+    // another host. Only addresses given to declining client are taken, so
+    // that nobody can quarantine whole pool.
+    fn decline(&mut self, packet: &Packet, client_id: &ClientId) -> anyhow::Result<()> {
+        let ip = match packet.options.get(&DHCP_REQUESTED_IP) {
+            Some(DhcpOption::Ipv4Addr(ip)) => *ip,
+            _ => bail!("missing/invalid requested ip option"),
+        };
+        if !matches!(packet.options.get(&DHCP_SERVER_ID), Some(DhcpOption::Ipv4Addr(x)) if *x == self.server_ip)
+        {
+            return Ok(());
+        }
+
+        let pending =
+            matches!(self.pending.get(&ip), Some((c, _, _)) if c.is_same_client(client_id));
+        let leased =
+            matches!(self.leases.get(&ip), Some((c, _, _, _)) if c.is_same_client(client_id));
+        if !pending && !leased {
+            bail!("{} declined {} it does not hold", client_id, ip);
+        }
+        if pending {
+            self.pending.remove(&ip);
+        }
+        if leased {
+            self.leases.remove(&ip);
+            self.leases_changed = true;
+            self.publish(ip, client_id, Duration::ZERO);
+            self.forget_client_ip(client_id, ip);
+        }
+        self.lease_names.lock().unwrap().remove(&ip);
+
+        warn!(
+            "{} declined by {} as already in use, not offered for {}",
+            ip,
+            client_id,
+            humantime::format_duration(self.quarantine)
+        );
+        self.conflicts.insert(ip, Instant::now() + self.quarantine);
+        stats::incr(&self.stats.dhcp_declines);
+        Ok(())
+    }
+
+    // PXE clients collect offers, address is taken from main DHCP server
+    // and boot file from ours (PXE 2.1 section 2.2.1), everything else is
+    // left to main server
+    async fn process_proxy_packet(
+        &mut self,
+        packet: &Packet,
+        client_id: &ClientId,
+        socket: &Transport,
+    ) -> anyhow::Result<()> {
+        if !matches!(
+            packet.options.get(&DHCP_MESSAGE_TYPE),
+            Some(DhcpOption::MessageType(MessageType::Discover))
+        ) {
+            return Ok(());
+        }
+        if !matches!(packet.vendor_class(), Some(x) if x.starts_with("PXEClient")) {
+            debug!("ignoring {}, not a PXE client", client_id);
+            return Ok(());
+        }
+
+        debug!("discover from {}", client_id);
+        sessions::discovered(&self.sessions, packet.mac);
+        self.record_client(packet, true, None);
+
+        let boot = self.boot_params(packet).await;
+        let file = match boot.file.clone() {
+            Some(x) => x,
+            None => {
+                debug!("nothing to boot for {}", client_id);
+                return Ok(());
+            }
+        };
+
+        let mut options = BTreeMap::new();
+        options.insert(
+            DHCP_MESSAGE_TYPE,
+            DhcpOption::MessageType(MessageType::Offer),
+        );
+        options.insert(DHCP_SERVER_ID, DhcpOption::Ipv4Addr(self.server_ip));
+        options.insert(
+            DHCP_VENDOR_CLASS_IDENTIFIER,
+            DhcpOption::String("PXEClient".to_string()),
+        );
+        self.insert_vendor_options(&mut options, packet);
+        if let Some(uuid) = packet.options.get(&DHCP_CLIENT_MACHINE_IDENTIFIER) {
+            options.insert(DHCP_CLIENT_MACHINE_IDENTIFIER, uuid.clone());
+        }
+        options.insert(DHCP_BOOT_FILE_NAME, DhcpOption::String(file.clone()));
+        self.insert_boot_options(&mut options, &boot);
+        echo_relay_agent_info(&mut options, packet);
+
+        let offer_packet = Packet {
+            bootp_message_type: BootpMessageType::Reply,
+            htype: 1,
+            hlen: 6,
+            hops: 0,
+            xid: packet.xid,
+            secs: 0,
+            flags: packet.flags,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: boot.next_server,
+            giaddr: packet.giaddr,
+            mac: packet.mac,
+            server_name: Some("dhcp-pxe-server".to_string()),
+            boot_file_name: Some(file.clone()),
+            options,
+        };
+        info!("offering boot file {} to {}", file, client_id);
+        if let Err(e) = self.send_reply(socket, packet, offer_packet).await {
+            error!("failed to send offer to {}: {}", client_id, e);
+        } else {
+            stats::incr(&self.stats.dhcp_offers);
+        }
+
+        Ok(())
+    }
+
+    // After getting address PXE clients may ask boot server for boot file
+    // with REQUEST sent directly to port 4011 (PXE 2.1 section 2.2.4), ACK
+    // goes back to where request came from. Boot item from client option
+    // 43 names profile picked from boot menu and is echoed.
+    async fn process_boot_request(&mut self, data: &[u8], client: SocketAddr, socket: &UdpSocket) {
+        capture::udp(
+            client,
+            SocketAddr::from((self.server_ip, BOOT_SERVER_PORT)),
+            data,
+        );
+        let packet = match Packet::parse(data) {
+            Ok(x) if x.bootp_message_type == BootpMessageType::Request => x,
+            Ok(_) => return,
+            Err(e) => {
+                debug!("invalid boot server request from {}: {}", client, e);
+                return;
+            }
+        };
+        if !matches!(
+            packet.options.get(&DHCP_MESSAGE_TYPE),
+            Some(DhcpOption::MessageType(MessageType::Request))
+        ) || !matches!(packet.vendor_class(), Some(x) if x.starts_with("PXEClient"))
+        {
+            debug!("ignoring non-PXE boot server request from {}", client);
+            return;
+        }
+
+        // menu item picked by user, see insert_vendor_options
+        let item = match packet.options.get(&DHCP_VENDOR_SPECIFIC) {
+            Some(DhcpOption::ByteArray(x)) => VendorOptions::decode(x).boot_item,
+            _ => None,
+        };
+        let boot = match item.and_then(|(kind, _)| self.menu_profile(kind)) {
+            Some(profile) => {
+                info!("{} picked {} from boot menu", packet.mac, profile.name);
+                self.profile_boot(&packet, profile)
+            }
+            None => self.boot_params(&packet).await,
+        };
+        let file = match boot.file.clone() {
+            Some(x) => x,
+            None => {
+                debug!("nothing to boot for {}", packet.mac);
+                return;
+            }
+        };
+
+        let mut options = BTreeMap::new();
+        options.insert(DHCP_MESSAGE_TYPE, DhcpOption::MessageType(MessageType::Ack));
+        options.insert(DHCP_SERVER_ID, DhcpOption::Ipv4Addr(self.server_ip));
+        options.insert(
+            DHCP_VENDOR_CLASS_IDENTIFIER,
+            DhcpOption::String("PXEClient".to_string()),
+        );
+        if let Some(uuid) = packet.options.get(&DHCP_CLIENT_MACHINE_IDENTIFIER) {
+            options.insert(DHCP_CLIENT_MACHINE_IDENTIFIER, uuid.clone());
+        }
+        if item.is_some() {
+            let vendor_options = VendorOptions {
+                boot_item: item,
+                ..Default::default()
+            };
+            options.insert(DHCP_VENDOR_SPECIFIC, vendor_options.encode());
+        }
+        options.insert(DHCP_BOOT_FILE_NAME, DhcpOption::String(file.clone()));
+        self.insert_boot_options(&mut options, &boot);
+        echo_relay_agent_info(&mut options, &packet);
+
+        let mut reply = Packet {
+            bootp_message_type: BootpMessageType::Reply,
+            htype: 1,
+            hlen: 6,
+            hops: 0,
+            xid: packet.xid,
+            secs: 0,
+            flags: 0,
+            ciaddr: packet.ciaddr,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: boot.next_server,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            mac: packet.mac,
+            server_name: Some("dhcp-pxe-server".to_string()),
+            boot_file_name: Some(file.clone()),
+            options,
+        };
+        fit_reply(&packet, &mut reply);
+        let reply = reply.encode();
+
+        info!("boot server sends {} to {}", file, packet.mac);
+        capture::udp(
+            SocketAddr::from((self.server_ip, BOOT_SERVER_PORT)),
+            client,
+            &reply,
+        );
+        match socket.send_to(&reply, client).await {
+            Ok(_) => stats::incr(&self.stats.dhcp_acks),
+            Err(e) => error!("failed to send ACK to {}: {}", client, e),
+        }
+    }
+
+    fn select_boot(&self, packet: &Packet) -> BootParams<'_> {
+        let vendor_class = packet.vendor_class();
+        let relay = packet.relay_agent_info();
+
+        match self.config.select(
+            &packet.mac,
+            packet.client_uuid().as_deref(),
+            packet.client_arch(),
+            vendor_class.as_deref(),
+            relay.as_ref(),
+        ) {
+            Some((selector, profile)) => {
+                debug!("{} matched profile {}", packet.mac, profile.name);
+                let mut boot = self.profile_boot(packet, profile);
+                boot.options.extend(selector.options.iter());
+                boot
+            }
+            None => match self.config.vendor_class_policy(vendor_class.as_deref()) {
+                Some(policy) => {
+                    debug!("{} matched vendor class {}", packet.mac, policy.prefix);
+                    let file = policy
+                        .boot_file
+                        .clone()
+                        .or_else(|| self.loader(packet.client_arch()));
+                    BootParams {
+                        file: self.chainload(packet, file),
+                        next_server: self.next_server,
+                        options: policy.options.iter().collect(),
+                        root_path: None,
+                        profile: None,
+                    }
+                }
+                None => self.default_boot(packet),
+            },
+        }
+    }
+
+    // profile of pool or loader
+    fn default_boot(&self, packet: &Packet) -> BootParams<'_> {
+        match self
+            .pool(packet)
+            .profile
+            .as_deref()
+            .and_then(|x| self.config.profile(x))
+        {
+            Some(profile) => {
+                debug!("{} gets profile {} of pool", packet.mac, profile.name);
+                self.profile_boot(packet, profile)
+            }
+            None => BootParams {
+                file: self.chainload(packet, self.loader(packet.client_arch())),
+                next_server: self.next_server,
+                options: Vec::new(),
+                root_path: None,
+                profile: None,
+            },
+        }
+    }
+
+    fn profile_boot<'a>(&self, packet: &Packet, profile: &'a Profile) -> BootParams<'a> {
+        // BIOS clients of Secure Boot profile get regular boot file if any
+        let file = profile
+            .secure_boot
+            .as_ref()
+            .and_then(|x| x.boot_file(packet.client_arch()))
+            .or_else(|| Some(profile.boot_file.clone()).filter(|x| !x.is_empty()));
+        BootParams {
+            file: self.chainload(packet, file),
+            next_server: profile.next_server.unwrap_or(self.next_server),
+            options: profile.options.iter().collect(),
+            root_path: profile.root_path(self.server_ip, self.nbd_port),
+            profile: Some(profile),
+        }
+    }
+
+    // provisioning state of inventory takes precedence over selectors
+    fn marked_boot(&self, packet: &Packet) -> Option<BootParams<'_>> {
+        let (state, install_profile) = self
+            .inventory
+            .lock()
+            .unwrap()
+            .get(&packet.mac)
+            .map(|x| (x.state, x.install_profile.clone()))?;
+
+        match (state, install_profile) {
+            // no boot file makes most firmware move on to next boot device
+            (State::Provisioned, _) => {
+                info!("{} boots from local disk", packet.mac);
+                Some(BootParams {
+                    file: self.local_boot_file.clone(),
+                    next_server: self.next_server,
+                    options: Vec::new(),
+                    root_path: None,
+                    profile: None,
+                })
+            }
+            (State::Installing, Some(name)) => match self.config.profile(&name) {
+                Some(profile) => {
+                    info!("{} installing with profile {}", packet.mac, name);
+                    Some(self.profile_boot(packet, profile))
+                }
+                None => {
+                    warn!("install profile {} of {} does not exist", name, packet.mac);
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    // for clients not matched by any selector
+    fn loader(&self, arch: Option<u16>) -> Option<String> {
+        self.arch_loaders
+            .iter()
+            .find(|x| Some(x.arch) == arch)
+            .map(|x| x.file.clone())
+            .or_else(|| self.tftp_loader_path.clone())
+    }
+
+    // iPXE identifies itself with user class and gets its own boot file,
+    // typically script, instead of being handed loader it was started from
+    fn chainload(&self, packet: &Packet, file: Option<String>) -> Option<String> {
+        match &self.ipxe_boot_file {
+            Some(x) if file.is_some() && packet.is_ipxe() => {
+                debug!("{} runs iPXE, boot file {}", packet.mac, x);
+                Some(x.clone())
+            }
+            _ => file,
+        }
+    }
+
+    fn reserved_boot(&self, packet: &Packet) -> Option<BootParams<'_>> {
+        let uuid = packet.client_uuid();
+        let file = reservation(
+            &self.config.reservations,
+            &client_id(packet),
+            uuid.as_deref(),
+        )?
+        .boot_file
+        .clone()?;
+        debug!("{} has reserved boot file {}", packet.mac, file);
+        Some(BootParams {
+            file: self.chainload(packet, Some(file)),
+            next_server: self.next_server,
+            options: Vec::new(),
+            root_path: None,
+            profile: None,
+        })
+    }
+
+    // profile selection refined by boot file hook
+    async fn boot_params(&self, packet: &Packet) -> BootParams<'_> {
+        let mut boot = self
+            .marked_boot(packet)
+            .or_else(|| self.reserved_boot(packet))
+            .unwrap_or_else(|| self.select_boot(packet));
+        // root of host, then of profile and of its pool
+        let uuid = packet.client_uuid();
+        if let Some(root_path) = reservation(
+            &self.config.reservations,
+            &client_id(packet),
+            uuid.as_deref(),
+        )
+        .and_then(|x| x.root_path.clone())
+        {
+            boot.root_path = Some(root_path);
+        } else if boot.root_path.is_none() {
+            boot.root_path = self.pool(packet).root_path.clone();
+        }
+        boot.file = hooks::boot_file(
+            &self.config.hooks,
+            packet.mac,
+            packet.client_arch(),
+            packet.vendor_class().as_deref(),
+            boot.profile.map(|x| x.name.as_str()),
+            boot.file,
+        )
+        .await;
+        boot
+    }
+
+    // Boot menu lists profiles followed by local boot and is shown to PXE
+    // clients not marked in inventory. Picked profile is asked for from boot
+    // server on port 4011. Proxy without menu tells clients to boot file
+    // from its offer instead of looking for boot servers.
+    fn insert_vendor_options(&self, options: &mut BTreeMap<u8, DhcpOption>, packet: &Packet) {
+        if !matches!(packet.vendor_class(), Some(x) if x.starts_with("PXEClient")) {
+            return;
+        }
+        let marked = matches!(
+            self.inventory.lock().unwrap().get(&packet.mac),
+            Some(x) if x.state != State::Discovered
+        );
+
+        let vendor_options = match self.pxe_menu_timeout {
+            Some(timeout) if !marked && !self.config.profiles.is_empty() => {
+                let mut menu = Vec::new();
+                let mut boot_servers = Vec::new();
+                for (i, profile) in self.config.profiles.iter().enumerate() {
+                    let kind = pxe::VENDOR_TYPES + i as u16;
+                    menu.push((kind, profile.name.clone()));
+                    boot_servers.push((kind, vec![self.server_ip]));
+                }
+                menu.push((pxe::LOCAL_BOOT, "Boot from local disk".to_string()));
+                VendorOptions {
+                    discovery_control: Some(
+                        pxe::DISABLE_BROADCAST | pxe::DISABLE_MULTICAST | pxe::ONLY_LISTED_SERVERS,
+                    ),
+                    boot_servers,
+                    menu,
+                    prompt: Some((timeout, PXE_MENU_PROMPT.to_string())),
+                    boot_item: None,
+                }
+            }
+            _ if self.proxy => VendorOptions {
+                discovery_control: Some(pxe::USE_BOOT_FILE),
+                ..Default::default()
+            },
+            _ => return,
+        };
+        options.insert(DHCP_VENDOR_SPECIFIC, vendor_options.encode());
+    }
+
+    fn menu_profile(&self, kind: u16) -> Option<&Profile> {
+        let index = kind.checked_sub(pxe::VENDOR_TYPES)?;
+        self.config.profiles.get(index as usize)
+    }
+
+    // that of first vendor class matching client, also when boot file
+    // comes from selector
+    fn lease_duration_secs(&self, packet: &Packet) -> u32 {
+        self.config
+            .vendor_class_policy(packet.vendor_class().as_deref())
+            .and_then(|x| x.lease_time)
+            // validated to fit
+            .map_or(self.default_lease_secs, |x| x.get().as_secs() as u32)
+    }
+
+    // renewal and rebinding at defaults of RFC 2131 section 4.4.5, clients
+    // left to compute them on their own do not always do so
+    fn insert_lease_time(&self, options: &mut BTreeMap<u8, DhcpOption>, request_packet: &Packet) {
+        let lease_time = self.lease_duration_secs(request_packet);
+        options.insert(DHCP_LEASE_TIME, DhcpOption::U32(lease_time));
+        options.insert(DHCP_RENEWAL_TIME, DhcpOption::U32(lease_time / 2));
+        options.insert(
+            DHCP_REBINDING_TIME,
+            DhcpOption::U32((u64::from(lease_time) * 7 / 8) as u32),
+        );
+    }
+
+    // relay agent is gateway of its subnet unless pool says otherwise,
+    // clients on local segment get no router as before
+    fn insert_subnet_options(
+        &self,
+        options: &mut BTreeMap<u8, DhcpOption>,
+        pool: &Pool,
+        request_packet: &Packet,
+    ) {
+        options.insert(DHCP_SUBNET_MASK, DhcpOption::Ipv4Addr(pool.subnet.mask()));
+        let relay = Some(request_packet.giaddr).filter(|x| !x.is_unspecified());
+        if let Some(router) = pool.router.or(relay) {
+            options.insert(DHCP_ROUTER_IP, DhcpOption::Ipv4Addr(router));
+        }
+    }
+
+    fn insert_network_options(&self, options: &mut BTreeMap<u8, DhcpOption>) {
+        if let Some(mtu) = self.mtu {
+            options.insert(DHCP_MTU, DhcpOption::U16(mtu));
+        }
+        if !self.dns_servers.is_empty() {
+            options.insert(
+                DHCP_DNS_SERVER,
+                DhcpOption::Ipv4Addrs(self.dns_servers.clone()),
+            );
+        }
+        if let Some(domain_name) = self.domain_name.as_ref() {
+            options.insert(DHCP_DOMAIN_NAME, DhcpOption::String(domain_name.clone()));
+        }
+        if !self.domain_search.is_empty() {
+            options.insert(
+                DHCP_DOMAIN_SEARCH,
+                DhcpOption::DomainSearch(self.domain_search.clone()),
+            );
+        }
+        if !self.ntp_servers.is_empty() {
+            options.insert(
+                DHCP_NTP_SERVERS,
+                DhcpOption::Ipv4Addrs(self.ntp_servers.clone()),
+            );
+        }
+    }
+
+    fn insert_boot_options(&self, options: &mut BTreeMap<u8, DhcpOption>, boot: &BootParams) {
+        // some PXE clients need this, name stands for default next server only
+        let tftp_server = match &self.tftp_server_name {
+            Some(x) if boot.next_server == self.next_server => x.clone(),
+            _ => boot.next_server.to_string(),
+        };
+        options.insert(DHCP_TFTP_SERVER_NAME, DhcpOption::String(tftp_server));
+        if let Some(root_path) = boot.root_path.as_ref() {
+            options.insert(DHCP_ROOT_PATH, DhcpOption::String(root_path.clone()));
+        }
+
+        // validated with configuration
+        for option in self
+            .config
+            .options
+            .iter()
+            .chain(boot.options.iter().copied())
+        {
+            if let Ok(data) = option.data() {
+                options.insert(option.code, DhcpOption::ByteArray(data));
+            }
+        }
+    }
+
+    // server name some clients put in sname is ignored, as is option
+    // overload (RFC 2132 section 9.3) no client in practice sends
+    fn filter_packet(&self, packet: &Packet) -> bool {
+        // proxy has no pools, its relayed clients are left to main server
+        if !packet.giaddr.is_unspecified() && (self.proxy || self.pool_of(packet.giaddr).is_none())
+        {
+            warn!(
+                "filtered out packet relayed by {}, no pool for its subnet",
+                packet.giaddr
+            );
+            return true;
+        }
+
+        // silently, another DHCP server answers them
+        if self.pxe_only && !matches!(packet.vendor_class(), Some(x) if x.starts_with("PXEClient"))
+        {
+            debug!("ignoring {}, not a PXE client", packet.mac);
+            return true;
+        }
+
+        if !self.mac_filter.permits(&packet.mac) {
+            debug!("ignoring {}, not allowed by MAC lists", packet.mac);
+            return true;
+        }
+
+        if matches!(
+            self.inventory.lock().unwrap().get(&packet.mac),
+            Some(x) if x.state == State::Retired
+        ) {
+            debug!("ignoring retired {}", packet.mac);
+            return true;
+        }
+
+        false
+    }
+
+    // relayed requests from subnets without pool are filtered out
+    fn pool(&self, packet: &Packet) -> &Pool {
+        match packet.giaddr {
+            x if x.is_unspecified() => &self.pools[0],
+            x => self.pool_of(x).unwrap_or(&self.pools[0]),
+        }
+    }
+
+    fn pool_of(&self, ip: Ipv4Addr) -> Option<&Pool> {
+        self.pools.iter().find(|x| x.subnet.contains(ip))
+    }
+
+    fn mask_width(&self, ip: Ipv4Addr) -> u8 {
+        self.pool_of(ip)
+            .unwrap_or(&self.pools[0])
+            .subnet
+            .mask_width()
+    }
+
+    async fn offer_ip_address(
+        &mut self,
+        request_packet: &Packet,
+        client_id: &ClientId,
+        socket: &Transport,
+    ) {
+        let pool = self.pool(request_packet).clone();
+        let ip_to_offer = match self
+            .choose_ip_address(request_packet, client_id, &pool)
+            .await
+        {
+            Some(x) => x,
+            None => return,
+        };
+
+        info!(
+            "offering {}/{} to {}",
+            ip_to_offer,
+            pool.subnet.mask_width(),
+            client_id
+        );
+        self.pending.insert(
+            ip_to_offer,
+            (client_id.clone(), request_packet.xid, Instant::now()),
+        );
+
+        let boot = self.boot_params(request_packet).await;
+        sessions::offered(
+            &self.sessions,
+            request_packet.mac,
+            ip_to_offer,
+            boot.profile.map(|x| x.name.as_str()),
+            boot.file.as_deref(),
+            boot.profile.and_then(|x| x.kernel.as_deref()),
+        );
+
+        let mut options = BTreeMap::new();
+        options.insert(
+            DHCP_MESSAGE_TYPE,
+            DhcpOption::MessageType(MessageType::Offer),
+        );
+        self.insert_subnet_options(&mut options, &pool, request_packet);
+        options.insert(DHCP_SERVER_ID, DhcpOption::Ipv4Addr(self.server_ip));
+        self.insert_lease_time(&mut options, request_packet);
+        self.insert_network_options(&mut options);
+        self.insert_vendor_options(&mut options, request_packet);
+        self.insert_boot_options(&mut options, &boot);
+        echo_relay_agent_info(&mut options, request_packet);
+
+        let offer_packet = Packet {
+            bootp_message_type: BootpMessageType::Reply,
+            htype: 1,
+            hlen: 6,
+            hops: 0,
+            xid: request_packet.xid,
+            secs: 0,
+            flags: request_packet.flags,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: ip_to_offer,
+            siaddr: boot.next_server,
+            giaddr: request_packet.giaddr,
+            mac: request_packet.mac,
+            // FIXME
+            server_name: Some("dhcp-pxe-server".to_string()),
+            boot_file_name: boot.file.clone(),
+            options,
+        };
+        if let Err(e) = self.send_reply(socket, request_packet, offer_packet).await {
+            error!("failed to send offer to {}: {}", client_id, e);
+        } else {
+            stats::incr(&self.stats.dhcp_offers);
+            self.lease_event(
+                LeaseEvent::Offer,
+                ip_to_offer,
+                client_id,
+                request_packet.hostname(),
+            );
         }
     }
 
-    fn filter_packet(&self, packet: &Packet) -> bool {
-        if packet.server_name.is_some() {
-            todo!();
+    // Plain BOOTP client (RFC 951) sends single request without message
+    // type and takes address from reply, it never confirms nor renews it.
+    // Address is bound right away for lease time as if client got ACK,
+    // same client asking again gets it back.
+    async fn process_bootp(
+        &mut self,
+        request_packet: &Packet,
+        client_id: &ClientId,
+        socket: &Transport,
+    ) {
+        debug!("BOOTP request from {}", client_id);
+        self.record_client(request_packet, true, None);
+        let pool = self.pool(request_packet).clone();
+        let ip = match self
+            .choose_ip_address(request_packet, client_id, &pool)
+            .await
+        {
+            Some(x) => x,
+            None => return,
+        };
+
+        // BOOTP clients never renew, their addresses are allocated
+        // permanently (RFC 1534 section 2)
+        self.bind_for(
+            request_packet,
+            client_id,
+            ip,
+            LeaseEvent::Ack,
+            INFINITE_LEASE,
+        );
+
+        // vendor extensions (RFC 1497) without DHCP only options
+        let boot = self.boot_params(request_packet).await;
+        let mut options = BTreeMap::new();
+        self.insert_subnet_options(&mut options, &pool, request_packet);
+        self.insert_network_options(&mut options);
+        self.insert_boot_options(&mut options, &boot);
+
+        let packet = Packet {
+            bootp_message_type: BootpMessageType::Reply,
+            htype: 1,
+            hlen: 6,
+            hops: 0,
+            xid: request_packet.xid,
+            secs: 0,
+            flags: request_packet.flags,
+            ciaddr: request_packet.ciaddr,
+            yiaddr: ip,
+            siaddr: boot.next_server,
+            giaddr: request_packet.giaddr,
+            mac: request_packet.mac,
+            server_name: Some("dhcp-pxe-server".to_string()),
+            boot_file_name: boot.file.clone(),
+            options,
+        };
+        if let Err(e) = self.send_reply(socket, request_packet, packet).await {
+            error!("failed to send BOOTP reply to {}: {}", client_id, e);
+        } else {
+            stats::incr(&self.stats.dhcp_acks);
         }
+    }
 
-        if packet.giaddr != Ipv4Addr::new(0, 0, 0, 0) {
-            // we don't support relay agents
-            // all clients must be in same subnet
-            warn!("filtered out packet with non-null relay agent address");
-            return true;
+    // DISCOVER with rapid commit option is answered with ACK right away,
+    // there is no OFFER nor REQUEST (RFC 4039 section 3)
+    async fn commit_ip_address(
+        &mut self,
+        request_packet: &Packet,
+        client_id: &ClientId,
+        socket: &Transport,
+    ) {
+        let pool = self.pool(request_packet).clone();
+        let ip = match self
+            .choose_ip_address(request_packet, client_id, &pool)
+            .await
+        {
+            Some(x) => x,
+            None => return,
+        };
+        debug!("rapid commit of {} to {}", ip, client_id);
+        self.send_ack(socket, client_id, request_packet, ip).await;
+        self.bind(request_packet, client_id, ip, LeaseEvent::Ack);
+    }
+
+    // lease of acknowledged or renewed address starts
+    fn bind(&mut self, packet: &Packet, client_id: &ClientId, ip: Ipv4Addr, event: LeaseEvent) {
+        let lease_duration = Duration::from_secs(self.lease_duration_secs(packet).into());
+        self.bind_for(packet, client_id, ip, event, lease_duration);
+    }
+
+    fn bind_for(
+        &mut self,
+        packet: &Packet,
+        client_id: &ClientId,
+        ip: Ipv4Addr,
+        event: LeaseEvent,
+        lease_duration: Duration,
+    ) {
+        self.pending.remove(&ip);
+        self.leases.insert(
+            ip,
+            (
+                client_id.clone(),
+                packet.xid,
+                Instant::now(),
+                lease_duration,
+            ),
+        );
+        self.leases_changed = true;
+        self.publish(ip, client_id, lease_duration);
+        self.update_lease_name(packet, ip);
+        sessions::acked(&self.sessions, packet.mac, ip);
+        self.record_client(packet, false, Some(ip));
+        info!(
+            "{}/{} bound to {}{}{}",
+            ip,
+            self.mask_width(ip),
+            client_id,
+            packet
+                .hostname()
+                .map_or(String::new(), |x| format!(" ({})", x)),
+            packet
+                .client_uuid()
+                .map_or(String::new(), |x| format!(", UUID {}", x))
+        );
+        self.lease_event(event, ip, client_id, packet.hostname());
+        // relayed clients are not on our link
+        #[cfg(target_os = "linux")]
+        if event == LeaseEvent::Ack && packet.giaddr.is_unspecified() {
+            self.check_conflict(ip, client_id);
         }
+    }
 
-        false
+    // Server goes on meanwhile, conflict comes back as command. Lease is
+    // kept, client most likely uses address already, but it is quarantined
+    // so that nobody gets it after client.
+    #[cfg(target_os = "linux")]
+    fn check_conflict(&self, ip: Ipv4Addr, client_id: &ClientId) {
+        let (interface, timeout) = match self.arp_check.clone() {
+            Some(x) => x,
+            None => return,
+        };
+        let (client_id, commands) = (client_id.clone(), self.commands.clone());
+        tokio::spawn(async move {
+            match arp::other_owner(&interface, ip, &client_id.mac, timeout).await {
+                Ok(Some(mac)) => {
+                    let _ = commands.send(Command::Conflict(ip, client_id, mac)).await;
+                }
+                Ok(None) => debug!("nobody else answered ARP for {}", ip),
+                Err(e) => warn!("failed to check {} over ARP: {}", ip, e),
+            }
+        });
     }
 
-    async fn offer_ip_address(
+    // address for client asking for one, previously offered or leased one
+    // is given again, lease hook may refuse it
+    async fn choose_ip_address(
         &mut self,
         request_packet: &Packet,
         client_id: &ClientId,
-        socket: &UdpSocket,
-    ) {
+        pool: &Pool,
+    ) -> Option<Ipv4Addr> {
         let mut ip_to_offer: Option<Ipv4Addr>;
+        // client that got reservation after its address moves to reserved one,
+        // address reserved since being given out is taken back, as is one
+        // from subnet client is no longer in
+        let reservations = &self.config.reservations;
+        let uuid = request_packet.client_uuid();
+        let uuid = uuid.as_deref();
+        let reserved = reservation(reservations, client_id, uuid)
+            .map(|x| x.ip)
+            .filter(|x| pool.subnet.contains(*x));
+        let keep = |ip: &Ipv4Addr| {
+            (reserved.is_none() || reserved == Some(*ip))
+                && !reserved_for_other(reservations, *ip, client_id, uuid)
+                && pool.subnet.contains(*ip)
+        };
 
         // if same client sends multiple discover message offer same IP as before
         ip_to_offer = self
             .pending
             .iter()
-            .find(|(_, (c, _))| c == client_id)
+            .find(|(ip, (c, _, _))| c.is_same_client(client_id) && keep(ip))
             .map(|(&ip, _)| ip);
 
         if ip_to_offer.is_none() {
             for (&ip, (_, _, allocation_time, lease_duration)) in self
                 .leases
                 .iter_mut()
-                .filter(|(_, (cid, _, _, _))| cid == client_id)
+                .filter(|(ip, (cid, _, _, _))| cid.is_same_client(client_id) && keep(ip))
             {
                 // if lease expired extend it
                 let now = Instant::now();
                 if now.duration_since(*allocation_time) > *lease_duration {
                     *allocation_time = now;
+                    self.leases_changed = true;
                 }
 
                 ip_to_offer = Some(ip);
@@ -249,81 +1974,104 @@ impl Server {
         }
 
         if ip_to_offer.is_none() {
-            ip_to_offer = self.find_free_ip_address(client_id);
+            ip_to_offer = self.find_unused_ip_address(client_id, uuid, pool).await;
         }
 
-        if let Some(ip_to_offer) = ip_to_offer {
-            info!(
-                "offering {}/{} to {}",
-                ip_to_offer, self.subnet_mask_width, client_id
-            );
-            self.pending
-                .insert(ip_to_offer, (client_id.clone(), request_packet.xid));
+        let ip_to_offer = match ip_to_offer {
+            Some(x) => x,
+            None => {
+                warn!(
+                    "no more IP addresses available, cannot offer IP to {}",
+                    client_id
+                );
+                return None;
+            }
+        };
 
-            let mut options = BTreeMap::new();
-            options.insert(
-                DHCP_MESSAGE_TYPE,
-                DhcpOption::MessageType(MessageType::Offer),
-            );
-            options.insert(DHCP_SUBNET_MASK, DhcpOption::Ipv4Addr(self.subnet_mask));
-            options.insert(DHCP_SERVER_ID, DhcpOption::Ipv4Addr(self.server_ip));
-            //options.insert(DHCP_ROUTER_IP, DhcpOption::RouterIp(self.server_ip));
-            options.insert(DHCP_LEASE_TIME, DhcpOption::U32(self.lease_duration_secs));
-            // some PXE clients need this
-            options.insert(
-                DHCP_TFTP_SERVER_NAME,
-                DhcpOption::String(self.server_ip.to_string()),
-            );
-            if let Some(mtu) = self.mtu {
-                options.insert(DHCP_MTU, DhcpOption::U16(mtu));
-            }
-
-            let offer_packet = Packet {
-                bootp_message_type: BootpMessageType::Reply,
-                htype: 1,
-                hlen: 6,
-                hops: 0,
-                xid: request_packet.xid,
-                secs: 0,
-                flags: 0,
-                ciaddr: Ipv4Addr::UNSPECIFIED,
-                yiaddr: ip_to_offer,
-                siaddr: self.server_ip,
-                giaddr: Ipv4Addr::UNSPECIFIED,
-                mac: request_packet.mac,
-                // FIXME
-                server_name: Some("dhcp-pxe-server".to_string()),
-                boot_file_name: Some(self.tftp_loader_path.clone()),
-                options,
-            };
-            if let Err(e) = socket
-                .send_to(offer_packet.encode().as_slice(), (self.broadcast_ip, 68))
-                .await
-            {
-                error!("failed to send offer to {}: {}", client_id, e);
+        if !hooks::allow_lease(
+            &self.config.hooks,
+            request_packet.mac,
+            ip_to_offer,
+            request_packet.hostname().as_deref(),
+        )
+        .await
+        {
+            info!("lease hook denied {} to {}", ip_to_offer, client_id);
+            return None;
+        }
+
+        Some(ip_to_offer)
+    }
+
+    // Address answering probe is quarantined like declined one and next
+    // free one is tried. Server waits for each probe, so only few are made
+    // before client gives up on DISCOVER.
+    async fn find_unused_ip_address(
+        &mut self,
+        client_id: &ClientId,
+        uuid: Option<&str>,
+        pool: &Pool,
+    ) -> Option<Ipv4Addr> {
+        let timeout = match self.probe_timeout {
+            Some(x) => x,
+            None => return self.find_free_ip_address(client_id, uuid, pool),
+        };
+        for _ in 0..MAX_PROBES {
+            let ip = self.find_free_ip_address(client_id, uuid, pool)?;
+            match probe::in_use(ip, timeout).await {
+                Ok(false) => return Some(ip),
+                Ok(true) => {
+                    warn!(
+                        "{} answered probe, not offered for {}",
+                        ip,
+                        humantime::format_duration(self.quarantine)
+                    );
+                    self.conflicts.insert(ip, Instant::now() + self.quarantine);
+                }
+                Err(e) => {
+                    warn!("failed to probe {}: {}", ip, e);
+                    return Some(ip);
+                }
             }
-        } else {
-            warn!(
-                "no more IP addresses available, cannot offer IP to {}",
-                client_id
-            )
         }
+        None
     }
 
-    fn find_free_ip_address(&mut self, client_id: &ClientId) -> Option<Ipv4Addr> {
-        for n in self.ip_range_start..=self.ip_range_end {
-            let mut ip = Into::<u32>::into(self.subnet);
-            ip |= n;
-            let ip = Into::<Ipv4Addr>::into(ip);
+    fn find_free_ip_address(
+        &mut self,
+        client_id: &ClientId,
+        uuid: Option<&str>,
+        pool: &Pool,
+    ) -> Option<Ipv4Addr> {
+        self.expire_offers();
+        if let Some(ip) = reservation(&self.config.reservations, client_id, uuid)
+            .map(|x| x.ip)
+            .filter(|x| pool.subnet.contains(*x))
+        {
             if self.is_ip_available(ip, client_id) {
                 return Some(ip);
             }
+            // given out before reservation was made, falls back to range
+            // until current holder lets it go
+            warn!("{} reserved for {} is still in use", ip, client_id);
         }
 
-        None
+        let offset = self.allocation.offset(client_id, pool.size());
+        pool.addresses_from(offset).find(|&ip| {
+            !reserved_for_other(&self.config.reservations, ip, client_id, uuid)
+                && self.is_ip_available(ip, client_id)
+        })
     }
 
     fn is_ip_available(&mut self, ip: Ipv4Addr, _client_id: &ClientId) -> bool {
+        if let Some(until) = self.conflicts.get(&ip) {
+            if Instant::now() < *until {
+                return false;
+            }
+            debug!("quarantine of {} is over", ip);
+            self.conflicts.remove(&ip);
+        }
+
         if let Some((_, _, allocation_time, lease_duration)) = self.leases.get(&ip) {
             if Instant::now().duration_since(*allocation_time) > *lease_duration {
                 self.leases.remove(&ip);
@@ -331,110 +2079,657 @@ impl Server {
             } else {
                 false
             }
-        } else if let Some((_c, _)) = self.pending.get(&ip) {
-            // FIXME: pending requests never expire
-            false
         } else {
-            true
+            !self.pending.contains_key(&ip)
+        }
+    }
+
+    // client that got offer and never asked for it releases address
+    fn expire_offers(&mut self) {
+        let now = Instant::now();
+        let timeout = self.offer_timeout;
+        self.pending.retain(|ip, (c, _, offered)| {
+            let keep = now.duration_since(*offered) <= timeout;
+            if !keep {
+                debug!("offer of {} to {} expired", ip, c);
+            }
+            keep
+        });
+    }
+
+    async fn send_reply(
+        &self,
+        socket: &Transport,
+        request: &Packet,
+        mut reply: Packet,
+    ) -> std::io::Result<()> {
+        let relayed = !request.giaddr.is_unspecified();
+        let destination = self.reply_destination(request, &reply);
+        let mac = Some(request.mac)
+            .filter(|_| self.unicast_replies && !relayed && destination != self.broadcast_ip);
+
+        fit_reply(request, &mut reply);
+        let data = reply.encode();
+        let port = if relayed { SERVER_PORT } else { CLIENT_PORT };
+        let destination = SocketAddrV4::new(destination, port);
+        capture::udp(
+            SocketAddr::from((self.server_ip, SERVER_PORT)),
+            SocketAddr::V4(destination),
+            &data,
+        );
+        socket.send_to(&data, destination, mac.as_ref()).await
+    }
+
+    // Relayed requests are answered through relay agent on server port. NAK
+    // is always broadcast as client may have moved to another subnet.
+    // Client that has address already gets OFFER and ACK sent straight to
+    // it, one that has not gets broadcast when it asks for it (RFC 2131
+    // section 4.1). Otherwise client can only be reached at its MAC, which
+    // needs packet socket, and gets broadcast too without it.
+    fn reply_destination(&self, request: &Packet, reply: &Packet) -> Ipv4Addr {
+        let nak = matches!(
+            reply.options.get(&DHCP_MESSAGE_TYPE),
+            Some(DhcpOption::MessageType(MessageType::Nak))
+        );
+        if !request.giaddr.is_unspecified() {
+            request.giaddr
+        } else if nak {
+            self.broadcast_ip
+        } else if !request.ciaddr.is_unspecified() {
+            request.ciaddr
+        } else if request.wants_broadcast() {
+            self.broadcast_ip
+        } else if self.unicast_replies && !reply.yiaddr.is_unspecified() {
+            reply.yiaddr
+        } else {
+            self.broadcast_ip
         }
     }
 
-    async fn send_nak(&self, socket: &UdpSocket, xid: u32, client_id: &ClientId, mac: Mac) {
+    async fn send_nak(&self, socket: &Transport, client_id: &ClientId, request_packet: &Packet) {
         let mut options = BTreeMap::new();
         options.insert(DHCP_MESSAGE_TYPE, DhcpOption::MessageType(MessageType::Nak));
+        echo_relay_agent_info(&mut options, request_packet);
 
         let packet = Packet {
             bootp_message_type: BootpMessageType::Reply,
             htype: 1,
             hlen: 6,
             hops: 0,
-            xid,
+            xid: request_packet.xid,
             secs: 0,
-            flags: 0,
+            // relay broadcasts it on client segment (RFC 2131 section 4.3.2)
+            flags: match request_packet.giaddr.is_unspecified() {
+                true => request_packet.flags,
+                false => request_packet.flags | BROADCAST_FLAG,
+            },
             ciaddr: Ipv4Addr::UNSPECIFIED,
             yiaddr: Ipv4Addr::UNSPECIFIED,
             siaddr: self.server_ip,
-            giaddr: Ipv4Addr::UNSPECIFIED,
-            mac,
+            giaddr: request_packet.giaddr,
+            mac: request_packet.mac,
             // FIXME
             server_name: Some("dhcp-pxe-server".to_string()),
             boot_file_name: Some("BOOT.COM".to_string()),
             options,
         };
-        if let Err(e) = socket
-            .send_to(packet.encode().as_slice(), (self.broadcast_ip, 68))
-            .await
-        {
+        if let Err(e) = self.send_reply(socket, request_packet, packet).await {
             error!("failed to send NAK to {}: {}", client_id, e);
+        } else {
+            stats::incr(&self.stats.dhcp_naks);
         }
     }
 
     async fn send_ack(
         &self,
-        socket: &UdpSocket,
+        socket: &Transport,
         client_id: &ClientId,
-        xid: u32,
+        request_packet: &Packet,
         ip_address: Ipv4Addr,
-        mac: Mac,
     ) {
+        let boot = self.boot_params(request_packet).await;
+        let pool = self.pool_of(ip_address).unwrap_or(&self.pools[0]);
+
         let mut options = BTreeMap::new();
         options.insert(DHCP_MESSAGE_TYPE, DhcpOption::MessageType(MessageType::Ack));
-        options.insert(DHCP_SUBNET_MASK, DhcpOption::Ipv4Addr(self.subnet_mask));
-        options.insert(DHCP_SERVER_ID, DhcpOption::Ipv4Addr(self.server_ip));
-        options.insert(DHCP_LEASE_TIME, DhcpOption::U32(self.lease_duration_secs));
-        // some PXE clients need this
-        options.insert(
-            DHCP_TFTP_SERVER_NAME,
-            DhcpOption::String(self.server_ip.to_string()),
-        );
-        if let Some(mtu) = self.mtu {
-            options.insert(DHCP_MTU, DhcpOption::U16(mtu));
+        // only rapid commit acknowledges DISCOVER
+        if matches!(
+            request_packet.options.get(&DHCP_MESSAGE_TYPE),
+            Some(DhcpOption::MessageType(MessageType::Discover))
+        ) {
+            options.insert(DHCP_RAPID_COMMIT, DhcpOption::ByteArray(Vec::new()));
         }
+        self.insert_subnet_options(&mut options, pool, request_packet);
+        options.insert(DHCP_SERVER_ID, DhcpOption::Ipv4Addr(self.server_ip));
+        self.insert_lease_time(&mut options, request_packet);
+        self.insert_network_options(&mut options);
+        self.insert_vendor_options(&mut options, request_packet);
+        self.insert_boot_options(&mut options, &boot);
+        echo_relay_agent_info(&mut options, request_packet);
 
         let packet = Packet {
             bootp_message_type: BootpMessageType::Reply,
             htype: 1,
             hlen: 6,
             hops: 0,
-            xid,
+            xid: request_packet.xid,
             secs: 0,
-            flags: 0,
-            ciaddr: Ipv4Addr::UNSPECIFIED,
+            flags: request_packet.flags,
+            ciaddr: request_packet.ciaddr,
             yiaddr: ip_address,
-            siaddr: self.server_ip,
-            giaddr: Ipv4Addr::UNSPECIFIED,
-            mac,
+            siaddr: boot.next_server,
+            giaddr: request_packet.giaddr,
+            mac: request_packet.mac,
             // TODO
             server_name: Some("dhcp-pxe-server".to_string()),
-            boot_file_name: Some(self.tftp_loader_path.clone()),
+            boot_file_name: boot.file.clone(),
             options,
         };
-        if let Err(e) = socket
-            .send_to(packet.encode().as_slice(), (self.broadcast_ip, 68))
-            .await
-        {
+        if let Err(e) = self.send_reply(socket, request_packet, packet).await {
+            error!("failed to send ACK to {}: {}", client_id, e);
+        } else {
+            stats::incr(&self.stats.dhcp_acks);
+        }
+    }
+
+    // Client configured statically asks for everything but address, no lease
+    // is made and lease time is left out (RFC 2131 section 4.3.5). Reply goes
+    // straight to address client already has.
+    async fn send_inform_ack(
+        &self,
+        socket: &Transport,
+        client_id: &ClientId,
+        request_packet: &Packet,
+    ) {
+        let boot = self.boot_params(request_packet).await;
+
+        let mut options = BTreeMap::new();
+        options.insert(DHCP_MESSAGE_TYPE, DhcpOption::MessageType(MessageType::Ack));
+        self.insert_subnet_options(&mut options, self.pool(request_packet), request_packet);
+        options.insert(DHCP_SERVER_ID, DhcpOption::Ipv4Addr(self.server_ip));
+        self.insert_network_options(&mut options);
+        self.insert_vendor_options(&mut options, request_packet);
+        self.insert_boot_options(&mut options, &boot);
+        echo_relay_agent_info(&mut options, request_packet);
+
+        let packet = Packet {
+            bootp_message_type: BootpMessageType::Reply,
+            htype: 1,
+            hlen: 6,
+            hops: 0,
+            xid: request_packet.xid,
+            secs: 0,
+            flags: request_packet.flags,
+            ciaddr: request_packet.ciaddr,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: boot.next_server,
+            giaddr: request_packet.giaddr,
+            mac: request_packet.mac,
+            server_name: Some("dhcp-pxe-server".to_string()),
+            boot_file_name: boot.file.clone(),
+            options,
+        };
+        if let Err(e) = self.send_reply(socket, request_packet, packet).await {
             error!("failed to send ACK to {}: {}", client_id, e);
+        } else {
+            stats::incr(&self.stats.dhcp_acks);
         }
     }
 }
 
+fn client_id(packet: &Packet) -> ClientId {
+    let ext = match packet.options.get(&DHCP_CLIENT_IDENTIFIER) {
+        Some(DhcpOption::ByteArray(x)) => x.clone(),
+        _ => Vec::new(),
+    };
+    ClientId {
+        mac: packet.mac,
+        ext,
+    }
+}
+
+// uuid is that of option 97, see Packet::client_uuid
+fn reservation<'a>(
+    reservations: &'a [Reservation],
+    client_id: &ClientId,
+    uuid: Option<&str>,
+) -> Option<&'a Reservation> {
+    reservations
+        .iter()
+        .find(|x| x.matches(&client_id.mac, &client_id.ext, uuid))
+}
+
+fn reserved_for_other(
+    reservations: &[Reservation],
+    ip: Ipv4Addr,
+    client_id: &ClientId,
+    uuid: Option<&str>,
+) -> bool {
+    reservations
+        .iter()
+        .any(|x| x.ip == ip && !x.matches(&client_id.mac, &client_id.ext, uuid))
+}
+
+// client that limits message size (option 57) gets reply without options
+// it can live without rather than one it would drop
+fn fit_reply(request: &Packet, reply: &mut Packet) {
+    let max_size = match request.max_message_size() {
+        Some(x) => x,
+        None => return,
+    };
+    let dropped = reply.trim_options(max_size);
+    if !dropped.is_empty() {
+        debug!(
+            "dropped options {:?} from reply to {}, limit {} bytes",
+            dropped, request.mac, max_size
+        );
+    }
+    if reply.encoded_len() > max_size {
+        warn!(
+            "reply to {} exceeds {} bytes it accepts",
+            request.mac, max_size
+        );
+    }
+}
+
+// relay agent information goes back as it came (RFC 3046 section 2.2)
+fn echo_relay_agent_info(options: &mut BTreeMap<u8, DhcpOption>, request_packet: &Packet) {
+    if let Some(info) = request_packet.options.get(&DHCP_RELAY_AGENT_INFORMATION) {
+        options.insert(DHCP_RELAY_AGENT_INFORMATION, info.clone());
+    }
+}
+
+fn binding(ip: Ipv4Addr, client_id: &ClientId, remaining: Duration) -> Binding {
+    Binding {
+        ip,
+        mac: client_id.mac.to_string(),
+        client_id: client_id.ext.clone(),
+        remaining: remaining.as_secs(),
+    }
+}
+
+// never completes without socket
+async fn recv_optional(
+    socket: Option<&UdpSocket>,
+    buf: &mut [u8],
+) -> Option<std::io::Result<(usize, SocketAddr)>> {
+    match socket {
+        Some(socket) => Some(socket.recv_from(buf).await),
+        None => futures_util::future::pending().await,
+    }
+}
+
 struct PacketStream<'a> {
-    socket: &'a UdpSocket,
+    socket: &'a Transport,
+    buf: Vec<MaybeUninit<u8>>,
 }
 
 impl<'a> Stream for PacketStream<'a> {
     type Item = Result<Packet>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut buf: [MaybeUninit<u8>; MAX_PACKET_SIZE] = [MaybeUninit::uninit(); MAX_PACKET_SIZE];
-        let mut rb = ReadBuf::uninit(&mut buf);
+        let this = self.get_mut();
+        let mut rb = ReadBuf::uninit(&mut this.buf);
 
-        match self.socket.poll_recv(cx, &mut rb) {
+        match this.socket.poll_recv(cx, &mut rb) {
             Poll::Pending => Poll::Pending,
             Poll::Ready(Ok(())) => {
-                Poll::Ready(Some(Packet::parse(rb.filled()).map_err(|e| Error::from(e))))
+                let data = rb.filled();
+                // requests are broadcast, renewing clients send from their address
+                let client_ip = data.get(12..16).map_or(Ipv4Addr::UNSPECIFIED, |x| {
+                    Ipv4Addr::new(x[0], x[1], x[2], x[3])
+                });
+                capture::udp(
+                    SocketAddr::from((client_ip, CLIENT_PORT)),
+                    SocketAddr::from((Ipv4Addr::BROADCAST, SERVER_PORT)),
+                    data,
+                );
+                Poll::Ready(Some(Packet::parse(data).map_err(|e| Error::from(e))))
             }
             Poll::Ready(Err(x)) => Poll::Ready(Some(Err(Error::from(x)))),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Clap;
+
+    const CLIENT: &str = "52:54:00:12:34:56";
+
+    fn server(args: &[&str]) -> Server {
+        let mut options = crate::Options::parse_from(
+            [
+                "pxe",
+                "--server-ip",
+                "10.0.0.1",
+                "--dhcp-range",
+                "10.0.0.100-10.0.0.150/24",
+            ]
+            .iter()
+            .chain(args),
+        );
+        let range = options.dhcp_range.unwrap();
+        options.dhcp_ip_start = Some(range.start());
+        options.dhcp_ip_end = Some(range.end());
+        options.dhcp_subnet = Some(range.subnet());
+        new_server(
+            &options,
+            &Handle::new(),
+            &Arc::default(),
+            &LeaseNames::default(),
+            &Sessions::default(),
+            &Inventory::default(),
+        )
+        .unwrap()
+    }
+
+    fn socket() -> Transport {
+        Transport::Recorded(Default::default())
+    }
+
+    // replies sent since last call with their destination
+    fn sent(socket: &Transport) -> Vec<(SocketAddrV4, Packet)> {
+        match socket {
+            Transport::Recorded(sent) => sent
+                .lock()
+                .unwrap()
+                .drain(..)
+                .map(|(destination, data)| (destination, Packet::parse(&data).unwrap()))
+                .collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn packet(message_type: Option<MessageType>) -> Packet {
+        let mut options = BTreeMap::new();
+        if let Some(t) = message_type {
+            options.insert(DHCP_MESSAGE_TYPE, DhcpOption::MessageType(t));
+        }
+        Packet {
+            bootp_message_type: BootpMessageType::Request,
+            htype: 1,
+            hlen: 6,
+            mac: CLIENT.parse().unwrap(),
+            hops: 0,
+            xid: 1,
+            secs: 0,
+            flags: 0,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            server_name: None,
+            boot_file_name: None,
+            options,
+        }
+    }
+
+    fn lease_of(server: &Server, ip: Ipv4Addr) -> Option<(Mac, Duration)> {
+        server
+            .leases
+            .get(&ip)
+            .map(|(client_id, _, _, duration)| (client_id.mac, *duration))
+    }
+
+    #[tokio::test]
+    async fn test_bootp_lease_never_expires() {
+        let mut server = server(&[]);
+        let socket = socket();
+        server.process_packet(packet(None), &socket).await.unwrap();
+
+        let ip = Ipv4Addr::new(10, 0, 0, 100);
+        let sent = sent(&socket);
+        assert_eq!(sent.len(), 1);
+        let (destination, reply) = &sent[0];
+        assert_eq!(
+            *destination,
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 255), CLIENT_PORT)
+        );
+        assert_eq!(reply.bootp_message_type, BootpMessageType::Reply);
+        assert_eq!(reply.yiaddr, ip);
+        assert!(!reply.options.contains_key(&DHCP_MESSAGE_TYPE));
+
+        assert_eq!(
+            lease_of(&server, ip),
+            Some((CLIENT.parse().unwrap(), INFINITE_LEASE))
+        );
+        assert!(server.pending.is_empty());
+    }
+
+    fn rapid_commit_discover() -> Packet {
+        let mut discover = packet(Some(MessageType::Discover));
+        discover
+            .options
+            .insert(DHCP_RAPID_COMMIT, DhcpOption::ByteArray(Vec::new()));
+        discover
+    }
+
+    fn message_type(reply: &Packet) -> Option<MessageType> {
+        match reply.options.get(&DHCP_MESSAGE_TYPE) {
+            Some(DhcpOption::MessageType(t)) => Some(*t),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rapid_commit() {
+        let ip = Ipv4Addr::new(10, 0, 0, 100);
+        let socket = socket();
+
+        // without --rapid-commit client gets OFFER as usual
+        let mut server = server(&[]);
+        server
+            .process_packet(rapid_commit_discover(), &socket)
+            .await
+            .unwrap();
+        let replies = sent(&socket);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(message_type(&replies[0].1), Some(MessageType::Offer));
+        assert!(!replies[0].1.options.contains_key(&DHCP_RAPID_COMMIT));
+        assert!(server.pending.contains_key(&ip));
+        assert_eq!(lease_of(&server, ip), None);
+
+        let mut server = self::server(&["--rapid-commit"]);
+        server
+            .process_packet(rapid_commit_discover(), &socket)
+            .await
+            .unwrap();
+        let replies = sent(&socket);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(message_type(&replies[0].1), Some(MessageType::Ack));
+        assert!(replies[0].1.options.contains_key(&DHCP_RAPID_COMMIT));
+        assert!(server.pending.is_empty());
+        assert_eq!(
+            lease_of(&server, ip).map(|x| x.0),
+            Some(CLIENT.parse().unwrap())
+        );
+    }
+
+    // reply is sent where reply_destination says, only reply is returned
+    async fn reply_to(
+        server: &mut Server,
+        socket: &Transport,
+        request: impl Fn() -> Packet,
+    ) -> (SocketAddrV4, Packet) {
+        server.process_packet(request(), socket).await.unwrap();
+        let mut sent = sent(socket);
+        assert_eq!(sent.len(), 1);
+        let (destination, reply) = sent.remove(0);
+        assert_eq!(
+            *destination.ip(),
+            server.reply_destination(&request(), &reply)
+        );
+        (destination, reply)
+    }
+
+    #[tokio::test]
+    async fn test_reply_destination() {
+        let mut server = server(&["--rapid-commit"]);
+        let socket = socket();
+        let broadcast = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 255), CLIENT_PORT);
+        let ip = Ipv4Addr::new(10, 0, 0, 100);
+        let client = SocketAddrV4::new(ip, CLIENT_PORT);
+        let relay = Ipv4Addr::new(10, 0, 0, 2);
+
+        let discover = || packet(Some(MessageType::Discover));
+        let (destination, reply) = reply_to(&mut server, &socket, discover).await;
+        assert_eq!(message_type(&reply), Some(MessageType::Offer));
+        assert_eq!(destination, broadcast);
+        server.unicast_replies = true;
+        assert_eq!(reply_to(&mut server, &socket, discover).await.0, client);
+
+        let broadcast_flag = |message_type| Packet {
+            flags: packet::BROADCAST_FLAG,
+            ..packet(Some(message_type))
+        };
+        let destination = reply_to(&mut server, &socket, || {
+            broadcast_flag(MessageType::Discover)
+        })
+        .await
+        .0;
+        assert_eq!(destination, broadcast);
+
+        reply_to(&mut server, &socket, rapid_commit_discover).await;
+        assert_eq!(
+            lease_of(&server, ip).map(|x| x.0),
+            Some(CLIENT.parse().unwrap())
+        );
+
+        // client with address is reached at it even if it asks for broadcast
+        let renewing = || Packet {
+            ciaddr: ip,
+            ..broadcast_flag(MessageType::Request)
+        };
+        let (destination, reply) = reply_to(&mut server, &socket, renewing).await;
+        assert_eq!(message_type(&reply), Some(MessageType::Ack));
+        assert_eq!(destination, client);
+        let other = || Packet {
+            mac: "52:54:00:65:43:21".parse().unwrap(),
+            ..renewing()
+        };
+        let (destination, reply) = reply_to(&mut server, &socket, other).await;
+        assert_eq!(message_type(&reply), Some(MessageType::Nak));
+        assert_eq!(destination, broadcast);
+
+        let relayed = || Packet {
+            giaddr: relay,
+            ..renewing()
+        };
+        let (destination, reply) = reply_to(&mut server, &socket, relayed).await;
+        assert_eq!(message_type(&reply), Some(MessageType::Ack));
+        assert_eq!(destination, SocketAddrV4::new(relay, SERVER_PORT));
+        let relayed_other = || Packet {
+            giaddr: relay,
+            ..other()
+        };
+        let (destination, reply) = reply_to(&mut server, &socket, relayed_other).await;
+        assert_eq!(message_type(&reply), Some(MessageType::Nak));
+        assert_eq!(destination, SocketAddrV4::new(relay, SERVER_PORT));
+    }
+
+    // lease as (MAC, xid, expiry)
+    fn expiry_of(server: &Server, ip: Ipv4Addr) -> (Mac, u32, Instant) {
+        let (client_id, xid, allocated, duration) = &server.leases[&ip];
+        (client_id.mac, *xid, *allocated + *duration)
+    }
+
+    #[tokio::test]
+    async fn test_renewal() {
+        let mut server = server(&["--rapid-commit"]);
+        let socket = socket();
+        let ip = Ipv4Addr::new(10, 0, 0, 100);
+        let other_ip = Ipv4Addr::new(10, 0, 0, 101);
+        let other_mac: Mac = "52:54:00:65:43:21".parse().unwrap();
+        server
+            .process_packet(rapid_commit_discover(), &socket)
+            .await
+            .unwrap();
+        let other_discover = Packet {
+            mac: other_mac,
+            ..rapid_commit_discover()
+        };
+        server
+            .process_packet(other_discover, &socket)
+            .await
+            .unwrap();
+        sent(&socket);
+        let other_lease = expiry_of(&server, other_ip);
+        assert_eq!(other_lease.0, other_mac);
+
+        // bound a while ago, renewal counts lease time from now
+        let lease_time = Duration::from_secs(server.default_lease_secs.into());
+        server.leases.get_mut(&ip).unwrap().2 -= Duration::from_secs(60);
+        let expiry = expiry_of(&server, ip).2;
+
+        let renew = |xid| Packet {
+            ciaddr: ip,
+            xid,
+            ..packet(Some(MessageType::Request))
+        };
+        let before = Instant::now();
+        server.process_packet(renew(2), &socket).await.unwrap();
+        let after = Instant::now();
+        let replies = sent(&socket);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].0, SocketAddrV4::new(ip, CLIENT_PORT));
+        assert_eq!(message_type(&replies[0].1), Some(MessageType::Ack));
+        let (mac, xid, renewed) = expiry_of(&server, ip);
+        assert_eq!((mac, xid), (CLIENT.parse().unwrap(), 2));
+        assert!(renewed >= expiry + Duration::from_secs(60));
+        assert!(renewed >= before + lease_time && renewed <= after + lease_time);
+        assert_eq!(expiry_of(&server, other_ip), other_lease);
+
+        // address of another client is not extended
+        let other = Packet {
+            mac: other_mac,
+            ..renew(3)
+        };
+        server.process_packet(other, &socket).await.unwrap();
+        let replies = sent(&socket);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(message_type(&replies[0].1), Some(MessageType::Nak));
+        assert_eq!(expiry_of(&server, ip), (mac, xid, renewed));
+        assert_eq!(expiry_of(&server, other_ip), other_lease);
+
+        // renewal of address nobody holds is left to server that gave it out
+        let unknown = Packet {
+            ciaddr: Ipv4Addr::new(10, 0, 0, 120),
+            xid: 4,
+            ..packet(Some(MessageType::Request))
+        };
+        server.process_packet(unknown, &socket).await.unwrap();
+        assert!(sent(&socket).is_empty());
+        assert_eq!(lease_of(&server, Ipv4Addr::new(10, 0, 0, 120)), None);
+
+        // address offered to another client after lease was dropped
+        let offered = Ipv4Addr::new(10, 0, 0, 121);
+        let other_id = ClientId {
+            mac: other_mac,
+            ext: Vec::new(),
+        };
+        server
+            .pending
+            .insert(offered, (other_id, 5, Instant::now()));
+        let reassigned = Packet {
+            ciaddr: offered,
+            xid: 5,
+            ..packet(Some(MessageType::Request))
+        };
+        server.process_packet(reassigned, &socket).await.unwrap();
+        let replies = sent(&socket);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(message_type(&replies[0].1), Some(MessageType::Nak));
+        assert_eq!(server.pending[&offered].0.mac, other_mac);
+
+        // expired lease is not brought back
+        server.leases.get_mut(&ip).unwrap().2 -= lease_time + Duration::from_secs(1);
+        let expired = expiry_of(&server, ip);
+        server.process_packet(renew(6), &socket).await.unwrap();
+        let replies = sent(&socket);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(message_type(&replies[0].1), Some(MessageType::Nak));
+        assert_eq!(expiry_of(&server, ip), expired);
+    }
+}
@@ -2,8 +2,25 @@ use std::io::{Cursor, Write};
 
 use byteorder::{NetworkEndian, WriteBytesExt};
 
+use super::options::{
+    DHCP_LEASE_TIME, DHCP_MESSAGE_TYPE, DHCP_RELAY_AGENT_INFORMATION, DHCP_ROUTER_IP,
+    DHCP_SERVER_ID, DHCP_SUBNET_MASK, DHCP_VENDOR_CLASS_IDENTIFIER, DHCP_VENDOR_SPECIFIC,
+};
 use super::Packet;
 
+const MIN_SIZE: usize = 300;
+// options reply cannot do without, PXE menu included
+const REQUIRED_OPTIONS: &[u8] = &[
+    DHCP_SUBNET_MASK,
+    DHCP_ROUTER_IP,
+    DHCP_VENDOR_SPECIFIC,
+    DHCP_LEASE_TIME,
+    DHCP_MESSAGE_TYPE,
+    DHCP_SERVER_ID,
+    DHCP_VENDOR_CLASS_IDENTIFIER,
+    DHCP_RELAY_AGENT_INFORMATION,
+];
+
 impl Packet {
     pub fn encode(&self) -> Vec<u8> {
         assert!(self.server_name.as_deref().map(|x| x.len()).unwrap_or(0) < 64);
@@ -55,6 +72,48 @@ impl Packet {
             cursor.write_u8(0xff).unwrap();
         }
 
-        cursor.into_inner()
+        // BOOTP clients and relays may drop anything shorter than
+        // 300 bytes, 64 byte vendor area included (RFC 1542 section 2.1)
+        let mut buf = cursor.into_inner();
+        if buf.len() < MIN_SIZE {
+            buf.resize(MIN_SIZE, 0);
+        }
+        buf
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        let options = match self.options.is_empty() {
+            true => 0,
+            false => {
+                4 + self
+                    .options
+                    .values()
+                    .map(|x| 2 + x.len() as usize)
+                    .sum::<usize>()
+                    + 1
+            }
+        };
+        (236 + options).max(MIN_SIZE)
+    }
+
+    // Options not needed to configure client are dropped, highest code
+    // first, until reply fits into size client accepts. Returns codes of
+    // dropped options.
+    pub fn trim_options(&mut self, max_size: usize) -> Vec<u8> {
+        let mut dropped = Vec::new();
+        while self.encoded_len() > max_size {
+            let tag = match self
+                .options
+                .keys()
+                .rev()
+                .find(|x| !REQUIRED_OPTIONS.contains(x))
+            {
+                Some(&x) => x,
+                None => break,
+            };
+            self.options.remove(&tag);
+            dropped.push(tag);
+        }
+        dropped
     }
 }
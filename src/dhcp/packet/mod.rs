@@ -7,12 +7,24 @@ use std::net::Ipv4Addr;
 use byteorder::{NetworkEndian, ReadBytesExt};
 use thiserror::Error;
 
-pub use options::DhcpOption;
+pub use options::{encode_domain_search, DhcpOption, RelayAgentInfo};
+use options::{
+    DHCP_CLIENT_ARCHITECTURE, DHCP_CLIENT_MACHINE_IDENTIFIER, DHCP_HOST_NAME,
+    DHCP_MAXIMUM_DHCP_MESSAGE_SIZE, DHCP_RELAY_AGENT_INFORMATION, DHCP_USER_CLASS,
+    DHCP_VENDOR_CLASS_IDENTIFIER,
+};
 
 use super::id::Mac;
 
+// client cannot receive unicast before it is configured (RFC 2131 section 2)
+pub const BROADCAST_FLAG: u16 = 0x8000;
+// every client accepts datagram of 576 bytes, IP and UDP header take 28
+const MIN_MESSAGE_SIZE: u16 = 576;
+const IP_UDP_HEADER_SIZE: usize = 28;
+
 pub mod encode;
 pub mod options;
+pub mod pxe;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -195,6 +207,100 @@ impl Packet {
         })
     }
 
+    // first architecture listed in client system architecture option
+    pub fn client_arch(&self) -> Option<u16> {
+        match self.options.get(&DHCP_CLIENT_ARCHITECTURE) {
+            Some(DhcpOption::ByteArray(v)) if v.len() >= 2 => {
+                Some(u16::from_be_bytes([v[0], v[1]]))
+            }
+            _ => None,
+        }
+    }
+
+    // type 0 followed by 16 byte UUID (RFC 4578), printed in byte order
+    pub fn client_uuid(&self) -> Option<String> {
+        match self.options.get(&DHCP_CLIENT_MACHINE_IDENTIFIER) {
+            Some(DhcpOption::ByteArray(v)) if v.len() == 17 && v[0] == 0 => {
+                let hex = v[1..]
+                    .iter()
+                    .map(|x| format!("{:02x}", x))
+                    .collect::<String>();
+                Some(format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn vendor_class(&self) -> Option<String> {
+        match self.options.get(&DHCP_VENDOR_CLASS_IDENTIFIER) {
+            Some(DhcpOption::VendorClass(v)) => Some(v.clone()),
+            _ => None,
+        }
+    }
+
+    // RFC 3004 list of length prefixed classes, iPXE and some other
+    // clients send single class as is
+    pub fn user_classes(&self) -> Vec<String> {
+        let data = match self.options.get(&DHCP_USER_CLASS) {
+            Some(DhcpOption::ByteArray(v)) => v,
+            _ => return Vec::new(),
+        };
+
+        let mut classes = Vec::new();
+        let mut rest = &data[..];
+        while let Some((&len, tail)) = rest.split_first() {
+            if len == 0 || tail.len() < len as usize {
+                return vec![String::from_utf8_lossy(data).to_string()];
+            }
+            classes.push(String::from_utf8_lossy(&tail[..len as usize]).to_string());
+            rest = &tail[len as usize..];
+        }
+        classes
+    }
+
+    // malformed option is treated as missing
+    pub fn relay_agent_info(&self) -> Option<RelayAgentInfo> {
+        match self.options.get(&DHCP_RELAY_AGENT_INFORMATION) {
+            Some(DhcpOption::ByteArray(v)) => RelayAgentInfo::parse(v),
+            _ => None,
+        }
+    }
+
+    pub fn wants_broadcast(&self) -> bool {
+        self.flags & BROADCAST_FLAG != 0
+    }
+
+    // loaded by iPXE, which asks for boot file again
+    pub fn is_ipxe(&self) -> bool {
+        self.user_classes().iter().any(|x| x == "iPXE")
+    }
+
+    // client chosen name, accepted only if usable as DNS label
+    // largest reply client accepts without IP and UDP headers, option 57
+    // counts them too (RFC 2132 section 9.10)
+    pub fn max_message_size(&self) -> Option<usize> {
+        match self.options.get(&DHCP_MAXIMUM_DHCP_MESSAGE_SIZE) {
+            Some(DhcpOption::U16(v)) => {
+                Some(usize::from((*v).max(MIN_MESSAGE_SIZE)) - IP_UDP_HEADER_SIZE)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn hostname(&self) -> Option<String> {
+        match self.options.get(&DHCP_HOST_NAME) {
+            Some(DhcpOption::Hostname(v)) => Some(v.to_lowercase()),
+            _ => None,
+        }
+    }
+
     fn parse_str(cursor: &mut Cursor<&[u8]>, len: usize) -> Result<Option<String>, Error> {
         let raw = cursor
             .get_ref()
@@ -207,13 +313,7 @@ impl Packet {
             return Ok(None);
         }
 
-        let terminator_offset = raw
-            .iter()
-            .copied()
-            .enumerate()
-            .find(|(x, _)| *x == 0)
-            .map(|(i, _)| i)
-            .unwrap_or(len);
+        let terminator_offset = raw.iter().position(|&x| x == 0).unwrap_or(len);
 
         Ok(Some(
             String::from_utf8_lossy(&raw[..terminator_offset]).to_string(),
@@ -233,6 +333,11 @@ impl Packet {
             .read_exact(&mut cookie)
             .map_err(|_| Error::Truncated)?;
 
+        // plain BOOTP client leaves vendor area zeroed (RFC 951)
+        if cookie == [0; 4] {
+            return Ok(());
+        }
+
         if &cookie[..] != &[99, 130, 83, 99][..] {
             return Err(Error::InvalidCookie(
                 cookie[0], cookie[1], cookie[2], cookie[3],
@@ -276,3 +381,78 @@ impl Packet {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bootp_round_trip() {
+        let packet = Packet {
+            bootp_message_type: BootpMessageType::Reply,
+            htype: 1,
+            hlen: 6,
+            hops: 0,
+            xid: 0x1234,
+            secs: 0,
+            flags: 0,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::new(10, 0, 0, 100),
+            siaddr: Ipv4Addr::new(10, 0, 0, 1),
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            mac: "52:54:00:12:34:56".parse().unwrap(),
+            server_name: None,
+            boot_file_name: Some("pxelinux.0".to_string()),
+            options: BTreeMap::new(),
+        };
+
+        // no options, vendor area is left zeroed
+        let data = packet.encode();
+        assert_eq!(data.len(), 300);
+        let parsed = Packet::parse(&data).unwrap();
+        assert!(parsed.options.is_empty());
+        assert_eq!(parsed.yiaddr, packet.yiaddr);
+        assert_eq!(parsed.boot_file_name.as_deref(), Some("pxelinux.0"));
+    }
+
+    #[test]
+    fn test_server_name() {
+        let mut data = [0u8; 300];
+        data[..3].copy_from_slice(&[1, 1, 6]);
+        data[44..48].copy_from_slice(b"boot");
+        let packet = Packet::parse(&data[..]).unwrap();
+        assert_eq!(packet.server_name.as_deref(), Some("boot"));
+        assert_eq!(packet.boot_file_name, None);
+
+        // not terminated, whole field is taken
+        data[44..108].copy_from_slice(&[b'a'; 64]);
+        let packet = Packet::parse(&data[..]).unwrap();
+        assert_eq!(packet.server_name.map(|x| x.len()), Some(64));
+    }
+    #[test]
+    fn test_max_message_size() {
+        let mut data = [0u8; 300];
+        data[..3].copy_from_slice(&[1, 1, 6]);
+        let mut request = Packet::parse(&data[..]).unwrap();
+        assert_eq!(request.max_message_size(), None);
+        request.options.insert(
+            DHCP_MAXIMUM_DHCP_MESSAGE_SIZE,
+            DhcpOption::parse(DHCP_MAXIMUM_DHCP_MESSAGE_SIZE, &[0x01, 0x00]).unwrap(),
+        );
+        // below minimum every client has to accept
+        assert_eq!(request.max_message_size(), Some(548));
+
+        let mut reply = Packet::parse(&data[..]).unwrap();
+        for &(tag, len) in [(1, 4), (53, 1), (54, 4), (120, 200), (125, 200)].iter() {
+            reply
+                .options
+                .insert(tag, DhcpOption::ByteArray(vec![0; len]));
+        }
+        assert_eq!(reply.encoded_len(), 660);
+        assert_eq!(reply.trim_options(548), [125]);
+        assert_eq!(reply.encode().len(), 458);
+        assert_eq!(reply.trim_options(300), [120]);
+        assert!(reply.trim_options(250).is_empty());
+        assert_eq!(reply.options.len(), 3);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use std::io::{self, Write};
@@ -16,19 +17,36 @@ pub enum Error {
 }
 
 pub const DHCP_SUBNET_MASK: u8 = 1;
-// pub const DHCP_ROUTER_IP: u8 = 3;
+pub const DHCP_ROUTER_IP: u8 = 3;
+pub const DHCP_DNS_SERVER: u8 = 6;
+pub const DHCP_HOST_NAME: u8 = 12;
+pub const DHCP_DOMAIN_NAME: u8 = 15;
+pub const DHCP_ROOT_PATH: u8 = 17;
 pub const DHCP_MTU: u8 = 26;
+pub const DHCP_NTP_SERVERS: u8 = 42;
+pub const DHCP_VENDOR_SPECIFIC: u8 = 43;
 pub const DHCP_REQUESTED_IP: u8 = 50;
 pub const DHCP_LEASE_TIME: u8 = 51;
 pub const DHCP_MESSAGE_TYPE: u8 = 53;
 pub const DHCP_SERVER_ID: u8 = 54;
 // pub const DHCP_PARAMETER_REQUEST_LIST: u8 = 55;
-// pub const DHCP_MAXIMUM_DHCP_MESSAGE_SIZE: u8 = 57;
-// pub const DHCP_VENDOR_CLASS_IDENTIFIER: u8 = 60;
+pub const DHCP_MAXIMUM_DHCP_MESSAGE_SIZE: u8 = 57;
+pub const DHCP_RENEWAL_TIME: u8 = 58;
+pub const DHCP_REBINDING_TIME: u8 = 59;
+pub const DHCP_VENDOR_CLASS_IDENTIFIER: u8 = 60;
 pub const DHCP_CLIENT_IDENTIFIER: u8 = 61;
 pub const DHCP_TFTP_SERVER_NAME: u8 = 66;
-// pub const DHCP_USER_CLASS: u8 = 77;
-// pub const DHCP_CLIENT_ARCHITECTURE: u8 = 93;
+pub const DHCP_BOOT_FILE_NAME: u8 = 67;
+pub const DHCP_USER_CLASS: u8 = 77;
+pub const DHCP_RAPID_COMMIT: u8 = 80;
+pub const DHCP_RELAY_AGENT_INFORMATION: u8 = 82;
+pub const DHCP_CLIENT_ARCHITECTURE: u8 = 93;
+pub const DHCP_CLIENT_MACHINE_IDENTIFIER: u8 = 97;
+pub const DHCP_DOMAIN_SEARCH: u8 = 119;
+
+// suboptions of relay agent information
+pub const RELAY_CIRCUIT_ID: u8 = 1;
+pub const RELAY_REMOTE_ID: u8 = 2;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(u8)]
@@ -86,10 +104,20 @@ impl Into<u8> for MessageType {
 pub enum DhcpOption {
     ByteArray(Vec<u8>),
     Ipv4Addr(Ipv4Addr),
+    Ipv4Addrs(Vec<Ipv4Addr>),
     U16(u16),
     U32(u32),
     MessageType(MessageType),
     String(String),
+    // single label as sent by client, anything else is kept as bytes
+    Hostname(String),
+    // option 60, e.g. PXEClient:Arch:00007:UNDI:003016 or udhcp 1.30.1
+    VendorClass(String),
+    // suboptions of vendor specific information and alike, terminated
+    // with end option when encoded
+    Encapsulated(Vec<(u8, Vec<u8>)>),
+    // domain names, see encode_domain_search
+    DomainSearch(Vec<String>),
 }
 
 impl DhcpOption {
@@ -120,6 +148,19 @@ impl DhcpOption {
                     })
                 }
             }
+            DHCP_HOST_NAME
+                if !data.is_empty()
+                    && data.len() <= 63
+                    && data.iter().all(|x| x.is_ascii_alphanumeric() || *x == b'-') =>
+            {
+                Ok(Self::Hostname(String::from_utf8_lossy(data).into_owned()))
+            }
+            DHCP_VENDOR_CLASS_IDENTIFIER if !data.is_empty() => Ok(Self::VendorClass(
+                String::from_utf8_lossy(data).into_owned(),
+            )),
+            DHCP_MAXIMUM_DHCP_MESSAGE_SIZE if data.len() == 2 => {
+                Ok(Self::U16(u16::from_be_bytes([data[0], data[1]])))
+            }
             DHCP_CLIENT_IDENTIFIER => Ok(Self::ByteArray(data.to_vec())),
             _ => Ok(Self::ByteArray(data.to_vec())),
         }
@@ -128,22 +169,193 @@ impl DhcpOption {
     pub fn len(&self) -> u8 {
         match self {
             Self::Ipv4Addr(_) => 4,
+            Self::Ipv4Addrs(v) => TryInto::<u8>::try_into(v.len() * 4).expect("too many addresses"),
             Self::U16(_) => 2,
             Self::U32(_) => 4,
             Self::MessageType(_) => 1,
             Self::ByteArray(v) => TryInto::<u8>::try_into(v.len()).expect("array too big"),
-            Self::String(v) => TryInto::<u8>::try_into(v.len()).expect("string too big"),
+            Self::String(v) | Self::Hostname(v) | Self::VendorClass(v) => {
+                TryInto::<u8>::try_into(v.len()).expect("string too big")
+            }
+            Self::Encapsulated(v) => {
+                TryInto::<u8>::try_into(v.iter().map(|(_, data)| 2 + data.len()).sum::<usize>() + 1)
+                    .expect("suboptions too big")
+            }
+            Self::DomainSearch(v) => {
+                TryInto::<u8>::try_into(encode_domain_search(v).len()).expect("search list too big")
+            }
         }
     }
 
     pub fn encode(&self, writer: &mut dyn Write) -> io::Result<()> {
         match self {
             Self::Ipv4Addr(v) => writer.write_all(&v.octets()[..]),
+            Self::Ipv4Addrs(v) => v.iter().try_for_each(|x| writer.write_all(&x.octets()[..])),
             Self::U16(v) => writer.write_u16::<NetworkEndian>(*v),
             Self::U32(v) => writer.write_u32::<NetworkEndian>(*v),
             Self::MessageType(v) => writer.write_u8(Into::<u8>::into(*v)),
             Self::ByteArray(v) => writer.write_all(v.as_slice()),
-            Self::String(v) => writer.write_all(v.as_bytes()),
+            Self::String(v) | Self::Hostname(v) | Self::VendorClass(v) => {
+                writer.write_all(v.as_bytes())
+            }
+            Self::Encapsulated(v) => {
+                for (tag, data) in v.iter() {
+                    writer.write_u8(*tag)?;
+                    writer.write_u8(data.len() as u8)?;
+                    writer.write_all(data)?;
+                }
+                writer.write_u8(255)
+            }
+            Self::DomainSearch(v) => writer.write_all(&encode_domain_search(v)),
         }
     }
 }
+
+// Names in DNS wire format (RFC 1035 section 3.1), suffix already written
+// for earlier name is replaced with pointer to it, offsets counting from
+// start of option data (RFC 3397 section 2). Names are expected to be
+// validated, labels of at most 63 bytes.
+pub fn encode_domain_search(names: &[String]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut suffixes: HashMap<String, u16> = HashMap::new();
+    for name in names {
+        let labels: Vec<&str> = name
+            .trim_end_matches('.')
+            .split('.')
+            .filter(|x| !x.is_empty())
+            .collect();
+        let mut pointer = None;
+        for i in 0..labels.len() {
+            let suffix = labels[i..].join(".").to_ascii_lowercase();
+            if let Some(offset) = suffixes.get(&suffix) {
+                pointer = Some(*offset);
+                break;
+            }
+            // pointers have 14 bits
+            if out.len() < 0x4000 {
+                suffixes.insert(suffix, out.len() as u16);
+            }
+            out.push(labels[i].len() as u8);
+            out.extend_from_slice(labels[i].as_bytes());
+        }
+        match pointer {
+            Some(offset) => out.extend_from_slice(&(0xc000 | offset).to_be_bytes()),
+            None => out.push(0),
+        }
+    }
+    out
+}
+
+// Relay agent information (RFC 3046) added by relays and switches doing
+// DHCP snooping, tells where request entered network. Server must send it
+// back unchanged, unknown suboptions are only kept in raw option.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelayAgentInfo {
+    pub circuit_id: Option<RelayId>,
+    pub remote_id: Option<RelayId>,
+}
+
+impl RelayAgentInfo {
+    // unlike vendor options there is no end suboption,
+    // None when suboptions do not add up to option length
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut info = Self::default();
+        let mut rest = data;
+        while let [code, len, tail @ ..] = rest {
+            let len = *len as usize;
+            if tail.len() < len {
+                return None;
+            }
+            let id = Some(RelayId(tail[..len].to_vec()));
+            match *code {
+                RELAY_CIRCUIT_ID => info.circuit_id = id,
+                RELAY_REMOTE_ID => info.remote_id = id,
+                _ => (),
+            }
+            rest = &tail[len..];
+        }
+        if rest.is_empty() {
+            Some(info)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for RelayAgentInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.circuit_id, &self.remote_id) {
+            (Some(c), Some(r)) => write!(f, "circuit-id {} remote-id {}", c, r),
+            (Some(c), None) => write!(f, "circuit-id {}", c),
+            (None, Some(r)) => write!(f, "remote-id {}", r),
+            (None, None) => write!(f, "none"),
+        }
+    }
+}
+
+// Printed as text when printable, e.g. eth0/1:100, and as colon separated
+// hex otherwise, e.g. 00:04:00:64:01:07 of VLAN and port. Selectors are
+// compared against printed form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayId(pub Vec<u8>);
+
+impl fmt::Display for RelayId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.0.is_empty() && self.0.iter().all(|x| x.is_ascii_graphic() || *x == b' ') {
+            return write!(f, "{}", String::from_utf8_lossy(&self.0));
+        }
+        for (i, x) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02x}", x)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_agent_info() {
+        let info =
+            RelayAgentInfo::parse(b"\x01\x08eth0/1:5\x02\x06\x00\x1b\x21\x3c\x4d\x5e\x09\x00")
+                .unwrap();
+        assert_eq!(info.circuit_id.as_ref().unwrap().to_string(), "eth0/1:5");
+        assert_eq!(
+            info.remote_id.as_ref().unwrap().to_string(),
+            "00:1b:21:3c:4d:5e"
+        );
+        assert_eq!(
+            info.to_string(),
+            "circuit-id eth0/1:5 remote-id 00:1b:21:3c:4d:5e"
+        );
+
+        assert_eq!(
+            RelayAgentInfo::parse(b"").unwrap(),
+            RelayAgentInfo::default()
+        );
+        assert!(RelayAgentInfo::parse(b"\x01\x05eth0").is_none());
+        assert!(RelayAgentInfo::parse(b"\x01\x04eth0\x02").is_none());
+    }
+
+    #[test]
+    fn test_encode_domain_search() {
+        // example from RFC 3397 section 2
+        let names = [
+            "eng.apple.com.".to_string(),
+            "marketing.apple.com".to_string(),
+        ];
+        assert_eq!(
+            encode_domain_search(&names),
+            b"\x03eng\x05apple\x03com\x00\x09marketing\xc0\x04"
+        );
+        assert_eq!(
+            encode_domain_search(&["Apple.COM".to_string(), "apple.com".to_string()]),
+            b"\x05Apple\x03COM\x00\xc0\x00"
+        );
+        assert!(encode_domain_search(&[]).is_empty());
+    }
+}
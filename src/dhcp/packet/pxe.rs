@@ -0,0 +1,194 @@
+// PXE vendor options carried in option 43 of PXE clients and servers
+// (PXE 2.1 section 2.4.5). Server advertises how boot servers are found
+// and boot menu, client names menu item it picked in its request to boot
+// server.
+use std::net::Ipv4Addr;
+
+use super::options::DhcpOption;
+
+const PXE_DISCOVERY_CONTROL: u8 = 6;
+const PXE_BOOT_SERVERS: u8 = 8;
+const PXE_BOOT_MENU: u8 = 9;
+const PXE_MENU_PROMPT: u8 = 10;
+const PXE_BOOT_ITEM: u8 = 71;
+
+// discovery control bits
+pub const DISABLE_BROADCAST: u8 = 1;
+pub const DISABLE_MULTICAST: u8 = 2;
+pub const ONLY_LISTED_SERVERS: u8 = 4;
+pub const USE_BOOT_FILE: u8 = 8;
+
+// boot server type of local boot
+pub const LOCAL_BOOT: u16 = 0;
+// first of boot server types left to vendors
+pub const VENDOR_TYPES: u16 = 0x8000;
+
+// whole option 43 has to fit in single option
+const MAX_LEN: usize = 255;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VendorOptions {
+    pub discovery_control: Option<u8>,
+    // boot server type and addresses answering it
+    pub boot_servers: Vec<(u16, Vec<Ipv4Addr>)>,
+    // boot server type and description
+    pub menu: Vec<(u16, String)>,
+    // seconds, 255 waits for user, and prompt text
+    pub prompt: Option<(u8, String)>,
+    // type and layer
+    pub boot_item: Option<(u16, u16)>,
+}
+
+impl VendorOptions {
+    // menu items not fitting into option are left out
+    pub fn encode(&self) -> DhcpOption {
+        let mut suboptions = Vec::new();
+        if let Some(control) = self.discovery_control {
+            suboptions.push((PXE_DISCOVERY_CONTROL, vec![control]));
+        }
+        if let Some((kind, layer)) = self.boot_item {
+            let mut data = kind.to_be_bytes().to_vec();
+            data.extend_from_slice(&layer.to_be_bytes());
+            suboptions.push((PXE_BOOT_ITEM, data));
+        }
+        if let Some((timeout, prompt)) = self.prompt.as_ref() {
+            let mut data = vec![*timeout];
+            data.extend(prompt.bytes().take(MAX_LEN - 16));
+            suboptions.push((PXE_MENU_PROMPT, data));
+        }
+
+        let used = |suboptions: &[(u8, Vec<u8>)]| {
+            suboptions.iter().map(|(_, x)| 2 + x.len()).sum::<usize>() + 1
+        };
+        let mut servers = Vec::new();
+        let mut menu = Vec::new();
+        // boot servers are listed with their menu items, so that both
+        // are left out together
+        for (kind, description) in self.menu.iter() {
+            let mut item = kind.to_be_bytes().to_vec();
+            let description = &description.as_bytes()[..description.len().min(MAX_LEN - 16)];
+            item.push(description.len() as u8);
+            item.extend_from_slice(description);
+
+            let mut server = Vec::new();
+            if let Some((kind, addresses)) = self.boot_servers.iter().find(|(x, _)| x == kind) {
+                server.extend_from_slice(&kind.to_be_bytes());
+                server.push(addresses.len() as u8);
+                for address in addresses.iter() {
+                    server.extend_from_slice(&address.octets());
+                }
+            }
+
+            // two more suboption headers for menu and boot servers
+            if used(&suboptions) + 4 + menu.len() + item.len() + servers.len() + server.len()
+                > MAX_LEN
+            {
+                warn!("PXE boot menu truncated, too many items");
+                break;
+            }
+            menu.extend(item);
+            servers.extend(server);
+        }
+        if !servers.is_empty() {
+            suboptions.push((PXE_BOOT_SERVERS, servers));
+        }
+        if !menu.is_empty() {
+            suboptions.push((PXE_BOOT_MENU, menu));
+        }
+
+        DhcpOption::Encapsulated(suboptions)
+    }
+
+    // only boot item is of interest in options sent by client
+    pub fn decode(data: &[u8]) -> Self {
+        let mut options = Self::default();
+        let mut rest = data;
+        while let [tag, tail @ ..] = rest {
+            match *tag {
+                0 => {
+                    rest = tail;
+                    continue;
+                }
+                255 => break,
+                _ => (),
+            }
+            let (len, tail) = match tail {
+                [len, tail @ ..] if tail.len() >= *len as usize => (*len as usize, tail),
+                _ => break,
+            };
+            let value = &tail[..len];
+            match (*tag, value) {
+                (PXE_BOOT_ITEM, [a, b, c, d]) => {
+                    options.boot_item =
+                        Some((u16::from_be_bytes([*a, *b]), u16::from_be_bytes([*c, *d])))
+                }
+                (PXE_DISCOVERY_CONTROL, [control]) => options.discovery_control = Some(*control),
+                _ => (),
+            }
+            rest = &tail[len..];
+        }
+
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(options: &VendorOptions) -> Vec<u8> {
+        let option = options.encode();
+        let mut data = Vec::new();
+        option.encode(&mut data).unwrap();
+        assert_eq!(data.len(), option.len() as usize);
+        data
+    }
+
+    #[test]
+    fn test_encode() {
+        let options = VendorOptions {
+            discovery_control: Some(USE_BOOT_FILE),
+            ..Default::default()
+        };
+        assert_eq!(encoded(&options), [6, 1, 8, 255]);
+
+        let server = Ipv4Addr::new(10, 0, 0, 1);
+        let options = VendorOptions {
+            discovery_control: Some(DISABLE_BROADCAST | DISABLE_MULTICAST | ONLY_LISTED_SERVERS),
+            boot_servers: vec![(0x8001, vec![server])],
+            menu: vec![
+                (0x8001, "debian".to_string()),
+                (LOCAL_BOOT, "local".to_string()),
+            ],
+            prompt: Some((10, "F8".to_string())),
+            boot_item: None,
+        };
+        assert_eq!(
+            encoded(&options),
+            [
+                6, 1, 7, //
+                10, 3, 10, b'F', b'8', //
+                8, 7, 0x80, 1, 1, 10, 0, 0, 1, //
+                9, 17, 0x80, 1, 6, b'd', b'e', b'b', b'i', b'a', b'n', 0, 0, 5, b'l', b'o', b'c',
+                b'a', b'l', //
+                255
+            ]
+        );
+
+        let options = VendorOptions {
+            menu: (0..100).map(|x| (x, format!("item {}", x))).collect(),
+            ..Default::default()
+        };
+        assert!(encoded(&options).len() <= MAX_LEN);
+    }
+
+    #[test]
+    fn test_decode() {
+        let options = VendorOptions::decode(&[0, 71, 4, 0x80, 1, 0, 0, 255]);
+        assert_eq!(options.boot_item, Some((0x8001, 0)));
+        assert_eq!(
+            VendorOptions::decode(&[71, 4, 0x80]),
+            VendorOptions::default()
+        );
+    }
+}
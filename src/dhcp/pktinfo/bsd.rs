@@ -0,0 +1,115 @@
+// Interface datagram arrived on comes as IP_RECVIF link address. Replies
+// get server IP as source through IP_PKTINFO on Apple systems and through
+// IP_SENDSRCADDR on other BSDs, which send them out of interface owning
+// that address.
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::unix::io::AsRawFd;
+
+use nix::libc;
+use nix::net::if_::if_nametoindex;
+use nix::sys::socket::{recvmsg, setsockopt, sockopt, ControlMessageOwned, MsgFlags};
+use nix::sys::uio::IoVec;
+use socket2::Socket;
+use tokio::net::UdpSocket;
+
+pub fn interface_index(interface: Option<&str>, server_ip: Ipv4Addr) -> io::Result<u32> {
+    Ok(if_nametoindex(
+        super::interface_name(interface, server_ip)?.as_str(),
+    )?)
+}
+
+pub fn enable(socket: &Socket) -> io::Result<()> {
+    Ok(setsockopt(socket.as_raw_fd(), sockopt::Ipv4RecvIf, &true)?)
+}
+
+// length of datagram and index of interface it arrived on
+pub fn recv(socket: &UdpSocket, data: &mut [u8]) -> io::Result<(usize, Option<u32>)> {
+    let iov = [IoVec::from_mut_slice(data)];
+    let mut cmsg = nix::cmsg_space!(libc::sockaddr_dl);
+    let message = recvmsg(socket.as_raw_fd(), &iov, Some(&mut cmsg), MsgFlags::empty())?;
+    let ifindex = message.cmsgs().find_map(|x| match x {
+        ControlMessageOwned::Ipv4RecvIf(link) => Some(link.sdl_index as u32),
+        _ => None,
+    });
+    Ok((message.bytes, ifindex))
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub fn send_to(
+    socket: &UdpSocket,
+    data: &[u8],
+    destination: SocketAddrV4,
+    ifindex: u32,
+    source: Ipv4Addr,
+) -> io::Result<()> {
+    use nix::sys::socket::{sendmsg, ControlMessage, InetAddr, SockAddr};
+    use std::net::SocketAddr;
+
+    let info = libc::in_pktinfo {
+        ipi_ifindex: ifindex,
+        ipi_spec_dst: in_addr(source),
+        ipi_addr: libc::in_addr { s_addr: 0 },
+    };
+    let address = SockAddr::new_inet(InetAddr::from_std(&SocketAddr::V4(destination)));
+    sendmsg(
+        socket.as_raw_fd(),
+        &[IoVec::from_slice(data)],
+        &[ControlMessage::Ipv4PacketInfo(&info)],
+        MsgFlags::empty(),
+        Some(&address),
+    )?;
+    Ok(())
+}
+
+// nix has no control message for IP_SENDSRCADDR, it is built by hand
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+pub fn send_to(
+    socket: &UdpSocket,
+    data: &[u8],
+    destination: SocketAddrV4,
+    _ifindex: u32,
+    source: Ipv4Addr,
+) -> io::Result<()> {
+    use std::{mem, ptr};
+
+    let mut address: libc::sockaddr_in = unsafe { mem::zeroed() };
+    address.sin_len = mem::size_of::<libc::sockaddr_in>() as u8;
+    address.sin_family = libc::AF_INET as libc::sa_family_t;
+    address.sin_port = destination.port().to_be();
+    address.sin_addr = in_addr(*destination.ip());
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+
+    let len = mem::size_of::<libc::in_addr>() as libc::c_uint;
+    let space = unsafe { libc::CMSG_SPACE(len) } as usize;
+    // u64 keeps control messages aligned
+    let mut control = vec![0u64; (space + 7) / 8];
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_name = &mut address as *mut libc::sockaddr_in as *mut libc::c_void;
+    message.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = space as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&message);
+        (*cmsg).cmsg_level = libc::IPPROTO_IP;
+        (*cmsg).cmsg_type = libc::IP_SENDSRCADDR;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(len) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in_addr, in_addr(source));
+    }
+
+    match unsafe { libc::sendmsg(socket.as_raw_fd(), &message, 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn in_addr(ip: Ipv4Addr) -> libc::in_addr {
+    libc::in_addr {
+        s_addr: u32::from(ip).to_be(),
+    }
+}
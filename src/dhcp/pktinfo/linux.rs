@@ -0,0 +1,64 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::unix::io::AsRawFd;
+
+use nix::libc;
+use nix::net::if_::if_nametoindex;
+use nix::sys::socket::{
+    recvmsg, sendmsg, setsockopt, sockopt, ControlMessage, ControlMessageOwned, InetAddr, MsgFlags,
+    SockAddr,
+};
+use nix::sys::uio::IoVec;
+use socket2::Socket;
+use tokio::net::UdpSocket;
+
+pub fn interface_index(interface: Option<&str>, server_ip: Ipv4Addr) -> io::Result<u32> {
+    Ok(if_nametoindex(
+        super::interface_name(interface, server_ip)?.as_str(),
+    )?)
+}
+
+pub fn enable(socket: &Socket) -> io::Result<()> {
+    Ok(setsockopt(
+        socket.as_raw_fd(),
+        sockopt::Ipv4PacketInfo,
+        &true,
+    )?)
+}
+
+// length of datagram and index of interface it arrived on
+pub fn recv(socket: &UdpSocket, data: &mut [u8]) -> io::Result<(usize, Option<u32>)> {
+    let iov = [IoVec::from_mut_slice(data)];
+    let mut cmsg = nix::cmsg_space!(libc::in_pktinfo);
+    let message = recvmsg(socket.as_raw_fd(), &iov, Some(&mut cmsg), MsgFlags::empty())?;
+    let ifindex = message.cmsgs().find_map(|x| match x {
+        ControlMessageOwned::Ipv4PacketInfo(info) => Some(info.ipi_ifindex as u32),
+        _ => None,
+    });
+    Ok((message.bytes, ifindex))
+}
+
+pub fn send_to(
+    socket: &UdpSocket,
+    data: &[u8],
+    destination: SocketAddrV4,
+    ifindex: u32,
+    source: Ipv4Addr,
+) -> io::Result<()> {
+    let info = libc::in_pktinfo {
+        ipi_ifindex: ifindex as i32,
+        ipi_spec_dst: libc::in_addr {
+            s_addr: u32::from(source).to_be(),
+        },
+        ipi_addr: libc::in_addr { s_addr: 0 },
+    };
+    let address = SockAddr::new_inet(InetAddr::from_std(&SocketAddr::V4(destination)));
+    sendmsg(
+        socket.as_raw_fd(),
+        &[IoVec::from_slice(data)],
+        &[ControlMessage::Ipv4PacketInfo(&info)],
+        MsgFlags::empty(),
+        Some(&address),
+    )?;
+    Ok(())
+}
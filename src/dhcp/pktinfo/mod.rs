@@ -0,0 +1,161 @@
+// Wildcard bound transport. Socket bound to 0.0.0.0 gets broadcasts no
+// matter which addresses interface has, kernel tells interface datagram
+// arrived on and those from other interfaces are dropped. Replies leave
+// through served interface with server IP as source. Both are done with
+// IP_PKTINFO on Linux, with IP_RECVIF on macOS and BSDs, and with
+// WSARecvMsg and WSASendMsg on Windows, where interface is the one owning
+// server IP. Elsewhere opening socket fails.
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use futures_util::task::{Context, Poll};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{Interest, ReadBuf};
+use tokio::net::UdpSocket;
+
+use super::transport::SERVER_PORT;
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+mod bsd;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(not(any(
+    target_os = "linux",
+    windows,
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+mod unsupported;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(windows)]
+use self::windows as sys;
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+use bsd as sys;
+#[cfg(target_os = "linux")]
+use linux as sys;
+#[cfg(not(any(
+    target_os = "linux",
+    windows,
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+use unsupported as sys;
+
+pub struct PktinfoSocket {
+    socket: UdpSocket,
+    ifindex: u32,
+    // source address of replies
+    server_ip: Ipv4Addr,
+    #[cfg(windows)]
+    recv_msg: sys::RecvMsg,
+}
+
+impl PktinfoSocket {
+    pub fn open(interface: Option<&str>, server_ip: Ipv4Addr) -> io::Result<Self> {
+        let ifindex = sys::interface_index(interface, server_ip)?;
+        // servers of other interfaces share port
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        sys::enable(&socket)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SERVER_PORT)).into())?;
+        #[cfg(windows)]
+        let recv_msg = sys::recv_msg(&socket)?;
+
+        Ok(Self {
+            socket: UdpSocket::from_std(socket.into())?,
+            ifindex,
+            server_ip,
+            #[cfg(windows)]
+            recv_msg,
+        })
+    }
+
+    pub fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let mut data = vec![0u8; buf.remaining()];
+        loop {
+            match self.socket.poll_recv_ready(cx) {
+                Poll::Ready(result) => result?,
+                Poll::Pending => return Poll::Pending,
+            }
+
+            match self
+                .socket
+                .try_io(Interest::READABLE, || self.recv(&mut data))
+            {
+                Ok((n, Some(ifindex))) if ifindex == self.ifindex => {
+                    buf.put_slice(&data[..n]);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(_) => trace!("dropped datagram from another interface"),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    fn recv(&self, data: &mut [u8]) -> io::Result<(usize, Option<u32>)> {
+        sys::recv(&self.socket, self.recv_msg, data)
+    }
+
+    #[cfg(not(windows))]
+    fn recv(&self, data: &mut [u8]) -> io::Result<(usize, Option<u32>)> {
+        sys::recv(&self.socket, data)
+    }
+
+    pub async fn send_to(&self, data: &[u8], destination: SocketAddrV4) -> io::Result<()> {
+        self.socket
+            .async_io(Interest::WRITABLE, || {
+                sys::send_to(
+                    &self.socket,
+                    data,
+                    destination,
+                    self.ifindex,
+                    self.server_ip,
+                )
+            })
+            .await
+    }
+}
+
+// without interface given it is the one owning server IP
+#[cfg(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn interface_name(interface: Option<&str>, server_ip: Ipv4Addr) -> io::Result<String> {
+    match interface {
+        Some(x) => Ok(x.to_string()),
+        None => crate::netif::NetworkInterface::with_address(server_ip)
+            .ok()
+            .flatten()
+            .map(|x| x.name().to_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no interface has server IP")),
+    }
+}
@@ -0,0 +1,34 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use socket2::Socket;
+use tokio::net::UdpSocket;
+
+pub fn interface_index(_interface: Option<&str>, _server_ip: Ipv4Addr) -> io::Result<u32> {
+    Err(unsupported())
+}
+
+pub fn enable(_socket: &Socket) -> io::Result<()> {
+    Err(unsupported())
+}
+
+pub fn recv(_socket: &UdpSocket, _data: &mut [u8]) -> io::Result<(usize, Option<u32>)> {
+    Err(unsupported())
+}
+
+pub fn send_to(
+    _socket: &UdpSocket,
+    _data: &[u8],
+    _destination: SocketAddrV4,
+    _ifindex: u32,
+    _source: Ipv4Addr,
+) -> io::Result<()> {
+    Err(unsupported())
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        "--dhcp-bind-any is not supported on this platform",
+    )
+}
@@ -0,0 +1,250 @@
+// WSARecvMsg is not exported by ws2_32, its address is looked up with
+// WSAIoctl. Control messages are laid out as with WSA_CMSG_* macros, data
+// follows header aligned to pointer size.
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::windows::io::AsRawSocket;
+use std::ptr;
+
+use socket2::Socket;
+use tokio::net::UdpSocket;
+use windows_sys::Win32::Foundation::{ERROR_INSUFFICIENT_BUFFER, FALSE, NO_ERROR};
+use windows_sys::Win32::NetworkManagement::IpHelper::{GetIpAddrTable, MIB_IPADDRTABLE};
+use windows_sys::Win32::Networking::WinSock::{
+    setsockopt, WSAGetLastError, WSAIoctl, WSASendMsg, AF_INET, CMSGHDR, IN_ADDR, IN_ADDR_0,
+    IN_PKTINFO, IPPROTO_IP, IP_PKTINFO, LPFN_WSARECVMSG, LPWSAOVERLAPPED_COMPLETION_ROUTINE,
+    SIO_GET_EXTENSION_FUNCTION_POINTER, SOCKADDR, SOCKADDR_IN, SOCKET, SOCKET_ERROR, WSABUF,
+    WSAID_WSARECVMSG, WSAMSG,
+};
+use windows_sys::Win32::System::IO::OVERLAPPED;
+
+// WSA_CMSG_SPACE of IN_PKTINFO, u64 keeps it aligned
+const CONTROL_LEN: usize = 8;
+
+// WSARecvMsg of socket's provider, looked up once when socket is opened
+#[derive(Clone, Copy)]
+pub struct RecvMsg(
+    unsafe extern "system" fn(
+        SOCKET,
+        *mut WSAMSG,
+        *mut u32,
+        *mut OVERLAPPED,
+        LPWSAOVERLAPPED_COMPLETION_ROUTINE,
+    ) -> i32,
+);
+
+// interface is picked by server IP, names are not resolved on Windows
+pub fn interface_index(interface: Option<&str>, server_ip: Ipv4Addr) -> io::Result<u32> {
+    if let Some(interface) = interface {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "interface {} cannot be selected on Windows, one owning server IP is served",
+                interface
+            ),
+        ));
+    }
+
+    let mut size = 0u32;
+    let result = unsafe { GetIpAddrTable(ptr::null_mut(), &mut size, FALSE) };
+    if result != ERROR_INSUFFICIENT_BUFFER {
+        return Err(io::Error::from_raw_os_error(result as i32));
+    }
+    let mut buf = vec![0u64; (size as usize + 7) / 8];
+    let table = buf.as_mut_ptr() as *mut MIB_IPADDRTABLE;
+    let result = unsafe { GetIpAddrTable(table, &mut size, FALSE) };
+    if result != NO_ERROR {
+        return Err(io::Error::from_raw_os_error(result as i32));
+    }
+
+    let rows = unsafe {
+        std::slice::from_raw_parts((*table).table.as_ptr(), (*table).dwNumEntries as usize)
+    };
+    rows.iter()
+        .find(|x| Ipv4Addr::from(u32::from_be(x.dwAddr)) == server_ip)
+        .map(|x| x.dwIndex)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no interface has server IP"))
+}
+
+pub fn enable(socket: &Socket) -> io::Result<()> {
+    let enabled: u32 = 1;
+    let result = unsafe {
+        setsockopt(
+            socket.as_raw_socket() as SOCKET,
+            IPPROTO_IP,
+            IP_PKTINFO,
+            &enabled as *const u32 as *const u8,
+            mem::size_of_val(&enabled) as i32,
+        )
+    };
+    check(result)
+}
+
+pub fn recv_msg(socket: &Socket) -> io::Result<RecvMsg> {
+    recv_msg_fn(socket.as_raw_socket() as SOCKET)?
+        .map(RecvMsg)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "WSARecvMsg is not available"))
+}
+
+// length of datagram and index of interface it arrived on
+pub fn recv(
+    socket: &UdpSocket,
+    recv_msg: RecvMsg,
+    data: &mut [u8],
+) -> io::Result<(usize, Option<u32>)> {
+    let socket = socket.as_raw_socket() as SOCKET;
+
+    let mut control = [0u64; CONTROL_LEN];
+    let mut buffer = WSABUF {
+        len: data.len() as u32,
+        buf: data.as_mut_ptr(),
+    };
+    let mut message = WSAMSG {
+        name: ptr::null_mut(),
+        namelen: 0,
+        lpBuffers: &mut buffer,
+        dwBufferCount: 1,
+        Control: WSABUF {
+            len: mem::size_of_val(&control) as u32,
+            buf: control.as_mut_ptr() as *mut u8,
+        },
+        dwFlags: 0,
+    };
+    let mut n = 0u32;
+    check(unsafe { (recv_msg.0)(socket, &mut message, &mut n, ptr::null_mut(), None) })?;
+
+    let control = unsafe {
+        std::slice::from_raw_parts(control.as_ptr() as *const u8, message.Control.len as usize)
+    };
+    Ok((n as usize, pktinfo(control).map(|x| x.ipi_ifindex)))
+}
+
+pub fn send_to(
+    socket: &UdpSocket,
+    data: &[u8],
+    destination: SocketAddrV4,
+    ifindex: u32,
+    source: Ipv4Addr,
+) -> io::Result<()> {
+    let mut address = SOCKADDR_IN {
+        sin_family: AF_INET,
+        sin_port: destination.port().to_be(),
+        sin_addr: in_addr(*destination.ip()),
+        sin_zero: [0; 8],
+    };
+    let mut buffer = WSABUF {
+        len: data.len() as u32,
+        buf: data.as_ptr() as *mut u8,
+    };
+
+    let mut control = [0u64; CONTROL_LEN];
+    let header = CMSGHDR {
+        cmsg_len: data_offset() + mem::size_of::<IN_PKTINFO>(),
+        cmsg_level: IPPROTO_IP,
+        cmsg_type: IP_PKTINFO,
+    };
+    let info = IN_PKTINFO {
+        ipi_addr: in_addr(source),
+        ipi_ifindex: ifindex,
+    };
+    unsafe {
+        let base = control.as_mut_ptr() as *mut u8;
+        ptr::write_unaligned(base as *mut CMSGHDR, header);
+        ptr::write_unaligned(base.add(data_offset()) as *mut IN_PKTINFO, info);
+    }
+
+    let message = WSAMSG {
+        name: &mut address as *mut SOCKADDR_IN as *mut SOCKADDR,
+        namelen: mem::size_of::<SOCKADDR_IN>() as i32,
+        lpBuffers: &mut buffer,
+        dwBufferCount: 1,
+        Control: WSABUF {
+            len: space(mem::size_of::<IN_PKTINFO>()) as u32,
+            buf: control.as_mut_ptr() as *mut u8,
+        },
+        dwFlags: 0,
+    };
+    let mut sent = 0u32;
+    check(unsafe {
+        WSASendMsg(
+            socket.as_raw_socket() as SOCKET,
+            &message,
+            0,
+            &mut sent,
+            ptr::null_mut(),
+            None,
+        )
+    })
+}
+
+fn recv_msg_fn(socket: SOCKET) -> io::Result<LPFN_WSARECVMSG> {
+    let guid = WSAID_WSARECVMSG;
+    let mut function: LPFN_WSARECVMSG = None;
+    let mut returned = 0u32;
+    check(unsafe {
+        WSAIoctl(
+            socket,
+            SIO_GET_EXTENSION_FUNCTION_POINTER,
+            &guid as *const _ as *const _,
+            mem::size_of_val(&guid) as u32,
+            &mut function as *mut _ as *mut _,
+            mem::size_of_val(&function) as u32,
+            &mut returned,
+            ptr::null_mut(),
+            None,
+        )
+    })?;
+    Ok(function)
+}
+
+// IP_PKTINFO among received control messages
+fn pktinfo(control: &[u8]) -> Option<IN_PKTINFO> {
+    let mut offset = 0;
+    while offset + mem::size_of::<CMSGHDR>() <= control.len() {
+        let header = unsafe { ptr::read_unaligned(control[offset..].as_ptr() as *const CMSGHDR) };
+        if header.cmsg_len < mem::size_of::<CMSGHDR>() {
+            return None;
+        }
+        let data = offset + data_offset();
+        if header.cmsg_level == IPPROTO_IP
+            && header.cmsg_type == IP_PKTINFO
+            && data + mem::size_of::<IN_PKTINFO>() <= control.len()
+        {
+            return Some(unsafe {
+                ptr::read_unaligned(control[data..].as_ptr() as *const IN_PKTINFO)
+            });
+        }
+        offset += align(header.cmsg_len);
+    }
+
+    None
+}
+
+fn align(len: usize) -> usize {
+    let alignment = mem::align_of::<usize>();
+    (len + alignment - 1) & !(alignment - 1)
+}
+
+fn data_offset() -> usize {
+    align(mem::size_of::<CMSGHDR>())
+}
+
+fn space(len: usize) -> usize {
+    data_offset() + align(len)
+}
+
+fn in_addr(ip: Ipv4Addr) -> IN_ADDR {
+    IN_ADDR {
+        S_un: IN_ADDR_0 {
+            S_addr: u32::from(ip).to_be(),
+        },
+    }
+}
+
+fn check(result: i32) -> io::Result<()> {
+    match result {
+        SOCKET_ERROR => Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() })),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,109 @@
+// Free address is probed before it is offered, so that host configured
+// statically inside of pool does not get duplicate (RFC 2131 section 2.2).
+// Echo request goes out through unprivileged ping socket when allowed by
+// net.ipv4.ping_group_range and through raw socket otherwise. Host dropping
+// echo requests still answers ARP kernel sends on the way, on Linux neighbour
+// resolved meanwhile counts as answer too.
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::iputil::checksum;
+
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+const ICMP_HEADER_LEN: usize = 8;
+
+pub async fn in_use(ip: Ipv4Addr, timeout: Duration) -> io::Result<bool> {
+    let (socket, raw) = open()?;
+    // ping socket replaces identifier with its own and filters replies
+    let id = rand::random::<u16>();
+    socket
+        .send_to(&echo_request(id), SocketAddr::from((ip, 0)))
+        .await?;
+
+    let mut buf = [0u8; 1500];
+    let reply = tokio::time::timeout(timeout, async {
+        loop {
+            let (n, from) = socket.recv_from(&mut buf).await?;
+            // raw socket gets IP header and all ICMP traffic of host
+            let header_len = match raw {
+                true => (buf[0] & 0x0f) as usize * 4,
+                false => 0,
+            };
+            let message = &buf[header_len.min(n)..n];
+            if from.ip() == ip
+                && message.len() >= ICMP_HEADER_LEN
+                && message[0] == ECHO_REPLY
+                && (!raw || message[4..6] == id.to_be_bytes())
+            {
+                return Ok::<_, io::Error>(());
+            }
+        }
+    })
+    .await;
+
+    match reply {
+        Ok(result) => result.map(|_| true),
+        Err(_) => Ok(neighbour_resolved(ip)),
+    }
+}
+
+fn open() -> io::Result<(UdpSocket, bool)> {
+    let (socket, raw) = match Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4)) {
+        Ok(x) => (x, false),
+        Err(_) => (
+            Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?,
+            true,
+        ),
+    };
+    socket.set_nonblocking(true)?;
+    // only send and receive are used, which work the same as for UDP
+    Ok((UdpSocket::from_std(socket.into())?, raw))
+}
+
+fn echo_request(id: u16) -> Vec<u8> {
+    let mut message = vec![ECHO_REQUEST, 0, 0, 0];
+    message.extend_from_slice(&id.to_be_bytes());
+    // sequence number
+    message.extend_from_slice(&[0, 1]);
+    message.extend_from_slice(b"pxe address probe");
+    let checksum = checksum(&message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+    message
+}
+
+// /proc/net/arp lists IP, HW type, flags, MAC, mask and device,
+// flags 0x2 mark complete entry
+#[cfg(target_os = "linux")]
+fn neighbour_resolved(ip: Ipv4Addr) -> bool {
+    let table = std::fs::read_to_string("/proc/net/arp").unwrap_or_default();
+    let ip = ip.to_string();
+    table.lines().skip(1).any(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        fields.len() >= 3
+            && fields[0] == ip
+            && u32::from_str_radix(fields[2].trim_start_matches("0x"), 16).unwrap_or(0) & 0x2 != 0
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn neighbour_resolved(_ip: Ipv4Addr) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo_request() {
+        let message = echo_request(0x1234);
+        assert_eq!(message[0], ECHO_REQUEST);
+        assert_eq!(&message[4..8], &[0x12, 0x34, 0, 1]);
+        assert_eq!(checksum(&message), 0);
+    }
+}
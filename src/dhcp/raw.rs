@@ -0,0 +1,221 @@
+// Packet socket transport, DHCP works on interface that has no IPv4 address
+// configured since kernel IP stack is bypassed in both directions.
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use futures_util::task::{Context, Poll};
+use nix::libc;
+use nix::net::if_::if_nametoindex;
+use nix::sys::socket::{
+    bind, recv, sendto, socket, AddressFamily, LinkAddr, MsgFlags, SockAddr, SockFlag, SockType,
+};
+use tokio::io::unix::AsyncFd;
+use tokio::io::ReadBuf;
+
+use super::id::Mac;
+use super::transport::SERVER_PORT;
+use crate::iputil::checksum;
+
+const IP_HEADER_LEN: usize = 20;
+const MAX_IP_HEADER_LEN: usize = 60;
+const UDP_HEADER_LEN: usize = 8;
+const IPPROTO_UDP: u8 = 17;
+pub(super) const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+pub(super) struct Fd(pub(super) RawFd);
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.0);
+    }
+}
+
+pub struct RawSocket {
+    fd: AsyncFd<Fd>,
+    ifindex: u32,
+    // source address of replies
+    server_ip: Ipv4Addr,
+}
+
+impl RawSocket {
+    pub fn open(interface: &str, server_ip: Ipv4Addr) -> io::Result<Self> {
+        let ifindex = if_nametoindex(interface)?;
+        // link layer header is stripped and added by kernel
+        let fd = Fd(socket(
+            AddressFamily::Packet,
+            SockType::Datagram,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            None,
+        )?);
+        bind(fd.0, &link_address(ifindex, libc::ETH_P_IP, [0; 6]))?;
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+            ifindex,
+            server_ip,
+        })
+    }
+
+    // skips everything but datagrams sent to DHCP server port
+    pub fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let mut frame = vec![0u8; buf.remaining() + MAX_IP_HEADER_LEN + UDP_HEADER_LEN];
+        loop {
+            let mut guard = match self.fd.poll_read_ready(cx) {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match guard.try_io(|fd| {
+                recv(fd.as_raw_fd(), &mut frame, MsgFlags::empty()).map_err(io::Error::from)
+            }) {
+                Ok(Ok(n)) => {
+                    if let Some(payload) = udp_payload(&frame[..n], SERVER_PORT) {
+                        let len = payload.len().min(buf.remaining());
+                        buf.put_slice(&payload[..len]);
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    // there is no ARP without IP stack, frame is broadcast unless
+    // MAC of destination is known
+    pub async fn send_to(
+        &self,
+        data: &[u8],
+        destination: SocketAddrV4,
+        mac: Option<&Mac>,
+    ) -> io::Result<()> {
+        let datagram = encapsulate(
+            self.server_ip,
+            *destination.ip(),
+            SERVER_PORT,
+            destination.port(),
+            data,
+        );
+        let address = link_address(
+            self.ifindex,
+            libc::ETH_P_IP,
+            mac.map_or(BROADCAST_MAC, |x| {
+                let mut mac = [0u8; 6];
+                mac.copy_from_slice(&x.get_raw()[..6]);
+                mac
+            }),
+        );
+
+        loop {
+            let mut guard = self.fd.writable().await?;
+            match guard.try_io(|fd| {
+                sendto(fd.as_raw_fd(), &datagram, &address, MsgFlags::empty())
+                    .map_err(io::Error::from)
+            }) {
+                Ok(result) => return result.map(|_| ()),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+pub(super) fn link_address(ifindex: u32, protocol: i32, mac: [u8; 6]) -> SockAddr {
+    let mut sll_addr = [0u8; 8];
+    sll_addr[..6].copy_from_slice(&mac);
+
+    SockAddr::Link(LinkAddr(libc::sockaddr_ll {
+        sll_family: libc::AF_PACKET as u16,
+        sll_protocol: (protocol as u16).to_be(),
+        sll_ifindex: ifindex as i32,
+        sll_hatype: 0,
+        sll_pkttype: 0,
+        sll_halen: 6,
+        sll_addr,
+    }))
+}
+
+// payload of unfragmented UDP datagram sent to given port
+fn udp_payload(packet: &[u8], port: u16) -> Option<&[u8]> {
+    if packet.len() < IP_HEADER_LEN || packet[0] >> 4 != 4 || packet[9] != IPPROTO_UDP {
+        return None;
+    }
+    let header_len = ((packet[0] & 0x0f) as usize) * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    // more fragments flag or non-zero offset
+    if u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0 {
+        return None;
+    }
+    if header_len < IP_HEADER_LEN || total_len > packet.len() {
+        return None;
+    }
+
+    let udp = &packet[header_len..total_len];
+    if udp.len() < UDP_HEADER_LEN || u16::from_be_bytes([udp[2], udp[3]]) != port {
+        return None;
+    }
+    let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    if udp_len < UDP_HEADER_LEN || udp_len > udp.len() {
+        return None;
+    }
+
+    Some(&udp[UDP_HEADER_LEN..udp_len])
+}
+
+// UDP checksum is optional over IPv4 and left out
+fn encapsulate(
+    source: Ipv4Addr,
+    destination: Ipv4Addr,
+    source_port: u16,
+    destination_port: u16,
+    data: &[u8],
+) -> Vec<u8> {
+    let udp_len = UDP_HEADER_LEN + data.len();
+    let total_len = IP_HEADER_LEN + udp_len;
+
+    let mut packet = Vec::with_capacity(total_len);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&(total_len as u16).to_be_bytes());
+    // identification, flags and fragment offset
+    packet.extend_from_slice(&[0, 0, 0, 0]);
+    packet.extend_from_slice(&[64, IPPROTO_UDP, 0, 0]);
+    packet.extend_from_slice(&source.octets());
+    packet.extend_from_slice(&destination.octets());
+    let checksum = checksum(&packet);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    packet.extend_from_slice(&source_port.to_be_bytes());
+    packet.extend_from_slice(&destination_port.to_be_bytes());
+    packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(data);
+
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encapsulate_round_trip() {
+        let packet = encapsulate(
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::BROADCAST,
+            68,
+            67,
+            b"payload",
+        );
+
+        assert_eq!(checksum(&packet[..IP_HEADER_LEN]), 0);
+        assert_eq!(udp_payload(&packet, 67), Some(&b"payload"[..]));
+        assert_eq!(udp_payload(&packet, 68), None);
+        assert_eq!(udp_payload(&packet[..packet.len() - 1], 67), None);
+    }
+}
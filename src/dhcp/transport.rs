@@ -0,0 +1,62 @@
+use std::io;
+use std::net::SocketAddrV4;
+
+use futures_util::task::{Context, Poll};
+use tokio::io::ReadBuf;
+use tokio::net::UdpSocket;
+
+use super::id::Mac;
+use super::pktinfo::PktinfoSocket;
+#[cfg(target_os = "linux")]
+use super::raw::RawSocket;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+// PXE boot server, shared with BINL (see binl module)
+pub const BOOT_SERVER_PORT: u16 = 4011;
+
+// DHCP traffic goes through UDP socket unless packet socket or wildcard
+// bind was requested
+pub enum Transport {
+    Udp(UdpSocket),
+    #[cfg(target_os = "linux")]
+    Raw(RawSocket),
+    Pktinfo(PktinfoSocket),
+    // keeps sent datagrams with their destination, receives nothing
+    #[cfg(test)]
+    Recorded(std::sync::Mutex<Vec<(SocketAddrV4, Vec<u8>)>>),
+}
+
+impl Transport {
+    pub fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self {
+            Self::Udp(socket) => socket.poll_recv(cx, buf),
+            #[cfg(target_os = "linux")]
+            Self::Raw(socket) => socket.poll_recv(cx, buf),
+            Self::Pktinfo(socket) => socket.poll_recv(cx, buf),
+            #[cfg(test)]
+            Self::Recorded(_) => Poll::Pending,
+        }
+    }
+
+    // link layer address is resolved by kernel for UDP socket,
+    // packet socket broadcasts frame unless MAC is given
+    pub async fn send_to(
+        &self,
+        data: &[u8],
+        destination: SocketAddrV4,
+        #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] mac: Option<&Mac>,
+    ) -> io::Result<()> {
+        match self {
+            Self::Udp(socket) => socket.send_to(data, destination).await.map(|_| ()),
+            #[cfg(target_os = "linux")]
+            Self::Raw(socket) => socket.send_to(data, destination, mac).await,
+            Self::Pktinfo(socket) => socket.send_to(data, destination).await,
+            #[cfg(test)]
+            Self::Recorded(sent) => {
+                sent.lock().unwrap().push((destination, data.to_vec()));
+                Ok(())
+            }
+        }
+    }
+}
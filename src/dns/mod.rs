@@ -0,0 +1,160 @@
+// Minimal authoritative DNS responder for networks without any DNS server.
+// Answers A and PTR queries for configured records and hostnames of bound
+// DHCP leases, everything else is forwarded upstream when forwarder is given.
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+
+use crate::config::DnsRecord;
+use packet::{Answer, Query, CLASS_IN, RCODE_NOTIMP, RCODE_NXDOMAIN, TYPE_A, TYPE_ANY, TYPE_PTR};
+
+mod packet;
+
+const TTL: u32 = 60;
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(3);
+// large enough for EDNS replies relayed from upstream
+const MAX_PACKET_SIZE: usize = 4096;
+
+// hostnames clients sent in DHCP requests by leased address, filled by DHCP server
+pub type LeaseNames = Arc<Mutex<BTreeMap<Ipv4Addr, LeaseName>>>;
+
+#[derive(Debug, Clone)]
+pub struct LeaseName {
+    pub hostname: String,
+    pub expires: Instant,
+}
+
+pub async fn start(options: &super::Options, lease_names: &LeaseNames) -> anyhow::Result<()> {
+    let socket = Arc::new(UdpSocket::bind((options.server_ip(), 53)).await?);
+    let resolver = Resolver {
+        records: options.config.dns_records.clone(),
+        lease_names: Arc::clone(lease_names),
+    };
+    let forward = options.dns_forward;
+
+    debug!("server starting");
+
+    let mut buf = [0u8; MAX_PACKET_SIZE];
+    loop {
+        let (n, client) = socket.recv_from(&mut buf).await?;
+        let query = match packet::parse_query(&buf[..n]) {
+            Ok(query) => query,
+            Err(e) => {
+                debug!("invalid query from {}: {}", client, e);
+                continue;
+            }
+        };
+
+        let response = match (resolver.resolve(&query), forward) {
+            (Some(answers), _) => {
+                packet::encode_response(&query, 0, &answers, TTL, forward.is_some())
+            }
+            (None, Some(upstream)) => {
+                tokio::spawn(forward_query(
+                    Arc::clone(&socket),
+                    buf[..n].to_vec(),
+                    client,
+                    upstream,
+                ));
+                continue;
+            }
+            (None, None) if query.qclass != CLASS_IN => {
+                packet::encode_response(&query, RCODE_NOTIMP, &[], TTL, false)
+            }
+            (None, None) => packet::encode_response(&query, RCODE_NXDOMAIN, &[], TTL, false),
+        };
+
+        if let Err(e) = socket.send_to(&response, client).await {
+            error!("failed to answer {}: {}", client, e);
+        }
+    }
+}
+
+struct Resolver {
+    records: Vec<DnsRecord>,
+    lease_names: LeaseNames,
+}
+
+impl Resolver {
+    // None when name is not known here, empty answer when it is
+    // but has no records of requested type
+    fn resolve(&self, query: &Query) -> Option<Vec<Answer>> {
+        if query.qclass != CLASS_IN {
+            return None;
+        }
+
+        if let Some(ip) = packet::parse_reverse_name(&query.name) {
+            let name = self.name_of(ip)?;
+            return Some(match query.qtype {
+                TYPE_PTR | TYPE_ANY => vec![Answer::Ptr(name)],
+                _ => Vec::new(),
+            });
+        }
+
+        let ip = self.address_of(&query.name)?;
+        Some(match query.qtype {
+            TYPE_A | TYPE_ANY => vec![Answer::A(ip)],
+            _ => Vec::new(),
+        })
+    }
+
+    // configured records take precedence over names picked by clients
+    fn address_of(&self, name: &str) -> Option<Ipv4Addr> {
+        if let Some(record) = self
+            .records
+            .iter()
+            .find(|x| x.name.trim_end_matches('.').eq_ignore_ascii_case(name))
+        {
+            return Some(record.ip);
+        }
+
+        let now = Instant::now();
+        self.lease_names
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, x)| x.expires > now && x.hostname == name)
+            .map(|(&ip, _)| ip)
+    }
+
+    fn name_of(&self, ip: Ipv4Addr) -> Option<String> {
+        if let Some(record) = self.records.iter().find(|x| x.ip == ip) {
+            return Some(record.name.trim_end_matches('.').to_lowercase());
+        }
+
+        self.lease_names
+            .lock()
+            .unwrap()
+            .get(&ip)
+            .filter(|x| x.expires > Instant::now())
+            .map(|x| x.hostname.clone())
+    }
+}
+
+async fn forward_query(
+    socket: Arc<UdpSocket>,
+    query: Vec<u8>,
+    client: SocketAddr,
+    upstream: Ipv4Addr,
+) {
+    let result = async {
+        let upstream_socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        upstream_socket.connect((upstream, 53)).await?;
+        upstream_socket.send(&query).await?;
+
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        let n = tokio::time::timeout(FORWARD_TIMEOUT, upstream_socket.recv(&mut buf))
+            .await
+            .map_err(|_| anyhow!("no answer from {}", upstream))??;
+        socket.send_to(&buf[..n], client).await?;
+
+        Ok::<_, anyhow::Error>(())
+    };
+
+    if let Err(e) = result.await {
+        debug!("forwarding query from {} failed: {}", client, e);
+    }
+}
@@ -0,0 +1,201 @@
+use std::io::{Cursor, Write};
+use std::net::Ipv4Addr;
+
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_ANY: u16 = 255;
+pub const CLASS_IN: u16 = 1;
+
+pub const RCODE_NXDOMAIN: u8 = 3;
+pub const RCODE_NOTIMP: u8 = 4;
+
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_RECURSION_AVAILABLE: u16 = 0x0080;
+// opcode and recursion desired are copied from query
+const QUERY_FLAGS_MASK: u16 = 0x7900;
+// pointer to question name which always follows header
+const NAME_POINTER: u16 = 0xc000 | HEADER_LEN as u16;
+
+// first question of standard query, others are ignored
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    pub id: u16,
+    pub flags: u16,
+    // lowercase, without trailing dot
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Answer {
+    A(Ipv4Addr),
+    Ptr(String),
+}
+
+pub fn parse_query(data: &[u8]) -> anyhow::Result<Query> {
+    let mut cursor = Cursor::new(data);
+    let id = cursor.read_u16::<NetworkEndian>()?;
+    let flags = cursor.read_u16::<NetworkEndian>()?;
+    let qdcount = cursor.read_u16::<NetworkEndian>()?;
+    if flags & FLAG_RESPONSE != 0 {
+        bail!("not a query");
+    }
+    if qdcount == 0 {
+        bail!("no question");
+    }
+
+    cursor.set_position(HEADER_LEN as u64);
+    let mut labels = Vec::new();
+    loop {
+        let len = cursor.read_u8()? as usize;
+        if len == 0 {
+            break;
+        }
+        // queries have nothing to point back to, so compression is not expected
+        if len > 63 {
+            bail!("invalid label length {}", len);
+        }
+
+        let start = cursor.position() as usize;
+        let label = data
+            .get(start..start + len)
+            .ok_or_else(|| anyhow!("truncated name"))?;
+        labels.push(String::from_utf8_lossy(label).to_lowercase());
+        cursor.set_position((start + len) as u64);
+    }
+
+    Ok(Query {
+        id,
+        flags,
+        name: labels.join("."),
+        qtype: cursor.read_u16::<NetworkEndian>()?,
+        qclass: cursor.read_u16::<NetworkEndian>()?,
+    })
+}
+
+pub fn encode_response(
+    query: &Query,
+    rcode: u8,
+    answers: &[Answer],
+    ttl: u32,
+    recursion_available: bool,
+) -> Vec<u8> {
+    let mut flags = FLAG_RESPONSE | FLAG_AUTHORITATIVE | (query.flags & QUERY_FLAGS_MASK);
+    if recursion_available {
+        flags |= FLAG_RECURSION_AVAILABLE;
+    }
+    flags |= rcode as u16;
+
+    // writes to Vec do not fail
+    let mut out = Vec::with_capacity(512);
+    out.write_u16::<NetworkEndian>(query.id).unwrap();
+    out.write_u16::<NetworkEndian>(flags).unwrap();
+    out.write_u16::<NetworkEndian>(1).unwrap();
+    out.write_u16::<NetworkEndian>(answers.len() as u16)
+        .unwrap();
+    out.write_u32::<NetworkEndian>(0).unwrap();
+
+    encode_name(&mut out, &query.name);
+    out.write_u16::<NetworkEndian>(query.qtype).unwrap();
+    out.write_u16::<NetworkEndian>(query.qclass).unwrap();
+
+    for answer in answers {
+        let mut rdata = Vec::new();
+        let rtype = match answer {
+            Answer::A(ip) => {
+                rdata.write_all(&ip.octets()).unwrap();
+                TYPE_A
+            }
+            Answer::Ptr(name) => {
+                encode_name(&mut rdata, name);
+                TYPE_PTR
+            }
+        };
+
+        out.write_u16::<NetworkEndian>(NAME_POINTER).unwrap();
+        out.write_u16::<NetworkEndian>(rtype).unwrap();
+        out.write_u16::<NetworkEndian>(CLASS_IN).unwrap();
+        out.write_u32::<NetworkEndian>(ttl).unwrap();
+        out.write_u16::<NetworkEndian>(rdata.len() as u16).unwrap();
+        out.extend_from_slice(&rdata);
+    }
+
+    out
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|x| !x.is_empty()) {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+}
+
+// address from reverse lookup name, e.g. 4.3.2.1.in-addr.arpa
+pub fn parse_reverse_name(name: &str) -> Option<Ipv4Addr> {
+    let mut octets = [0u8; 4];
+    let mut parts = name.strip_suffix(".in-addr.arpa")?.split('.');
+    for octet in octets.iter_mut().rev() {
+        *octet = parts.next()?.parse().ok()?;
+    }
+
+    match parts.next() {
+        Some(_) => None,
+        None => Some(Ipv4Addr::from(octets)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_round_trip() {
+        let query = [
+            0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, b'H',
+            b'o', b's', b't', 0x03, b'l', b'a', b'n', 0x00, 0x00, 0x01, 0x00, 0x01,
+        ];
+        let parsed = parse_query(&query).unwrap();
+        assert_eq!(
+            parsed,
+            Query {
+                id: 0x1234,
+                flags: 0x0100,
+                name: "host.lan".to_string(),
+                qtype: TYPE_A,
+                qclass: CLASS_IN,
+            }
+        );
+
+        let response = encode_response(
+            &parsed,
+            0,
+            &[Answer::A(Ipv4Addr::new(10, 0, 0, 5))],
+            60,
+            false,
+        );
+        assert_eq!(&response[..4], &[0x12, 0x34, 0x85, 0x00]);
+        // question is echoed in lowercase
+        assert_eq!(&response[12..26], b"\x04host\x03lan\x00\x00\x01\x00\x01");
+        assert_eq!(&response[response.len() - 4..], &[10, 0, 0, 5]);
+
+        assert!(parse_query(&response).is_err());
+        assert!(parse_query(&query[..20]).is_err());
+    }
+
+    #[test]
+    fn test_parse_reverse_name() {
+        assert_eq!(
+            parse_reverse_name("5.0.0.10.in-addr.arpa"),
+            Some(Ipv4Addr::new(10, 0, 0, 5))
+        );
+        assert_eq!(parse_reverse_name("0.10.in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("1.5.0.0.10.in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("host.lan"), None);
+    }
+}
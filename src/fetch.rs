@@ -0,0 +1,266 @@
+// Downloads official netboot kernels, initrds and iPXE binaries into TFTP
+// root, verifying them against checksums published next to them, and adds
+// profiles booting them to configuration file.
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::Path;
+
+use anyhow::Context;
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+use crate::config::Config;
+
+struct Asset {
+    name: &'static str,
+    base_url: &'static str,
+    // SHA256SUMS file and prefix of file names listed in it,
+    // None when upstream publishes no checksums
+    checksums: Option<(&'static str, &'static str)>,
+    files: &'static [&'static str],
+    // profiles as (name, boot file), boot file relative to asset directory
+    profiles: &'static [(&'static str, &'static str)],
+    // iPXE script written as boot.ipxe, booted with iPXE loaded over TFTP
+    script: Option<&'static str>,
+}
+
+const ASSETS: &[Asset] = &[
+    Asset {
+        name: "debian-12",
+        base_url: "https://deb.debian.org/debian/dists/bookworm/main/installer-amd64/current/images/netboot/debian-installer/amd64/",
+        checksums: Some((
+            "https://deb.debian.org/debian/dists/bookworm/main/installer-amd64/current/images/SHA256SUMS",
+            "./netboot/debian-installer/amd64/",
+        )),
+        files: &["linux", "initrd.gz"],
+        profiles: &[("debian-12", "boot.ipxe")],
+        script: Some("#!ipxe\nkernel linux initrd=initrd.gz\ninitrd initrd.gz\nboot\n"),
+    },
+    Asset {
+        name: "ubuntu-24.04",
+        base_url: "https://releases.ubuntu.com/24.04/netboot/amd64/",
+        checksums: Some((
+            "https://releases.ubuntu.com/24.04/netboot/SHA256SUMS",
+            "amd64/",
+        )),
+        files: &["linux", "initrd"],
+        profiles: &[("ubuntu-24.04", "boot.ipxe")],
+        // installer needs url= pointing at live server ISO to install from
+        script: Some("#!ipxe\nkernel linux initrd=initrd ip=dhcp\ninitrd initrd\nboot\n"),
+    },
+    Asset {
+        name: "ipxe",
+        base_url: "https://boot.ipxe.org/",
+        checksums: None,
+        files: &["undionly.kpxe", "ipxe.efi", "snponly.efi"],
+        profiles: &[("ipxe-bios", "undionly.kpxe"), ("ipxe-uefi", "ipxe.efi")],
+        script: None,
+    },
+];
+
+fn asset_names() -> Vec<&'static str> {
+    ASSETS.iter().map(|x| x.name).collect()
+}
+
+pub async fn run(root: &Path, config_file: Option<&Path>, names: &[String]) -> anyhow::Result<()> {
+    let assets = names
+        .iter()
+        .map(|name| {
+            ASSETS.iter().find(|x| x.name == name).ok_or_else(|| {
+                anyhow!(
+                    "unknown asset {}, available: {}",
+                    name,
+                    asset_names().join(", ")
+                )
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let client = reqwest::Client::new();
+    let mut profiles = Vec::new();
+    for asset in assets {
+        fetch_asset(&client, root, asset)
+            .await
+            .with_context(|| format!("failed to fetch {}", asset.name))?;
+
+        for (name, boot_file) in asset.profiles {
+            profiles.push((*name, format!("{}/{}", asset.name, boot_file)));
+        }
+    }
+
+    match config_file {
+        Some(path) => record_profiles(path, &profiles),
+        None => {
+            println!("add following profiles to configuration file:");
+            for (name, boot_file) in profiles {
+                print!("{}", profile_entry(name, &boot_file));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn profile_entry(name: &str, boot_file: &str) -> String {
+    format!(
+        "\n[[profile]]\nname = \"{}\"\nboot_file = \"{}\"\n",
+        name, boot_file
+    )
+}
+
+async fn fetch_asset(client: &reqwest::Client, root: &Path, asset: &Asset) -> anyhow::Result<()> {
+    let checksums = match asset.checksums {
+        Some((url, prefix)) => Some(fetch_checksums(client, url, prefix).await?),
+        None => None,
+    };
+
+    let dir = root.join(asset.name);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("failed to create {}", dir.display()))?;
+
+    for file in asset.files {
+        let expected = match checksums.as_ref().map(|x| x.get(*file)) {
+            Some(Some(expected)) => Some(expected),
+            Some(None) => bail!("{} is not listed in checksum file", file),
+            None => None,
+        };
+
+        let url = format!("{}{}", asset.base_url, file);
+        print!("{} ... ", url);
+        std::io::stdout().flush()?;
+
+        // downloaded under temporary name, existing file is replaced
+        // only once new one is verified
+        let partial = dir.join(format!("{}.part", file));
+        let digest = download(client, &url, &partial).await?;
+        match expected {
+            Some(expected) if *expected == digest => println!("ok"),
+            Some(expected) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                bail!(
+                    "checksum mismatch for {}: expected {}, got {}",
+                    file,
+                    expected,
+                    digest
+                );
+            }
+            None => println!(
+                "sha256 {} (no upstream checksums to verify against)",
+                digest
+            ),
+        }
+        tokio::fs::rename(&partial, dir.join(file)).await?;
+    }
+
+    if let Some(script) = asset.script {
+        let path = dir.join("boot.ipxe");
+        tokio::fs::write(&path, script)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+// checksums keyed by file name with given prefix stripped
+async fn fetch_checksums(
+    client: &reqwest::Client,
+    url: &str,
+    prefix: &str,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let data = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    Ok(parse_checksums(&data, prefix))
+}
+
+// sha256sum output, binary mode marker before file name is optional
+fn parse_checksums(data: &str, prefix: &str) -> BTreeMap<String, String> {
+    data.lines()
+        .filter_map(|line| {
+            let (digest, name) = line.split_once(char::is_whitespace)?;
+            let name = name.trim_start().trim_start_matches('*');
+            Some((
+                name.strip_prefix(prefix)?.to_string(),
+                digest.to_lowercase(),
+            ))
+        })
+        .collect()
+}
+
+// returns hex encoded SHA-256 of what was written
+async fn download(client: &reqwest::Client, url: &str, path: &Path) -> anyhow::Result<String> {
+    let response = client.get(url).send().await?.error_for_status()?;
+
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+
+    Ok(hasher.finalize().iter().fold(String::new(), |mut s, x| {
+        write!(s, "{:02x}", x).unwrap();
+        s
+    }))
+}
+
+// appends profiles not defined yet, leaving rest of the file untouched
+fn record_profiles(path: &Path, profiles: &[(&str, String)]) -> anyhow::Result<()> {
+    let config = if path.exists() {
+        Config::load(path)?
+    } else {
+        Config::default()
+    };
+
+    let mut new = String::new();
+    for (name, boot_file) in profiles {
+        if config.profile(name).is_some() {
+            println!("profile {} already defined in {}", name, path.display());
+        } else {
+            println!("adding profile {} to {}", name, path.display());
+            new.push_str(&profile_entry(name, boot_file));
+        }
+    }
+
+    if !new.is_empty() {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(new.as_bytes()))
+            .with_context(|| format!("failed to update {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_checksums() {
+        let data = "\
+0123abcd  ./netboot/debian-installer/amd64/linux
+4567EF01 *./netboot/debian-installer/amd64/initrd.gz
+89ab0000  ./cdrom/vmlinuz
+";
+        let checksums = parse_checksums(data, "./netboot/debian-installer/amd64/");
+        assert_eq!(checksums.len(), 2);
+        assert_eq!(checksums["linux"], "0123abcd");
+        assert_eq!(checksums["initrd.gz"], "4567ef01");
+    }
+}
@@ -0,0 +1,34 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Whole file is rewritten, new one replaces old only once complete.
+// Partial file is named after process, so that instances sharing file do
+// not write into each other's.
+pub fn replace(path: &Path, data: &[u8], private: bool) -> io::Result<()> {
+    let partial = partial_path(path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // readable by owner only
+    #[cfg(unix)]
+    if private {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+
+    let result = options
+        .open(&partial)
+        .and_then(|mut file| file.write_all(data))
+        .and_then(|_| fs::rename(&partial, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
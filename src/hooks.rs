@@ -0,0 +1,283 @@
+// Site policies kept outside the server. Hook is an executable run at
+// a decision point, it gets JSON request on stdin and answers with JSON
+// object on stdout. Fields left out of the answer keep server's own
+// decision. Failing hooks are logged and ignored, except lease hook whose
+// failure denies the lease. Lease event hooks are only told what happened,
+// executable gets event on stdin, URL gets it POSTed, answers are ignored.
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+
+use crate::config::Hooks;
+use crate::dhcp::id::Mac;
+
+// DHCP server waits for hooks, slow ones delay other clients
+const HOOK_TIMEOUT: Duration = Duration::from_secs(5);
+// lease events waiting for slow hooks, later ones are dropped
+const MAX_QUEUED_EVENTS: usize = 1024;
+// file field of BOOTP header holds name with terminating NUL
+const MAX_BOOT_FILE_LEN: usize = 128;
+
+#[derive(Serialize)]
+struct BootFileRequest<'a> {
+    mac: String,
+    arch: Option<u16>,
+    vendor_class: Option<&'a str>,
+    profile: Option<&'a str>,
+    boot_file: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct BootFileResponse {
+    // empty string sends no boot file at all
+    boot_file: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LeaseRequest<'a> {
+    mac: String,
+    ip: Ipv4Addr,
+    hostname: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct LeaseResponse {
+    allow: Option<bool>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseEvent {
+    Offer,
+    Ack,
+    Renew,
+    Release,
+    Expiry,
+}
+
+#[derive(Serialize)]
+struct LeaseEventRequest {
+    event: LeaseEvent,
+    mac: String,
+    ip: Ipv4Addr,
+    hostname: Option<String>,
+    // option 61 in hex, none for clients identified by MAC
+    client_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TftpPathRequest<'a> {
+    client: Ipv4Addr,
+    path: &'a str,
+}
+
+#[derive(Deserialize)]
+struct TftpPathResponse {
+    path: Option<String>,
+}
+
+// boot file chosen by server from profiles is passed in and returned
+// unless hook replaces it
+pub async fn boot_file(
+    hooks: &Hooks,
+    mac: Mac,
+    arch: Option<u16>,
+    vendor_class: Option<&str>,
+    profile: Option<&str>,
+    boot_file: Option<String>,
+) -> Option<String> {
+    let path = match hooks.boot_file.as_deref() {
+        Some(path) => path,
+        None => return boot_file,
+    };
+
+    let request = BootFileRequest {
+        mac: mac.to_string(),
+        arch,
+        vendor_class,
+        profile,
+        boot_file: boot_file.as_deref(),
+    };
+    match run::<BootFileResponse>(path, &request).await {
+        Ok(BootFileResponse {
+            boot_file: Some(file),
+        }) if file.len() >= MAX_BOOT_FILE_LEN => {
+            warn!(
+                "boot file hook {} chose file name of {} bytes, longer than BOOTP allows",
+                path.display(),
+                file.len()
+            );
+            boot_file
+        }
+        Ok(BootFileResponse {
+            boot_file: Some(file),
+        }) => {
+            debug!("boot file hook chose \"{}\"", file);
+            Some(file).filter(|x| !x.is_empty())
+        }
+        Ok(_) => boot_file,
+        Err(e) => {
+            warn!("boot file hook {} failed: {:#}", path.display(), e);
+            boot_file
+        }
+    }
+}
+
+pub async fn allow_lease(hooks: &Hooks, mac: Mac, ip: Ipv4Addr, hostname: Option<&str>) -> bool {
+    let path = match hooks.lease.as_deref() {
+        Some(path) => path,
+        None => return true,
+    };
+
+    let request = LeaseRequest {
+        mac: mac.to_string(),
+        ip,
+        hostname,
+    };
+    match run::<LeaseResponse>(path, &request).await {
+        Ok(response) => response.allow.unwrap_or(true),
+        Err(e) => {
+            warn!("lease hook {} failed, denying: {:#}", path.display(), e);
+            false
+        }
+    }
+}
+
+pub async fn tftp_path(hooks: &Hooks, client: Ipv4Addr, path: String) -> String {
+    let hook = match hooks.tftp_path.as_deref() {
+        Some(hook) => hook,
+        None => return path,
+    };
+
+    let request = TftpPathRequest {
+        client,
+        path: &path,
+    };
+    match run::<TftpPathResponse>(hook, &request).await {
+        Ok(TftpPathResponse {
+            path: Some(rewritten),
+        }) => {
+            if rewritten != path {
+                info!("TFTP path hook rewrote {} to {}", path, rewritten);
+            }
+            rewritten
+        }
+        Ok(_) => path,
+        Err(e) => {
+            warn!("TFTP path hook {} failed: {:#}", hook.display(), e);
+            path
+        }
+    }
+}
+
+// Events are reported one by one in background, in order they happened, so
+// that DHCP server does not wait for hooks.
+pub struct LeaseEvents(mpsc::Sender<(Hooks, LeaseEventRequest)>);
+
+impl LeaseEvents {
+    pub fn start() -> Self {
+        let (sender, receiver) = mpsc::channel(MAX_QUEUED_EVENTS);
+        tokio::spawn(report_lease_events(receiver));
+        Self(sender)
+    }
+
+    pub fn send(
+        &self,
+        hooks: &Hooks,
+        event: LeaseEvent,
+        mac: Mac,
+        ip: Ipv4Addr,
+        hostname: Option<String>,
+        client_id: &[u8],
+    ) {
+        if hooks.lease_event.is_none() && hooks.lease_event_url.is_none() {
+            return;
+        }
+        let request = LeaseEventRequest {
+            event,
+            mac: mac.to_string(),
+            ip,
+            hostname,
+            client_id: Some(client_id)
+                .filter(|x| !x.is_empty())
+                .map(crate::signature::to_hex),
+        };
+        if self.0.try_send((hooks.clone(), request)).is_err() {
+            warn!("lease hooks are too slow, event of {} not reported", ip);
+        }
+    }
+}
+
+async fn report_lease_events(mut events: mpsc::Receiver<(Hooks, LeaseEventRequest)>) {
+    #[cfg(feature = "fetch")]
+    let client = reqwest::Client::builder()
+        .timeout(HOOK_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    while let Some((hooks, request)) = events.recv().await {
+        if let Some(path) = hooks.lease_event.as_deref() {
+            if let Err(e) = execute(path, &request).await {
+                warn!("lease event hook {} failed: {:#}", path.display(), e);
+            }
+        }
+        // validated with configuration
+        #[cfg(feature = "fetch")]
+        if let Some(url) = hooks.lease_event_url.as_deref() {
+            let result = async {
+                client
+                    .post(url)
+                    .header("Content-Type", "application/json")
+                    .body(serde_json::to_vec(&request)?)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok::<_, anyhow::Error>(())
+            };
+            if let Err(e) = result.await {
+                warn!("lease event webhook {} failed: {:#}", url, e);
+            }
+        }
+    }
+}
+
+async fn run<T: DeserializeOwned>(path: &Path, request: &impl Serialize) -> anyhow::Result<T> {
+    let output = execute(path, request).await?;
+    serde_json::from_slice(&output).context("invalid response")
+}
+
+// standard output of hook that succeeded
+async fn execute(path: &Path, request: &impl Serialize) -> anyhow::Result<Vec<u8>> {
+    let request = serde_json::to_vec(request)?;
+    let mut child = Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to start")?;
+    let mut stdin = child.stdin.take().unwrap();
+
+    let output = tokio::time::timeout(HOOK_TIMEOUT, async move {
+        // hooks which decide without reading request may exit early
+        match stdin.write_all(&request).await {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+            _ => drop(stdin),
+        }
+        child.wait_with_output().await
+    })
+    .await
+    .map_err(|_| anyhow!("timed out after {} s", HOOK_TIMEOUT.as_secs()))??;
+
+    if !output.status.success() {
+        bail!("{}", output.status);
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,216 @@
+// Metadata of netbooted clients in the formats cloud-init and Ignition
+// expect, so images built for clouds configure themselves on bare metal.
+// Client is recognized by address it leased from us and gets templates of
+// profile it booted.
+//
+// NoCloud: ds=nocloud-net;s=http://<server>:<port>/nocloud/
+// EC2: /latest/meta-data/..., for cloud-init pointed at us by metadata_urls
+// Ignition: ignition.config.url=http://<server>:<port>/ignition.json
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+use crate::config::Profile;
+use crate::inventory::Client;
+
+const NOCLOUD_PREFIX: &str = "/nocloud/";
+const IGNITION_PATH: &str = "/ignition.json";
+// listed by EC2 meta-data index, leaves only
+const EC2_KEYS: &[&str] = &[
+    "hostname",
+    "instance-id",
+    "local-hostname",
+    "local-ipv4",
+    "mac",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Document {
+    // NoCloud
+    MetaData,
+    VendorData,
+    // NoCloud and EC2
+    UserData,
+    // EC2, key is empty for index
+    Ec2MetaData(String),
+    Ignition,
+}
+
+impl Document {
+    pub fn parse(path: &str) -> Option<Self> {
+        if let Some(name) = path.strip_prefix(NOCLOUD_PREFIX) {
+            return match name {
+                "meta-data" => Some(Self::MetaData),
+                "user-data" => Some(Self::UserData),
+                "vendor-data" => Some(Self::VendorData),
+                _ => None,
+            };
+        }
+        if path == IGNITION_PATH {
+            return Some(Self::Ignition);
+        }
+
+        // EC2 paths start with API version, either latest or a date
+        let (version, rest) = path.strip_prefix('/')?.split_once('/')?;
+        let is_date =
+            version.len() == 10 && version.chars().all(|x| x.is_ascii_digit() || x == '-');
+        if version != "latest" && !is_date {
+            return None;
+        }
+        match rest {
+            "user-data" => Some(Self::UserData),
+            rest => rest
+                .strip_prefix("meta-data")
+                .filter(|x| x.is_empty() || x.starts_with('/'))
+                .map(|x| Self::Ec2MetaData(x.trim_matches('/').to_string())),
+        }
+    }
+}
+
+// values substituted in templates
+#[derive(Debug)]
+pub struct Context<'a> {
+    pub server_ip: Ipv4Addr,
+    pub http_port: u16,
+    pub http_auth: &'a str,
+    pub client: &'a Client,
+    pub profile: Option<&'a Profile>,
+}
+
+impl Context<'_> {
+    // UUID survives NIC replacement, MAC is there for clients without one
+    fn instance_id(&self) -> String {
+        match self.client.uuid.as_deref() {
+            Some(uuid) => format!("pxe-{}", uuid.to_lowercase()),
+            None => format!("pxe-{}", self.client.mac.to_string().replace(':', "")),
+        }
+    }
+
+    fn hostname(&self) -> String {
+        self.client
+            .hostname
+            .clone()
+            .unwrap_or_else(|| self.instance_id())
+    }
+
+    fn ip(&self) -> String {
+        self.client.ip.map(|x| x.to_string()).unwrap_or_default()
+    }
+
+    fn expand(&self, template: &str) -> String {
+        template
+            .replace("{{server_ip}}", &self.server_ip.to_string())
+            .replace("{{http_port}}", &self.http_port.to_string())
+            .replace("{{http_auth}}", self.http_auth)
+            .replace("{{profile}}", self.profile.map_or("", |x| x.name.as_str()))
+            .replace("{{instance_id}}", &self.instance_id())
+            .replace("{{hostname}}", &self.hostname())
+            .replace("{{mac}}", &self.client.mac.to_string())
+            .replace("{{ip}}", &self.ip())
+    }
+}
+
+// returns content type and body, error when document does not exist
+pub async fn render(
+    document: &Document,
+    context: &Context<'_>,
+) -> anyhow::Result<(&'static str, String)> {
+    let template = |select: fn(&Profile) -> Option<&PathBuf>| context.profile.and_then(select);
+
+    match document {
+        Document::MetaData => Ok((
+            "text/yaml",
+            format!(
+                "instance-id: {}\nlocal-hostname: {}\n",
+                context.instance_id(),
+                context.hostname()
+            ),
+        )),
+        // NoCloud requires user data, empty configuration changes nothing
+        Document::UserData => match template(|x| x.user_data.as_ref()) {
+            Some(path) => Ok((
+                "text/plain",
+                context.expand(&tokio::fs::read_to_string(path).await?),
+            )),
+            None => Ok(("text/yaml", "#cloud-config\n{}\n".to_string())),
+        },
+        Document::VendorData => Ok(("text/plain", String::new())),
+        Document::Ec2MetaData(key) => {
+            let value = match key.as_str() {
+                "" => EC2_KEYS.join("\n"),
+                "instance-id" => context.instance_id(),
+                "hostname" | "local-hostname" => context.hostname(),
+                "local-ipv4" => context.ip(),
+                "mac" => context.client.mac.to_string(),
+                key => bail!("no meta-data {}", key),
+            };
+            Ok(("text/plain", value))
+        }
+        Document::Ignition => match template(|x| x.ignition.as_ref()) {
+            Some(path) => Ok((
+                "application/json",
+                context.expand(&tokio::fs::read_to_string(path).await?),
+            )),
+            None => bail!("profile has no Ignition config"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Document::parse("/nocloud/meta-data"),
+            Some(Document::MetaData)
+        );
+        assert_eq!(Document::parse("/ignition.json"), Some(Document::Ignition));
+        assert_eq!(
+            Document::parse("/latest/user-data"),
+            Some(Document::UserData)
+        );
+        assert_eq!(
+            Document::parse("/2009-04-04/meta-data/"),
+            Some(Document::Ec2MetaData(String::new()))
+        );
+        assert_eq!(
+            Document::parse("/latest/meta-data/local-ipv4"),
+            Some(Document::Ec2MetaData("local-ipv4".to_string()))
+        );
+        assert_eq!(Document::parse("/latest/meta-datax"), None);
+        assert_eq!(Document::parse("/nocloud/network"), None);
+        assert_eq!(Document::parse("/debian/linux"), None);
+    }
+
+    #[tokio::test]
+    async fn test_render() {
+        let mut client = Client::new("52:54:00:12:34:56".parse().unwrap());
+        client.ip = Some(Ipv4Addr::new(10, 0, 0, 100));
+        let context = Context {
+            server_ip: Ipv4Addr::new(10, 0, 0, 1),
+            http_port: 8080,
+            http_auth: "",
+            client: &client,
+            profile: None,
+        };
+
+        assert_eq!(
+            render(&Document::MetaData, &context).await.unwrap().1,
+            "instance-id: pxe-525400123456\nlocal-hostname: pxe-525400123456\n"
+        );
+        assert_eq!(
+            render(&Document::Ec2MetaData("local-ipv4".to_string()), &context)
+                .await
+                .unwrap()
+                .1,
+            "10.0.0.100"
+        );
+        assert!(
+            render(&Document::Ec2MetaData("ami-id".to_string()), &context)
+                .await
+                .is_err()
+        );
+        assert!(render(&Document::Ignition, &context).await.is_err());
+    }
+}
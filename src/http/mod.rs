@@ -1,56 +1,220 @@
 use std::io;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use crate::bootcfg::{self, Generator};
+use crate::capture;
+use crate::config::Profile;
+use crate::dhcp;
+use crate::dhcp::id::Mac;
+use crate::inventory::{self, Inventory, State};
+use crate::iputil::{self, Ipv4AddrAndMask};
+use crate::sessions::{self, Sessions};
+use crate::signature::Verifier;
+use crate::sockutil;
+use crate::stats::{self, Stats};
 use crate::tftp::pathutils;
 use futures_util::task::{Context, Poll};
 use futures_util::{future, FutureExt, StreamExt};
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn, Service};
 use hyper::{header, Body, Method, Request, Response, StatusCode};
 use tokio::fs::{File, OpenOptions};
 use tokio_util::codec::{BytesCodec, FramedRead};
+use tracing::Instrument;
 
-pub async fn start(options: &super::Options) {
+mod metadata;
+
+pub async fn start(
+    options: &super::Options,
+    stats: &Arc<Stats>,
+    sessions: &Sessions,
+    inventory: &Inventory,
+    dhcp: Option<dhcp::Handle>,
+) -> anyhow::Result<()> {
     if let Some(root) = options.tftp_root.clone() {
-        let config = Arc::new(Config { root });
+        let config = Arc::new(Config {
+            root,
+            profiles: options.config.profiles.clone(),
+            server_ip: options.server_ip(),
+            http_port: options.http_port,
+            nbd_port: options.nbd_port,
+            generator: Generator::new(options, inventory),
+            stats: Arc::clone(stats),
+            sessions: Arc::clone(sessions),
+            inventory: Arc::clone(inventory),
+            dhcp,
+            verifier: options.verify_key.map(Verifier::new),
+            client_subnets: options.client_subnet.clone(),
+            authorization: options
+                .http_credentials
+                .as_deref()
+                .map(|x| format!("Basic {}", base64(x.as_bytes()))),
+            http_auth: options
+                .http_credentials
+                .as_deref()
+                .map_or(String::new(), |x| format!("{}@", x)),
+        });
 
-        let make_service = make_service_fn(move |_| {
+        let make_service = make_service_fn(move |conn: &AddrStream| {
             let config = Arc::clone(&config);
+            let remote_addr = conn.remote_addr();
+            let connection = capture::TcpConnection::new(
+                SocketAddr::from((config.server_ip, config.http_port)),
+                remote_addr,
+            );
 
-            let service = service_fn(move |req| {
+            let service = service_fn(move |req: Request<Body>| {
+                // captured sequence numbers are dropped with connection
+                let _ = &connection;
                 let config = Arc::clone(&config);
-                Server { config }.serve(req).map(Ok::<_, hyper::Error>)
+                let span = info_span!("http", client = %remote_addr, path = %req.uri().path());
+                Server {
+                    config,
+                    client: remote_addr,
+                }
+                .serve(req)
+                .instrument(span)
+                .map(Ok::<_, hyper::Error>)
             });
 
             future::ok::<_, hyper::Error>(service)
         });
 
-        if let Err(e) =
-            hyper::Server::bind(&SocketAddr::from((options.server_ip, options.http_port)))
-                .serve(make_service)
-                .await
-        {
-            error!("{}", e)
+        let listeners = sockutil::bind_tcp(
+            SocketAddr::from((options.server_ip(), options.http_port)),
+            options.workers,
+        )?;
+        // task per listener, so that workers run on separate threads
+        let workers = listeners
+            .into_iter()
+            .map(|listener| {
+                hyper::Server::from_tcp(listener)
+                    .map(|server| tokio::spawn(server.serve(make_service.clone())))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for worker in future::join_all(workers).await {
+            worker.map_err(io::Error::from)??;
         }
     }
+
+    Ok(())
 }
 
 #[derive(Debug)]
 struct Config {
     pub root: PathBuf,
+    pub profiles: Vec<Profile>,
+    pub server_ip: Ipv4Addr,
+    pub http_port: u16,
+    pub nbd_port: u16,
+    pub generator: Generator,
+    pub stats: Arc<Stats>,
+    pub sessions: Sessions,
+    pub inventory: Inventory,
+    // tells which client asks for metadata, none is served without it
+    pub dhcp: Option<dhcp::Handle>,
+    pub verifier: Option<Arc<Verifier>>,
+    pub client_subnets: Vec<Ipv4AddrAndMask>,
+    // expected Authorization header
+    pub authorization: Option<String>,
+    // user:password@ prefix of host in URLs, empty without credentials
+    pub http_auth: String,
 }
 
 #[derive(Debug)]
 struct Server {
     pub config: Arc<Config>,
+    pub client: SocketAddr,
 }
 
 impl Server {
     async fn serve(self, req: Request<Body>) -> Response<Body> {
+        if !capture::enabled() {
+            return self.respond(req).await;
+        }
+
+        // only heads and body are captured, hyper does not expose raw bytes
+        let local = SocketAddr::from((self.config.server_ip, self.config.http_port));
+        let client = self.client;
+        let mut head = format!("{} {} {:?}\r\n", req.method(), req.uri(), req.version());
+        write_headers(&mut head, req.headers());
+        capture::tcp(client, local, head.as_bytes());
+
+        let response = self.respond(req).await;
+        let mut head = format!("{:?} {}\r\n", response.version(), response.status());
+        write_headers(&mut head, response.headers());
+        capture::tcp(local, client, head.as_bytes());
+
+        response.map(|body| {
+            Body::wrap_stream(body.map(move |chunk| {
+                if let Ok(data) = &chunk {
+                    capture::tcp(local, client, data);
+                }
+                chunk
+            }))
+        })
+    }
+
+    async fn respond(&self, req: Request<Body>) -> Response<Body> {
+        if !iputil::allowed(&self.config.client_subnets, self.client.ip()) {
+            warn!("refused client outside of allowed subnets");
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::empty())
+                .unwrap();
+        }
+        if let Some(expected) = self.config.authorization.as_deref() {
+            let given = req
+                .headers()
+                .get(header::AUTHORIZATION)
+                .map_or(&[][..], |x| x.as_bytes());
+            // compared in constant time, so that timing does not reveal
+            // how much of guessed credentials is right
+            #[allow(deprecated)]
+            let valid =
+                ring::constant_time::verify_slices_are_equal(given, expected.as_bytes()).is_ok();
+            if !valid {
+                warn!("refused client without valid credentials");
+                return Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(header::WWW_AUTHENTICATE, "Basic realm=\"pxe\"")
+                    .body(Body::empty())
+                    .unwrap();
+            }
+        }
+
+        if req.method() == Method::POST {
+            if let Some(mac) = req.uri().path().strip_prefix("/provisioned") {
+                return self.mark_provisioned(mac.trim_start_matches('/')).await;
+            }
+        }
+
         if req.method() == Method::GET {
-            match self.serve_file(req.uri().path()).await {
-                Ok(response) => response,
+            let path = req.uri().path();
+            let result = if let Some(name) = path
+                .strip_prefix("/profiles/")
+                .and_then(|x| x.strip_suffix(".ipxe"))
+            {
+                self.serve_ipxe_script(name).await
+            } else if let Some(mac) = path.strip_prefix("/logs/") {
+                self.serve_log(mac)
+            } else if let Some(document) = metadata::Document::parse(path) {
+                self.serve_metadata(&document).await
+            } else {
+                self.serve_file(path).await
+            };
+
+            match result {
+                Ok(response) => {
+                    // counted once response starts, HTTP clients rarely
+                    // abort downloads they asked for
+                    if let IpAddr::V4(ip) = self.client.ip() {
+                        sessions::fetched(&self.config.sessions, ip, path);
+                    }
+                    response
+                }
                 Err(e) => {
                     error!("{}", e);
                     self.respond_404().await
@@ -62,6 +226,61 @@ impl Server {
         }
     }
 
+    // called by installer once done, either with MAC of host or without it
+    // in which case host is looked up by address it was given
+    async fn mark_provisioned(&self, mac: &str) -> Response<Body> {
+        let respond = |status, text: String| {
+            Response::builder()
+                .status(status)
+                .header(header::CONTENT_TYPE, "text/plain")
+                .body(Body::from(text))
+                .unwrap()
+        };
+
+        // lock is not held across saving
+        let provisioned = {
+            let mut inventory = self.config.inventory.lock().unwrap();
+            let mac = if mac.is_empty() {
+                let client = match self.client.ip() {
+                    IpAddr::V4(ip) => inventory.by_ip(ip),
+                    IpAddr::V6(_) => None,
+                };
+                match client {
+                    Some(x) => x.mac,
+                    None => {
+                        return respond(
+                            StatusCode::NOT_FOUND,
+                            format!("no host with address {}\n", self.client.ip()),
+                        )
+                    }
+                }
+            } else {
+                match mac.parse::<Mac>() {
+                    Ok(x) => x,
+                    Err(e) => return respond(StatusCode::BAD_REQUEST, format!("{}\n", e)),
+                }
+            };
+
+            inventory.provisioned(&mac).map(|x| (mac, x))
+        };
+        let provisioned = match provisioned {
+            Ok((mac, true)) => inventory::flush(&self.config.inventory)
+                .await
+                .map(|_| (mac, true)),
+            x => x,
+        };
+        match provisioned {
+            Ok((mac, true)) => respond(StatusCode::OK, format!("{} boots from local disk\n", mac)),
+            Ok((mac, false)) => {
+                respond(StatusCode::CONFLICT, format!("{} is not installing\n", mac))
+            }
+            Err(e) => {
+                error!("failed to save inventory: {:#}", e);
+                respond(StatusCode::INTERNAL_SERVER_ERROR, String::new())
+            }
+        }
+    }
+
     async fn respond_404(&self) -> Response<Body> {
         Response::builder()
             .status(StatusCode::NOT_FOUND)
@@ -70,7 +289,22 @@ impl Server {
     }
 
     async fn serve_file(&self, file_name: &str) -> anyhow::Result<Response<Body>> {
-        let file = self.open_file(file_name, false).await?;
+        // files in root take precedence over generated ones
+        let file = match self.open_file(file_name, false).await {
+            Ok(file) => file,
+            Err(e) => match self.config.generator.generate(file_name) {
+                Some(data) => {
+                    info!("serving generated {}", file_name);
+                    return Ok(Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "text/plain")
+                        .header(header::CONTENT_LENGTH, data.len())
+                        .body(Body::from(data))
+                        .unwrap());
+                }
+                None => return Err(e),
+            },
+        };
 
         let len = if let Some(metadata) = file.metadata().await.ok() {
             Some(metadata.len())
@@ -78,10 +312,25 @@ impl Server {
             None
         };
 
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        let file = crate::uring::reader(file).await;
+
         info!("commencing {} transfer", file_name);
+        stats::incr(&self.config.stats.http.started);
 
+        let mut progress = Progress {
+            stats: Arc::clone(&self.config.stats),
+            expected: len,
+            sent: 0,
+        };
         let codec = BytesCodec::new();
-        let stream = FramedRead::new(file, codec).map(|x| x.map(bytes::BytesMut::freeze));
+        let stream = FramedRead::new(file, codec).map(move |x| {
+            if let Ok(chunk) = x.as_ref() {
+                progress.sent += chunk.len() as u64;
+                stats::add(&progress.stats.http.bytes, chunk.len() as u64);
+            }
+            x.map(bytes::BytesMut::freeze)
+        });
         let body = Body::wrap_stream(stream);
         let mut builder = Response::builder().status(StatusCode::OK);
         if let Some(len) = len {
@@ -91,12 +340,148 @@ impl Server {
         Ok(builder.body(body).unwrap())
     }
 
+    // syslog collected during boot session of client
+    fn serve_log(&self, mac: &str) -> anyhow::Result<Response<Body>> {
+        let mac: Mac = mac.parse()?;
+        let sessions = self.config.sessions.lock().unwrap();
+        let session = sessions
+            .get(&mac)
+            .ok_or_else(|| anyhow!("no boot session of {}", mac))?;
+        let log = session
+            .log
+            .iter()
+            .fold(String::new(), |mut out, (time, line)| {
+                out += &format!("{} {}\n", humantime::format_rfc3339_seconds(*time), line);
+                out
+            });
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .header(header::CONTENT_LENGTH, log.len())
+            .body(Body::from(log))
+            .unwrap())
+    }
+
+    async fn serve_metadata(
+        &self,
+        document: &metadata::Document,
+    ) -> anyhow::Result<Response<Body>> {
+        let ip = match self.client.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(ip) => bail!("no lease for {}", ip),
+        };
+        // inventory keeps last address of each client, which may have
+        // been given to another one since, lease is what counts
+        let mac = match self.config.dhcp.as_ref() {
+            Some(dhcp) => dhcp.client_of(ip).await?,
+            None => None,
+        }
+        .ok_or_else(|| anyhow!("no lease for {}", ip))?;
+        let client = self
+            .config
+            .inventory
+            .lock()
+            .unwrap()
+            .get(&mac)
+            .cloned()
+            .ok_or_else(|| anyhow!("{} holding {} is not in inventory", mac, ip))?;
+        let context = metadata::Context {
+            server_ip: self.config.server_ip,
+            http_port: self.config.http_port,
+            http_auth: &self.config.http_auth,
+            client: &client,
+            profile: self
+                .config
+                .profiles
+                .iter()
+                .find(|x| Some(&x.name) == client.profile.as_ref()),
+        };
+        let (content_type, data) = metadata::render(document, &context).await?;
+
+        info!("serving {:?} of {}", document, client.mac);
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CONTENT_LENGTH, data.len())
+            .body(Body::from(data))
+            .unwrap())
+    }
+
+    fn boots_locally(&self) -> bool {
+        let ip = match self.client.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => return false,
+        };
+        matches!(
+            self.config.inventory.lock().unwrap().by_ip(ip),
+            Some(x) if x.state == State::Provisioned
+        )
+    }
+
+    async fn serve_ipxe_script(&self, profile_name: &str) -> anyhow::Result<Response<Body>> {
+        // iPXE still chainloading scripts after install falls through to
+        // next boot device
+        if self.boots_locally() {
+            info!("serving local boot script");
+            let script = bootcfg::IPXE_LOCAL_BOOT;
+            return Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "text/plain")
+                .header(header::CONTENT_LENGTH, script.len())
+                .body(Body::from(script))
+                .unwrap());
+        }
+
+        let profile = self
+            .config
+            .profiles
+            .iter()
+            .find(|p| p.name == profile_name)
+            .ok_or_else(|| anyhow!("unknown profile {}", profile_name))?;
+        let nbd_root = profile.nbd_export.as_deref().map_or(String::new(), |x| {
+            crate::nbd::root_path(self.config.server_ip, self.config.nbd_port, x)
+        });
+        let root_path = profile
+            .root_path(self.config.server_ip, self.config.nbd_port)
+            .unwrap_or_default();
+
+        let script = match (profile.ipxe_template.as_deref(), profile.iscsi.as_ref()) {
+            // template is read on every request so it can be edited without restart
+            (Some(template), _) => tokio::fs::read_to_string(template)
+                .await?
+                .replace("{{server_ip}}", &self.config.server_ip.to_string())
+                .replace("{{http_port}}", &self.config.http_port.to_string())
+                .replace("{{http_auth}}", &self.config.http_auth)
+                .replace("{{boot_file}}", &profile.boot_file)
+                .replace("{{profile}}", &profile.name)
+                .replace("{{root_path}}", &root_path)
+                .replace("{{nbd_root}}", &nbd_root),
+            (None, Some(iscsi)) => format!("#!ipxe\nsanboot {}\n", iscsi),
+            (None, None) => bail!("profile {} has no iPXE template", profile_name),
+        };
+
+        info!("serving iPXE script for profile {}", profile.name);
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .header(header::CONTENT_LENGTH, script.len())
+            .body(Body::from(script))
+            .unwrap())
+    }
+
     async fn open_file(&self, file: &str, write: bool) -> anyhow::Result<File> {
         let path = pathutils::append_path(
             self.config.root.as_path(),
             pathutils::convert_path(file)?.as_path(),
         )?;
 
+        if let (Some(verifier), false) = (self.config.verifier.as_ref(), write) {
+            return Ok(File::from_std(verifier.open(&path).await?));
+        }
+
         Ok(OpenOptions::new()
             .read(!write)
             .write(write)
@@ -104,3 +489,69 @@ impl Server {
             .await?)
     }
 }
+
+// Tracks streamed response body, transfer is counted as completed
+// only if whole file was sent before body got dropped.
+struct Progress {
+    stats: Arc<Stats>,
+    expected: Option<u64>,
+    sent: u64,
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        match self.expected {
+            Some(len) if len != self.sent => stats::incr(&self.stats.http.failed),
+            _ => stats::incr(&self.stats.http.completed),
+        }
+    }
+}
+
+fn write_headers(out: &mut String, headers: &header::HeaderMap) {
+    for (name, value) in headers {
+        out.push_str(&format!(
+            "{}: {}\r\n",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        ));
+    }
+    out.push_str("\r\n");
+}
+
+// standard alphabet with padding, as used by Basic authentication
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &x)| n | (x as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(
+            base64(b"Aladdin:open sesame"),
+            "QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"abc"), "YWJj");
+        assert_eq!(base64(b""), "");
+    }
+}
@@ -0,0 +1,559 @@
+// Every client DHCP server has ever seen, with what it told about itself,
+// profile it was given and where it is in its provisioning lifecycle. Kept
+// in a file so it survives restarts and can later back reservations.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::dhcp::id::Mac;
+use crate::fsutil;
+
+// shared by DHCP servers of all instances and control socket
+pub type Inventory = Arc<Mutex<Store>>;
+
+// how often changes recorded by DHCP servers are written
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Provisioning lifecycle of host, hosts missing from inventory are unknown.
+// Discovered hosts boot profile picked by selectors, installing ones boot
+// install profile until they report success, from then on they boot from
+// local disk. Retired hosts are not answered at all.
+//
+//   unknown -> discovered -> installing -> provisioned
+//
+// Installing and provisioned hosts can go back to discovered, provisioned
+// ones can be installed again, and any host can be retired, after which it
+// has to be discovered again.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    #[default]
+    Discovered,
+    // values of boot_once in older inventories
+    #[serde(alias = "install")]
+    Installing,
+    #[serde(alias = "local")]
+    Provisioned,
+    Retired,
+}
+
+impl State {
+    // only installation makes host provisioned, retired
+    // hosts have to be discovered again first
+    pub fn can_become(self, next: Self) -> bool {
+        match (self, next) {
+            (current, next) if current == next => true,
+            (Self::Retired, next) => next == Self::Discovered,
+            (current, Self::Provisioned) => current == Self::Installing,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Discovered => write!(f, "discovered"),
+            Self::Installing => write!(f, "installing"),
+            Self::Provisioned => write!(f, "provisioned"),
+            Self::Retired => write!(f, "retired"),
+        }
+    }
+}
+
+impl std::str::FromStr for State {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "discovered" => Ok(Self::Discovered),
+            "installing" => Ok(Self::Installing),
+            "provisioned" => Ok(Self::Provisioned),
+            "retired" => Ok(Self::Retired),
+            _ => bail!("expected discovered, installing, provisioned or retired"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BmcProtocol {
+    #[default]
+    Ipmi,
+    Redfish,
+}
+
+impl fmt::Display for BmcProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ipmi => write!(f, "IPMI"),
+            Self::Redfish => write!(f, "Redfish"),
+        }
+    }
+}
+
+impl std::str::FromStr for BmcProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ipmi" => Ok(Self::Ipmi),
+            "redfish" => Ok(Self::Redfish),
+            _ => bail!("expected ipmi or redfish"),
+        }
+    }
+}
+
+// baseboard management controller of host, used by power command
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bmc {
+    // host name or IP address, optionally with port
+    pub address: String,
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub protocol: BmcProtocol,
+    // Redfish BMCs mostly come with self-signed certificates
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+// keeps password out of logs
+impl fmt::Debug for Bmc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Bmc")
+            .field("address", &self.address)
+            .field("username", &self.username)
+            .field("protocol", &self.protocol)
+            .field("insecure", &self.insecure)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Client {
+    pub mac: Mac,
+    // from client machine identifier option
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor_class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    // last address acknowledged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<Ipv4Addr>,
+    #[serde(default, alias = "boot_once")]
+    pub state: State,
+    // booted instead of selected profile while installing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_profile: Option<String>,
+    // seconds since Unix epoch
+    pub first_boot: u64,
+    pub last_boot: u64,
+    pub boots: u64,
+    // tables have to follow plain values in TOML
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bmc: Option<Bmc>,
+}
+
+impl Client {
+    pub fn new(mac: Mac) -> Self {
+        Self {
+            mac,
+            uuid: None,
+            arch: None,
+            vendor_class: None,
+            hostname: None,
+            profile: None,
+            ip: None,
+            state: State::Discovered,
+            install_profile: None,
+            first_boot: 0,
+            last_boot: 0,
+            boots: 0,
+            bmc: None,
+        }
+    }
+
+    // MAC, UUID, hostname or profile, case insensitive
+    pub fn matches(&self, filter: &str) -> bool {
+        if let Ok(mac) = filter.parse::<Mac>() {
+            return self.mac == mac;
+        }
+        [&self.uuid, &self.hostname, &self.profile]
+            .iter()
+            .any(|x| x.as_deref().map(|x| x.eq_ignore_ascii_case(filter)) == Some(true))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct InventoryFile {
+    #[serde(default, rename = "client")]
+    clients: Vec<Client>,
+}
+
+// Changes are only marked in store, file is written by flush
+#[derive(Debug, Default)]
+pub struct Store {
+    // None keeps inventory in memory only
+    path: Option<PathBuf>,
+    clients: BTreeMap<Mac, Client>,
+    // changed since file was last written
+    dirty: bool,
+    // held while file is written, see flush
+    writer: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Store {
+    // missing file is created on first update
+    pub fn open(path: Option<&Path>) -> anyhow::Result<Self> {
+        let clients = match path {
+            Some(path) if path.exists() => {
+                let data = fs::read_to_string(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                toml::from_str::<InventoryFile>(&data)
+                    .with_context(|| format!("failed to parse {}", path.display()))?
+                    .clients
+            }
+            _ => Vec::new(),
+        };
+
+        Ok(Self {
+            path: path.map(Path::to_path_buf),
+            clients: clients.into_iter().map(|x| (x.mac, x)).collect(),
+            ..Self::default()
+        })
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+
+    pub fn get(&self, mac: &Mac) -> Option<&Client> {
+        self.clients.get(mac)
+    }
+
+    // address is kept only while leased, see record and forget_ip
+    #[cfg(any(feature = "http", test))]
+    pub fn by_ip(&self, ip: Ipv4Addr) -> Option<&Client> {
+        self.clients.values().find(|x| x.ip == Some(ip))
+    }
+
+    // Hosts can be moved before they ever booted, install profile is kept
+    // only while installing, None there means profile picked by selectors.
+    // Returns previous state.
+    pub fn set_state(
+        &mut self,
+        mac: Mac,
+        state: State,
+        install_profile: Option<String>,
+    ) -> anyhow::Result<State> {
+        let client = self.clients.entry(mac).or_insert_with(|| Client::new(mac));
+        let previous = client.state;
+        if !previous.can_become(state) {
+            bail!("{} is {} and cannot become {}", mac, previous, state);
+        }
+        client.state = state;
+        client.install_profile = install_profile.filter(|_| state == State::Installing);
+        if previous != state {
+            info!("{} is {}, was {}", mac, state, previous);
+        }
+
+        self.dirty = true;
+        Ok(previous)
+    }
+
+    // exactly one client matching filter, see Client::matches
+    pub fn find(&self, filter: &str) -> anyhow::Result<&Client> {
+        let mut found = self.clients().filter(|x| x.matches(filter));
+        match (found.next(), found.next()) {
+            (Some(client), None) => Ok(client),
+            (Some(_), Some(_)) => bail!("{} matches more than one client", filter),
+            (None, _) => bail!("no client matches {}", filter),
+        }
+    }
+
+    pub fn set_bmc(&mut self, mac: Mac, bmc: Option<Bmc>) {
+        self.clients
+            .entry(mac)
+            .or_insert_with(|| Client::new(mac))
+            .bmc = bmc;
+
+        self.dirty = true;
+    }
+
+    // reported by installer, false if host was not installing
+    pub fn provisioned(&mut self, mac: &Mac) -> anyhow::Result<bool> {
+        match self.clients.get(mac) {
+            Some(client) if client.state == State::Installing => {
+                self.set_state(*mac, State::Provisioned, None)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // lease of address to client ended
+    pub fn forget_ip(&mut self, mac: &Mac, ip: Ipv4Addr) {
+        if let Some(client) = self.clients.get_mut(mac).filter(|x| x.ip == Some(ip)) {
+            client.ip = None;
+            self.dirty = true;
+        }
+    }
+
+    // merges what client sent now with what is known about it,
+    // details it did not repeat are kept, boot counts DISCOVERs
+    pub fn record(&mut self, seen: Client, boot: bool) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+
+        // address moved on to this client, whoever had it before lost it
+        if let Some(ip) = seen.ip {
+            for other in self.clients.values_mut() {
+                if other.mac != seen.mac && other.ip == Some(ip) {
+                    other.ip = None;
+                    self.dirty = true;
+                }
+            }
+        }
+
+        let previous = self.clients.get(&seen.mac).cloned();
+        let client = self.clients.entry(seen.mac).or_insert_with(|| {
+            info!("new client {}", seen.mac);
+            Client {
+                first_boot: now,
+                ..Client::new(seen.mac)
+            }
+        });
+        client.uuid = seen.uuid.or_else(|| client.uuid.take());
+        client.arch = seen.arch.or(client.arch);
+        client.vendor_class = seen.vendor_class.or_else(|| client.vendor_class.take());
+        client.hostname = seen.hostname.or_else(|| client.hostname.take());
+        client.ip = seen.ip.or(client.ip);
+        // no longer matching any profile is worth recording too
+        client.profile = seen.profile;
+        // hosts moved to installing before they ever booted have none yet
+        if client.first_boot == 0 {
+            client.first_boot = now;
+        }
+        if boot {
+            client.last_boot = now;
+            client.boots += 1;
+        }
+
+        if previous.as_ref() != Some(client) {
+            self.dirty = true;
+        }
+    }
+
+    // contents of file if anything changed since it was last written
+    fn changes(&mut self) -> anyhow::Result<Option<(PathBuf, String)>> {
+        let path = match self.path.as_ref() {
+            Some(path) if self.dirty => path.clone(),
+            _ => return Ok(None),
+        };
+
+        let data = toml::to_string(&InventoryFile {
+            clients: self.clients.values().cloned().collect(),
+        })?;
+        self.dirty = false;
+        Ok(Some((path, data)))
+    }
+}
+
+// Writes inventory file if it changed. Contents are taken under lock, file
+// is written outside of it and off runtime threads. Writes are done one at
+// a time, so that older contents never replace newer ones.
+pub async fn flush(inventory: &Inventory) -> anyhow::Result<()> {
+    let writer = Arc::clone(&inventory.lock().unwrap().writer);
+    let _writing = writer.lock().await;
+
+    let changes = inventory.lock().unwrap().changes()?;
+    let (path, data) = match changes {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let result = tokio::task::spawn_blocking(move || write(&path, &data))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|x| x);
+    if result.is_err() {
+        inventory.lock().unwrap().dirty = true;
+    }
+    result
+}
+
+// changes recorded by DHCP servers are written every SAVE_INTERVAL,
+// not for every packet
+pub async fn run_writer(inventory: Inventory) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = flush(&inventory).await {
+            warn!("failed to save client inventory: {:#}", e);
+        }
+    }
+}
+
+// BMC passwords are kept in it
+fn write(path: &Path, data: &str) -> anyhow::Result<()> {
+    fsutil::replace(path, data.as_bytes(), true)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mac: Mac = "52:54:00:12:34:56".parse().unwrap();
+        let mut store = Store::default();
+        store.record(
+            Client {
+                arch: Some(7),
+                hostname: Some("node1".to_string()),
+                profile: Some("debian".to_string()),
+                ..Client::new(mac)
+            },
+            true,
+        );
+        store.record(
+            Client {
+                profile: Some("rescue".to_string()),
+                ..Client::new(mac)
+            },
+            true,
+        );
+
+        let client = store.clients().next().unwrap();
+        assert_eq!(client.boots, 2);
+        assert_eq!(client.arch, Some(7));
+        assert_eq!(client.hostname.as_deref(), Some("node1"));
+        assert!(client.matches("rescue"));
+        assert!(client.matches("52-54-00-12-34-56"));
+        assert!(!client.matches("debian"));
+
+        let data = toml::to_string(&InventoryFile {
+            clients: store.clients().cloned().collect(),
+        })
+        .unwrap();
+        let parsed: InventoryFile = toml::from_str(&data).unwrap();
+        assert_eq!(parsed.clients[0].mac, mac);
+        assert_eq!(parsed.clients[0].uuid, None);
+    }
+
+    #[test]
+    fn test_address_moves() {
+        let first: Mac = "52:54:00:12:34:56".parse().unwrap();
+        let second: Mac = "52:54:00:12:34:57".parse().unwrap();
+        let ip = Ipv4Addr::new(10, 0, 0, 100);
+        let mut store = Store::default();
+        for &mac in [first, second].iter() {
+            store.record(
+                Client {
+                    ip: Some(ip),
+                    ..Client::new(mac)
+                },
+                false,
+            );
+        }
+        assert_eq!(store.by_ip(ip).map(|x| x.mac), Some(second));
+        assert_eq!(store.get(&first).unwrap().ip, None);
+
+        // ended lease of someone else leaves current holder alone
+        store.forget_ip(&first, ip);
+        assert_eq!(store.by_ip(ip).map(|x| x.mac), Some(second));
+        store.forget_ip(&second, ip);
+        assert!(store.by_ip(ip).is_none());
+    }
+
+    #[test]
+    fn test_unchanged_not_saved() {
+        let mac: Mac = "52:54:00:12:34:56".parse().unwrap();
+        let seen = Client {
+            hostname: Some("node1".to_string()),
+            ..Client::new(mac)
+        };
+        let mut store = Store {
+            path: Some(std::env::temp_dir().join("pxe-inventory-unchanged.toml")),
+            ..Store::default()
+        };
+        store.record(seen.clone(), true);
+        assert!(store.changes().unwrap().is_some());
+        // repeated REQUEST of known client
+        store.record(seen.clone(), false);
+        assert!(store.changes().unwrap().is_none());
+        store.record(seen, true);
+        assert!(store.changes().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_find() {
+        let mut store = Store::default();
+        for (mac, hostname) in [
+            ("52:54:00:12:34:56", "node1"),
+            ("52:54:00:12:34:57", "node1"),
+        ]
+        .iter()
+        {
+            store.record(
+                Client {
+                    hostname: Some(hostname.to_string()),
+                    ..Client::new(mac.parse().unwrap())
+                },
+                true,
+            );
+        }
+
+        assert_eq!(store.find("52:54:00:12:34:57").unwrap().boots, 1);
+        assert!(store.find("node1").is_err());
+        assert!(store.find("node2").is_err());
+    }
+
+    #[test]
+    fn test_state() {
+        let mac: Mac = "52:54:00:12:34:56".parse().unwrap();
+        let mut store = Store::default();
+        assert!(!store.provisioned(&mac).unwrap());
+        assert!(store.set_state(mac, State::Provisioned, None).is_err());
+
+        store
+            .set_state(mac, State::Installing, Some("debian".to_string()))
+            .unwrap();
+        assert!(store.provisioned(&mac).unwrap());
+        let client = store.get(&mac).unwrap();
+        assert_eq!(client.state, State::Provisioned);
+        assert_eq!(client.install_profile, None);
+        // only installing hosts become provisioned
+        assert!(!store.provisioned(&mac).unwrap());
+
+        store.set_state(mac, State::Retired, None).unwrap();
+        assert!(store.set_state(mac, State::Installing, None).is_err());
+        assert_eq!(
+            store.set_state(mac, State::Discovered, None).unwrap(),
+            State::Retired
+        );
+
+        // inventories written before states were introduced
+        let parsed: InventoryFile = toml::from_str(
+            "[[client]]\nmac = \"52:54:00:12:34:56\"\nboot_once = \"local\"\n\
+             first_boot = 0\nlast_boot = 0\nboots = 0\n",
+        )
+        .unwrap();
+        assert_eq!(parsed.clients[0].state, State::Provisioned);
+    }
+}
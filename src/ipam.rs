@@ -0,0 +1,362 @@
+// Addresses handed out by DHCP, exported so that IPAM used as source of
+// truth for the lab reflects what clients actually got. Snapshot is written
+// as JSON or CSV, depending on file extension, and can be pushed to NetBox.
+use std::fmt::Write as _;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::dhcp;
+use crate::fsutil;
+use crate::inventory::Inventory;
+use crate::iputil::Ipv4AddrAndMask;
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub export: Option<PathBuf>,
+    pub interval: Duration,
+    #[cfg(feature = "fetch")]
+    pub netbox: Option<netbox::Settings>,
+}
+
+// DHCP server of single instance
+#[derive(Clone)]
+pub struct Source {
+    pub instance: Option<String>,
+    // DHCP range subnet followed by those of relayed pools
+    pub subnets: Vec<Ipv4AddrAndMask>,
+    pub dhcp: dhcp::Handle,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    pub instance: Option<String>,
+    pub ip: Ipv4Addr,
+    pub prefix_len: u8,
+    pub client: String,
+    // None for reservations made by client identifier or UUID
+    pub mac: Option<String>,
+    // following are known only for clients in inventory
+    pub hostname: Option<String>,
+    pub profile: Option<String>,
+    // active, offered or reserved
+    pub state: &'static str,
+    // seconds, None for offers and unused reservations
+    pub expires_in: Option<u64>,
+}
+
+pub async fn run(settings: Settings, sources: Vec<Source>, inventory: Inventory) {
+    #[cfg(feature = "fetch")]
+    let mut netbox = settings.netbox.clone().map(netbox::Client::new);
+    let mut interval = tokio::time::interval(settings.interval);
+
+    loop {
+        interval.tick().await;
+
+        let entries = match snapshot(&sources, &inventory).await {
+            Ok(x) => x,
+            Err(e) => {
+                error!("failed to collect leases: {:#}", e);
+                continue;
+            }
+        };
+        if let Some(path) = settings.export.as_deref() {
+            if let Err(e) = write(path, &entries) {
+                error!("IPAM export failed: {:#}", e);
+            }
+        }
+        #[cfg(feature = "fetch")]
+        if let Some(netbox) = netbox.as_mut() {
+            if let Err(e) = netbox.sync(&entries).await {
+                error!("NetBox synchronization failed: {:#}", e);
+            }
+        }
+    }
+}
+
+pub async fn snapshot(sources: &[Source], inventory: &Inventory) -> anyhow::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for source in sources {
+        let prefix_len = |ip| {
+            source
+                .subnets
+                .iter()
+                .find(|x: &&Ipv4AddrAndMask| x.contains(ip))
+                .map_or(32, |x| x.mask_width())
+        };
+        let leases = source.dhcp.leases().await?;
+        // reserved addresses not taken by their client are exported too,
+        // so that nobody else assigns them in IPAM
+        for reservation in source.dhcp.reservations().await? {
+            if leases.iter().any(|x| x.ip == reservation.ip) {
+                continue;
+            }
+            entries.push(Entry {
+                instance: source.instance.clone(),
+                ip: reservation.ip,
+                prefix_len: prefix_len(reservation.ip),
+                client: reservation.client(),
+                mac: reservation.mac.map(|x| x.to_string()),
+                hostname: None,
+                profile: None,
+                state: "reserved",
+                expires_in: None,
+            });
+        }
+        for lease in leases {
+            let inventory = inventory.lock().unwrap();
+            let client = inventory.get(&lease.mac);
+            entries.push(Entry {
+                instance: source.instance.clone(),
+                ip: lease.ip,
+                prefix_len: prefix_len(lease.ip),
+                client: lease.client,
+                mac: Some(lease.mac.to_string()),
+                hostname: lease
+                    .hostname
+                    .or_else(|| client.and_then(|x| x.hostname.clone())),
+                profile: client.and_then(|x| x.profile.clone()),
+                state: match lease.remaining {
+                    Some(_) => "active",
+                    None => "offered",
+                },
+                expires_in: lease.remaining.map(|x| x.as_secs()),
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+pub fn write(path: &Path, entries: &[Entry]) -> anyhow::Result<()> {
+    let data = match path.extension().and_then(|x| x.to_str()) {
+        Some("csv") => to_csv(entries),
+        _ => serde_json::to_string_pretty(entries)?,
+    };
+    fsutil::replace(path, data.as_bytes(), false)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn to_csv(entries: &[Entry]) -> String {
+    let field = |x: &Option<String>| csv_field(x.as_deref().unwrap_or_default());
+
+    let mut out =
+        String::from("instance,ip,prefix_len,client,mac,hostname,profile,state,expires_in\n");
+    for entry in entries {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            field(&entry.instance),
+            entry.ip,
+            entry.prefix_len,
+            csv_field(&entry.client),
+            field(&entry.mac),
+            field(&entry.hostname),
+            field(&entry.profile),
+            entry.state,
+            entry.expires_in.map(|x| x.to_string()).unwrap_or_default()
+        )
+        .unwrap();
+    }
+
+    out
+}
+
+// RFC 4180 quoting, hostnames come from clients and may contain anything
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(feature = "fetch")]
+pub mod netbox {
+    use std::collections::BTreeMap;
+    use std::net::Ipv4Addr;
+
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::Entry;
+
+    // addresses created by us are told apart by description prefix,
+    // only those are removed once lease is gone
+    const DESCRIPTION_PREFIX: &str = "pxeserver: ";
+
+    #[derive(Debug, Clone)]
+    pub struct Settings {
+        pub url: String,
+        pub token: String,
+    }
+
+    #[derive(Deserialize)]
+    struct IpAddresses {
+        results: Vec<IpAddress>,
+    }
+
+    #[derive(Deserialize)]
+    struct IpAddress {
+        id: u64,
+        #[serde(default)]
+        description: String,
+    }
+
+    pub struct Client {
+        settings: Settings,
+        http: reqwest::Client,
+        // request bodies last pushed, unchanged addresses are skipped
+        pushed: BTreeMap<Ipv4Addr, serde_json::Value>,
+    }
+
+    impl Client {
+        pub fn new(settings: Settings) -> Self {
+            Self {
+                settings,
+                http: reqwest::Client::new(),
+                pushed: BTreeMap::new(),
+            }
+        }
+
+        // offers are not pushed, client may still take address elsewhere
+        pub async fn sync(&mut self, entries: &[Entry]) -> anyhow::Result<()> {
+            let mut current = BTreeMap::new();
+            for entry in entries.iter().filter(|x| x.state != "offered") {
+                let description = format!(
+                    "{}{}",
+                    DESCRIPTION_PREFIX,
+                    entry.mac.as_deref().unwrap_or(&entry.client)
+                );
+                current.insert(
+                    entry.ip,
+                    json!({
+                        "address": format!("{}/{}", entry.ip, entry.prefix_len),
+                        "status": match entry.state {
+                            "reserved" => "reserved",
+                            _ => "dhcp",
+                        },
+                        "dns_name": entry.hostname.as_deref().unwrap_or_default(),
+                        "description": description,
+                    }),
+                );
+            }
+
+            for (ip, body) in current.iter() {
+                if self.pushed.get(ip) == Some(body) {
+                    continue;
+                }
+                // records made by operator are left as they are
+                match self.find(*ip).await?.first() {
+                    Some(existing) if !existing.description.starts_with(DESCRIPTION_PREFIX) => {
+                        warn!(
+                            "{} is already in NetBox as record {} not made by pxeserver, skipped",
+                            ip, existing.id
+                        );
+                    }
+                    Some(existing) => {
+                        let url = format!("{}{}/", self.endpoint(), existing.id);
+                        self.send(self.http.patch(&url), body).await?;
+                        debug!("pushed {} to NetBox", ip);
+                    }
+                    None => {
+                        self.send(self.http.post(self.endpoint()), body).await?;
+                        debug!("pushed {} to NetBox", ip);
+                    }
+                }
+                self.pushed.insert(*ip, body.clone());
+            }
+
+            let gone = self
+                .pushed
+                .keys()
+                .filter(|x| !current.contains_key(x))
+                .copied()
+                .collect::<Vec<_>>();
+            for ip in gone {
+                for existing in self.find(ip).await? {
+                    if existing.description.starts_with(DESCRIPTION_PREFIX) {
+                        let url = format!("{}{}/", self.endpoint(), existing.id);
+                        self.authorize(self.http.delete(&url))
+                            .send()
+                            .await?
+                            .error_for_status()?;
+                        debug!("removed {} from NetBox", ip);
+                    }
+                }
+                self.pushed.remove(&ip);
+            }
+
+            Ok(())
+        }
+
+        fn endpoint(&self) -> String {
+            format!(
+                "{}/api/ipam/ip-addresses/",
+                self.settings.url.trim_end_matches('/')
+            )
+        }
+
+        fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+            request
+                .header("Authorization", format!("Token {}", self.settings.token))
+                .header("Accept", "application/json")
+        }
+
+        async fn find(&self, ip: Ipv4Addr) -> anyhow::Result<Vec<IpAddress>> {
+            let data = self
+                .authorize(self.http.get(self.endpoint()))
+                .query(&[("address", ip.to_string())])
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+
+            Ok(serde_json::from_str::<IpAddresses>(&data)?.results)
+        }
+
+        async fn send(
+            &self,
+            request: reqwest::RequestBuilder,
+            body: &serde_json::Value,
+        ) -> anyhow::Result<()> {
+            self.authorize(request)
+                .header("Content-Type", "application/json")
+                .body(body.to_string())
+                .send()
+                .await?
+                .error_for_status()?;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_csv() {
+        let entry = Entry {
+            instance: None,
+            ip: Ipv4Addr::new(10, 0, 0, 100),
+            prefix_len: 24,
+            client: "52:54:00:12:34:56".to_string(),
+            mac: Some("52:54:00:12:34:56".to_string()),
+            hostname: Some("rack \"a\", node 1".to_string()),
+            profile: None,
+            state: "active",
+            expires_in: Some(3600),
+        };
+        assert_eq!(
+            to_csv(&[entry]),
+            "instance,ip,prefix_len,client,mac,hostname,profile,state,expires_in\n\
+             ,10.0.0.100,24,52:54:00:12:34:56,52:54:00:12:34:56,\"rack \"\"a\"\", node 1\",,active,3600\n"
+        );
+    }
+}
@@ -1,14 +1,28 @@
+use std::convert::TryFrom;
 use std::fmt;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
 
-#[derive(Debug, Copy, Clone)]
+use serde::Deserialize;
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(try_from = "String")]
 pub struct Ipv4AddrAndMask {
     address: Ipv4Addr,
     mask_width: u8,
 }
 
 impl Ipv4AddrAndMask {
+    // subnet containing given address, mask width must be in 1..=30
+    pub fn network_of(address: Ipv4Addr, mask_width: u8) -> Self {
+        let mut subnet = Self {
+            address,
+            mask_width,
+        };
+        subnet.address = Ipv4Addr::from(u32::from(address) & subnet.mask_raw());
+        subnet
+    }
+
     #[inline]
     pub fn address(&self) -> Ipv4Addr {
         self.address
@@ -21,7 +35,9 @@ impl Ipv4AddrAndMask {
 
     #[inline]
     pub fn mask_raw(&self) -> u32 {
-        ((1 << self.mask_width) - 1) << (32 - self.mask_width)
+        u32::MAX
+            .checked_shl(32 - u32::from(self.mask_width))
+            .unwrap_or(0)
     }
 
     #[inline]
@@ -29,6 +45,10 @@ impl Ipv4AddrAndMask {
         Into::<Ipv4Addr>::into(self.mask_raw())
     }
 
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        u32::from(address) & self.mask_raw() == u32::from(self.address)
+    }
+
     fn verify(&self) -> bool {
         Into::<u32>::into(self.address) & !self.mask_raw() == 0
     }
@@ -69,12 +89,137 @@ impl FromStr for Ipv4AddrAndMask {
     }
 }
 
+impl TryFrom<String> for Ipv4AddrAndMask {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 impl fmt::Display for Ipv4AddrAndMask {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}/{}", self.address, self.mask_width)
     }
 }
 
+// DHCP address range with subnet inferred from mask width
+// e.g. 192.168.1.100-192.168.1.200/24
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Ipv4Range {
+    start: Ipv4Addr,
+    end: Ipv4Addr,
+    subnet: Ipv4AddrAndMask,
+}
+
+impl Ipv4Range {
+    #[inline]
+    pub fn start(&self) -> Ipv4Addr {
+        self.start
+    }
+
+    #[inline]
+    pub fn end(&self) -> Ipv4Addr {
+        self.end
+    }
+
+    #[inline]
+    pub fn subnet(&self) -> Ipv4AddrAndMask {
+        self.subnet
+    }
+
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        (u32::from(self.start)..=u32::from(self.end)).contains(&u32::from(address))
+    }
+
+    pub fn overlaps(&self, other: &Ipv4Range) -> bool {
+        u32::from(self.start) <= u32::from(other.end)
+            && u32::from(other.start) <= u32::from(self.end)
+    }
+}
+
+impl fmt::Display for Ipv4Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}/{}",
+            self.start,
+            self.end,
+            self.subnet.mask_width()
+        )
+    }
+}
+
+impl FromStr for Ipv4Range {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, mask_width) = s
+            .split_once('/')
+            .ok_or_else(|| anyhow!("missing mask, expected START-END/MASK"))?;
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| anyhow!("missing range end, expected START-END/MASK"))?;
+
+        let start: Ipv4Addr = start.parse()?;
+        let end: Ipv4Addr = end.parse()?;
+        let mask_width: u8 = mask_width
+            .parse()
+            .map_err(|_| anyhow::Error::msg("invalid mask"))?;
+
+        if !(1..=30).contains(&mask_width) {
+            bail!("invalid mask");
+        }
+
+        let mut subnet = Ipv4AddrAndMask {
+            address: start,
+            mask_width,
+        };
+        subnet.address = Into::<Ipv4Addr>::into(Into::<u32>::into(start) & subnet.mask_raw());
+
+        if !belongs(end, subnet.address(), subnet.mask()) {
+            bail!("{} does not belong to {}", end, subnet);
+        }
+
+        if Into::<u32>::into(start) > Into::<u32>::into(end) {
+            bail!("range start {} is greater than range end {}", start, end);
+        }
+
+        Ok(Self { start, end, subnet })
+    }
+}
+
+impl TryFrom<String> for Ipv4Range {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+// empty list allows any client
+pub fn allowed(subnets: &[Ipv4AddrAndMask], address: IpAddr) -> bool {
+    match address {
+        _ if subnets.is_empty() => true,
+        IpAddr::V4(address) => subnets.iter().any(|x| x.contains(address)),
+        IpAddr::V6(_) => false,
+    }
+}
+
+// internet checksum of IP header or ICMP message (RFC 1071)
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|x| u16::from_be_bytes([x[0], *x.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
 pub fn belongs(address: Ipv4Addr, subnet: Ipv4Addr, subnet_mask: Ipv4Addr) -> bool {
     let address = Into::<u32>::into(address);
     let subnet = Into::<u32>::into(subnet);
@@ -84,3 +229,37 @@ pub fn belongs(address: Ipv4Addr, subnet: Ipv4Addr, subnet_mask: Ipv4Addr) -> bo
 
     address & subnet_mask == subnet
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{allowed, Ipv4AddrAndMask, Ipv4Range};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_parse_range() {
+        let range: Ipv4Range = "192.168.1.100-192.168.1.200/24".parse().unwrap();
+        assert_eq!(range.start(), Ipv4Addr::new(192, 168, 1, 100));
+        assert_eq!(range.end(), Ipv4Addr::new(192, 168, 1, 200));
+        assert_eq!(range.subnet().address(), Ipv4Addr::new(192, 168, 1, 0));
+        assert_eq!(range.subnet().mask_width(), 24);
+
+        let range: Ipv4Range = "10.0.2.1-10.0.3.254/22".parse().unwrap();
+        assert_eq!(range.subnet().address(), Ipv4Addr::new(10, 0, 0, 0));
+
+        assert!("192.168.1.100-192.168.2.1/24".parse::<Ipv4Range>().is_err());
+        assert!("192.168.1.200-192.168.1.100/24"
+            .parse::<Ipv4Range>()
+            .is_err());
+        assert!("192.168.1.100-192.168.1.200".parse::<Ipv4Range>().is_err());
+        assert!("192.168.1.100/24".parse::<Ipv4Range>().is_err());
+    }
+
+    #[test]
+    fn test_allowed() {
+        let subnets: Vec<Ipv4AddrAndMask> = vec!["10.1.0.0/16".parse().unwrap()];
+        assert!(allowed(&subnets, IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3))));
+        assert!(!allowed(&subnets, IpAddr::V4(Ipv4Addr::new(10, 2, 0, 1))));
+        assert!(!allowed(&subnets, "::1".parse().unwrap()));
+        assert!(allowed(&[], IpAddr::V4(Ipv4Addr::new(10, 2, 0, 1))));
+    }
+}
@@ -1,28 +1,114 @@
 #[macro_use]
-extern crate log;
+extern crate tracing;
 
 #[macro_use]
 extern crate anyhow;
 
+use std::fmt;
 use std::fs;
-use std::net::Ipv4Addr;
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 use clap::{ArgGroup, Clap};
+use completions::Shell;
+use config::{Config, Diagnostics, Instance};
+use dhcp::id::Mac;
+use dhcp::lease_formats::{self, LeaseFormat};
 use futures_util::stream::FuturesUnordered;
-use futures_util::StreamExt;
-use iputil::Ipv4AddrAndMask;
+use futures_util::{FutureExt, StreamExt};
+use iputil::{Ipv4AddrAndMask, Ipv4Range};
+use netif::{LinkState, NetworkInterface};
+use stats::Stats;
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
+#[cfg(feature = "otlp")]
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+use units::{ByteSize, HumanDuration};
 
+mod bench;
+mod binl;
+mod bootcfg;
+mod capture;
+mod completions;
+mod config;
+mod control;
 mod dhcp;
+mod dns;
+#[cfg(feature = "fetch")]
+mod fetch;
+mod fsutil;
+mod hooks;
 #[cfg(feature = "http")]
 mod http;
+mod inventory;
+mod ipam;
 mod iputil;
+mod man;
+mod nbd;
+mod netif;
+mod power;
+mod preflight;
+mod registry;
+mod secureboot;
+mod sessions;
+mod signature;
+mod simulate;
+mod sockutil;
+mod stats;
+mod summary;
+mod syslog;
+#[cfg(feature = "otlp")]
+mod telemetry;
 mod tftp;
+mod top;
+mod units;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
+mod verify;
 
-#[derive(Clap)]
+const RESTART_BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+// four bytes each in single DHCP option
+const MAX_OPTION_ADDRESSES: usize = 255 / 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FailurePolicy {
+    // exit with non-zero status as soon as any subsystem fails
+    Exit,
+    // restart failed subsystem after a delay, doubled on each consecutive failure
+    Retry,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exit" => Ok(Self::Exit),
+            "retry" => Ok(Self::Retry),
+            _ => bail!("invalid failure policy \"{}\", expected exit or retry", s),
+        }
+    }
+}
+
+impl fmt::Display for FailurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Exit => write!(f, "exit"),
+            Self::Retry => write!(f, "retry"),
+        }
+    }
+}
+
+#[derive(Clap, Clone)]
 #[clap(group =
     ArgGroup::new("dhcp")
         .required(false)
@@ -30,13 +116,57 @@ mod tftp;
         .requires_all(
             &[
                 "dhcp-ip-start",
-                "dhcp-ip-end",
-                "dhcp-subnet"
+                "dhcp-ip-end"
             ])
 )]
 pub struct Options {
+    // may be omitted when configuration file defines instances
     #[clap(short, long)]
-    pub server_ip: Ipv4Addr,
+    pub server_ip: Option<Ipv4Addr>,
+
+    #[clap(
+        short,
+        long,
+        about = "Network interface to serve, its first IPv4 address is used as default server IP"
+    )]
+    pub interface: Option<String>,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        about = "Network namespace to run in, name from /var/run/netns or path"
+    )]
+    pub netns: Option<PathBuf>,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        about = "Assign server IP to --interface at startup if it has no IPv4 address, \
+                 prefix length is taken from DHCP subnet, address is removed on exit"
+    )]
+    pub assign_address: bool,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        about = "Serve DHCP through packet socket on --interface, works before it has IPv4 address"
+    )]
+    pub raw_socket: bool,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        requires = "raw-socket",
+        about = "Send OFFER and ACK to client MAC instead of broadcast, for PXE ROMs ignoring broadcast replies"
+    )]
+    pub unicast_replies: bool,
+
+    #[cfg_attr(target_os = "linux", clap(conflicts_with = "raw-socket"))]
+    #[clap(
+        long,
+        about = "Bind DHCP to 0.0.0.0 and drop packets arriving through other interfaces than --interface or one owning server IP, for interfaces with several addresses"
+    )]
+    pub dhcp_bind_any: bool,
 
     #[clap(long, about = "IP range start", group = "dhcp")]
     pub dhcp_ip_start: Option<Ipv4Addr>,
@@ -44,94 +174,2033 @@ pub struct Options {
     #[clap(long, about = "IP range end", group = "dhcp")]
     pub dhcp_ip_end: Option<Ipv4Addr>,
 
-    #[clap(long, group = "dhcp")]
+    #[clap(
+        long,
+        group = "dhcp",
+        about = "Subnet of DHCP range, defaults to subnet of --interface"
+    )]
     pub dhcp_subnet: Option<Ipv4AddrAndMask>,
 
+    #[clap(
+        long,
+        conflicts_with = "dhcp",
+        about = "IP range with subnet mask, e.g. 192.168.1.100-192.168.1.200/24"
+    )]
+    pub dhcp_range: Option<Ipv4Range>,
+
+    #[clap(
+        long,
+        default_value = "1h",
+        about = "Lease time, clients renew after half of it (option 58) and rebind after 7/8 (option 59)"
+    )]
+    pub dhcp_lease_time: HumanDuration,
+
+    #[clap(
+        long,
+        default_value = "first-free",
+        conflicts_with = "proxy-dhcp",
+        about = "How free addresses are picked, first-free or hash (same client tends to get same address)"
+    )]
+    pub dhcp_allocation: dhcp::allocation::Allocation,
+
+    #[clap(
+        long,
+        default_value = "24h",
+        about = "How long address declined by client or answering probe is not offered again"
+    )]
+    pub decline_quarantine: HumanDuration,
+
+    #[clap(
+        long,
+        default_value = "60s",
+        about = "How long offered address is held for client that does not request it"
+    )]
+    pub offer_timeout: HumanDuration,
+
+    #[clap(
+        long,
+        about = "Probe free address with ICMP echo and ARP before offering it, skip it if anybody answers"
+    )]
+    pub ping_check: bool,
+
+    #[clap(
+        long,
+        default_value = "500ms",
+        about = "How long to wait for answer to address probe"
+    )]
+    pub ping_timeout: HumanDuration,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        conflicts_with = "proxy-dhcp",
+        about = "Probe address over ARP after ACK, warn and mark lease conflicted if host other than client answers within --ping-timeout"
+    )]
+    pub arp_check: bool,
+
+    #[clap(
+        long,
+        about = "File of MACs and OUIs (e.g. 52:54:00) served over DHCP, nobody else is, reread on reload"
+    )]
+    pub mac_allow: Option<PathBuf>,
+
+    #[clap(
+        long,
+        about = "File of MACs and OUIs never served over DHCP, reread on reload"
+    )]
+    pub mac_deny: Option<PathBuf>,
+
     #[clap(long)]
     pub mtu: Option<u16>,
 
+    #[clap(
+        long,
+        number_of_values = 1,
+        about = "DNS server announced to DHCP clients instead of own one, may be repeated"
+    )]
+    pub dhcp_dns: Vec<Ipv4Addr>,
+
+    #[clap(long, about = "Domain name announced to DHCP clients (option 15)")]
+    pub dhcp_domain: Option<String>,
+
+    #[clap(
+        long,
+        number_of_values = 1,
+        about = "Domain appended to short names by DHCP clients (option 119), may be repeated"
+    )]
+    pub dhcp_search: Vec<String>,
+
+    #[clap(
+        long,
+        number_of_values = 1,
+        about = "NTP server announced to DHCP clients (option 42), may be repeated"
+    )]
+    pub dhcp_ntp: Vec<Ipv4Addr>,
+
+    #[clap(
+        long,
+        about = "Answer DNS queries for lease hostnames and configured records, announced to DHCP clients"
+    )]
+    pub dns: bool,
+
+    #[clap(
+        long,
+        about = "Answer BINL network card queries of legacy Windows setup on UDP port 4011"
+    )]
+    pub binl: bool,
+
+    #[clap(
+        long,
+        conflicts_with = "binl",
+        about = "Answer PXE boot server requests on UDP port 4011 with boot file, needed by some UNDI and UEFI firmware"
+    )]
+    pub boot_server: bool,
+
+    #[clap(
+        long,
+        requires = "boot-server",
+        about = "Offer PXE boot menu listing profiles to clients not marked in inventory"
+    )]
+    pub pxe_menu: bool,
+
+    #[clap(
+        long,
+        default_value = "10s",
+        about = "How long PXE boot menu prompt waits for F8 before default boot"
+    )]
+    pub pxe_menu_timeout: HumanDuration,
+
+    #[clap(
+        long,
+        about = "Collect syslog of booting clients on UDP port 514 into their boot sessions"
+    )]
+    pub syslog: bool,
+
+    #[clap(
+        long,
+        about = "Also append collected syslog to <mac>.log files in given directory"
+    )]
+    pub syslog_dir: Option<PathBuf>,
+
+    #[clap(
+        long,
+        requires = "dns",
+        about = "Upstream DNS server for names not known locally"
+    )]
+    pub dns_forward: Option<Ipv4Addr>,
+
+    #[clap(
+        long,
+        default_value = "10809",
+        about = "NBD port, server is started when configuration file has exports"
+    )]
+    pub nbd_port: u16,
+
     #[clap(short = 'r', long)]
     pub tftp_root: Option<PathBuf>,
 
+    #[clap(
+        long,
+        about = "Directory under TFTP root whose kernel and initrd subdirectories are listed in generated boot menus"
+    )]
+    pub image_dir: Option<PathBuf>,
+
     #[clap(index = 1)]
-    pub loader: PathBuf,
+    pub loader: Option<PathBuf>,
+
+    #[clap(
+        long,
+        number_of_values = 1,
+        about = "Boot file for clients of given architecture (option 93) instead of loader, e.g. bios=undionly.kpxe or efi-x64=ipxe.efi, may be repeated"
+    )]
+    pub arch_loader: Vec<dhcp::arch::ArchLoader>,
+
+    #[clap(
+        long,
+        about = "Boot file offered to clients running iPXE (user class option 77) instead of loader or profile boot file, e.g. menu.ipxe, so that chainloaded iPXE does not load itself again"
+    )]
+    pub ipxe_boot_file: Option<String>,
+
+    #[clap(
+        long,
+        about = "Boot file offered to hosts marked for local boot instead of none, e.g. pxelinux.0 or GRUB which get generated configuration leaving for local disk"
+    )]
+    pub local_boot_file: Option<String>,
+
+    #[clap(
+        long,
+        about = "TFTP server clients load boot files from (siaddr and option 66) when it is not this one"
+    )]
+    pub next_server: Option<Ipv4Addr>,
+
+    #[clap(
+        long,
+        about = "Host name sent as TFTP server (option 66) instead of next server address"
+    )]
+    pub tftp_server_name: Option<String>,
+
+    #[clap(long, about = "Do not start DHCP server")]
+    pub no_dhcp: bool,
+
+    #[clap(
+        long,
+        about = "Only tell PXE clients what to boot, addresses are left to existing DHCP server"
+    )]
+    pub proxy_dhcp: bool,
+
+    #[clap(
+        long,
+        conflicts_with = "proxy-dhcp",
+        about = "Ignore DHCP clients whose vendor class does not start with PXEClient"
+    )]
+    pub pxe_only: bool,
+
+    #[clap(
+        long,
+        conflicts_with = "proxy-dhcp",
+        about = "Acknowledge DISCOVER of clients asking for rapid commit (RFC 4039)"
+    )]
+    pub rapid_commit: bool,
+
+    #[clap(
+        long,
+        requires = "failover-role",
+        about = "Other DHCP server of failover pair leases are synchronized with, e.g. 10.0.0.2:647"
+    )]
+    pub failover_peer: Option<SocketAddr>,
+
+    #[clap(
+        long,
+        requires = "failover-peer",
+        about = "primary (lower half of pool, waits for peer) or secondary (upper half, connects to peer)"
+    )]
+    pub failover_role: Option<dhcp::failover::Role>,
+
+    #[clap(
+        long,
+        about = "JSON file leases are kept in across restarts, rewritten whenever they change"
+    )]
+    pub lease_file: Option<PathBuf>,
+
+    #[cfg(feature = "sqlite")]
+    #[clap(
+        long,
+        conflicts_with = "lease-file",
+        about = "SQLite database leases are kept in across restarts, table leases"
+    )]
+    pub lease_db: Option<PathBuf>,
+
+    #[clap(long, about = "Do not start TFTP server")]
+    pub no_tftp: bool,
+
+    #[clap(
+        long,
+        default_value = "3s",
+        about = "Time to wait for TFTP ACK before retransmitting"
+    )]
+    pub tftp_timeout: HumanDuration,
+
+    #[clap(
+        long,
+        default_value = "5",
+        about = "TFTP retransmissions before giving up"
+    )]
+    pub tftp_retries: u32,
+
+    #[clap(
+        long,
+        default_value = "65464",
+        about = "Largest TFTP block size accepted during negotiation, e.g. 1432 or 8KiB"
+    )]
+    pub tftp_max_block_size: ByteSize,
+
+    #[clap(
+        long,
+        default_value = "64",
+        about = "Largest TFTP window size (RFC 7440) accepted during negotiation, 1 keeps transfers lockstep"
+    )]
+    pub tftp_max_window_size: u16,
 
     #[cfg(feature = "http")]
     #[clap(long, default_value = "8080")]
     pub http_port: u16,
+
+    #[cfg(feature = "http")]
+    #[clap(
+        long,
+        about = "File whose first line is user:password required from HTTP clients, read from PXE_HTTP_CREDENTIALS otherwise"
+    )]
+    pub http_credentials_file: Option<PathBuf>,
+
+    #[clap(
+        long,
+        number_of_values = 1,
+        about = "Serve TFTP and HTTP only to clients from given subnet, may be repeated"
+    )]
+    pub client_subnet: Vec<Ipv4AddrAndMask>,
+
+    #[clap(
+        long,
+        default_value = "1",
+        about = "TFTP and HTTP receive workers, more than one shares the port using SO_REUSEPORT"
+    )]
+    pub workers: usize,
+
+    #[clap(
+        long,
+        default_value = "1m",
+        about = "How often to log statistics summary, 0s disables it"
+    )]
+    pub stats_interval: HumanDuration,
+
+    #[clap(
+        long,
+        default_value = "exit",
+        about = "What to do when a subsystem fails (exit or retry)"
+    )]
+    pub on_failure: FailurePolicy,
+
+    #[clap(short, long, about = "Configuration file")]
+    pub config_file: Option<PathBuf>,
+
+    #[clap(
+        long,
+        about = "Unix socket accepting ctl commands, ctl defaults to /run/pxeserver.sock"
+    )]
+    pub control_socket: Option<PathBuf>,
+
+    #[clap(
+        long,
+        about = "File recording every client seen by DHCP, kept across restarts"
+    )]
+    pub inventory_file: Option<PathBuf>,
+
+    #[clap(
+        long,
+        about = "Directory of versioned boot artifacts, placed under TFTP root profiles refer to <name>/current/<file> in it"
+    )]
+    pub registry: Option<PathBuf>,
+
+    #[clap(
+        long,
+        about = "Write DHCP, TFTP and HTTP traffic handled by the server to pcap file"
+    )]
+    pub capture: Option<PathBuf>,
+
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    #[clap(
+        long,
+        about = "Read files served over TFTP and HTTP through io_uring, falls back to regular reads when kernel lacks it"
+    )]
+    pub io_uring: bool,
+
+    #[clap(
+        long,
+        about = "Write DHCP leases and reservations to JSON or CSV file (chosen by extension) for IPAM"
+    )]
+    pub ipam_export: Option<PathBuf>,
+
+    #[clap(
+        long,
+        about = "Ed25519 public key (hex), TFTP and HTTP serve only files with matching <file>.sig"
+    )]
+    pub verify_key: Option<signature::PublicKey>,
+
+    #[cfg(feature = "fetch")]
+    #[clap(
+        long,
+        about = "NetBox active DHCP leases and reservations are pushed to, e.g. https://netbox.example.com"
+    )]
+    pub netbox_url: Option<String>,
+
+    #[cfg(feature = "fetch")]
+    #[clap(
+        long,
+        about = "File whose first line is NetBox API token, read from PXE_NETBOX_TOKEN otherwise"
+    )]
+    pub netbox_token_file: Option<PathBuf>,
+
+    #[clap(
+        long,
+        default_value = "1m",
+        about = "How often leases are exported to IPAM"
+    )]
+    pub ipam_interval: HumanDuration,
+
+    #[cfg(feature = "otlp")]
+    #[clap(
+        long,
+        about = "OTLP/gRPC collector receiving traces and metrics, e.g. http://localhost:4317"
+    )]
+    pub otlp_endpoint: Option<String>,
+
+    #[clap(skip)]
+    pub config: Config,
+
+    // set when options were built from configuration file instance
+    #[clap(skip)]
+    pub instance_name: Option<String>,
+
+    // server IP was taken from interface and follows its changes
+    #[clap(skip)]
+    pub server_ip_from_interface: bool,
+
+    // set when server IP has to be assigned to interface at startup
+    #[clap(skip)]
+    pub assign_prefix_len: Option<u8>,
+
+    // MTU of interface server IP belongs to, if it could be determined
+    #[clap(skip)]
+    pub interface_mtu: Option<u32>,
+
+    // state of interface, servers are paused while it is unusable
+    // and restarted whenever it changes
+    #[clap(skip)]
+    pub link_updates: Option<watch::Receiver<LinkState>>,
+
+    // read from --netbox-token-file or environment, kept out of arguments
+    #[cfg(feature = "fetch")]
+    #[clap(skip)]
+    pub netbox_token: Option<String>,
+
+    // read from --http-credentials-file or environment, kept out of arguments
+    #[cfg(feature = "http")]
+    #[clap(skip)]
+    pub http_credentials: Option<String>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clap, Clone)]
+pub enum Command {
+    #[clap(about = "Print shell completion script")]
+    Completions {
+        #[clap(about = "bash, elvish, fish, powershell or zsh")]
+        shell: Shell,
+    },
+
+    #[clap(about = "Print man page in roff format")]
+    Man,
+
+    #[clap(about = "Send command to running server over control socket")]
+    Ctl {
+        #[clap(
+            required = true,
+            about = "leases, transfers, sessions, clients [MAC|UUID|hostname|profile], boot-once <MAC> [profile], provisioned <MAC>, boot-normal <MAC>, versions, switch <name> <version>, rollback <name>, state <MAC> <discovered|installing|provisioned|retired> [profile], logs <MAC>, bmc <MAC> [address user [ipmi|redfish] [insecure]], expire-lease <IP|MAC>, arp <IP>, reload, status or stats"
+        )]
+        command: Vec<String>,
+        #[clap(
+            long,
+            about = "File whose first line is BMC password for bmc command, read from stdin otherwise"
+        )]
+        password_file: Option<PathBuf>,
+    },
+
+    #[clap(
+        about = "Live view of leases, transfers and recent boot events of running server over control socket"
+    )]
+    Top {
+        #[clap(long, default_value = "1s", about = "How often view is refreshed")]
+        interval: HumanDuration,
+    },
+
+    #[cfg(feature = "fetch")]
+    #[clap(
+        about = "Download netboot files into --tftp-root and add profiles booting them to --config-file"
+    )]
+    Fetch {
+        #[clap(required = true, about = "debian-12, ubuntu-24.04 or ipxe")]
+        assets: Vec<String>,
+    },
+
+    #[clap(about = "Control power of host through its BMC, as known to --inventory-file")]
+    Power {
+        #[clap(about = "MAC, UUID or hostname of host")]
+        host: String,
+
+        #[clap(about = "on, off, cycle or pxe (next boot from network)")]
+        action: power::Action,
+
+        #[clap(
+            long,
+            default_value = "ipmitool",
+            about = "ipmitool executable, used for BMCs speaking IPMI"
+        )]
+        ipmitool: PathBuf,
+    },
+
+    #[clap(about = "Generate Ed25519 key signing boot files, prints public key for --verify-key")]
+    GenerateSigningKey {
+        #[clap(about = "Where to write private key (PKCS#8)")]
+        path: PathBuf,
+    },
+
+    #[clap(
+        about = "Store files in --registry as new version of artifact, switch to it with ctl switch"
+    )]
+    Publish {
+        #[clap(about = "Artifact name, e.g. debian")]
+        name: String,
+
+        #[clap(about = "Version, e.g. 12.1 or build number")]
+        version: String,
+
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+    },
+
+    #[clap(about = "Write detached signature <file>.sig for each file")]
+    Sign {
+        #[clap(long, about = "Private key made by generate-signing-key")]
+        key: PathBuf,
+
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+    },
+
+    #[clap(
+        about = "Emulate PXE clients booting from running server at --server-ip, for load and regression testing"
+    )]
+    SimulateClient {
+        #[clap(long, default_value = "1", about = "Number of clients booting at once")]
+        count: usize,
+
+        #[clap(
+            long,
+            default_value = "02:00:00:00:00:01",
+            about = "MAC of the first client, next ones are incremented"
+        )]
+        first_mac: Mac,
+
+        #[clap(
+            long,
+            default_value = "0",
+            about = "Client system architecture sent in option 93, e.g. 0 for BIOS or 7 for x64 UEFI"
+        )]
+        arch: u16,
+
+        #[clap(
+            long,
+            about = "Vendor class identifier, PXEClient one matching --arch by default"
+        )]
+        vendor_class: Option<String>,
+
+        #[clap(long, about = "Stop after DHCP, do not fetch boot file over TFTP")]
+        no_fetch: bool,
+    },
+
+    #[clap(
+        about = "Download file from TFTP server at --server-ip with concurrent clients and report throughput, loss and latency"
+    )]
+    BenchTftp {
+        #[clap(about = "File to download, relative to TFTP root")]
+        file: String,
+
+        #[clap(long, default_value = "1", about = "Number of concurrent downloads")]
+        clients: usize,
+
+        #[clap(
+            long,
+            about = "Block size to negotiate, e.g. 1432 or 8KiB, 512 bytes without negotiation"
+        )]
+        blksize: Option<ByteSize>,
+
+        #[clap(
+            long,
+            about = "Window size to negotiate (RFC 7440), lockstep by default"
+        )]
+        windowsize: Option<u16>,
+    },
+
+    #[clap(
+        about = "Boot throwaway QEMU VM with profile from running server and report whether it reached kernel"
+    )]
+    VerifyBoot {
+        #[clap(long, about = "Profile VM is marked for installation with")]
+        profile: String,
+
+        #[clap(
+            long,
+            about = "Tap interface bridged to server network, QEMU user-mode network fetching iPXE script of profile from --server-ip over HTTP otherwise"
+        )]
+        tap: Option<String>,
+
+        #[clap(long, about = "OVMF firmware image, VM boots BIOS without it")]
+        ovmf: Option<PathBuf>,
+
+        #[clap(long, default_value = "qemu-system-x86_64")]
+        qemu: PathBuf,
+
+        #[clap(long, default_value = "1024", about = "Memory of VM in MiB")]
+        memory: u32,
+
+        #[clap(long, default_value = "5m", about = "How long to wait for kernel")]
+        timeout: HumanDuration,
+    },
+
+    #[clap(
+        about = "Merge leases file of another DHCP server into --lease-file or --lease-db, refused while server answers on --control-socket"
+    )]
+    ImportLeases {
+        #[clap(long, about = "dnsmasq or isc (dhcpd.leases)")]
+        format: LeaseFormat,
+
+        path: PathBuf,
+    },
+
+    #[clap(
+        about = "Write active leases of --lease-file or --lease-db in format of another DHCP server"
+    )]
+    ExportLeases {
+        #[clap(long, about = "dnsmasq or isc (dhcpd.leases)")]
+        format: LeaseFormat,
+
+        #[clap(about = "Standard output if not given")]
+        path: Option<PathBuf>,
+    },
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let mut options: Options = Options::parse();
-    options.tftp_root = if let Some(root) = options.tftp_root.as_deref() {
-        Some(fs::canonicalize(root).context("Failed to canonicalize root path")?)
+impl Options {
+    // always set after prepare_options
+    pub fn server_ip(&self) -> Ipv4Addr {
+        self.server_ip.expect("server IP not resolved")
+    }
+
+    pub fn serves_dhcp(&self) -> bool {
+        !self.no_dhcp && (self.dhcp_ip_start.is_some() || self.proxy_dhcp)
+    }
+
+    fn with_instance(&self, instance: &Instance) -> Self {
+        let mut options = self.clone();
+        options.instance_name = Some(instance.name.clone());
+
+        if instance.server_ip.is_some() {
+            options.server_ip = instance.server_ip;
+        }
+        if instance.interface.is_some() {
+            options.interface = instance.interface.clone();
+        }
+        if let Some(range) = instance.dhcp_range {
+            options.dhcp_range = Some(range);
+        }
+        if let Some(lease_time) = instance.dhcp_lease_time {
+            options.dhcp_lease_time = lease_time;
+        }
+        if let Some(allocation) = instance.dhcp_allocation {
+            options.dhcp_allocation = allocation;
+        }
+        if instance.tftp_root.is_some() {
+            options.tftp_root = instance.tftp_root.clone();
+        }
+        if instance.loader.is_some() {
+            options.loader = instance.loader.clone();
+        }
+        if instance.ipxe_boot_file.is_some() {
+            options.ipxe_boot_file = instance.ipxe_boot_file.clone();
+        }
+        if instance.next_server.is_some() {
+            options.next_server = instance.next_server;
+        }
+        if instance.tftp_server_name.is_some() {
+            options.tftp_server_name = instance.tftp_server_name.clone();
+        }
+        // shared file would be overwritten by every instance
+        options.lease_file = instance.lease_file.clone();
+        #[cfg(feature = "sqlite")]
+        {
+            options.lease_db = instance.lease_db.clone();
+        }
+        if instance.mac_allow.is_some() {
+            options.mac_allow = instance.mac_allow.clone();
+        }
+        if instance.mac_deny.is_some() {
+            options.mac_deny = instance.mac_deny.clone();
+        }
+        #[cfg(feature = "http")]
+        if let Some(port) = instance.http_port {
+            options.http_port = port;
+        }
+        #[cfg(feature = "http")]
+        if instance.http_credentials.is_some() {
+            options.http_credentials = instance.http_credentials.clone();
+        }
+        if !instance.client_subnets.is_empty() {
+            options.client_subnet = instance.client_subnets.clone();
+        }
+        if !instance.dhcp_dns.is_empty() {
+            options.dhcp_dns = instance.dhcp_dns.clone();
+        }
+        if instance.dhcp_domain.is_some() {
+            options.dhcp_domain = instance.dhcp_domain.clone();
+        }
+        if !instance.dhcp_search.is_empty() {
+            options.dhcp_search = instance.dhcp_search.clone();
+        }
+        if !instance.dhcp_ntp.is_empty() {
+            options.dhcp_ntp = instance.dhcp_ntp.clone();
+        }
+        options.no_dhcp |= instance.no_dhcp;
+        options.proxy_dhcp |= instance.proxy_dhcp;
+        options.pxe_only |= instance.pxe_only;
+        options.rapid_commit |= instance.rapid_commit;
+        options.no_tftp |= instance.no_tftp;
+
+        // instance profiles and selectors take precedence over global ones,
+        // isolated instance sees only its own
+        let (global_profiles, global_selectors) = if instance.isolated {
+            (&[][..], &[][..])
+        } else {
+            (&self.config.profiles[..], &self.config.selectors[..])
+        };
+        options.config = Config {
+            options: self.config.options.clone(),
+            profiles: instance
+                .profiles
+                .iter()
+                .chain(global_profiles)
+                .cloned()
+                .collect(),
+            selectors: instance
+                .selectors
+                .iter()
+                .chain(global_selectors)
+                .cloned()
+                .collect(),
+            vendor_classes: self.config.vendor_classes.clone(),
+            nbd_exports: self.config.nbd_exports.clone(),
+            dns_records: self.config.dns_records.clone(),
+            binl_drivers: self.config.binl_drivers.clone(),
+            reservations: instance.reservations.clone(),
+            pools: instance.pools.clone(),
+            hooks: self.config.hooks.clone(),
+            instances: Vec::new(),
+        };
+
+        options
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let options: Options = Options::parse();
+
+    // must happen before runtime starts its threads, namespace is per thread
+    // and new threads inherit it from their creator
+    #[cfg(target_os = "linux")]
+    if let Some(netns) = options.netns.as_deref() {
+        netif::enter_netns(netns)?;
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(options))
+}
+
+async fn run(mut options: Options) -> anyhow::Result<()> {
+    #[cfg(feature = "fetch")]
+    {
+        options.netbox_token =
+            read_secret(options.netbox_token_file.as_deref(), "PXE_NETBOX_TOKEN")?;
+    }
+    #[cfg(feature = "http")]
+    {
+        options.http_credentials = read_secret(
+            options.http_credentials_file.as_deref(),
+            "PXE_HTTP_CREDENTIALS",
+        )?;
+    }
+
+    match options.command.as_ref() {
+        Some(Command::Completions { shell }) => {
+            completions::print(*shell);
+            return Ok(());
+        }
+        Some(Command::Man) => {
+            return Ok(man::print()?);
+        }
+        Some(Command::Ctl {
+            command,
+            password_file,
+        }) => {
+            return control::run_ctl(
+                &control::socket_path(&options),
+                command,
+                password_file.as_deref(),
+            )
+            .await;
+        }
+        Some(Command::Top { interval }) => {
+            return top::run(&control::socket_path(&options), interval.get()).await;
+        }
+        #[cfg(feature = "fetch")]
+        Some(Command::Fetch { assets }) => {
+            let root = options
+                .tftp_root
+                .as_deref()
+                .ok_or_else(|| anyhow!("--tftp-root is required to fetch files"))?;
+            return fetch::run(root, options.config_file.as_deref(), assets).await;
+        }
+        Some(Command::Power {
+            host,
+            action,
+            ipmitool,
+        }) => {
+            let inventory_file = options
+                .inventory_file
+                .as_deref()
+                .ok_or_else(|| anyhow!("--inventory-file is required to find BMC of host"))?;
+            return power::run(inventory_file, host, *action, ipmitool).await;
+        }
+        Some(Command::GenerateSigningKey { path }) => {
+            println!("{}", signature::generate_key(path)?);
+            return Ok(());
+        }
+        Some(Command::Sign { key, files }) => {
+            return signature::sign(key, files);
+        }
+        Some(Command::Publish {
+            name,
+            version,
+            files,
+        }) => {
+            let dir = options
+                .registry
+                .as_deref()
+                .ok_or_else(|| anyhow!("--registry is required to publish files"))?;
+            return registry::Registry::open(dir)?.publish(name, version, files);
+        }
+        Some(Command::SimulateClient {
+            count,
+            first_mac,
+            arch,
+            vendor_class,
+            no_fetch,
+        }) => {
+            let server_ip = options
+                .server_ip
+                .ok_or_else(|| anyhow!("--server-ip is required to simulate clients"))?;
+            return simulate::run(simulate::Settings {
+                server_ip,
+                count: *count,
+                first_mac: *first_mac,
+                arch: *arch,
+                vendor_class: vendor_class.clone(),
+                fetch: !no_fetch,
+            })
+            .await;
+        }
+        Some(Command::VerifyBoot {
+            profile,
+            tap,
+            ovmf,
+            qemu,
+            memory,
+            timeout,
+        }) => {
+            let network = match tap {
+                Some(tap) => verify::Network::Tap(tap.clone()),
+                None => verify::Network::User(user_network_boot_file(&options, profile)?),
+            };
+            return verify::run(
+                &control::socket_path(&options),
+                &verify::Settings {
+                    profile: profile.clone(),
+                    network,
+                    qemu: qemu.clone(),
+                    ovmf: ovmf.clone(),
+                    memory: *memory,
+                    timeout: timeout.get(),
+                },
+            )
+            .await;
+        }
+        Some(Command::BenchTftp {
+            file,
+            clients,
+            blksize,
+            windowsize,
+        }) => {
+            let server_ip = options
+                .server_ip
+                .ok_or_else(|| anyhow!("--server-ip is required to benchmark TFTP"))?;
+            let block_size = blksize.as_ref().map(ByteSize::get);
+            if let Some(x) = block_size {
+                if !(tftp::TFTP_MIN_BLOCK_SIZE..=tftp::TFTP_MAX_BLOCK_SIZE).contains(&x) {
+                    bail!(
+                        "--blksize must be between {} and {} bytes, got {}",
+                        tftp::TFTP_MIN_BLOCK_SIZE,
+                        tftp::TFTP_MAX_BLOCK_SIZE,
+                        x
+                    );
+                }
+            }
+            return bench::tftp(bench::Settings {
+                server_ip,
+                file: file.clone(),
+                clients: *clients,
+                // validated to fit
+                block_size: block_size.map(|x| x as u32),
+                window_size: *windowsize,
+            })
+            .await;
+        }
+        Some(Command::ImportLeases { format, path }) => {
+            // running server would overwrite imported leases with its own
+            let socket = control::socket_path(&options);
+            if control::answers(&socket).await {
+                bail!(
+                    "server answers on {}, stop it before importing leases",
+                    socket.display()
+                );
+            }
+            return lease_formats::import(&options, *format, path);
+        }
+        Some(Command::ExportLeases { format, path }) => {
+            return lease_formats::export(&options, *format, path.as_deref());
+        }
+        None => (),
+    }
+
+    if let Some(path) = options.config_file.as_deref() {
+        options.config = Config::load(path)?;
+    }
+
+    let mut diagnostics = Diagnostics::default();
+    options.config.verify_into(&mut diagnostics);
+    #[cfg(feature = "fetch")]
+    if options.netbox_url.is_some() && options.netbox_token.is_none() {
+        diagnostics.error(
+            "--netbox-token-file",
+            "required by --netbox-url unless PXE_NETBOX_TOKEN is set",
+        );
+    }
+    if options.ipam_interval.get() == Duration::ZERO {
+        diagnostics.error("--ipam-interval", "must not be zero");
+    }
+    if options.failover_peer.is_some() && !options.config.instances.is_empty() {
+        diagnostics.error("--failover-peer", "not supported with multiple instances");
+    }
+    if options.lease_file.is_some() && !options.config.instances.is_empty() {
+        diagnostics.error(
+            "--lease-file",
+            "not supported with multiple instances, use instance.lease_file",
+        );
+    }
+    #[cfg(feature = "sqlite")]
+    if options.lease_db.is_some() && !options.config.instances.is_empty() {
+        diagnostics.error(
+            "--lease-db",
+            "not supported with multiple instances, use instance.lease_db",
+        );
+    }
+
+    // kept for reloading configuration
+    let base_options = options.clone();
+
+    let mut instances = if options.config.instances.is_empty() {
+        vec![options]
     } else {
-        None
+        options
+            .config
+            .instances
+            .iter()
+            .map(|instance| options.with_instance(instance))
+            .collect()
     };
-    options.loader =
-        fs::canonicalize(options.loader).context("Failed to canonicalize loader path")?;
 
-    let options = Arc::new(options);
+    for (i, options) in instances.iter_mut().enumerate() {
+        prepare_options(options, i, &mut diagnostics);
+    }
+    // each would answer broadcasts of the other
+    for (i, options) in instances.iter().enumerate() {
+        let interface = match options.interface.as_deref() {
+            Some(x) if options.serves_dhcp() => x,
+            _ => continue,
+        };
+        if let Some((j, other)) = instances[..i]
+            .iter()
+            .enumerate()
+            .find(|(_, x)| x.serves_dhcp() && x.interface.as_deref() == Some(interface))
+        {
+            diagnostics.error(
+                format!("instance[{}].interface", i),
+                format!(
+                    "DHCP on {} is served by instance[{}] ({}) already",
+                    interface,
+                    j,
+                    other.instance_name.as_deref().unwrap_or_default()
+                ),
+            );
+        }
+    }
+    preflight::check(&instances, &mut diagnostics);
+    diagnostics.into_result()?;
+
+    let inventory = Arc::new(Mutex::new(inventory::Store::open(
+        base_options.inventory_file.as_deref(),
+    )?));
 
-    pretty_env_logger::init();
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .finish();
+    #[cfg(feature = "otlp")]
+    let subscriber = subscriber.with(
+        base_options
+            .otlp_endpoint
+            .as_deref()
+            .map(telemetry::tracer)
+            .transpose()?
+            .map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)),
+    );
+    subscriber.init();
+    install_panic_hook();
+
+    if let Some(path) = base_options.inventory_file.as_deref() {
+        info!(
+            "client inventory {} with {} client(s)",
+            path.display(),
+            inventory.lock().unwrap().clients().count()
+        );
+    }
+    if let Some(path) = base_options.capture.as_deref() {
+        capture::open(path)?;
+        info!("capturing traffic to {}", path.display());
+    }
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    if base_options.io_uring {
+        match uring::start() {
+            Ok(()) => info!("reading served files through io_uring"),
+            Err(e) => warn!("io_uring unavailable, reading files as usual: {}", e),
+        }
+    }
 
     let mut fut_list = FuturesUnordered::new();
+    let mut handles = Vec::new();
+    let mut ipam_sources = Vec::new();
+    // addresses are removed when dropped on return
+    #[cfg(target_os = "linux")]
+    let mut assigned_addresses = Vec::new();
+
+    for options in instances {
+        #[cfg(target_os = "linux")]
+        let mut options = options;
+        #[cfg(target_os = "linux")]
+        if let (Some(interface), Some(prefix_len)) =
+            (options.interface.as_deref(), options.assign_prefix_len)
+        {
+            assigned_addresses.push(
+                NetworkInterface::new(interface).assign_address(options.server_ip(), prefix_len)?,
+            );
+        }
+
+        summary::log(&options);
+
+        #[cfg(target_os = "linux")]
+        if let Some(interface) = options.interface.as_deref() {
+            let interface = NetworkInterface::new(interface);
+            let (sender, receiver) = watch::channel(interface.link_state());
+            options.link_updates = Some(receiver);
+
+            tokio::spawn(async move {
+                if let Err(e) = netif::monitor(interface, sender).await {
+                    error!("interface monitoring failed: {:#}", e);
+                }
+            });
+        }
+
+        let options = Arc::new(options);
+        let mut instance = control::Instance {
+            name: options.instance_name.clone(),
+            interface: options.interface.clone(),
+            link: options.link_updates.clone(),
+            dhcp: None,
+            transfers: Default::default(),
+            stats: Default::default(),
+            sessions: Default::default(),
+        };
+
+        if options.stats_interval.get() != Duration::ZERO {
+            tokio::spawn(stats::log_periodically(
+                options
+                    .instance_name
+                    .as_deref()
+                    .unwrap_or("default")
+                    .to_string(),
+                Arc::clone(&instance.stats),
+                options.stats_interval.get(),
+            ));
+        }
+
+        let lease_names = dns::LeaseNames::default();
+
+        if options.serves_dhcp() {
+            let handle = dhcp::Handle::new();
+            let fut = start_dhcp_server(
+                Arc::clone(&options),
+                handle.clone(),
+                Arc::clone(&instance.stats),
+                Arc::clone(&lease_names),
+                Arc::clone(&instance.sessions),
+                Arc::clone(&inventory),
+            )
+            .context("failed to spawn DHCP server")?;
+            fut_list.push(fut);
+            if let (Some(role), Some(peer)) = (options.failover_role, options.failover_peer) {
+                tokio::spawn(dhcp::failover::run(
+                    role,
+                    options.server_ip(),
+                    peer,
+                    handle.clone(),
+                ));
+            }
+            if let Some(subnet) = options.dhcp_subnet.filter(|_| !options.proxy_dhcp) {
+                ipam_sources.push(ipam::Source {
+                    instance: options.instance_name.clone(),
+                    subnets: std::iter::once(subnet)
+                        .chain(options.config.pools.iter().map(|x| x.range.subnet()))
+                        .collect(),
+                    dhcp: handle.clone(),
+                });
+            }
+            instance.dhcp = Some(handle);
+        }
 
-    if options.dhcp_ip_start.is_some() {
-        let fut = start_dhcp_server(Arc::clone(&options)).context("failed to spawn DHCP server")?;
-        fut_list.push(fut);
+        if options.dns {
+            fut_list.push(
+                start_dns_server(Arc::clone(&options), lease_names)
+                    .context("failed to spawn DNS server")?,
+            );
+        }
+
+        if options.binl {
+            fut_list.push(
+                start_binl_server(Arc::clone(&options)).context("failed to spawn BINL server")?,
+            );
+        }
+
+        if options.syslog {
+            fut_list.push(
+                start_syslog_server(Arc::clone(&options), Arc::clone(&instance.sessions))
+                    .context("failed to spawn syslog server")?,
+            );
+        }
+
+        if !options.config.nbd_exports.is_empty() {
+            fut_list.push(
+                start_nbd_server(Arc::clone(&options)).context("failed to spawn NBD server")?,
+            );
+        }
+
+        if !options.no_tftp {
+            fut_list.push(
+                start_tftp_server(
+                    Arc::clone(&options),
+                    Arc::clone(&instance.transfers),
+                    Arc::clone(&instance.stats),
+                    Arc::clone(&instance.sessions),
+                    Arc::clone(&inventory),
+                )
+                .context("failed to spawn TFTP server")?,
+            );
+        }
+
+        #[cfg(feature = "http")]
+        {
+            fut_list.push(
+                start_http_server(
+                    Arc::clone(&options),
+                    Arc::clone(&instance.stats),
+                    Arc::clone(&instance.sessions),
+                    Arc::clone(&inventory),
+                    instance.dhcp.clone(),
+                )
+                .context("failed to spawn HTTP server")?,
+            );
+        }
+
+        handles.push(instance);
     }
 
-    fut_list.push(start_tftp_server(Arc::clone(&options)).context("failed to spawn TFTP server")?);
+    // stopped when dropped on return
+    #[cfg(feature = "otlp")]
+    let _metrics = match base_options.otlp_endpoint.as_deref() {
+        Some(endpoint) => Some(telemetry::export_metrics(
+            endpoint,
+            handles
+                .iter()
+                .map(|x| {
+                    let name = x.name.as_deref().unwrap_or("default").to_string();
+                    (name, Arc::clone(&x.stats))
+                })
+                .collect(),
+        )?),
+        None => None,
+    };
 
-    #[cfg(feature = "http")]
-    {
-        fut_list
-            .push(start_http_server(Arc::clone(&options)).context("failed to spawn HTTP server")?);
+    let ipam_settings = ipam::Settings {
+        export: base_options.ipam_export.clone(),
+        interval: base_options.ipam_interval.get(),
+        #[cfg(feature = "fetch")]
+        netbox: base_options
+            .netbox_url
+            .clone()
+            .zip(base_options.netbox_token.clone())
+            .map(|(url, token)| ipam::netbox::Settings { url, token }),
+    };
+    #[cfg(feature = "fetch")]
+    let ipam_enabled = ipam_settings.export.is_some() || ipam_settings.netbox.is_some();
+    #[cfg(not(feature = "fetch"))]
+    let ipam_enabled = ipam_settings.export.is_some();
+    if ipam_enabled {
+        tokio::spawn(ipam::run(
+            ipam_settings,
+            ipam_sources,
+            Arc::clone(&inventory),
+        ));
+    }
+
+    if base_options.inventory_file.is_some() {
+        tokio::spawn(inventory::run_writer(Arc::clone(&inventory)));
     }
+    // saved once more on shutdown
+    let unsaved = Arc::clone(&inventory);
 
-    while let Some(x) = fut_list.next().await {
-        if let Err(e) = x {
-            error!("{}", e)
+    if base_options.control_socket.is_some() {
+        let handles = Arc::new(handles);
+        fut_list.push(spawn_subsystem(
+            "control",
+            Arc::new(base_options),
+            false,
+            move |options| {
+                let handles = Arc::clone(&handles);
+                let inventory = Arc::clone(&inventory);
+                async move { control::serve(options, handles, inventory).await }
+            },
+        ));
+    }
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            x = fut_list.next() => match x {
+                // with exit policy first failure terminates whole program,
+                // remaining subsystems are dropped together with runtime
+                Some(x) => x.context("subsystem task failed")??,
+                None => break,
+            },
+            result = &mut shutdown => {
+                result?;
+                info!("shutting down");
+                break;
+            }
         }
     }
 
+    if let Err(e) = inventory::flush(&unsaved).await {
+        warn!("failed to save client inventory: {:#}", e);
+    }
+    #[cfg(feature = "otlp")]
+    telemetry::shutdown();
+
     Ok(())
 }
 
-fn start_dhcp_server(options: Arc<Options>) -> anyhow::Result<JoinHandle<()>> {
-    let dhcp_ip_start = options.dhcp_ip_start.unwrap();
-    let dhcp_ip_end = options.dhcp_ip_end.unwrap();
-    let dhcp_subnet = options.dhcp_subnet.unwrap();
+// Secrets are kept out of arguments, which other users can see. First line
+// of given file is taken, environment variable otherwise.
+#[cfg(any(feature = "fetch", feature = "http"))]
+fn read_secret(path: Option<&std::path::Path>, var: &str) -> anyhow::Result<Option<String>> {
+    match path {
+        Some(path) => {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            Ok(Some(content.lines().next().unwrap_or_default().to_string()))
+        }
+        None => Ok(std::env::var(var).ok().filter(|x| !x.is_empty())),
+    }
+}
+
+// SIGINT or SIGTERM, lets cleanup such as removing assigned addresses happen
+#[cfg(unix)]
+async fn shutdown_signal() -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
 
-    if !iputil::belongs(dhcp_ip_start, dhcp_subnet.address(), dhcp_subnet.mask()) {
-        bail!("{} does not belong to {}", dhcp_ip_start, dhcp_subnet);
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => (),
     }
 
-    if !iputil::belongs(dhcp_ip_end, dhcp_subnet.address(), dhcp_subnet.mask()) {
-        bail!("{} does not belong to {}", dhcp_ip_end, dhcp_subnet);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> anyhow::Result<()> {
+    Ok(tokio::signal::ctrl_c().await?)
+}
+
+// index is position of instance in configuration file, used in error paths
+fn prepare_options(options: &mut Options, index: usize, diagnostics: &mut Diagnostics) {
+    let from_config = options.instance_name.is_some();
+    let field_path = |field: &str| {
+        if from_config {
+            format!("instance[{}].{}", index, field)
+        } else {
+            format!("--{}", field.replace('_', "-"))
+        }
+    };
+
+    // checked before canonicalization, which clears paths it reports as invalid
+    let has_tftp_source = options.loader.is_some() || options.tftp_root.is_some();
+
+    #[cfg(target_os = "linux")]
+    let (assign_address, raw_socket) = (options.assign_address, options.raw_socket);
+    #[cfg(not(target_os = "linux"))]
+    let (assign_address, raw_socket) = (false, false);
+
+    // interface lacking address is fine if DHCP bypasses IP stack or server IP
+    // gets assigned to it once configuration is known to be valid
+    let mut missing_address = false;
+    let interface = options.interface.as_deref().map(NetworkInterface::new);
+    if let Some(interface) = interface.as_ref() {
+        match interface.ip_address() {
+            Ok((address, _)) if options.server_ip.is_none() => {
+                options.server_ip = Some(address);
+                options.server_ip_from_interface = true;
+            }
+            Ok(_) => (),
+            Err(_) if (assign_address || raw_socket) && options.server_ip.is_some() => {
+                missing_address = true
+            }
+            Err(e) => diagnostics.error(field_path("interface"), e),
+        }
+    } else if assign_address {
+        diagnostics.error(field_path("interface"), "required by --assign-address");
+    } else if raw_socket {
+        diagnostics.error(field_path("interface"), "required by --raw-socket");
     }
 
-    Ok(tokio::spawn(async move {
-        dhcp::start(
-            &*options,
-            options.server_ip,
-            dhcp_ip_start,
-            dhcp_ip_end,
-            dhcp_subnet,
-        )
-        .await
+    let server_ip = options.server_ip;
+    if server_ip.is_none() {
+        diagnostics.error(field_path("server_ip"), "not set");
+    }
+
+    #[cfg(unix)]
+    if let Some(server_ip) = server_ip.filter(|_| options.dhcp_bind_any && interface.is_none()) {
+        if !matches!(NetworkInterface::with_address(server_ip), Ok(Some(_))) {
+            diagnostics.error(
+                field_path("interface"),
+                format!(
+                    "required by --dhcp-bind-any, no interface has {}",
+                    server_ip
+                ),
+            );
+        }
+    }
+
+    // interface names are not resolved on Windows
+    #[cfg(windows)]
+    if options.dhcp_bind_any && interface.is_some() {
+        diagnostics.error(
+            field_path("interface"),
+            "cannot be used with --dhcp-bind-any on Windows, interface owning server IP is served",
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(server_ip) = server_ip.filter(|_| options.arp_check && interface.is_none()) {
+        if !matches!(NetworkInterface::with_address(server_ip), Ok(Some(_))) {
+            diagnostics.error(
+                field_path("interface"),
+                format!("required by --arp-check, no interface has {}", server_ip),
+            );
+        }
+    }
+
+    // without explicit interface the one owning server IP is used,
+    // unknown MTU leaves default packet sizes in place
+    #[cfg(target_os = "linux")]
+    if let Some(server_ip) = server_ip {
+        let mtu_interface = match interface.clone() {
+            Some(interface) => Some(interface),
+            None => NetworkInterface::with_address(server_ip).ok().flatten(),
+        };
+        options.interface_mtu = mtu_interface.and_then(|x| x.mtu().ok());
+    }
+
+    if let Some(range) = options.dhcp_range {
+        options.dhcp_ip_start = Some(range.start());
+        options.dhcp_ip_end = Some(range.end());
+        options.dhcp_subnet = Some(range.subnet());
+
+        if let Some(server_ip) = server_ip.filter(|ip| range.contains(*ip)) {
+            diagnostics.error(
+                field_path("dhcp_range"),
+                format!("{} includes server IP {}", range, server_ip),
+            );
+        }
+    }
+
+    if options.dhcp_ip_start.is_some() && options.dhcp_subnet.is_none() {
+        match interface
+            .as_ref()
+            .filter(|_| !missing_address)
+            .map(NetworkInterface::subnet)
+        {
+            Some(Ok(subnet)) => options.dhcp_subnet = Some(subnet),
+            Some(Err(e)) => diagnostics.error(field_path("dhcp_subnet"), e),
+            None => diagnostics.error(
+                field_path("dhcp_subnet"),
+                "required unless interface with IPv4 address is given",
+            ),
+        }
+    }
+
+    // prefix length is taken from DHCP subnet, which therefore has to contain server IP
+    if missing_address && assign_address {
+        match (options.server_ip, options.dhcp_subnet) {
+            (Some(server_ip), Some(subnet))
+                if Ipv4AddrAndMask::network_of(server_ip, subnet.mask_width()).address()
+                    == subnet.address() =>
+            {
+                options.assign_prefix_len = Some(subnet.mask_width())
+            }
+            (Some(server_ip), Some(subnet)) => diagnostics.error(
+                field_path("dhcp_subnet"),
+                format!("{} does not contain server IP {}", subnet, server_ip),
+            ),
+            // already reported above
+            _ if options.dhcp_ip_start.is_some() => (),
+            _ => diagnostics.error(
+                field_path("dhcp_subnet"),
+                "required by --assign-address to determine prefix length",
+            ),
+        }
+    }
+
+    // pools hand out addresses to relayed clients next to local ones
+    let config_path = |field: String| {
+        if from_config {
+            format!("instance[{}].{}", index, field)
+        } else {
+            field
+        }
+    };
+    for (i, pool) in options.config.pools.iter().enumerate() {
+        let path = config_path(format!("pool[{}].range", i));
+        let pool_subnet = pool.range.subnet();
+        match options.dhcp_subnet {
+            _ if options.proxy_dhcp => {
+                diagnostics.error(path, "proxy does not answer relayed requests")
+            }
+            Some(subnet)
+                if subnet.contains(pool_subnet.address())
+                    || pool_subnet.contains(subnet.address()) =>
+            {
+                diagnostics.error(
+                    path,
+                    format!("{} overlaps with DHCP subnet {}", pool.range, subnet),
+                )
+            }
+            Some(_) => (),
+            None => diagnostics.error(path, "relayed pools require DHCP range"),
+        }
+        if let Some(server_ip) = server_ip.filter(|ip| pool.range.contains(*ip)) {
+            diagnostics.error(
+                config_path(format!("pool[{}].range", i)),
+                format!("{} includes server IP {}", pool.range, server_ip),
+            );
+        }
+        if options.failover_role.is_some() && pool.range.start() == pool.range.end() {
+            diagnostics.error(
+                config_path(format!("pool[{}].range", i)),
+                "failover pair needs pool of at least two addresses",
+            );
+        }
+    }
+
+    // reserved addresses are outside of range as often as not
+    if let Some(subnet) = options.dhcp_subnet {
+        for (i, reservation) in options.config.reservations.iter().enumerate() {
+            let path = config_path(format!("reservation[{}].ip", i));
+            let pooled = options
+                .config
+                .pools
+                .iter()
+                .any(|x| x.range.subnet().contains(reservation.ip));
+            if !subnet.contains(reservation.ip) && !pooled {
+                diagnostics.error(
+                    path,
+                    format!("{} is outside of DHCP subnet {}", reservation.ip, subnet),
+                );
+            } else if server_ip == Some(reservation.ip) {
+                diagnostics.error(path, format!("{} is server IP", reservation.ip));
+            }
+        }
+    }
+
+    for (field, path) in [
+        ("mac_allow", &options.mac_allow),
+        ("mac_deny", &options.mac_deny),
+    ]
+    .iter()
+    {
+        if let Some(Err(e)) = path.as_deref().map(dhcp::mac_filter::MacList::load) {
+            diagnostics.error(field_path(field), format!("{:#}", e));
+        }
+    }
+
+    // whole seconds, all ones would mean infinite lease (RFC 2131 section 3.3)
+    let lease_time = options.dhcp_lease_time.get();
+    if lease_time < Duration::from_secs(1) || lease_time.as_secs() >= u32::MAX.into() {
+        diagnostics.error(
+            field_path("dhcp_lease_time"),
+            format!("{} is out of range", options.dhcp_lease_time),
+        );
+    }
+
+    for (field, servers) in [
+        ("dhcp_dns", &options.dhcp_dns),
+        ("dhcp_ntp", &options.dhcp_ntp),
+    ]
+    .iter()
+    {
+        if servers.len() > MAX_OPTION_ADDRESSES {
+            diagnostics.error(
+                field_path(field),
+                format!("at most {} servers can be announced", MAX_OPTION_ADDRESSES),
+            );
+        }
+    }
+
+    for (field, name) in options
+        .dhcp_domain
+        .iter()
+        .map(|x| ("dhcp_domain", x))
+        .chain(options.dhcp_search.iter().map(|x| ("dhcp_search", x)))
+    {
+        let trimmed = name.trim_end_matches('.');
+        if trimmed.is_empty()
+            || trimmed.len() > 253
+            || trimmed.split('.').any(|x| x.is_empty() || x.len() > 63)
+        {
+            diagnostics.error(field_path(field), format!("invalid name \"{}\"", name));
+        }
+    }
+    if let Some(ip) = options.next_server {
+        if ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast() {
+            diagnostics.error(
+                field_path("next_server"),
+                format!("{} is not host address", ip),
+            );
+        }
+    }
+    if let Some(name) = options.tftp_server_name.as_deref() {
+        if name.is_empty() || name.len() > u8::MAX as usize {
+            diagnostics.error(
+                field_path("tftp_server_name"),
+                format!("invalid name \"{}\"", name),
+            );
+        }
+    }
+
+    if dhcp::packet::encode_domain_search(&options.dhcp_search).len() > u8::MAX as usize {
+        diagnostics.error(
+            field_path("dhcp_search"),
+            "search list does not fit into single option",
+        );
+    }
+
+    if options.proxy_dhcp && options.dhcp_ip_start.is_some() {
+        diagnostics.error(
+            field_path("proxy_dhcp"),
+            "cannot be used with DHCP range, proxy hands out no addresses",
+        );
+    }
+    if let (Some(start), Some(end)) = (options.dhcp_ip_start, options.dhcp_ip_end) {
+        if u32::from(end) < u32::from(start) {
+            diagnostics.error(
+                field_path("dhcp_ip_end"),
+                format!("{} precedes range start {}", end, start),
+            );
+        } else if options.failover_role.is_some() && end == start {
+            diagnostics.error(
+                field_path("dhcp_ip_end"),
+                "failover pair needs pool of at least two addresses",
+            );
+        }
+        if let Some(subnet) = options.dhcp_subnet {
+            for (field, ip) in [("dhcp_ip_start", start), ("dhcp_ip_end", end)].iter() {
+                if !subnet.contains(*ip) {
+                    diagnostics.error(
+                        field_path(field),
+                        format!("{} does not belong to {}", ip, subnet),
+                    );
+                }
+            }
+        }
+    }
+
+    options.tftp_root = match options
+        .tftp_root
+        .as_deref()
+        .map(|root| fs::canonicalize(root).map_err(|e| (root, e)))
+    {
+        Some(Ok(root)) if root.is_dir() => Some(root),
+        Some(Ok(root)) => {
+            diagnostics.error(
+                field_path("tftp_root"),
+                format!("{} is not a directory", root.display()),
+            );
+            None
+        }
+        Some(Err((root, e))) => {
+            diagnostics.error(
+                field_path("tftp_root"),
+                format!("cannot access {}: {}", root.display(), e),
+            );
+            None
+        }
+        None => None,
+    };
+
+    options.loader = match options.loader.as_deref() {
+        Some(loader) => {
+            match fs::canonicalize(loader).and_then(|x| fs::File::open(&x).map(|_| x)) {
+                Ok(loader) => Some(loader),
+                Err(e) => {
+                    diagnostics.error(
+                        field_path("loader"),
+                        format!("cannot read {}: {}", loader.display(), e),
+                    );
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    options.image_dir = match (options.image_dir.as_deref(), options.tftp_root.as_deref()) {
+        (Some(directory), Some(root)) => match fs::canonicalize(root.join(directory)) {
+            Ok(directory) if directory.is_dir() && directory.starts_with(root) => Some(directory),
+            Ok(directory) => {
+                diagnostics.error(
+                    field_path("image_dir"),
+                    format!(
+                        "{} is not a directory under {}",
+                        directory.display(),
+                        root.display()
+                    ),
+                );
+                None
+            }
+            Err(e) => {
+                diagnostics.error(
+                    field_path("image_dir"),
+                    format!("cannot access {}: {}", directory.display(), e),
+                );
+                None
+            }
+        },
+        (Some(_), None) => {
+            diagnostics.error(field_path("image_dir"), "requires TFTP root directory");
+            None
+        }
+        (None, _) => None,
+    };
+
+    if let (Some(root), Some(loader)) = (options.tftp_root.as_deref(), options.loader.as_deref()) {
+        if !loader.starts_with(root) {
+            diagnostics.error(
+                field_path("loader"),
+                format!(
+                    "{} is outside of root directory {}",
+                    loader.display(),
+                    root.display()
+                ),
+            );
+        }
+    }
+
+    for profile in options.config.profiles.iter() {
+        let secure_boot = match profile.secure_boot.as_ref() {
+            Some(secure_boot) => secure_boot,
+            None => continue,
+        };
+        let path = if from_config {
+            format!("instance[{}] profile {}.secure_boot", index, profile.name)
+        } else {
+            format!("profile {}.secure_boot", profile.name)
+        };
+
+        match options.tftp_root.as_deref() {
+            Some(root) => {
+                for problem in secureboot::verify(root, profile, secure_boot) {
+                    diagnostics.error(path.clone(), problem);
+                }
+            }
+            None => diagnostics.error(path, "requires TFTP root directory"),
+        }
+    }
+
+    if options.workers == 0 {
+        diagnostics.error(field_path("workers"), "must be at least 1");
+    }
+
+    if let Some(directory) = options.syslog_dir.as_deref() {
+        if !options.syslog {
+            diagnostics.error(field_path("syslog_dir"), "requires --syslog");
+        } else if !directory.is_dir() {
+            diagnostics.error(
+                field_path("syslog_dir"),
+                format!("{} is not a directory", directory.display()),
+            );
+        }
+    }
+
+    #[cfg(feature = "http")]
+    if matches!(&options.http_credentials, Some(x) if !x.contains(':')) {
+        let path = match from_config {
+            true => field_path("http_credentials"),
+            false => "--http-credentials-file or PXE_HTTP_CREDENTIALS".to_string(),
+        };
+        diagnostics.error(path, "expected user:password");
+    }
+
+    if options.tftp_max_window_size == 0 {
+        diagnostics.error(field_path("tftp_max_window_size"), "must be at least 1");
+    }
+
+    let block_size = options.tftp_max_block_size.get();
+    if !(tftp::TFTP_MIN_BLOCK_SIZE..=tftp::TFTP_MAX_BLOCK_SIZE).contains(&block_size) {
+        diagnostics.error(
+            field_path("tftp_max_block_size"),
+            format!(
+                "must be between {} and {} bytes, got {}",
+                tftp::TFTP_MIN_BLOCK_SIZE,
+                tftp::TFTP_MAX_BLOCK_SIZE,
+                block_size
+            ),
+        );
+    }
+
+    if !options.no_tftp && !has_tftp_source {
+        diagnostics.error(
+            field_path("tftp_root"),
+            "TFTP server needs loader or root directory, use --no-tftp to disable it",
+        );
+    }
+}
+
+// servers that do not require address keep running while interface has none
+fn spawn_subsystem<F, Fut>(
+    name: &'static str,
+    options: Arc<Options>,
+    requires_address: bool,
+    f: F,
+) -> JoinHandle<anyhow::Result<()>>
+where
+    F: Fn(Arc<Options>) -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<()>> + Send,
+{
+    tokio::spawn(async move {
+        let mut options = options;
+        let mut link_updates = options.link_updates.clone();
+        let mut backoff = RESTART_BACKOFF_INITIAL;
+        let is_usable = |state: LinkState| {
+            if requires_address {
+                state.is_usable()
+            } else {
+                state.running
+            }
+        };
+
+        loop {
+            if let Some(updates) = link_updates.as_mut() {
+                let mut state = *updates.borrow();
+                if !is_usable(state) {
+                    info!(
+                        "{} server{} paused, {}: {}",
+                        name,
+                        instance_suffix(&options),
+                        options.interface.as_deref().unwrap_or("interface"),
+                        state
+                    );
+                    // monitor is gone when channel is closed, try running anyway
+                    while !is_usable(state) && updates.changed().await.is_ok() {
+                        state = *updates.borrow();
+                    }
+                }
+
+                if let Some(address) = state.address {
+                    if options.server_ip_from_interface && options.server_ip != Some(address) {
+                        let mut new_options = (*options).clone();
+                        new_options.server_ip = Some(address);
+                        options = Arc::new(new_options);
+                    }
+                }
+            }
+
+            let started = Instant::now();
+            let run = AssertUnwindSafe(f(Arc::clone(&options))).catch_unwind();
+            let result = match link_updates.as_mut() {
+                Some(updates) => tokio::select! {
+                    result = run => result,
+                    Ok(()) = updates.changed() => {
+                        if is_usable(*updates.borrow()) {
+                            info!(
+                                "restarting {} server{} after interface change",
+                                name,
+                                instance_suffix(&options)
+                            );
+                        }
+                        continue;
+                    }
+                },
+                None => run.await,
+            };
+
+            let reason = match result {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(e)) if options.on_failure == FailurePolicy::Retry => {
+                    format!("failed: {:#}", e)
+                }
+                Ok(Err(e)) => {
+                    return Err(e.context(format!(
+                        "{} server{} failed",
+                        name,
+                        instance_suffix(&options)
+                    )))
+                }
+                // panic is a bug rather than environment problem,
+                // restart regardless of policy, details were logged by panic hook
+                Err(_) => "panicked".to_string(),
+            };
+
+            // server that ran for a while before failing starts with fresh backoff
+            if started.elapsed() > RESTART_BACKOFF_MAX {
+                backoff = RESTART_BACKOFF_INITIAL;
+            }
+
+            error!(
+                "{} server{} {}, restarting in {} s",
+                name,
+                instance_suffix(&options),
+                reason,
+                backoff.as_secs()
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RESTART_BACKOFF_MAX);
+        }
+    })
+}
+
+fn install_panic_hook() {
+    // logged through tracing so message carries span of failing exchange
+    panic::set_hook(Box::new(|info| {
+        let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+            s
+        } else if let Some(s) = info.payload().downcast_ref::<String>() {
+            s.as_str()
+        } else {
+            "<unknown>"
+        };
+
+        match info.location() {
+            Some(location) => error!(
+                "panic at {}:{}: {}",
+                location.file(),
+                location.line(),
+                message
+            ),
+            None => error!("panic: {}", message),
+        }
+    }));
+}
+
+// iPXE script of profile, reached through NAT of QEMU user-mode network
+#[cfg(feature = "http")]
+fn user_network_boot_file(options: &Options, profile: &str) -> anyhow::Result<String> {
+    let server_ip = options
+        .server_ip
+        .ok_or_else(|| anyhow!("--server-ip is required without --tap"))?;
+    let credentials = options
+        .http_credentials
+        .as_deref()
+        .map(|x| format!("{}@", x))
+        .unwrap_or_default();
+    Ok(format!(
+        "http://{}{}:{}/profiles/{}.ipxe",
+        credentials, server_ip, options.http_port, profile
+    ))
+}
+
+#[cfg(not(feature = "http"))]
+fn user_network_boot_file(_options: &Options, _profile: &str) -> anyhow::Result<String> {
+    bail!("--tap is required, user-mode network needs HTTP support")
+}
+
+fn instance_suffix(options: &Options) -> String {
+    match options.instance_name.as_deref() {
+        Some(name) => format!(" ({})", name),
+        None => String::new(),
+    }
+}
+
+fn start_dhcp_server(
+    options: Arc<Options>,
+    handle: dhcp::Handle,
+    stats: Arc<Stats>,
+    lease_names: dns::LeaseNames,
+    sessions: sessions::Sessions,
+    inventory: inventory::Inventory,
+) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    // packet socket does not depend on interface address
+    #[cfg(target_os = "linux")]
+    let requires_address = !options.raw_socket;
+    #[cfg(not(target_os = "linux"))]
+    let requires_address = true;
+
+    Ok(spawn_subsystem(
+        "DHCP",
+        options,
+        requires_address,
+        move |options| {
+            let handle = handle.clone();
+            let stats = Arc::clone(&stats);
+            let lease_names = Arc::clone(&lease_names);
+            let sessions = Arc::clone(&sessions);
+            let inventory = Arc::clone(&inventory);
+            async move {
+                dhcp::start(
+                    &options,
+                    &handle,
+                    &stats,
+                    &lease_names,
+                    &sessions,
+                    &inventory,
+                )
+                .await
+                .map_err(anyhow::Error::from)
+            }
+        },
+    ))
+}
+
+fn start_dns_server(
+    options: Arc<Options>,
+    lease_names: dns::LeaseNames,
+) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    Ok(spawn_subsystem("DNS", options, true, move |options| {
+        let lease_names = Arc::clone(&lease_names);
+        async move { dns::start(&options, &lease_names).await }
     }))
 }
 
-fn start_tftp_server(options: Arc<Options>) -> anyhow::Result<JoinHandle<()>> {
-    Ok(tokio::spawn(async move {
-        tftp::start(&*options).await;
+fn start_binl_server(options: Arc<Options>) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    Ok(spawn_subsystem(
+        "BINL",
+        options,
+        true,
+        move |options| async move { binl::start(&options).await },
+    ))
+}
+
+fn start_syslog_server(
+    options: Arc<Options>,
+    sessions: sessions::Sessions,
+) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    Ok(spawn_subsystem("syslog", options, true, move |options| {
+        let sessions = Arc::clone(&sessions);
+        async move { syslog::start(&options, &sessions).await }
+    }))
+}
+
+fn start_nbd_server(options: Arc<Options>) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    Ok(spawn_subsystem(
+        "NBD",
+        options,
+        true,
+        move |options| async move { nbd::start(&options).await },
+    ))
+}
+
+fn start_tftp_server(
+    options: Arc<Options>,
+    transfers: tftp::Transfers,
+    stats: Arc<Stats>,
+    sessions: sessions::Sessions,
+    inventory: inventory::Inventory,
+) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    Ok(spawn_subsystem("TFTP", options, true, move |options| {
+        let transfers = Arc::clone(&transfers);
+        let stats = Arc::clone(&stats);
+        let sessions = Arc::clone(&sessions);
+        let inventory = Arc::clone(&inventory);
+        async move {
+            tftp::start(&options, &transfers, &stats, &sessions, &inventory)
+                .await
+                .map_err(anyhow::Error::from)
+        }
     }))
 }
 
 #[cfg(feature = "http")]
-fn start_http_server(options: Arc<Options>) -> anyhow::Result<JoinHandle<()>> {
-    Ok(tokio::spawn(async move { http::start(&*options).await }))
+fn start_http_server(
+    options: Arc<Options>,
+    stats: Arc<Stats>,
+    sessions: sessions::Sessions,
+    inventory: inventory::Inventory,
+    dhcp: Option<dhcp::Handle>,
+) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    Ok(spawn_subsystem("HTTP", options, true, move |options| {
+        let stats = Arc::clone(&stats);
+        let sessions = Arc::clone(&sessions);
+        let inventory = Arc::clone(&inventory);
+        let dhcp = dhcp.clone();
+        async move { http::start(&options, &stats, &sessions, &inventory, dhcp).await }
+    }))
 }
@@ -0,0 +1,109 @@
+// Man page in roff, built from the same clap definitions as --help so it
+// cannot fall behind them. Subcommands get own subsection with their
+// arguments, e.g. pxe man > pxe.1 && man ./pxe.1
+use std::io::{self, Write};
+
+use clap::{App, Arg, ArgSettings, IntoApp};
+
+use crate::Options;
+
+const BIN_NAME: &str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub fn print() -> io::Result<()> {
+    let mut out = io::stdout();
+    out.write_all(render(&Options::into_app()).as_bytes())?;
+    out.flush()
+}
+
+fn render(app: &App) -> String {
+    let mut out = format!(
+        ".TH {} 1 \"\" \"{} {}\"\n",
+        BIN_NAME.to_uppercase(),
+        BIN_NAME,
+        VERSION
+    );
+    out += ".SH NAME\n";
+    out += &format!("{} \\- PXE boot server\n", BIN_NAME);
+    out += ".SH SYNOPSIS\n";
+    out += &format!(
+        "\\fB{}\\fR [\\fIOPTIONS\\fR] [\\fISUBCOMMAND\\fR]\n",
+        BIN_NAME
+    );
+
+    out += ".SH OPTIONS\n";
+    arguments(&mut out, app);
+
+    if app.has_subcommands() {
+        out += ".SH SUBCOMMANDS\n";
+        for subcommand in app.get_subcommands() {
+            out += &format!(".SS {}\n", escape(subcommand.get_name()));
+            if let Some(about) = subcommand.get_about() {
+                out += &format!("{}\n", escape(about));
+            }
+            arguments(&mut out, subcommand);
+        }
+    }
+    out
+}
+
+fn arguments(out: &mut String, app: &App) {
+    for arg in app.get_arguments() {
+        if arg.is_set(ArgSettings::Hidden) {
+            continue;
+        }
+        *out += ".TP\n";
+        *out += &synopsis(arg);
+        *out += "\n";
+        if let Some(about) = arg.get_about() {
+            *out += &escape(about);
+            *out += "\n";
+        }
+    }
+}
+
+fn synopsis(arg: &Arg) -> String {
+    let value = format!("\\fI{}\\fR", escape(&arg.get_name().to_uppercase()));
+    if arg.get_index().is_some() || (arg.get_short().is_none() && arg.get_long().is_none()) {
+        return value;
+    }
+
+    let mut names = Vec::new();
+    if let Some(short) = arg.get_short() {
+        names.push(format!("\\fB\\-{}\\fR", escape(&short.to_string())));
+    }
+    if let Some(long) = arg.get_long() {
+        names.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    let mut synopsis = names.join(", ");
+    if arg.is_set(ArgSettings::TakesValue) {
+        synopsis += " ";
+        synopsis += &value;
+    }
+    synopsis
+}
+
+// lines must not start with control character, dashes are not hyphens
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    text.lines()
+        .map(|x| match x.starts_with('.') || x.starts_with('\'') {
+            true => format!("\\&{}", x),
+            false => x.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let page = render(&Options::into_app());
+        assert!(page.starts_with(".TH PXE 1 "));
+        assert!(page.contains("\\fB\\-s\\fR, \\fB\\-\\-server\\-ip\\fR \\fISERVER\\-IP\\fR\n"));
+        assert!(page.contains(".SS import\\-leases\n"));
+    }
+}
@@ -0,0 +1,336 @@
+// Read-only NBD server exporting configured image files, lets diskless
+// clients mount their root filesystem from the same host they booted from.
+// Only fixed newstyle negotiation is supported, which every client since
+// nbd 3.10 and qemu 2.x uses.
+use std::convert::TryInto;
+use std::io::SeekFrom;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+use anyhow::Context;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::Instrument;
+
+use crate::config::NbdExport;
+
+const MAGIC: &[u8; 8] = b"NBDMAGIC";
+const OPTION_MAGIC: u64 = 0x4948_4156_454f_5054;
+const REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+const FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const FLAG_NO_ZEROES: u16 = 1 << 1;
+const CLIENT_FLAG_NO_ZEROES: u32 = 1 << 1;
+
+const TRANSMISSION_HAS_FLAGS: u16 = 1 << 0;
+const TRANSMISSION_READ_ONLY: u16 = 1 << 1;
+const TRANSMISSION_CAN_MULTI_CONN: u16 = 1 << 8;
+
+const OPT_EXPORT_NAME: u32 = 1;
+const OPT_ABORT: u32 = 2;
+const OPT_LIST: u32 = 3;
+const OPT_INFO: u32 = 6;
+const OPT_GO: u32 = 7;
+
+const REP_ACK: u32 = 1;
+const REP_SERVER: u32 = 2;
+const REP_INFO: u32 = 3;
+const REP_ERR_UNSUP: u32 = (1 << 31) + 1;
+const REP_ERR_INVALID: u32 = (1 << 31) + 3;
+const REP_ERR_UNKNOWN: u32 = (1 << 31) + 6;
+
+const INFO_EXPORT: u16 = 0;
+
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+
+const EPERM: u32 = 1;
+const EIO: u32 = 5;
+const EINVAL: u32 = 22;
+
+// options and reads larger than that are refused instead of allocated
+const MAX_OPTION_LEN: u32 = 4096;
+const MAX_READ_LEN: u32 = 32 * 1024 * 1024;
+
+pub async fn start(options: &super::Options) -> anyhow::Result<()> {
+    let listener = TcpListener::bind((options.server_ip(), options.nbd_port)).await?;
+    let exports = options.config.nbd_exports.clone();
+
+    debug!("server starting");
+
+    loop {
+        let (stream, client) = listener.accept().await?;
+        let exports = exports.clone();
+        let span = info_span!("nbd", client = %client);
+        tokio::spawn(
+            async move {
+                if let Err(e) = serve(stream, &exports).await {
+                    warn!("connection failed: {:#}", e);
+                }
+            }
+            .instrument(span),
+        );
+    }
+}
+
+struct Image {
+    name: String,
+    file: File,
+    size: u64,
+}
+
+async fn serve(stream: TcpStream, exports: &[NbdExport]) -> anyhow::Result<()> {
+    stream.set_nodelay(true)?;
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+
+    match negotiate(&mut reader, &mut writer, exports).await? {
+        Some(image) => transmit(&mut reader, &mut writer, image).await,
+        None => Ok(()),
+    }
+}
+
+// None when client aborted negotiation
+async fn negotiate<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    exports: &[NbdExport],
+) -> anyhow::Result<Option<Image>>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    writer.write_all(MAGIC).await?;
+    writer.write_u64(OPTION_MAGIC).await?;
+    writer
+        .write_u16(FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES)
+        .await?;
+    writer.flush().await?;
+
+    let client_flags = reader.read_u32().await?;
+    let no_zeroes = client_flags & CLIENT_FLAG_NO_ZEROES != 0;
+
+    loop {
+        if reader.read_u64().await? != OPTION_MAGIC {
+            bail!("invalid option magic");
+        }
+        let option = reader.read_u32().await?;
+        let len = reader.read_u32().await?;
+        if len > MAX_OPTION_LEN {
+            bail!("option {} too long ({} bytes)", option, len);
+        }
+        let mut data = vec![0u8; len as usize];
+        reader.read_exact(&mut data).await?;
+
+        match option {
+            OPT_EXPORT_NAME => {
+                let name = String::from_utf8_lossy(&data);
+                // there is no way to report an error here other than hanging up
+                let image = open_export(exports, &name).await?;
+                writer.write_u64(image.size).await?;
+                writer.write_u16(transmission_flags()).await?;
+                if !no_zeroes {
+                    writer.write_all(&[0u8; 124]).await?;
+                }
+                writer.flush().await?;
+                return Ok(Some(image));
+            }
+            OPT_INFO | OPT_GO => {
+                let name = match parse_info_request(&data) {
+                    Some(name) => name,
+                    None => {
+                        reply(writer, option, REP_ERR_INVALID, b"").await?;
+                        continue;
+                    }
+                };
+                let image = match open_export(exports, &name).await {
+                    Ok(image) => image,
+                    Err(e) => {
+                        debug!("{}", e);
+                        reply(writer, option, REP_ERR_UNKNOWN, b"").await?;
+                        continue;
+                    }
+                };
+
+                let mut info = Vec::with_capacity(12);
+                info.extend_from_slice(&INFO_EXPORT.to_be_bytes());
+                info.extend_from_slice(&image.size.to_be_bytes());
+                info.extend_from_slice(&transmission_flags().to_be_bytes());
+                reply(writer, option, REP_INFO, &info).await?;
+                reply(writer, option, REP_ACK, b"").await?;
+
+                if option == OPT_GO {
+                    return Ok(Some(image));
+                }
+            }
+            OPT_LIST => {
+                for export in exports {
+                    let mut entry = Vec::with_capacity(4 + export.name.len());
+                    entry.extend_from_slice(&(export.name.len() as u32).to_be_bytes());
+                    entry.extend_from_slice(export.name.as_bytes());
+                    reply(writer, option, REP_SERVER, &entry).await?;
+                }
+                reply(writer, option, REP_ACK, b"").await?;
+            }
+            OPT_ABORT => {
+                // client may close connection without waiting for reply
+                let _ = reply(writer, option, REP_ACK, b"").await;
+                return Ok(None);
+            }
+            _ => reply(writer, option, REP_ERR_UNSUP, b"").await?,
+        }
+    }
+}
+
+// export name from NBD_OPT_INFO or NBD_OPT_GO, requested information
+// types are ignored since only export size and flags are ever sent
+fn parse_info_request(data: &[u8]) -> Option<String> {
+    let len = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let name = data.get(4..)?.get(..len)?;
+    let rest = &data[4 + len..];
+    let count = u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as usize;
+    if rest.len() != 2 + count * 2 {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(name).into_owned())
+}
+
+fn transmission_flags() -> u16 {
+    TRANSMISSION_HAS_FLAGS | TRANSMISSION_READ_ONLY | TRANSMISSION_CAN_MULTI_CONN
+}
+
+async fn reply<W: AsyncWriteExt + Unpin>(
+    writer: &mut W,
+    option: u32,
+    kind: u32,
+    data: &[u8],
+) -> anyhow::Result<()> {
+    writer.write_u64(REPLY_MAGIC).await?;
+    writer.write_u32(option).await?;
+    writer.write_u32(kind).await?;
+    writer.write_u32(data.len() as u32).await?;
+    writer.write_all(data).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+// empty name selects the first export, as with nbd-server default export
+async fn open_export(exports: &[NbdExport], name: &str) -> anyhow::Result<Image> {
+    let export = exports
+        .iter()
+        .find(|x| x.name == name || name.is_empty())
+        .ok_or_else(|| anyhow!("unknown export \"{}\"", name))?;
+    open_image(&export.name, &export.path).await
+}
+
+async fn open_image(name: &str, path: &Path) -> anyhow::Result<Image> {
+    let mut file = File::open(path)
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    // seeking also works for block devices, whose metadata length is zero
+    let size = file.seek(SeekFrom::End(0)).await?;
+
+    Ok(Image {
+        name: name.to_string(),
+        file,
+        size,
+    })
+}
+
+async fn transmit<R, W>(reader: &mut R, writer: &mut W, mut image: Image) -> anyhow::Result<()>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    info!("serving export {} ({} bytes)", image.name, image.size);
+
+    let mut buf = Vec::new();
+    loop {
+        if reader.read_u32().await? != REQUEST_MAGIC {
+            bail!("invalid request magic");
+        }
+        let _flags = reader.read_u16().await?;
+        let command = reader.read_u16().await?;
+        let handle = reader.read_u64().await?;
+        let offset = reader.read_u64().await?;
+        let len = reader.read_u32().await?;
+
+        let error = match command {
+            CMD_READ if len > MAX_READ_LEN => EINVAL,
+            CMD_READ if offset.saturating_add(len as u64) > image.size => EINVAL,
+            CMD_READ => {
+                buf.resize(len as usize, 0);
+                let result = async {
+                    image.file.seek(SeekFrom::Start(offset)).await?;
+                    image.file.read_exact(&mut buf).await
+                }
+                .await;
+
+                match result {
+                    Ok(_) => {
+                        write_reply(writer, 0, handle).await?;
+                        writer.write_all(&buf).await?;
+                        writer.flush().await?;
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("read of {} bytes at {} failed: {}", len, offset, e);
+                        EIO
+                    }
+                }
+            }
+            CMD_WRITE => {
+                // payload has to be consumed to stay in sync with client
+                tokio::io::copy(&mut reader.take(len as u64), &mut tokio::io::sink()).await?;
+                EPERM
+            }
+            CMD_DISC => {
+                debug!("client disconnected");
+                return Ok(());
+            }
+            // nothing is ever written, so there is nothing to flush
+            CMD_FLUSH => 0,
+            _ => EINVAL,
+        };
+
+        write_reply(writer, error, handle).await?;
+        writer.flush().await?;
+    }
+}
+
+async fn write_reply<W: AsyncWriteExt + Unpin>(
+    writer: &mut W,
+    error: u32,
+    handle: u64,
+) -> anyhow::Result<()> {
+    writer.write_u32(SIMPLE_REPLY_MAGIC).await?;
+    writer.write_u32(error).await?;
+    writer.write_u64(handle).await?;
+    Ok(())
+}
+
+// kernel command line root for dracut's nbd module
+pub fn root_path(server_ip: Ipv4Addr, port: u16, export: &str) -> String {
+    format!("nbd:{}:{}/{}", server_ip, port, export)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_info_request() {
+        let request = [0, 0, 0, 4, b'r', b'o', b'o', b't', 0, 1, 0, 0];
+        assert_eq!(parse_info_request(&request), Some("root".to_string()));
+        assert_eq!(parse_info_request(&request[..11]), None);
+        assert_eq!(parse_info_request(&[0, 0, 0, 9, b'r']), None);
+        assert_eq!(parse_info_request(&[0, 0, 0, 0, 0, 0]), Some(String::new()));
+    }
+}
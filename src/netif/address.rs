@@ -0,0 +1,87 @@
+use std::io::Cursor;
+use std::net::Ipv4Addr;
+use std::os::unix::io::AsRawFd;
+
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use nix::errno::Errno;
+use nix::libc;
+use nix::net::if_::if_nametoindex;
+use nix::sys::socket::{recv, send, MsgFlags};
+
+use super::netlink::{NetlinkSocket, NLMSG_HEADER_LEN, RTATTR_HEADER_LEN};
+
+const IFADDRMSG_LEN: usize = 8;
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const IFA_BROADCAST: u16 = 4;
+
+pub fn add_address(name: &str, address: Ipv4Addr, prefix_len: u8) -> anyhow::Result<()> {
+    change_address(
+        libc::RTM_NEWADDR,
+        libc::NLM_F_CREATE | libc::NLM_F_EXCL,
+        name,
+        address,
+        prefix_len,
+    )
+}
+
+pub fn remove_address(name: &str, address: Ipv4Addr, prefix_len: u8) -> anyhow::Result<()> {
+    change_address(libc::RTM_DELADDR, 0, name, address, prefix_len)
+}
+
+fn change_address(
+    kind: u16,
+    flags: libc::c_int,
+    name: &str,
+    address: Ipv4Addr,
+    prefix_len: u8,
+) -> anyhow::Result<()> {
+    let index = if_nametoindex(name)?;
+    let socket = NetlinkSocket::open(0, false)?;
+
+    let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+    let broadcast = Ipv4Addr::from(u32::from(address) | !mask);
+    let attributes = [
+        (IFA_LOCAL, address),
+        (IFA_ADDRESS, address),
+        (IFA_BROADCAST, broadcast),
+    ];
+    let len = NLMSG_HEADER_LEN + IFADDRMSG_LEN + attributes.len() * (RTATTR_HEADER_LEN + 4);
+
+    let mut request = Vec::with_capacity(len);
+    request.write_u32::<NativeEndian>(len as u32)?;
+    request.write_u16::<NativeEndian>(kind)?;
+    request.write_u16::<NativeEndian>((libc::NLM_F_REQUEST | libc::NLM_F_ACK | flags) as u16)?;
+    request.write_u32::<NativeEndian>(1)?;
+    request.write_u32::<NativeEndian>(0)?;
+    // ifaddrmsg: family, prefix length, flags, scope, index
+    request.write_u8(libc::AF_INET as u8)?;
+    request.write_u8(prefix_len)?;
+    request.write_u8(0)?;
+    request.write_u8(libc::RT_SCOPE_UNIVERSE)?;
+    request.write_u32::<NativeEndian>(index)?;
+    for (kind, value) in attributes.iter() {
+        request.write_u16::<NativeEndian>((RTATTR_HEADER_LEN + 4) as u16)?;
+        request.write_u16::<NativeEndian>(*kind)?;
+        request.extend_from_slice(&value.octets());
+    }
+    send(socket.as_raw_fd(), &request, MsgFlags::empty())?;
+
+    // kernel acknowledges with error message carrying zero error code
+    let mut buffer = [0u8; 1024];
+    let n = recv(socket.as_raw_fd(), &mut buffer, MsgFlags::empty())?;
+    if n < NLMSG_HEADER_LEN + 4 {
+        bail!("truncated netlink message");
+    }
+
+    let mut reply = Cursor::new(&buffer[..n]);
+    reply.set_position(4);
+    if reply.read_u16::<NativeEndian>()? as i32 != libc::NLMSG_ERROR {
+        bail!("unexpected netlink reply");
+    }
+    reply.set_position(NLMSG_HEADER_LEN as u64);
+    match -reply.read_i32::<NativeEndian>()? {
+        0 => Ok(()),
+        errno => Err(Errno::from_i32(errno).into()),
+    }
+}
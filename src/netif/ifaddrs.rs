@@ -0,0 +1,59 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use nix::ifaddrs::getifaddrs;
+#[cfg(target_os = "linux")]
+use nix::net::if_::InterfaceFlags;
+use nix::sys::socket::SockAddr;
+
+use super::NetworkInterface;
+
+impl NetworkInterface {
+    // interface owning given address, if any
+    pub fn with_address(address: Ipv4Addr) -> anyhow::Result<Option<Self>> {
+        Ok(getifaddrs()?
+            .find(|x| to_ipv4(x.address.as_ref()) == Some(address))
+            .map(|x| Self::new(&x.interface_name)))
+    }
+
+    // first IPv4 address assigned to interface together with its prefix length
+    pub fn ip_address(&self) -> anyhow::Result<(Ipv4Addr, u8)> {
+        for ifaddr in getifaddrs()? {
+            if ifaddr.interface_name != self.name {
+                continue;
+            }
+
+            if let (Some(address), Some(netmask)) = (
+                to_ipv4(ifaddr.address.as_ref()),
+                to_ipv4(ifaddr.netmask.as_ref()),
+            ) {
+                return Ok((address, u32::from(netmask).count_ones() as u8));
+            }
+        }
+
+        bail!("interface {} has no IPv4 address", self.name)
+    }
+
+    // whether interface is up and has carrier
+    #[cfg(target_os = "linux")]
+    pub fn is_running(&self) -> anyhow::Result<bool> {
+        for ifaddr in getifaddrs()? {
+            if ifaddr.interface_name == self.name {
+                return Ok(ifaddr
+                    .flags
+                    .contains(InterfaceFlags::IFF_UP | InterfaceFlags::IFF_RUNNING));
+            }
+        }
+
+        bail!("no such interface {}", self.name)
+    }
+}
+
+fn to_ipv4(address: Option<&SockAddr>) -> Option<Ipv4Addr> {
+    match address {
+        Some(SockAddr::Inet(inet)) => match inet.ip().to_std() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,64 @@
+use std::io::Cursor;
+use std::os::unix::io::AsRawFd;
+
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use nix::libc;
+use nix::net::if_::if_nametoindex;
+use nix::sys::socket::{recv, send, MsgFlags};
+
+use super::netlink::{align, NetlinkSocket, NLMSG_HEADER_LEN, RTATTR_HEADER_LEN};
+
+const IFINFOMSG_LEN: usize = 16;
+const IFLA_MTU: u16 = 4;
+
+// queries MTU of interface with RTM_GETLINK
+pub fn link_mtu(name: &str) -> anyhow::Result<u32> {
+    let index = if_nametoindex(name)?;
+    let socket = NetlinkSocket::open(0, false)?;
+
+    let mut request = Vec::with_capacity(NLMSG_HEADER_LEN + IFINFOMSG_LEN);
+    request.write_u32::<NativeEndian>((NLMSG_HEADER_LEN + IFINFOMSG_LEN) as u32)?;
+    request.write_u16::<NativeEndian>(libc::RTM_GETLINK)?;
+    request.write_u16::<NativeEndian>(libc::NLM_F_REQUEST as u16)?;
+    request.write_u32::<NativeEndian>(1)?;
+    request.write_u32::<NativeEndian>(0)?;
+    // ifinfomsg: family, padding, type, index, flags, change mask
+    request.write_u8(libc::AF_UNSPEC as u8)?;
+    request.write_u8(0)?;
+    request.write_u16::<NativeEndian>(0)?;
+    request.write_i32::<NativeEndian>(index as i32)?;
+    request.resize(NLMSG_HEADER_LEN + IFINFOMSG_LEN, 0);
+    send(socket.as_raw_fd(), &request, MsgFlags::empty())?;
+
+    let mut buffer = vec![0u8; 32768];
+    let n = recv(socket.as_raw_fd(), &mut buffer, MsgFlags::empty())?;
+    let data = &buffer[..n];
+    if data.len() < NLMSG_HEADER_LEN {
+        bail!("truncated netlink message");
+    }
+
+    let mut header = Cursor::new(data);
+    let len = header.read_u32::<NativeEndian>()? as usize;
+    let kind = header.read_u16::<NativeEndian>()?;
+    if len < NLMSG_HEADER_LEN + IFINFOMSG_LEN || len > data.len() || kind != libc::RTM_NEWLINK {
+        bail!("failed to query link {}", name);
+    }
+
+    let mut attributes = &data[NLMSG_HEADER_LEN + IFINFOMSG_LEN..len];
+    while attributes.len() >= RTATTR_HEADER_LEN {
+        let mut header = Cursor::new(attributes);
+        let len = header.read_u16::<NativeEndian>()? as usize;
+        let kind = header.read_u16::<NativeEndian>()?;
+        if len < RTATTR_HEADER_LEN || len > attributes.len() {
+            bail!("malformed netlink attribute");
+        }
+
+        if kind == IFLA_MTU && len == RTATTR_HEADER_LEN + 4 {
+            return Ok(header.read_u32::<NativeEndian>()?);
+        }
+
+        attributes = &attributes[align(len).min(attributes.len())..];
+    }
+
+    bail!("link {} has no MTU", name)
+}
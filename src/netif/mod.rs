@@ -0,0 +1,173 @@
+// Interface queries are implemented with getifaddrs on Unix systems,
+// elsewhere using interfaces fails while everything else keeps working.
+// Monitoring, address management and neighbor table need rtnetlink
+// and are available on Linux only.
+use std::fmt;
+use std::net::Ipv4Addr;
+#[cfg(target_os = "linux")]
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+use anyhow::Context;
+
+use crate::iputil::Ipv4AddrAndMask;
+
+// what servers bound to interface care about, they are paused while it is unusable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkState {
+    pub address: Option<Ipv4Addr>,
+    pub running: bool,
+}
+
+impl LinkState {
+    pub fn is_usable(&self) -> bool {
+        self.running && self.address.is_some()
+    }
+}
+
+impl fmt::Display for LinkState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.running, self.address) {
+            (false, _) => write!(f, "link down"),
+            (true, None) => write!(f, "no IPv4 address"),
+            (true, Some(address)) => write!(f, "up, {}", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NetworkInterface {
+    name: String,
+}
+
+impl NetworkInterface {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn mtu(&self) -> anyhow::Result<u32> {
+        link::link_mtu(&self.name)
+    }
+
+    // address stays assigned until returned guard is dropped
+    #[cfg(target_os = "linux")]
+    pub fn assign_address(
+        &self,
+        address: Ipv4Addr,
+        prefix_len: u8,
+    ) -> anyhow::Result<AssignedAddress> {
+        address::add_address(&self.name, address, prefix_len).with_context(|| {
+            format!(
+                "failed to assign {}/{} to {}",
+                address, prefix_len, self.name
+            )
+        })?;
+        info!("assigned {}/{} to {}", address, prefix_len, self.name);
+
+        Ok(AssignedAddress {
+            interface: self.name.clone(),
+            address,
+            prefix_len,
+        })
+    }
+
+    // errors are treated as interface being gone
+    #[cfg(target_os = "linux")]
+    pub fn link_state(&self) -> LinkState {
+        LinkState {
+            address: self.ip_address().ok().map(|(address, _)| address),
+            running: self.is_running().unwrap_or(false),
+        }
+    }
+
+    // subnet of first IPv4 address, usable as DHCP subnet
+    pub fn subnet(&self) -> anyhow::Result<Ipv4AddrAndMask> {
+        let (address, prefix_len) = self.ip_address()?;
+        if !(1..=30).contains(&prefix_len) {
+            bail!(
+                "{}/{} on {} leaves no room for DHCP pool",
+                address,
+                prefix_len,
+                self.name
+            );
+        }
+
+        Ok(Ipv4AddrAndMask::network_of(address, prefix_len))
+    }
+}
+
+// address assigned by server at startup, removed from interface on drop
+#[cfg(target_os = "linux")]
+pub struct AssignedAddress {
+    interface: String,
+    address: Ipv4Addr,
+    prefix_len: u8,
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for AssignedAddress {
+    fn drop(&mut self) {
+        match address::remove_address(&self.interface, self.address, self.prefix_len) {
+            Ok(()) => info!(
+                "removed {}/{} from {}",
+                self.address, self.prefix_len, self.interface
+            ),
+            Err(e) => warn!(
+                "failed to remove {}/{} from {}: {}",
+                self.address, self.prefix_len, self.interface, e
+            ),
+        }
+    }
+}
+
+// switches calling thread to network namespace given by name (as created by ip netns)
+// or path to namespace file
+#[cfg(target_os = "linux")]
+pub fn enter_netns(netns: &Path) -> anyhow::Result<()> {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    use nix::sched::{setns, CloneFlags};
+
+    let path = if netns.components().count() == 1 {
+        Path::new("/var/run/netns").join(netns)
+    } else {
+        netns.to_path_buf()
+    };
+
+    let file = File::open(&path)
+        .with_context(|| format!("failed to open network namespace {}", path.display()))?;
+    setns(file.as_raw_fd(), CloneFlags::CLONE_NEWNET)
+        .with_context(|| format!("failed to enter network namespace {}", path.display()))?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+mod ifaddrs;
+#[cfg(not(unix))]
+mod unsupported;
+
+#[cfg(target_os = "linux")]
+pub use monitor::monitor;
+#[cfg(target_os = "linux")]
+pub use neighbor::lookup_neighbor;
+
+#[cfg(target_os = "linux")]
+mod address;
+#[cfg(target_os = "linux")]
+mod link;
+#[cfg(target_os = "linux")]
+mod monitor;
+#[cfg(target_os = "linux")]
+mod neighbor;
+#[cfg(target_os = "linux")]
+mod netlink;
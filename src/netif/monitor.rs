@@ -0,0 +1,70 @@
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::socket::{recv, MsgFlags};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::watch;
+
+use super::netlink::NetlinkSocket;
+use super::{LinkState, NetworkInterface};
+
+// notifications tend to come in bursts, e.g. address removal followed by addition
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+// Follows rtnetlink address and link notifications and publishes interface
+// state whenever it changes. Servers bound to interface are paused while link
+// is down or address is missing and restarted once it is usable again.
+pub async fn monitor(
+    interface: NetworkInterface,
+    sender: watch::Sender<LinkState>,
+) -> anyhow::Result<()> {
+    let socket = NetlinkSocket::open((libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR) as u32, true)?;
+    let fd = socket.as_raw_fd();
+    let socket = AsyncFd::new(socket)?;
+
+    let mut state = interface.link_state();
+    let mut buffer = [0u8; 8192];
+
+    loop {
+        socket.readable().await?.clear_ready();
+        tokio::time::sleep(SETTLE_TIME).await;
+
+        // content is irrelevant, current state is queried below
+        loop {
+            match recv(fd, &mut buffer, MsgFlags::empty()) {
+                Ok(_) => continue,
+                Err(Errno::EAGAIN) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let new_state = interface.link_state();
+        if new_state == state {
+            continue;
+        }
+
+        if state.running && !new_state.running {
+            warn!("{} link is down, pausing servers", interface.name);
+        } else if !state.running && new_state.running {
+            info!("{} link is up", interface.name);
+        }
+        match new_state.address {
+            Some(a) if state.address.is_some() && new_state.address != state.address => {
+                info!("{} address changed to {}", interface.name, a)
+            }
+            Some(a) if state.address.is_none() => info!("{} got address {}", interface.name, a),
+            None if state.address.is_some() => {
+                warn!("{} has no IPv4 address anymore", interface.name)
+            }
+            _ => (),
+        }
+        if new_state.is_usable() && !state.is_usable() {
+            info!("{} is usable again, resuming servers", interface.name);
+        }
+
+        state = new_state;
+        let _ = sender.send(state);
+    }
+}
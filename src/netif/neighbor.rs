@@ -0,0 +1,148 @@
+use std::fmt;
+use std::io::Cursor;
+use std::net::Ipv4Addr;
+use std::os::unix::io::AsRawFd;
+
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use nix::libc;
+use nix::sys::socket::{recv, send, MsgFlags};
+
+use super::netlink::{align, NetlinkSocket, NLMSG_HEADER_LEN, RTATTR_HEADER_LEN};
+use crate::dhcp::id::Mac;
+
+const NDMSG_LEN: usize = 12;
+
+// entry of kernel neighbor (ARP) table
+#[derive(Debug, Clone)]
+pub struct Neighbor {
+    pub ip: Ipv4Addr,
+    pub mac: Option<Mac>,
+    pub ifindex: u32,
+    // NUD_* bitmask
+    pub state: u16,
+}
+
+impl Neighbor {
+    fn state_str(&self) -> &'static str {
+        match self.state {
+            libc::NUD_INCOMPLETE => "incomplete",
+            libc::NUD_REACHABLE => "reachable",
+            libc::NUD_STALE => "stale",
+            libc::NUD_DELAY => "delay",
+            libc::NUD_PROBE => "probe",
+            libc::NUD_FAILED => "failed",
+            libc::NUD_NOARP => "noarp",
+            libc::NUD_PERMANENT => "permanent",
+            _ => "none",
+        }
+    }
+}
+
+impl fmt::Display for Neighbor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mac.as_ref() {
+            Some(mac) => write!(f, "{} lladdr {}", self.ip, mac)?,
+            None => write!(f, "{}", self.ip)?,
+        }
+        write!(f, " ifindex {} {}", self.ifindex, self.state_str())
+    }
+}
+
+// finds given address in kernel neighbor table
+pub fn lookup_neighbor(ip: Ipv4Addr) -> anyhow::Result<Option<Neighbor>> {
+    Ok(dump_neighbors()?.into_iter().find(|x| x.ip == ip))
+}
+
+fn dump_neighbors() -> anyhow::Result<Vec<Neighbor>> {
+    let socket = NetlinkSocket::open(0, false)?;
+
+    // RTM_GETNEIGH dump request for all IPv4 entries
+    let mut request = Vec::with_capacity(NLMSG_HEADER_LEN + NDMSG_LEN);
+    request.write_u32::<NativeEndian>((NLMSG_HEADER_LEN + NDMSG_LEN) as u32)?;
+    request.write_u16::<NativeEndian>(libc::RTM_GETNEIGH)?;
+    request.write_u16::<NativeEndian>((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16)?;
+    request.write_u32::<NativeEndian>(1)?;
+    request.write_u32::<NativeEndian>(0)?;
+    request.push(libc::AF_INET as u8);
+    request.resize(NLMSG_HEADER_LEN + NDMSG_LEN, 0);
+    send(socket.as_raw_fd(), &request, MsgFlags::empty())?;
+
+    let mut neighbors = Vec::new();
+    let mut buffer = vec![0u8; 32768];
+    loop {
+        let n = recv(socket.as_raw_fd(), &mut buffer, MsgFlags::empty())?;
+        let mut data = &buffer[..n];
+
+        while data.len() >= NLMSG_HEADER_LEN {
+            let mut header = Cursor::new(data);
+            let len = header.read_u32::<NativeEndian>()? as usize;
+            let kind = header.read_u16::<NativeEndian>()?;
+            if len < NLMSG_HEADER_LEN || len > data.len() {
+                bail!("malformed netlink message");
+            }
+
+            match kind as i32 {
+                libc::NLMSG_DONE => return Ok(neighbors),
+                libc::NLMSG_ERROR => bail!("netlink neighbor dump failed"),
+                _ if kind == libc::RTM_NEWNEIGH => {
+                    if let Some(neighbor) = parse_neighbor(&data[NLMSG_HEADER_LEN..len])? {
+                        neighbors.push(neighbor);
+                    }
+                }
+                _ => (),
+            }
+
+            data = &data[align(len).min(data.len())..];
+        }
+    }
+}
+
+fn parse_neighbor(data: &[u8]) -> anyhow::Result<Option<Neighbor>> {
+    if data.len() < NDMSG_LEN {
+        bail!("truncated neighbor message");
+    }
+
+    let mut ndmsg = Cursor::new(data);
+    let family = ndmsg.read_u8()?;
+    ndmsg.set_position(4);
+    let ifindex = ndmsg.read_u32::<NativeEndian>()?;
+    let state = ndmsg.read_u16::<NativeEndian>()?;
+
+    if family != libc::AF_INET as u8 {
+        return Ok(None);
+    }
+
+    let mut ip = None;
+    let mut mac = None;
+    let mut attributes = &data[NDMSG_LEN..];
+    while attributes.len() >= RTATTR_HEADER_LEN {
+        let mut header = Cursor::new(attributes);
+        let len = header.read_u16::<NativeEndian>()? as usize;
+        let kind = header.read_u16::<NativeEndian>()?;
+        if len < RTATTR_HEADER_LEN || len > attributes.len() {
+            bail!("malformed netlink attribute");
+        }
+        let value = &attributes[RTATTR_HEADER_LEN..len];
+
+        match kind {
+            libc::NDA_DST if value.len() == 4 => {
+                ip = Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+            }
+            libc::NDA_LLADDR if value.len() == 6 => {
+                let mut raw = [0u8; 16];
+                raw[..6].copy_from_slice(value);
+                mac = Some(Mac::from(raw));
+            }
+            _ => (),
+        }
+
+        attributes = &attributes[align(len).min(attributes.len())..];
+    }
+
+    Ok(ip.map(|ip| Neighbor {
+        ip,
+        mac,
+        ifindex,
+        state,
+    }))
+}
@@ -0,0 +1,46 @@
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use nix::sys::socket::{bind, socket, AddressFamily, SockAddr, SockFlag, SockProtocol, SockType};
+
+pub const NLMSG_HEADER_LEN: usize = 16;
+pub const RTATTR_HEADER_LEN: usize = 4;
+
+// rtnetlink socket, closed on drop
+pub struct NetlinkSocket(RawFd);
+
+impl NetlinkSocket {
+    // groups is bitmask of RTMGRP_* multicast groups to receive notifications from
+    pub fn open(groups: u32, nonblocking: bool) -> nix::Result<Self> {
+        let mut flags = SockFlag::SOCK_CLOEXEC;
+        if nonblocking {
+            flags |= SockFlag::SOCK_NONBLOCK;
+        }
+
+        let socket = Self(socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            flags,
+            SockProtocol::NetlinkRoute,
+        )?);
+        bind(socket.0, &SockAddr::new_netlink(0, groups))?;
+
+        Ok(socket)
+    }
+}
+
+impl AsRawFd for NetlinkSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.0);
+    }
+}
+
+// netlink messages and attributes are 4 byte aligned
+pub fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
@@ -0,0 +1,9 @@
+use std::net::Ipv4Addr;
+
+use super::NetworkInterface;
+
+impl NetworkInterface {
+    pub fn ip_address(&self) -> anyhow::Result<(Ipv4Addr, u8)> {
+        bail!("network interfaces are not supported on this platform")
+    }
+}
@@ -0,0 +1,208 @@
+// Power control of hosts through their BMC, so reimaging can be driven from
+// here: move host to installing with boot-once, set next boot to PXE, power
+// cycle it and watch its boot session. BMC address, credentials and protocol are taken
+// from inventory. IPMI over LAN (RMCP+) is spoken by ipmitool, Redfish is
+// called directly.
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::process::Command;
+
+use crate::inventory::{Bmc, BmcProtocol, Store};
+
+// BMCs retry lost packets for a while before giving up
+const IPMI_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    On,
+    Off,
+    Cycle,
+    // next boot only, from network
+    Pxe,
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            "cycle" => Ok(Self::Cycle),
+            "pxe" => Ok(Self::Pxe),
+            _ => bail!("expected on, off, cycle or pxe"),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::On => "power on",
+            Self::Off => "power off",
+            Self::Cycle => "power cycle",
+            Self::Pxe => "next boot from PXE",
+        })
+    }
+}
+
+// host is MAC, UUID or hostname of client in inventory
+pub async fn run(
+    inventory_file: &Path,
+    host: &str,
+    action: Action,
+    ipmitool: &Path,
+) -> anyhow::Result<()> {
+    let inventory = Store::open(Some(inventory_file))?;
+    let client = inventory.find(host)?;
+    let bmc = client
+        .bmc
+        .as_ref()
+        .ok_or_else(|| anyhow!("no BMC known for {}", client.mac))?;
+
+    let result = match bmc.protocol {
+        BmcProtocol::Ipmi => ipmi(ipmitool, bmc, action).await,
+        #[cfg(feature = "fetch")]
+        BmcProtocol::Redfish => redfish::run(bmc, action).await,
+        #[cfg(not(feature = "fetch"))]
+        BmcProtocol::Redfish => Err(anyhow!("Redfish requires fetch feature")),
+    };
+    result.with_context(|| format!("{} of {} failed", action, client.mac))?;
+    println!("{}: {}", client.mac, action);
+
+    Ok(())
+}
+
+fn ipmi_command(action: Action) -> &'static [&'static str] {
+    match action {
+        Action::On => &["chassis", "power", "on"],
+        Action::Off => &["chassis", "power", "off"],
+        Action::Cycle => &["chassis", "power", "cycle"],
+        Action::Pxe => &["chassis", "bootdev", "pxe"],
+    }
+}
+
+// password is passed in environment, unlike arguments
+// it is not visible to other users
+async fn ipmi(ipmitool: &Path, bmc: &Bmc, action: Action) -> anyhow::Result<()> {
+    let (host, port) = match bmc.address.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (bmc.address.as_str(), None),
+    };
+
+    let mut command = Command::new(ipmitool);
+    command
+        .args(["-I", "lanplus", "-H", host, "-U", &bmc.username, "-E"])
+        .env("IPMI_PASSWORD", &bmc.password)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    if let Some(port) = port {
+        command.args(["-p", port]);
+    }
+    command.args(ipmi_command(action));
+
+    let output = tokio::time::timeout(IPMI_TIMEOUT, command.output())
+        .await
+        .map_err(|_| anyhow!("timed out after {} s", IPMI_TIMEOUT.as_secs()))?
+        .with_context(|| format!("failed to run {}", ipmitool.display()))?;
+    if !output.status.success() {
+        bail!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "fetch")]
+mod redfish {
+    use std::time::Duration;
+
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::Action;
+    use crate::inventory::Bmc;
+
+    const REDFISH_TIMEOUT: Duration = Duration::from_secs(30);
+
+    #[derive(Deserialize)]
+    struct Collection {
+        #[serde(rename = "Members")]
+        members: Vec<Link>,
+    }
+
+    #[derive(Deserialize)]
+    struct Link {
+        #[serde(rename = "@odata.id")]
+        id: String,
+    }
+
+    // first computer system of BMC is controlled, BMCs of
+    // multi-node chassis have one address per node anyway
+    pub async fn run(bmc: &Bmc, action: Action) -> anyhow::Result<()> {
+        let base = if bmc.address.contains("://") {
+            bmc.address.trim_end_matches('/').to_string()
+        } else {
+            format!("https://{}", bmc.address)
+        };
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(bmc.insecure)
+            .timeout(REDFISH_TIMEOUT)
+            .build()?;
+
+        let systems = client
+            .get(format!("{}/redfish/v1/Systems", base))
+            .basic_auth(&bmc.username, Some(&bmc.password))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let system = serde_json::from_str::<Collection>(&systems)?
+            .members
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("BMC manages no computer system"))?;
+        let system = format!("{}{}", base, system.id);
+
+        let request = match action {
+            Action::Pxe => client.patch(&system).body(
+                json!({
+                    "Boot": {
+                        "BootSourceOverrideTarget": "Pxe",
+                        "BootSourceOverrideEnabled": "Once",
+                    }
+                })
+                .to_string(),
+            ),
+            action => client
+                .post(format!("{}/Actions/ComputerSystem.Reset", system))
+                .body(json!({ "ResetType": reset_type(action) }).to_string()),
+        };
+        request
+            .basic_auth(&bmc.username, Some(&bmc.password))
+            .header("Content-Type", "application/json")
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    fn reset_type(action: Action) -> &'static str {
+        match action {
+            Action::On => "On",
+            Action::Off => "ForceOff",
+            Action::Cycle => "PowerCycle",
+            Action::Pxe => unreachable!(),
+        }
+    }
+}
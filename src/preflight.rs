@@ -0,0 +1,115 @@
+use std::fs;
+
+#[cfg(unix)]
+use nix::unistd::geteuid;
+
+use crate::config::Diagnostics;
+use crate::Options;
+
+#[cfg(target_os = "linux")]
+const CAP_NET_BIND_SERVICE: u32 = 10;
+#[cfg(target_os = "linux")]
+const CAP_NET_RAW: u32 = 13;
+const DEFAULT_UNPRIVILEGED_PORT_START: u16 = 1024;
+
+// Verifies that ports about to be bound are accessible to this process,
+// so missing privileges are reported at startup rather than as bind
+// errors from inside server tasks.
+pub fn check(instances: &[Options], diagnostics: &mut Diagnostics) {
+    #[cfg(target_os = "linux")]
+    if !has_capability(CAP_NET_RAW) {
+        for (i, options) in instances.iter().enumerate() {
+            if options.raw_socket && options.serves_dhcp() {
+                diagnostics.error(
+                    service_path(i, options, "DHCP"),
+                    "packet socket requires root or CAP_NET_RAW",
+                );
+            }
+        }
+    }
+
+    if can_bind_privileged() {
+        return;
+    }
+
+    let unprivileged_start = unprivileged_port_start();
+
+    for (i, options) in instances.iter().enumerate() {
+        let mut ports = Vec::new();
+        if options.serves_dhcp() {
+            ports.push(("DHCP", 67));
+        }
+        if !options.no_tftp {
+            ports.push(("TFTP", 69));
+        }
+        if options.dns {
+            ports.push(("DNS", 53));
+        }
+        if !options.config.nbd_exports.is_empty() {
+            ports.push(("NBD", options.nbd_port));
+        }
+        #[cfg(feature = "http")]
+        if options.tftp_root.is_some() {
+            ports.push(("HTTP", options.http_port));
+        }
+
+        for (service, port) in ports {
+            if port < unprivileged_start {
+                diagnostics.error(
+                    service_path(i, options, service),
+                    format!(
+                        "port {} requires root or CAP_NET_BIND_SERVICE, \
+                         e.g. setcap cap_net_bind_service=+ep on the binary",
+                        port
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn service_path(index: usize, options: &Options, service: &str) -> String {
+    match options.instance_name {
+        Some(_) => format!("instance[{}] {}", index, service),
+        None => service.to_string(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn can_bind_privileged() -> bool {
+    has_capability(CAP_NET_BIND_SERVICE)
+}
+
+// on Linux even root may lack capabilities, e.g. in containers,
+// so effective capability set from /proc/self/status decides
+#[cfg(target_os = "linux")]
+fn has_capability(capability: u32) -> bool {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))
+                .and_then(|x| u64::from_str_radix(x.trim(), 16).ok())
+        })
+        .map_or_else(|| geteuid().is_root(), |caps| caps & (1 << capability) != 0)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn can_bind_privileged() -> bool {
+    geteuid().is_root()
+}
+
+// there are no privileged ports on Windows
+#[cfg(not(unix))]
+fn can_bind_privileged() -> bool {
+    true
+}
+
+// first port bindable without privileges, lowered by some distributions
+fn unprivileged_port_start() -> u16 {
+    fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+        .ok()
+        .and_then(|x| x.trim().parse().ok())
+        .unwrap_or(DEFAULT_UNPRIVILEGED_PORT_START)
+}
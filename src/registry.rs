@@ -0,0 +1,267 @@
+// Managed directory of boot artifacts. Files are stored once under their
+// SHA-256 digest and grouped into named, immutable versions. Profiles refer
+// to <name>/current/<file> where current is a symlink to one of versions,
+// switched by renaming new link over it, so clients never get kernel of one
+// version and initrd of another. Version it replaced is kept for rollback.
+//
+// <registry>/blobs/<sha256>
+// <registry>/<name>/versions/<version>/<file>, hard links to blobs
+// <registry>/<name>/current -> versions/<version>
+// <registry>/<name>/previous -> versions/<version>
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::signature;
+
+const BLOBS_DIR: &str = "blobs";
+const VERSIONS_DIR: &str = "versions";
+const CURRENT_LINK: &str = "current";
+const PREVIOUS_LINK: &str = "previous";
+// prefix of entries still being written, never referred to
+const PARTIAL_PREFIX: &str = ".";
+
+#[derive(Debug)]
+pub struct Registry {
+    dir: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct Artifact {
+    pub name: String,
+    pub versions: Vec<String>,
+    pub current: Option<String>,
+    pub previous: Option<String>,
+}
+
+impl Registry {
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        fs::create_dir_all(dir.join(BLOBS_DIR))
+            .with_context(|| format!("failed to create registry in {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    // Version is written under temporary name and renamed once complete.
+    // First version of artifact becomes current right away.
+    pub fn publish(&self, name: &str, version: &str, files: &[PathBuf]) -> anyhow::Result<()> {
+        check_component("name", name)?;
+        check_component("version", version)?;
+        if name == BLOBS_DIR {
+            bail!("name {} is reserved", name);
+        }
+
+        let versions = self.dir.join(name).join(VERSIONS_DIR);
+        let path = versions.join(version);
+        if path.exists() {
+            bail!(
+                "{} {} already exists, versions are immutable",
+                name,
+                version
+            );
+        }
+        let partial = versions.join(format!("{}{}", PARTIAL_PREFIX, version));
+        let _ = fs::remove_dir_all(&partial);
+        fs::create_dir_all(&partial)?;
+
+        for file in files {
+            let file_name = file
+                .file_name()
+                .ok_or_else(|| anyhow!("{} is not a file", file.display()))?;
+            let blob = self.store(file)?;
+            fs::hard_link(&blob, partial.join(file_name))
+                .with_context(|| format!("failed to link {}", file.display()))?;
+        }
+        fs::rename(&partial, &path)?;
+        info!("published {} {}", name, version);
+
+        if self.link(name, CURRENT_LINK)?.is_none() {
+            self.switch(name, version)?;
+        }
+
+        Ok(())
+    }
+
+    // returns version that was current before
+    pub fn switch(&self, name: &str, version: &str) -> anyhow::Result<Option<String>> {
+        check_component("name", name)?;
+        check_component("version", version)?;
+        if !self
+            .dir
+            .join(name)
+            .join(VERSIONS_DIR)
+            .join(version)
+            .is_dir()
+        {
+            bail!("{} has no version {}", name, version);
+        }
+
+        let current = self.link(name, CURRENT_LINK)?;
+        if current.as_deref() == Some(version) {
+            return Ok(current);
+        }
+        if let Some(current) = current.as_deref() {
+            self.set_link(name, PREVIOUS_LINK, current)?;
+        }
+        self.set_link(name, CURRENT_LINK, version)?;
+        info!("{} switched to {}", name, version);
+
+        Ok(current)
+    }
+
+    // swaps current and previous version, returns version now current
+    pub fn rollback(&self, name: &str) -> anyhow::Result<String> {
+        check_component("name", name)?;
+        let previous = self
+            .link(name, PREVIOUS_LINK)?
+            .ok_or_else(|| anyhow!("{} has no previous version", name))?;
+        self.switch(name, &previous)?;
+
+        Ok(previous)
+    }
+
+    pub fn artifacts(&self) -> anyhow::Result<Vec<Artifact>> {
+        let mut artifacts = Vec::new();
+        for name in list(&self.dir)? {
+            if name == BLOBS_DIR || !self.dir.join(&name).join(VERSIONS_DIR).is_dir() {
+                continue;
+            }
+            artifacts.push(Artifact {
+                versions: list(&self.dir.join(&name).join(VERSIONS_DIR))?,
+                current: self.link(&name, CURRENT_LINK)?,
+                previous: self.link(&name, PREVIOUS_LINK)?,
+                name,
+            });
+        }
+
+        Ok(artifacts)
+    }
+
+    // same content is stored once however many versions contain it
+    fn store(&self, file: &Path) -> anyhow::Result<PathBuf> {
+        let digest = signature::file_digest(file)?;
+        let blob = self
+            .dir
+            .join(BLOBS_DIR)
+            .join(signature::to_hex(digest.as_ref()));
+        if !blob.exists() {
+            let partial = blob.with_file_name(format!(
+                "{}{}",
+                PARTIAL_PREFIX,
+                blob.file_name().unwrap().to_string_lossy()
+            ));
+            fs::copy(file, &partial)
+                .and_then(|_| fs::rename(&partial, &blob))
+                .with_context(|| format!("failed to store {}", file.display()))?;
+        }
+
+        Ok(blob)
+    }
+
+    fn link(&self, name: &str, link: &str) -> anyhow::Result<Option<String>> {
+        match fs::read_link(self.dir.join(name).join(link)) {
+            Ok(target) => Ok(target.file_name().map(|x| x.to_string_lossy().into_owned())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // rename replaces link atomically, readers see either old or new one
+    fn set_link(&self, name: &str, link: &str, version: &str) -> anyhow::Result<()> {
+        let path = self.dir.join(name).join(link);
+        let partial = self
+            .dir
+            .join(name)
+            .join(format!("{}{}", PARTIAL_PREFIX, link));
+        let _ = fs::remove_file(&partial);
+        symlink(Path::new(VERSIONS_DIR).join(version), &partial)
+            .and_then(|_| fs::rename(&partial, &path))
+            .with_context(|| format!("failed to update {}", path.display()))
+    }
+}
+
+// names and versions become single path components
+fn check_component(what: &str, value: &str) -> anyhow::Result<()> {
+    if value.is_empty()
+        || value.starts_with(PARTIAL_PREFIX)
+        || value.contains(['/', '\\'])
+        || value == CURRENT_LINK
+        || value == PREVIOUS_LINK
+    {
+        bail!("invalid {} \"{}\"", what, value);
+    }
+
+    Ok(())
+}
+
+// sorted, entries being written are skipped
+fn list(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut names = fs::read_dir(dir)?
+        .filter_map(|x| x.ok()?.file_name().into_string().ok())
+        .filter(|x| !x.starts_with(PARTIAL_PREFIX))
+        .collect::<Vec<_>>();
+    names.sort();
+
+    Ok(names)
+}
+
+#[cfg(unix)]
+fn symlink(target: PathBuf, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: PathBuf, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_switch_and_rollback() {
+        let dir = std::env::temp_dir().join(format!("pxe-registry-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let registry = Registry::open(&dir.join("registry")).unwrap();
+        let kernel = dir.join("linux");
+        let initrd = dir.join("initrd.gz");
+        let files = [kernel.clone(), initrd.clone()];
+
+        fs::write(&kernel, b"kernel 1").unwrap();
+        fs::write(&initrd, b"initrd").unwrap();
+        registry.publish("debian", "1", &files).unwrap();
+        fs::write(&kernel, b"kernel 2").unwrap();
+        registry.publish("debian", "2", &files).unwrap();
+        assert!(registry.publish("debian", "2", &files).is_err());
+        assert!(registry.publish("debian", "../2", &files).is_err());
+
+        // unchanged initrd is stored once
+        assert_eq!(
+            list(&dir.join("registry").join(BLOBS_DIR)).unwrap().len(),
+            3
+        );
+
+        let current = dir.join("registry/debian/current/linux");
+        assert_eq!(fs::read(&current).unwrap(), b"kernel 1");
+        assert_eq!(
+            registry.switch("debian", "2").unwrap().as_deref(),
+            Some("1")
+        );
+        assert_eq!(fs::read(&current).unwrap(), b"kernel 2");
+        assert_eq!(registry.rollback("debian").unwrap(), "1");
+        assert_eq!(fs::read(&current).unwrap(), b"kernel 1");
+        assert!(registry.switch("debian", "3").is_err());
+
+        let artifacts = registry.artifacts().unwrap();
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0].versions, ["1", "2"]);
+        assert_eq!(artifacts[0].current.as_deref(), Some("1"));
+        assert_eq!(artifacts[0].previous.as_deref(), Some("2"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+// Secure Boot chain of signed shim, GRUB and kernel. Firmware only runs
+// shim signed by Microsoft, shim then loads GRUB from the directory it was
+// loaded from and GRUB verifies kernel through shim. Binaries have to keep
+// names shim expects, one pair per architecture.
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::config::{Profile, SecureBoot};
+
+struct Chain {
+    // client system architectures (DHCP option 93) booting this chain
+    arches: &'static [u16],
+    shim: &'static str,
+    grub: &'static str,
+}
+
+const CHAINS: &[Chain] = &[
+    Chain {
+        // EFI x64 and EFI BC
+        arches: &[7, 9],
+        shim: "shimx64.efi",
+        grub: "grubx64.efi",
+    },
+    Chain {
+        arches: &[11],
+        shim: "shimaa64.efi",
+        grub: "grubaa64.efi",
+    },
+];
+
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+const SECURITY_DIRECTORY: u32 = 4;
+const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 2;
+
+impl SecureBoot {
+    // shim path relative to TFTP root, None for architectures
+    // without Secure Boot chain such as legacy BIOS
+    pub fn boot_file(&self, arch: Option<u16>) -> Option<String> {
+        let arch = arch?;
+        let chain = CHAINS.iter().find(|x| x.arches.contains(&arch))?;
+        Some(format!(
+            "{}/{}",
+            self.directory.trim_end_matches('/'),
+            chain.shim
+        ))
+    }
+}
+
+// checks layout of chain under TFTP root, returns problems found
+pub fn verify(root: &Path, profile: &Profile, secure_boot: &SecureBoot) -> Vec<String> {
+    let mut problems = Vec::new();
+    let directory = root.join(secure_boot.directory.trim_start_matches('/'));
+
+    let mut found = false;
+    for chain in CHAINS {
+        let shim = directory.join(chain.shim);
+        if !shim.exists() {
+            continue;
+        }
+        found = true;
+
+        for path in [shim, directory.join(chain.grub)].iter() {
+            if let Err(e) = check_signed(path) {
+                problems.push(format!("{}: {}", path.display(), e));
+            }
+        }
+    }
+    if !found {
+        problems.push(format!(
+            "{} contains none of {}",
+            directory.display(),
+            CHAINS.iter().map(|x| x.shim).collect::<Vec<_>>().join(", ")
+        ));
+    }
+
+    // GRUB refuses unsigned kernel once shim lock protocol is present
+    if let Some(kernel) = profile.kernel.as_deref() {
+        let path = root.join(kernel.trim_start_matches('/'));
+        if let Err(e) = check_signed(&path) {
+            problems.push(format!("{}: {}", path.display(), e));
+        }
+    }
+
+    problems
+}
+
+fn check_signed(path: &Path) -> anyhow::Result<()> {
+    let mut file = File::open(path)?;
+    match has_signature(&mut file) {
+        Ok(true) => (),
+        Ok(false) => bail!("not signed"),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => bail!("truncated PE image"),
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(())
+}
+
+// Authenticode signature is stored in certificate table pointed to by
+// security data directory, its validity is left to firmware and shim
+fn has_signature<R: Read + Seek>(image: &mut R) -> io::Result<bool> {
+    let invalid = |what| io::Error::new(io::ErrorKind::InvalidData, what);
+
+    let mut mz = [0u8; 2];
+    image.read_exact(&mut mz)?;
+    if &mz != b"MZ" {
+        return Err(invalid("not a PE image"));
+    }
+    image.seek(SeekFrom::Start(0x3c))?;
+    let pe_offset = image.read_u32::<LittleEndian>()? as u64;
+
+    let mut signature = [0u8; 4];
+    image.seek(SeekFrom::Start(pe_offset))?;
+    image.read_exact(&mut signature)?;
+    if &signature != b"PE\0\0" {
+        return Err(invalid("not a PE image"));
+    }
+
+    // optional header follows 20 byte COFF header
+    let optional_header = pe_offset + 4 + 20;
+    image.seek(SeekFrom::Start(optional_header))?;
+    let (directory_count, directories) = match image.read_u16::<LittleEndian>()? {
+        PE32_MAGIC => (optional_header + 92, optional_header + 96),
+        PE32_PLUS_MAGIC => (optional_header + 108, optional_header + 112),
+        _ => return Err(invalid("unknown optional header")),
+    };
+
+    image.seek(SeekFrom::Start(directory_count))?;
+    if image.read_u32::<LittleEndian>()? <= SECURITY_DIRECTORY {
+        return Ok(false);
+    }
+    image.seek(SeekFrom::Start(directories + SECURITY_DIRECTORY as u64 * 8))?;
+    // unlike other directories this one holds file offset, not RVA
+    let offset = image.read_u32::<LittleEndian>()? as u64;
+    let size = image.read_u32::<LittleEndian>()?;
+    if offset == 0 || size < 8 {
+        return Ok(false);
+    }
+
+    // first WIN_CERTIFICATE: length, revision, type
+    image.seek(SeekFrom::Start(offset + 6))?;
+    Ok(image.read_u16::<LittleEndian>()? == WIN_CERT_TYPE_PKCS_SIGNED_DATA)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // minimal PE32+ image with security directory pointing at certificate
+    fn image(certificate_type: Option<u16>) -> Vec<u8> {
+        let mut image = vec![0u8; 0x200];
+        image[..2].copy_from_slice(b"MZ");
+        image[0x3c..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        image[0x40..0x44].copy_from_slice(b"PE\0\0");
+        let optional_header = 0x40 + 4 + 20;
+        image[optional_header..optional_header + 2].copy_from_slice(&PE32_PLUS_MAGIC.to_le_bytes());
+        image[optional_header + 108..optional_header + 112].copy_from_slice(&16u32.to_le_bytes());
+
+        if let Some(kind) = certificate_type {
+            let directory = optional_header + 112 + 4 * 8;
+            image[directory..directory + 4].copy_from_slice(&0x180u32.to_le_bytes());
+            image[directory + 4..directory + 8].copy_from_slice(&0x10u32.to_le_bytes());
+            image[0x180..0x184].copy_from_slice(&0x10u32.to_le_bytes());
+            image[0x184..0x186].copy_from_slice(&0x200u16.to_le_bytes());
+            image[0x186..0x188].copy_from_slice(&kind.to_le_bytes());
+        }
+
+        image
+    }
+
+    #[test]
+    fn test_has_signature() {
+        assert!(has_signature(&mut Cursor::new(image(Some(2)))).unwrap());
+        assert!(!has_signature(&mut Cursor::new(image(Some(1)))).unwrap());
+        assert!(!has_signature(&mut Cursor::new(image(None))).unwrap());
+        assert!(has_signature(&mut Cursor::new(b"#!ipxe\n".to_vec())).is_err());
+    }
+}
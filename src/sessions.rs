@@ -0,0 +1,189 @@
+// Boot sessions follow a client from its first DISCOVER through loader and
+// kernel downloads, so a boot that got stuck shows where it stopped. They are
+// keyed by MAC, later TFTP and HTTP fetches are matched by offered address.
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::dhcp::id::Mac;
+
+// sessions idle for longer are dropped, next DISCOVER starts a new one
+const SESSION_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+// syslog lines kept per session, oldest are dropped first
+const MAX_LOG_LINES: usize = 1000;
+
+// filled by DHCP, TFTP and HTTP servers, shared with control socket
+pub type Sessions = Arc<Mutex<BTreeMap<Mac, Session>>>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Discovered,
+    Offered,
+    Acked,
+    LoaderFetched,
+    KernelFetched,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Discovered => "discovered",
+            Self::Offered => "offered",
+            Self::Acked => "acked",
+            Self::LoaderFetched => "loader fetched",
+            Self::KernelFetched => "kernel fetched",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub stage: Stage,
+    pub ip: Option<Ipv4Addr>,
+    pub profile: Option<String>,
+    // boot file and profile kernel, paths relative to root or URLs
+    pub loader: Option<String>,
+    pub kernel: Option<String>,
+    // most recent file fetched over TFTP or HTTP
+    pub last_file: Option<String>,
+    // syslog messages sent by kernel and installer, see syslog module
+    pub log: VecDeque<(SystemTime, String)>,
+    pub started: Instant,
+    pub updated: Instant,
+}
+
+impl Session {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            stage: Stage::Discovered,
+            ip: None,
+            profile: None,
+            loader: None,
+            kernel: None,
+            last_file: None,
+            log: VecDeque::new(),
+            started: now,
+            updated: now,
+        }
+    }
+
+    // retransmissions and loaders repeating DHCP, like chainloaded iPXE,
+    // never move session back
+    fn advance(&mut self, mac: Mac, stage: Stage) {
+        self.updated = Instant::now();
+        if stage > self.stage {
+            info!("boot session of {}: {}", mac, stage);
+            self.stage = stage;
+        }
+    }
+}
+
+pub fn discovered(sessions: &Sessions, mac: Mac) {
+    let mut sessions = sessions.lock().unwrap();
+    let now = Instant::now();
+    sessions.retain(|_, x| now.duration_since(x.updated) < SESSION_TIMEOUT);
+
+    match sessions.get_mut(&mac) {
+        // client which fetched its kernel and asks again has rebooted
+        Some(session) if session.stage != Stage::KernelFetched => session.updated = now,
+        _ => {
+            info!("boot session of {} started", mac);
+            sessions.insert(mac, Session::new());
+        }
+    }
+}
+
+pub fn offered(
+    sessions: &Sessions,
+    mac: Mac,
+    ip: Ipv4Addr,
+    profile: Option<&str>,
+    loader: Option<&str>,
+    kernel: Option<&str>,
+) {
+    let mut sessions = sessions.lock().unwrap();
+    let session = sessions.entry(mac).or_insert_with(Session::new);
+    session.ip = Some(ip);
+    session.profile = profile.map(str::to_string);
+    session.loader = loader.map(str::to_string);
+    session.kernel = kernel.map(str::to_string);
+    session.advance(mac, Stage::Offered);
+}
+
+pub fn acked(sessions: &Sessions, mac: Mac, ip: Ipv4Addr) {
+    let mut sessions = sessions.lock().unwrap();
+    let session = sessions.entry(mac).or_insert_with(Session::new);
+    session.ip = Some(ip);
+    session.advance(mac, Stage::Acked);
+}
+
+// file is path requested by client, fetches from addresses
+// without session are ignored
+pub fn fetched(sessions: &Sessions, ip: Ipv4Addr, file: &str) {
+    let mut sessions = sessions.lock().unwrap();
+    let (mac, session) = match sessions
+        .iter_mut()
+        .filter(|(_, x)| x.ip == Some(ip))
+        .max_by_key(|(_, x)| x.updated)
+    {
+        Some((&mac, session)) => (mac, session),
+        None => return,
+    };
+
+    session.last_file = Some(file.to_string());
+    let matches = |expected: Option<&str>| expected.map(|x| same_file(file, x)) == Some(true);
+    let stage = if matches(session.kernel.as_deref()) {
+        Stage::KernelFetched
+    } else if matches(session.loader.as_deref()) {
+        Stage::LoaderFetched
+    } else {
+        session.stage
+    };
+    session.advance(mac, stage);
+}
+
+// returns MAC of client given address belongs to, lines from
+// addresses without session are dropped
+pub fn logged(sessions: &Sessions, ip: Ipv4Addr, line: String) -> Option<Mac> {
+    let mut sessions = sessions.lock().unwrap();
+    let (&mac, session) = sessions
+        .iter_mut()
+        .filter(|(_, x)| x.ip == Some(ip))
+        .max_by_key(|(_, x)| x.updated)?;
+
+    if session.log.len() == MAX_LOG_LINES {
+        session.log.pop_front();
+    }
+    session.log.push_back((SystemTime::now(), line));
+    // installers may run for longer than session timeout
+    session.updated = Instant::now();
+    Some(mac)
+}
+
+// boot files may be URLs, only their paths are compared
+fn same_file(requested: &str, expected: &str) -> bool {
+    let expected = match expected.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+        None => expected,
+    };
+    requested.trim_start_matches('/') == expected.trim_start_matches('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_file() {
+        assert!(same_file("pxelinux.0", "pxelinux.0"));
+        assert!(same_file("/debian/linux", "debian/linux"));
+        assert!(same_file(
+            "/profiles/debian.ipxe",
+            "http://10.0.0.1:8080/profiles/debian.ipxe"
+        ));
+        assert!(!same_file("linux", "debian/linux"));
+    }
+}
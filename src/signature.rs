@@ -0,0 +1,260 @@
+// Detached Ed25519 signatures of boot artifacts. Signature covers SHA-256
+// digest of file, so large images are hashed in chunks instead of being read
+// into memory, and is kept next to it as hex in <file>.sig. With public key
+// given, TFTP and HTTP servers refuse files whose signature is missing or
+// does not match, so tampered root or mirror is noticed before clients boot
+// from it.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use ring::digest;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+
+pub const SIGNATURE_SUFFIX: &str = ".sig";
+
+const PUBLIC_KEY_LEN: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey([u8; PUBLIC_KEY_LEN]);
+
+impl FromStr for PublicKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = from_hex(s.trim())?;
+        if raw.len() != PUBLIC_KEY_LEN {
+            bail!("expected {} byte Ed25519 public key", PUBLIC_KEY_LEN);
+        }
+        let mut key = [0u8; PUBLIC_KEY_LEN];
+        key.copy_from_slice(&raw);
+        Ok(Self(key))
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_hex(&self.0))
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PublicKey({})", self)
+    }
+}
+
+// private key is written as PKCS#8 readable only by owner,
+// returns public key to be given to servers
+pub fn generate_key(path: &Path) -> anyhow::Result<PublicKey> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| anyhow!("failed to generate key"))?;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    io::Write::write_all(&mut options.open(path)?, pkcs8.as_ref())
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|e| anyhow!("generated key rejected: {}", e))?;
+    let mut key = [0u8; PUBLIC_KEY_LEN];
+    key.copy_from_slice(key_pair.public_key().as_ref());
+    Ok(PublicKey(key))
+}
+
+// writes <file>.sig next to every file
+pub fn sign(key: &Path, files: &[PathBuf]) -> anyhow::Result<()> {
+    let pkcs8 = fs::read(key).with_context(|| format!("failed to read {}", key.display()))?;
+    let key_pair = Ed25519KeyPair::from_pkcs8(&pkcs8)
+        .map_err(|e| anyhow!("{} is not Ed25519 PKCS#8 key: {}", key.display(), e))?;
+
+    for file in files {
+        let digest = file_digest(file)?;
+        let path = signature_path(file);
+        fs::write(
+            &path,
+            to_hex(key_pair.sign(digest.as_ref()).as_ref()) + "\n",
+        )
+        .with_context(|| format!("failed to write {}", path.display()))?;
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
+
+// device, inode, change time and size, unlike modification time change
+// time cannot be set back by whoever replaced file
+type Version = (u64, u64, i64, i64, u64);
+
+// Files are verified once and again only after they change, judging by
+// their version. File is verified through handle it is then served from,
+// so that it cannot be swapped in between. Shared by all transfers of server.
+#[derive(Debug)]
+pub struct Verifier {
+    key: PublicKey,
+    verified: Mutex<HashMap<PathBuf, Version>>,
+}
+
+impl Verifier {
+    pub fn new(key: PublicKey) -> Arc<Self> {
+        Arc::new(Self {
+            key,
+            verified: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // opens file for reading, hashing may take a while, so it is done
+    // outside of runtime threads
+    pub async fn open(self: &Arc<Self>, path: &Path) -> io::Result<File> {
+        let this = Arc::clone(self);
+        let path = path.to_path_buf();
+        match tokio::task::spawn_blocking(move || this.open_blocking(&path)).await? {
+            Ok(file) => Ok(file),
+            Err(e) => match e.downcast::<io::Error>() {
+                Ok(e) => Err(e),
+                Err(e) => Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{:#}", e),
+                )),
+            },
+        }
+    }
+
+    fn open_blocking(&self, path: &Path) -> anyhow::Result<File> {
+        let mut file = File::open(path)?;
+        // signatures themselves are served as they are
+        if path.to_string_lossy().ends_with(SIGNATURE_SUFFIX) {
+            return Ok(file);
+        }
+
+        let version = version(&file.metadata()?);
+        if version.is_some() && self.verified.lock().unwrap().get(path) == version.as_ref() {
+            return Ok(file);
+        }
+
+        let signature_path = signature_path(path);
+        let signature = fs::read_to_string(&signature_path)
+            .map_err(|e| anyhow!("cannot read {}: {}", signature_path.display(), e))
+            .and_then(|x| from_hex(x.trim()))?;
+        UnparsedPublicKey::new(&ED25519, &self.key.0)
+            .verify(digest(&mut file)?.as_ref(), &signature)
+            .map_err(|_| anyhow!("{} does not match signature", path.display()))?;
+        io::Seek::seek(&mut file, io::SeekFrom::Start(0))?;
+
+        debug!("{} matches signature", path.display());
+        if let Some(version) = version {
+            self.verified
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), version);
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(unix)]
+fn version(metadata: &fs::Metadata) -> Option<Version> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((
+        metadata.dev(),
+        metadata.ino(),
+        metadata.ctime(),
+        metadata.ctime_nsec(),
+        metadata.len(),
+    ))
+}
+
+// files are verified every time they are opened
+#[cfg(not(unix))]
+fn version(_metadata: &fs::Metadata) -> Option<Version> {
+    None
+}
+
+fn signature_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(SIGNATURE_SUFFIX);
+    PathBuf::from(path)
+}
+
+pub fn file_digest(path: &Path) -> anyhow::Result<digest::Digest> {
+    let mut file =
+        File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    Ok(digest(&mut file)?)
+}
+
+fn digest(file: &mut File) -> io::Result<digest::Digest> {
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(context.finish()),
+            n => context.update(&buf[..n]),
+        }
+    }
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+pub fn from_hex(s: &str) -> anyhow::Result<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|x| {
+            std::str::from_utf8(x)
+                .ok()
+                .filter(|x| x.len() == 2)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .ok_or_else(|| anyhow!("invalid hex string"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let dir = std::env::temp_dir().join(format!("pxe-signature-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let key_path = dir.join("key.pk8");
+        let file = dir.join("vmlinuz");
+        let _ = fs::remove_file(&key_path);
+        fs::write(&file, b"kernel").unwrap();
+
+        let key = generate_key(&key_path).unwrap();
+        assert_eq!(key.to_string().parse::<PublicKey>().unwrap(), key);
+        sign(&key_path, std::slice::from_ref(&file)).unwrap();
+
+        let verifier = Verifier::new(key);
+        let mut served = String::new();
+        verifier
+            .open_blocking(&file)
+            .unwrap()
+            .read_to_string(&mut served)
+            .unwrap();
+        assert_eq!(served, "kernel");
+        verifier.open_blocking(&file).unwrap();
+        verifier.open_blocking(&signature_path(&file)).unwrap();
+
+        // same size, verified version is not reused
+        fs::write(&file, b"kernal").unwrap();
+        assert!(verifier.open_blocking(&file).is_err());
+        fs::write(&file, b"tampered kernel").unwrap();
+        assert!(verifier.open_blocking(&file).is_err());
+        fs::remove_file(signature_path(&file)).unwrap();
+        assert!(verifier.open_blocking(&file).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+// Emulates PXE clients against running server: DISCOVER, REQUEST and TFTP
+// download of boot file they were given. Replies are told apart by
+// transaction ID, so all clients share one socket bound to client port.
+use std::collections::{BTreeMap, HashMap};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::timeout_at;
+
+use crate::dhcp::id::Mac;
+use crate::dhcp::packet::options::{
+    DhcpOption, MessageType, DHCP_CLIENT_ARCHITECTURE, DHCP_CLIENT_MACHINE_IDENTIFIER,
+    DHCP_LEASE_TIME, DHCP_MESSAGE_TYPE, DHCP_REQUESTED_IP, DHCP_SERVER_ID,
+    DHCP_VENDOR_CLASS_IDENTIFIER,
+};
+use crate::dhcp::packet::{BootpMessageType, Packet};
+use crate::dhcp::{CLIENT_PORT, SERVER_PORT};
+use crate::tftp::{self, client};
+
+const RETRIES: u32 = 3;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub server_ip: Ipv4Addr,
+    pub count: usize,
+    pub first_mac: Mac,
+    pub arch: u16,
+    pub vendor_class: Option<String>,
+    pub fetch: bool,
+}
+
+// replies waiting for their client, by transaction ID
+type Waiting = Arc<Mutex<HashMap<u32, mpsc::UnboundedSender<Packet>>>>;
+
+struct Client {
+    mac: Mac,
+    xid: u32,
+    socket: Arc<UdpSocket>,
+    replies: mpsc::UnboundedReceiver<Packet>,
+    settings: Arc<Settings>,
+}
+
+#[derive(Debug)]
+struct Outcome {
+    ip: Ipv4Addr,
+    boot_file: Option<String>,
+    lease_time: Duration,
+    download: Option<client::Download>,
+}
+
+pub async fn run(settings: Settings) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, CLIENT_PORT)).await?;
+    socket.set_broadcast(true)?;
+    let socket = Arc::new(socket);
+    let waiting = Waiting::default();
+    tokio::spawn(dispatch(Arc::clone(&socket), Arc::clone(&waiting)));
+
+    let settings = Arc::new(settings);
+    let mut clients = (0..settings.count)
+        .map(|i| {
+            let (sender, replies) = mpsc::unbounded_channel();
+            let xid = rand::random();
+            waiting.lock().unwrap().insert(xid, sender);
+            let client = Client {
+                mac: nth_mac(settings.first_mac, i as u32),
+                xid,
+                socket: Arc::clone(&socket),
+                replies,
+                settings: Arc::clone(&settings),
+            };
+            let mac = client.mac;
+            async move { (mac, client.boot().await) }
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut failed = 0;
+    while let Some((mac, result)) = clients.next().await {
+        match result {
+            Ok(outcome) => {
+                let download = match outcome.download {
+                    Some(x) => format!(
+                        ", fetched {} bytes in {} ms",
+                        x.bytes,
+                        x.elapsed.as_millis()
+                    ),
+                    None => String::new(),
+                };
+                println!(
+                    "{}: {} for {}, boot file {}{}",
+                    mac,
+                    outcome.ip,
+                    humantime::format_duration(outcome.lease_time),
+                    outcome.boot_file.as_deref().unwrap_or("none"),
+                    download
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{}: failed: {:#}", mac, e);
+            }
+        }
+    }
+
+    println!(
+        "{} of {} client(s) booted",
+        settings.count - failed,
+        settings.count
+    );
+    if failed > 0 {
+        bail!("{} client(s) failed", failed);
+    }
+
+    Ok(())
+}
+
+async fn dispatch(socket: Arc<UdpSocket>, waiting: Waiting) {
+    let mut buf = [0u8; 1500];
+    loop {
+        let n = match socket.recv(&mut buf).await {
+            Ok(n) => n,
+            Err(e) => {
+                error!("receive failed: {}", e);
+                return;
+            }
+        };
+        match Packet::parse(&buf[..n]) {
+            Ok(packet) if packet.bootp_message_type == BootpMessageType::Reply => {
+                if let Some(sender) = waiting.lock().unwrap().get(&packet.xid) {
+                    let _ = sender.send(packet);
+                }
+            }
+            Ok(_) => (),
+            Err(e) => debug!("ignoring invalid packet: {}", e),
+        }
+    }
+}
+
+// locally administered addresses are used unless told otherwise,
+// i-th client gets i added to lower three octets
+fn nth_mac(first: Mac, i: u32) -> Mac {
+    let mut raw = *first.get_raw();
+    let nic = u32::from_be_bytes([0, raw[3], raw[4], raw[5]]).wrapping_add(i);
+    raw[3..6].copy_from_slice(&nic.to_be_bytes()[1..]);
+    Mac::from(raw)
+}
+
+impl Client {
+    async fn boot(mut self) -> anyhow::Result<Outcome> {
+        let offer = self
+            .exchange(MessageType::Discover, MessageType::Offer, BTreeMap::new())
+            .await?;
+        let server_id = match offer.options.get(&DHCP_SERVER_ID) {
+            Some(DhcpOption::Ipv4Addr(ip)) => *ip,
+            _ => bail!("offer without server identifier"),
+        };
+
+        let mut options = BTreeMap::new();
+        options.insert(DHCP_REQUESTED_IP, DhcpOption::Ipv4Addr(offer.yiaddr));
+        options.insert(DHCP_SERVER_ID, DhcpOption::Ipv4Addr(server_id));
+        let ack = self
+            .exchange(MessageType::Request, MessageType::Ack, options)
+            .await?;
+        let lease_time = match ack.options.get(&DHCP_LEASE_TIME) {
+            Some(DhcpOption::ByteArray(v)) if v.len() == 4 => {
+                Duration::from_secs(u32::from_be_bytes([v[0], v[1], v[2], v[3]]) as u64)
+            }
+            _ => Duration::from_secs(0),
+        };
+
+        // PXE firmware fetches from next server, falling back to DHCP server
+        let download = match ack.boot_file_name.as_deref() {
+            Some(file) if self.settings.fetch => {
+                let tftp_server = if ack.siaddr.is_unspecified() {
+                    server_id
+                } else {
+                    ack.siaddr
+                };
+                let download = client::download(
+                    SocketAddr::from((tftp_server, tftp::SERVER_PORT)),
+                    file,
+                    None,
+                    None,
+                    REPLY_TIMEOUT,
+                )
+                .await
+                .map_err(|e| anyhow!("failed to fetch {}: {:#}", file, e))?;
+                Some(download)
+            }
+            _ => None,
+        };
+
+        Ok(Outcome {
+            ip: ack.yiaddr,
+            boot_file: ack.boot_file_name,
+            lease_time,
+            download,
+        })
+    }
+
+    // sends request until reply of expected type arrives, NAK ends exchange
+    async fn exchange(
+        &mut self,
+        kind: MessageType,
+        expected: MessageType,
+        extra: BTreeMap<u8, DhcpOption>,
+    ) -> anyhow::Result<Packet> {
+        let data = self.request(kind, extra).encode();
+        let start = Instant::now();
+
+        for _ in 0..RETRIES {
+            self.socket
+                .send_to(&data, (self.settings.server_ip, SERVER_PORT))
+                .await?;
+
+            let deadline = tokio::time::Instant::now() + REPLY_TIMEOUT;
+            while let Ok(Some(reply)) = timeout_at(deadline, self.replies.recv()).await {
+                match reply.options.get(&DHCP_MESSAGE_TYPE) {
+                    Some(DhcpOption::MessageType(x)) if *x == expected => {
+                        debug!("{} {} after {:?}", self.mac, expected, start.elapsed());
+                        return Ok(reply);
+                    }
+                    Some(DhcpOption::MessageType(MessageType::Nak)) => {
+                        bail!("{} refused with NAK", kind)
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        bail!("no {} after {} {}s", expected, RETRIES, kind)
+    }
+
+    fn request(&self, kind: MessageType, extra: BTreeMap<u8, DhcpOption>) -> Packet {
+        let mut options = extra;
+        options.insert(DHCP_MESSAGE_TYPE, DhcpOption::MessageType(kind));
+        options.insert(
+            DHCP_CLIENT_ARCHITECTURE,
+            DhcpOption::U16(self.settings.arch),
+        );
+        let vendor_class = match self.settings.vendor_class.as_deref() {
+            Some(x) => x.to_string(),
+            None => format!("PXEClient:Arch:{:05}:UNDI:003016", self.settings.arch),
+        };
+        options.insert(
+            DHCP_VENDOR_CLASS_IDENTIFIER,
+            DhcpOption::String(vendor_class),
+        );
+        // UUID derived from MAC, so repeated runs look like the same machines
+        let mut uuid = vec![0u8; 17];
+        uuid[11..].copy_from_slice(&self.mac.get_raw()[..6]);
+        options.insert(DHCP_CLIENT_MACHINE_IDENTIFIER, DhcpOption::ByteArray(uuid));
+
+        Packet {
+            bootp_message_type: BootpMessageType::Request,
+            htype: 1,
+            hlen: 6,
+            mac: self.mac,
+            hops: 0,
+            xid: self.xid,
+            secs: 0,
+            flags: 0,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            server_name: None,
+            boot_file_name: None,
+            options,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nth_mac() {
+        let first: Mac = "02:00:00:00:00:ff".parse().unwrap();
+        assert_eq!(nth_mac(first, 0), first);
+        assert_eq!(nth_mac(first, 1).to_string(), "02:00:00:00:01:00");
+        assert_eq!(
+            nth_mac("02:00:00:ff:ff:ff".parse().unwrap(), 1).to_string(),
+            "02:00:00:00:00:00"
+        );
+    }
+}
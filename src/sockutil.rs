@@ -0,0 +1,149 @@
+use std::io;
+use std::net::SocketAddr;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+// Binds given number of sockets to the same address.
+// More than one socket requires SO_REUSEPORT, kernel then spreads
+// incoming packets and connections among them.
+
+pub fn bind_udp(addr: SocketAddr, count: usize) -> io::Result<Vec<tokio::net::UdpSocket>> {
+    (0..count)
+        .map(|_| {
+            let socket = new_socket(addr, Type::DGRAM, Protocol::UDP, count > 1)?;
+            tokio::net::UdpSocket::from_std(socket.into())
+        })
+        .collect()
+}
+
+#[cfg(feature = "http")]
+pub fn bind_tcp(addr: SocketAddr, count: usize) -> io::Result<Vec<std::net::TcpListener>> {
+    (0..count)
+        .map(|_| {
+            let socket = new_socket(addr, Type::STREAM, Protocol::TCP, count > 1)?;
+            socket.listen(1024)?;
+            Ok(socket.into())
+        })
+        .collect()
+}
+
+// Socket bound to network interface gets only what arrives through it and
+// sends through it regardless of routing. Sockets bound to different
+// interfaces share address, so that each network gets its own server.
+#[cfg(target_os = "linux")]
+pub fn bind_udp_to_device(addr: SocketAddr, interface: &str) -> io::Result<tokio::net::UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.bind_device(Some(interface.as_bytes()))?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    tokio::net::UdpSocket::from_std(socket.into())
+}
+
+// Sends datagrams through connected socket, with sendmmsg as many as
+// kernel takes go out in single system call. Number of calls is returned.
+#[cfg(all(feature = "sendmmsg", target_os = "linux"))]
+pub async fn send_all(socket: &tokio::net::UdpSocket, datagrams: &[&[u8]]) -> io::Result<usize> {
+    use nix::sys::socket::{sendmmsg, ControlMessage, MsgFlags, SendMmsgData};
+    use nix::sys::uio::IoVec;
+    use std::os::unix::io::AsRawFd;
+    use tokio::io::Interest;
+
+    let mut calls = 0;
+    let mut sent = 0;
+    while sent < datagrams.len() {
+        // messages point into datagrams and cannot be held across await
+        sent += socket
+            .async_io(Interest::WRITABLE, || {
+                let messages: Vec<SendMmsgData<_, [ControlMessage; 0]>> = datagrams[sent..]
+                    .iter()
+                    .map(|x| SendMmsgData {
+                        iov: [IoVec::from_slice(x)],
+                        cmsgs: [],
+                        addr: None,
+                        _lt: Default::default(),
+                    })
+                    .collect();
+                Ok(sendmmsg(socket.as_raw_fd(), &messages, MsgFlags::empty())?.len())
+            })
+            .await?;
+        calls += 1;
+    }
+
+    Ok(calls)
+}
+
+#[cfg(not(all(feature = "sendmmsg", target_os = "linux")))]
+pub async fn send_all(socket: &tokio::net::UdpSocket, datagrams: &[&[u8]]) -> io::Result<usize> {
+    for data in datagrams {
+        socket.send(data).await?;
+    }
+
+    Ok(datagrams.len())
+}
+
+fn new_socket(
+    addr: SocketAddr,
+    ty: Type,
+    protocol: Protocol,
+    reuse_port: bool,
+) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+    if ty == Type::STREAM {
+        // same as std, lets server restart while old connections linger in TIME_WAIT
+        socket.set_reuse_address(true)?;
+    }
+    if reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+
+    Ok(socket)
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+// Windows has no option spreading load among sockets bound to the same port
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "multiple workers are not supported on this platform",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_several() {
+        let addr = std::net::UdpSocket::bind("127.0.0.1:0")
+            .and_then(|x| x.local_addr())
+            .unwrap();
+        let sockets = bind_udp(addr, 4).unwrap();
+        assert_eq!(sockets.len(), 4);
+        for socket in sockets.iter() {
+            assert_eq!(socket.local_addr().unwrap(), addr);
+        }
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_bind_several_tcp() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|x| x.local_addr())
+            .unwrap();
+        let listeners = bind_tcp(addr, 3).unwrap();
+        assert_eq!(listeners.len(), 3);
+        for listener in listeners.iter() {
+            assert_eq!(listener.local_addr().unwrap(), addr);
+        }
+        // without SO_REUSEPORT the address is taken
+        assert!(bind_tcp(addr, 1).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// counters shared by all servers of single instance
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub dhcp_offers: AtomicU64,
+    pub dhcp_acks: AtomicU64,
+    pub dhcp_naks: AtomicU64,
+    pub dhcp_declines: AtomicU64,
+    // current number of bound leases and size of address pool
+    pub dhcp_leases: AtomicU64,
+    pub dhcp_pool_size: AtomicU64,
+    pub tftp: TransferStats,
+    // DATA datagrams and system calls sending them, batching shows as
+    // fewer calls than datagrams
+    pub tftp_datagrams: AtomicU64,
+    pub tftp_send_calls: AtomicU64,
+    pub http: TransferStats,
+}
+
+#[derive(Debug, Default)]
+pub struct TransferStats {
+    pub started: AtomicU64,
+    pub completed: AtomicU64,
+    pub failed: AtomicU64,
+    pub bytes: AtomicU64,
+}
+
+#[inline]
+pub fn incr(counter: &AtomicU64) {
+    add(counter, 1);
+}
+
+#[inline]
+pub fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn get(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let leases = get(&self.dhcp_leases);
+        let pool_size = get(&self.dhcp_pool_size);
+
+        write!(
+            f,
+            "DHCP {} offers, {} acks, {} NAKs, {} declines, pool {}/{}",
+            get(&self.dhcp_offers),
+            get(&self.dhcp_acks),
+            get(&self.dhcp_naks),
+            get(&self.dhcp_declines),
+            leases,
+            pool_size
+        )?;
+        if let Some(percent) = (leases * 100).checked_div(pool_size) {
+            write!(f, " ({}%)", percent)?;
+        }
+
+        write!(
+            f,
+            "; TFTP {}, {} datagrams in {} sends; HTTP {}",
+            self.tftp,
+            get(&self.tftp_datagrams),
+            get(&self.tftp_send_calls),
+            self.http
+        )
+    }
+}
+
+impl fmt::Display for TransferStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} started, {} completed, {} failed, {} B served",
+            get(&self.started),
+            get(&self.completed),
+            get(&self.failed),
+            get(&self.bytes)
+        )
+    }
+}
+
+pub async fn log_periodically(name: String, stats: Arc<Stats>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    // first tick completes immediately
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        info!("stats {}: {}", name, stats);
+    }
+}
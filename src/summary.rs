@@ -0,0 +1,254 @@
+use crate::dhcp::allocation::Allocation;
+use crate::Options;
+
+// logs effective configuration of single instance at startup
+pub fn log(options: &Options) {
+    let name = options.instance_name.as_deref().unwrap_or("default");
+    match options.interface.as_deref() {
+        Some(interface) => info!(
+            "instance {}: server IP {} on {}",
+            name,
+            options.server_ip(),
+            interface
+        ),
+        None => info!("instance {}: server IP {}", name, options.server_ip()),
+    }
+    if let Some(mtu) = options.interface_mtu {
+        info!("  interface MTU {}", mtu);
+    }
+
+    match (
+        options.no_dhcp,
+        options.dhcp_ip_start,
+        options.dhcp_ip_end,
+        options.dhcp_subnet,
+    ) {
+        (false, Some(start), Some(end), Some(subnet)) => info!(
+            "  DHCP: pool {} - {} in {}, lease time {}{}",
+            start,
+            end,
+            subnet,
+            options.dhcp_lease_time,
+            options
+                .mtu
+                .map_or(String::new(), |mtu| format!(", MTU {}", mtu))
+        ),
+        (false, _, _, _) if options.proxy_dhcp => info!("  DHCP: proxy, boot files only"),
+        _ => info!("  DHCP: disabled"),
+    }
+    for pool in options.config.pools.iter().filter(|_| !options.no_dhcp) {
+        info!(
+            "  DHCP: relayed pool {} - {} in {}{}{}{}",
+            pool.range.start(),
+            pool.range.end(),
+            pool.range.subnet(),
+            pool.router
+                .map_or(String::new(), |x| format!(", router {}", x)),
+            pool.profile
+                .as_deref()
+                .map_or(String::new(), |x| format!(", profile {}", x)),
+            pool.root_path
+                .as_deref()
+                .map_or(String::new(), |x| format!(", root path {}", x))
+        );
+    }
+    if !options.no_dhcp && options.dhcp_bind_any {
+        info!("  DHCP bound to 0.0.0.0, other interfaces filtered out");
+    }
+    if !options.no_dhcp && options.pxe_only {
+        info!("  DHCP served only to PXE clients");
+    }
+    if !options.no_dhcp && !options.proxy_dhcp && options.dhcp_allocation == Allocation::Hash {
+        info!("  DHCP addresses picked by client identifier hash");
+    }
+    if !options.no_dhcp && options.rapid_commit {
+        info!("  DHCP rapid commit enabled");
+    }
+    if let Some(path) = options.mac_allow.as_deref().filter(|_| !options.no_dhcp) {
+        info!("  DHCP served only to MACs listed in {}", path.display());
+    }
+    if let Some(path) = options.mac_deny.as_deref().filter(|_| !options.no_dhcp) {
+        info!("  DHCP never served to MACs listed in {}", path.display());
+    }
+    if !options.no_dhcp && options.ping_check {
+        info!(
+            "  addresses probed before offer, answer awaited for {}",
+            options.ping_timeout
+        );
+    }
+    #[cfg(target_os = "linux")]
+    if !options.no_dhcp && options.arp_check {
+        info!(
+            "  addresses checked over ARP after ACK, answer awaited for {}",
+            options.ping_timeout
+        );
+    }
+    if !options.no_dhcp && !options.dhcp_dns.is_empty() {
+        info!(
+            "  DNS servers for DHCP clients: {}",
+            options
+                .dhcp_dns
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if !options.no_dhcp && !options.dhcp_ntp.is_empty() {
+        info!(
+            "  NTP servers for DHCP clients: {}",
+            options
+                .dhcp_ntp
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if !options.no_dhcp && (options.dhcp_domain.is_some() || !options.dhcp_search.is_empty()) {
+        info!(
+            "  domain for DHCP clients: {}{}",
+            options.dhcp_domain.as_deref().unwrap_or("<none>"),
+            if options.dhcp_search.is_empty() {
+                String::new()
+            } else {
+                format!(", search {}", options.dhcp_search.join(" "))
+            }
+        );
+    }
+
+    if options.no_tftp {
+        info!("  TFTP: disabled");
+    } else {
+        info!(
+            "  TFTP: root {}, timeout {}, {} retries, max block size {}, max window size {}, {} worker(s)",
+            options
+                .tftp_root
+                .as_deref()
+                .map_or("<none>".into(), |x| x.display().to_string()),
+            options.tftp_timeout,
+            options.tftp_retries,
+            options.tftp_max_block_size,
+            options.tftp_max_window_size,
+            options.workers
+        );
+    }
+
+    if options.dns {
+        info!(
+            "  DNS: {} static record(s), forwarding to {}",
+            options.config.dns_records.len(),
+            options
+                .dns_forward
+                .map_or("<none>".into(), |x| x.to_string())
+        );
+    }
+
+    if !options.config.nbd_exports.is_empty() {
+        info!("  NBD: port {}, read-only", options.nbd_port);
+        for export in options.config.nbd_exports.iter() {
+            info!("    export {}: {}", export.name, export.path.display());
+        }
+    }
+
+    #[cfg(feature = "http")]
+    match options.tftp_root.as_deref() {
+        Some(_) => info!("  HTTP: port {}", options.http_port),
+        None => info!("  HTTP: disabled, no root directory"),
+    }
+
+    info!(
+        "  default loader: {}",
+        options
+            .loader
+            .as_deref()
+            .map_or("<none>".into(), |x| x.display().to_string())
+    );
+    for loader in options.arch_loader.iter() {
+        info!("  loader by architecture: {}", loader);
+    }
+    if let Some(file) = options.ipxe_boot_file.as_deref() {
+        info!("  boot file for iPXE: {}", file);
+    }
+    if let Some(ip) = options.next_server {
+        info!(
+            "  boot files from TFTP server {}{}",
+            ip,
+            options
+                .tftp_server_name
+                .as_deref()
+                .map_or(String::new(), |x| format!(" ({})", x))
+        );
+    } else if let Some(name) = options.tftp_server_name.as_deref() {
+        info!("  TFTP server announced as {}", name);
+    }
+
+    for profile in options.config.profiles.iter() {
+        info!(
+            "  profile {}: boot file {}{}",
+            profile.name,
+            profile.boot_file,
+            profile
+                .next_server
+                .map_or(String::new(), |x| format!(" from {}", x))
+        );
+        if let Some(secure_boot) = profile.secure_boot.as_ref() {
+            info!("    Secure Boot chain from {}", secure_boot.directory);
+        }
+        if let Some(root_path) = profile.root_path(options.server_ip(), options.nbd_port) {
+            info!("    root path {}", root_path);
+        }
+    }
+
+    for selector in options.config.selectors.iter() {
+        let mut criteria = Vec::new();
+        if let Some(mac) = selector.mac.as_ref() {
+            criteria.push(format!("MAC {}", mac));
+        }
+        if let Some(uuid) = selector.uuid.as_deref() {
+            criteria.push(format!("UUID {}", uuid));
+        }
+        if let Some(arch) = selector.arch {
+            criteria.push(format!("arch {}", arch));
+        }
+        if let Some(vendor_class) = selector.vendor_class.as_deref() {
+            criteria.push(format!("vendor class {}*", vendor_class));
+        }
+        if let Some(circuit_id) = selector.circuit_id.as_deref() {
+            criteria.push(format!("circuit-id {}", circuit_id));
+        }
+        if let Some(remote_id) = selector.remote_id.as_deref() {
+            criteria.push(format!("remote-id {}", remote_id));
+        }
+        if criteria.is_empty() {
+            criteria.push("any client".to_string());
+        }
+
+        info!("  {} -> {}", criteria.join(", "), selector.profile);
+    }
+
+    for policy in options.config.vendor_classes.iter() {
+        info!(
+            "  vendor class {}* -> {}{}",
+            policy.prefix,
+            policy.boot_file.as_deref().unwrap_or("loader"),
+            policy
+                .lease_time
+                .map(|x| format!(", lease time {}", x))
+                .unwrap_or_default()
+        );
+    }
+
+    for reservation in options.config.reservations.iter() {
+        info!(
+            "  {} reserved for {}{}",
+            reservation.ip,
+            reservation.client(),
+            reservation
+                .boot_file
+                .as_deref()
+                .map(|x| format!(", boot file {}", x))
+                .unwrap_or_default()
+        );
+    }
+}
@@ -0,0 +1,98 @@
+// Collects syslog of booting clients. Kernels, initramfs and installers can
+// usually be pointed at remote syslog on their command line, e.g.
+// log_host={{server_ip}} for debian-installer. Messages are attached to boot
+// session of client they came from, and with --syslog-dir also appended to
+// <mac>.log in it, so they outlive the session.
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UdpSocket;
+
+use crate::dhcp::id::Mac;
+use crate::sessions::{self, Sessions};
+
+pub const SYSLOG_PORT: u16 = 514;
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+pub async fn start(options: &super::Options, sessions: &Sessions) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind((options.server_ip(), SYSLOG_PORT)).await?;
+
+    debug!("server starting");
+
+    let mut buf = [0u8; 8192];
+    loop {
+        let (n, client) = socket.recv_from(&mut buf).await?;
+        let ip = match client.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => continue,
+        };
+        let line = parse(&buf[..n]);
+
+        match sessions::logged(sessions, ip, line.clone()) {
+            Some(mac) => {
+                if let Some(directory) = options.syslog_dir.as_deref() {
+                    if let Err(e) = append(directory, mac, &line).await {
+                        error!("{:#}", e);
+                    }
+                }
+            }
+            None => debug!("dropping message from {} without boot session", ip),
+        }
+    }
+}
+
+// priority is replaced by severity name, rest of message is kept as sent
+// since clients rarely agree on RFC 3164 or RFC 5424 format
+fn parse(message: &[u8]) -> String {
+    let message = String::from_utf8_lossy(message);
+    let message = message.trim_end_matches(['\n', '\r', '\0']);
+
+    let priority = message
+        .strip_prefix('<')
+        .and_then(|x| x.split_once('>'))
+        .and_then(|(priority, rest)| Some((priority.parse::<u8>().ok()?, rest)));
+    match priority {
+        Some((priority, rest)) => format!("{}: {}", SEVERITIES[(priority & 7) as usize], rest),
+        None => message.to_string(),
+    }
+}
+
+async fn append(directory: &Path, mac: Mac, line: &str) -> anyhow::Result<()> {
+    let path = directory.join(format!("{}.log", mac.to_string().replace(':', "-")));
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let time = humantime::format_rfc3339_seconds(SystemTime::now());
+    file.write_all(format!("{} {}\n", time, line).as_bytes())
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(b"<14>Oct 16 10:00:00 main-menu[312]: INFO: Menu item 'netcfg' selected\n"),
+            "info: Oct 16 10:00:00 main-menu[312]: INFO: Menu item 'netcfg' selected"
+        );
+        assert_eq!(
+            parse(b"<3>1 2026-10-16T10:00:00Z node1 kernel - - - oops"),
+            "err: 1 2026-10-16T10:00:00Z node1 kernel - - - oops"
+        );
+        assert_eq!(parse(b"no priority\0"), "no priority");
+        assert_eq!(parse(b"<x>bad"), "<x>bad");
+    }
+}
@@ -0,0 +1,131 @@
+// OpenTelemetry export over OTLP/gRPC. Tracing spans (DHCP exchanges, TFTP
+// and HTTP transfers, NBD connections) become traces and instance counters
+// are pushed as metrics, so the server shows up in existing observability
+// stacks without anything scraping it.
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use opentelemetry::metrics::ObserverResult;
+use opentelemetry::sdk::metrics::PushController;
+use opentelemetry::sdk::trace::{self, Tracer};
+use opentelemetry::sdk::Resource;
+use opentelemetry::{global, KeyValue};
+use tokio_stream::wrappers::IntervalStream;
+
+use crate::stats::{self, Stats, TransferStats};
+
+const SERVICE_NAME: &str = "pxeserver";
+const METRICS_PERIOD: Duration = Duration::from_secs(10);
+
+// counters of single instance labelled with its name
+type Instances = Arc<Vec<(String, Arc<Stats>)>>;
+// reads single counter
+type Read<T> = fn(&T) -> u64;
+
+pub fn tracer(endpoint: &str) -> anyhow::Result<Tracer> {
+    opentelemetry_otlp::new_pipeline()
+        .with_endpoint(endpoint)
+        .with_trace_config(trace::config().with_resource(Resource::new(resource())))
+        .with_tonic()
+        .install_batch(opentelemetry::runtime::Tokio)
+        .context("failed to set up OTLP trace exporter")
+}
+
+// pushes metrics until returned controller is dropped
+pub fn export_metrics(
+    endpoint: &str,
+    instances: Vec<(String, Arc<Stats>)>,
+) -> anyhow::Result<PushController> {
+    let controller = opentelemetry_otlp::new_metrics_pipeline(tokio::spawn, |period| {
+        IntervalStream::new(tokio::time::interval_at(
+            (Instant::now() + period).into(),
+            period,
+        ))
+    })
+    .with_export_config(opentelemetry_otlp::ExporterConfig {
+        endpoint: endpoint.to_string(),
+        ..Default::default()
+    })
+    .with_resource(resource())
+    .with_period(METRICS_PERIOD)
+    .build()
+    .context("failed to set up OTLP metrics exporter")?;
+
+    let meter = global::meter(SERVICE_NAME);
+    let instances: Instances = Arc::new(instances);
+
+    let counters: [(&str, Read<Stats>); 6] = [
+        ("pxe.dhcp.offers", |x| stats::get(&x.dhcp_offers)),
+        ("pxe.dhcp.acks", |x| stats::get(&x.dhcp_acks)),
+        ("pxe.dhcp.naks", |x| stats::get(&x.dhcp_naks)),
+        ("pxe.dhcp.declines", |x| stats::get(&x.dhcp_declines)),
+        ("pxe.tftp.datagrams", |x| stats::get(&x.tftp_datagrams)),
+        ("pxe.tftp.send_calls", |x| stats::get(&x.tftp_send_calls)),
+    ];
+    for (name, read) in counters.iter().copied() {
+        let instances = Arc::clone(&instances);
+        meter
+            .u64_sum_observer(name, move |result| observe(&result, &instances, read))
+            .init();
+    }
+
+    let gauges: [(&str, Read<Stats>); 2] = [
+        ("pxe.dhcp.leases", |x| stats::get(&x.dhcp_leases)),
+        ("pxe.dhcp.pool_size", |x| stats::get(&x.dhcp_pool_size)),
+    ];
+    for (name, read) in gauges.iter().copied() {
+        let instances = Arc::clone(&instances);
+        meter
+            .u64_value_observer(name, move |result| observe(&result, &instances, read))
+            .init();
+    }
+
+    // same set for both protocols, told apart by label
+    let transfers: [(&str, Read<TransferStats>); 4] = [
+        ("pxe.transfers.started", |x| stats::get(&x.started)),
+        ("pxe.transfers.completed", |x| stats::get(&x.completed)),
+        ("pxe.transfers.failed", |x| stats::get(&x.failed)),
+        ("pxe.transfers.bytes", |x| stats::get(&x.bytes)),
+    ];
+    for (name, read) in transfers.iter().copied() {
+        let instances = Arc::clone(&instances);
+        meter
+            .u64_sum_observer(name, move |result| {
+                for (instance, stats) in instances.iter() {
+                    for (protocol, transfers) in
+                        [("tftp", &stats.tftp), ("http", &stats.http)].iter()
+                    {
+                        result.observe(
+                            read(transfers),
+                            &[
+                                KeyValue::new("instance", instance.clone()),
+                                KeyValue::new("protocol", *protocol),
+                            ],
+                        );
+                    }
+                }
+            })
+            .init();
+    }
+
+    Ok(controller)
+}
+
+fn observe(result: &ObserverResult<u64>, instances: &Instances, read: Read<Stats>) {
+    for (instance, stats) in instances.iter() {
+        result.observe(read(stats), &[KeyValue::new("instance", instance.clone())]);
+    }
+}
+
+fn resource() -> Vec<KeyValue> {
+    vec![
+        KeyValue::new("service.name", SERVICE_NAME),
+        KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+    ]
+}
+
+// spans still buffered by batch exporter are sent before exit
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
@@ -0,0 +1,184 @@
+// Minimal read-only TFTP client used to exercise server the way PXE
+// firmware does. Besides blksize it can ask for windowsize (RFC 7440),
+// falling back to lockstep when server does not acknowledge it.
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+use super::packet::Packet;
+use super::{TFTP_DEFAULT_BLOCK_SIZE, TFTP_MAX_BLOCK_SIZE};
+
+const RETRIES: u32 = 5;
+
+#[derive(Debug)]
+pub struct Download {
+    pub bytes: u64,
+    pub blocks: u64,
+    // as acknowledged by server
+    pub block_size: u32,
+    pub window_size: u16,
+    // from request to first data block
+    pub first_block: Duration,
+    pub elapsed: Duration,
+    // server went silent and last packet had to be sent again
+    pub timeouts: u64,
+    // blocks arriving out of order, which makes server resend the window
+    pub out_of_order: u64,
+}
+
+pub async fn download(
+    server: SocketAddr,
+    file: &str,
+    block_size: Option<u32>,
+    window_size: Option<u16>,
+    retry_timeout: Duration,
+) -> anyhow::Result<Download> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+
+    let mut request = Vec::new();
+    request.extend_from_slice(&1u16.to_be_bytes());
+    request.extend_from_slice(file.as_bytes());
+    request.extend_from_slice(b"\0octet\0");
+    if let Some(size) = block_size {
+        request.extend_from_slice(format!("blksize\0{}\0", size).as_bytes());
+    }
+    if let Some(size) = window_size {
+        request.extend_from_slice(format!("windowsize\0{}\0", size).as_bytes());
+    }
+
+    let start = Instant::now();
+    let mut download = Download {
+        bytes: 0,
+        blocks: 0,
+        block_size: TFTP_DEFAULT_BLOCK_SIZE,
+        window_size: 1,
+        first_block: Duration::default(),
+        elapsed: Duration::default(),
+        timeouts: 0,
+        out_of_order: 0,
+    };
+    let mut buf = vec![0u8; TFTP_MAX_BLOCK_SIZE as usize + 4];
+    // last packet sent, repeated when server does not answer
+    let mut last = request;
+    let mut destination = server;
+    // transfer ID of server is learned from its first reply
+    let mut peer = None;
+    let mut expected: u16 = 1;
+    let mut in_window = 0;
+    let mut retries = RETRIES;
+
+    socket.send_to(&last, destination).await?;
+    loop {
+        let (n, from) = match timeout(retry_timeout, socket.recv_from(&mut buf)).await {
+            Ok(result) => result?,
+            Err(_) if retries > 0 => {
+                retries -= 1;
+                download.timeouts += 1;
+                in_window = 0;
+                socket.send_to(&last, destination).await?;
+                continue;
+            }
+            Err(_) => bail!("timed out"),
+        };
+        if matches!(peer, Some(x) if x != from) || from.ip() != server.ip() {
+            continue;
+        }
+        peer = Some(from);
+        destination = from;
+        retries = RETRIES;
+
+        let packet = &buf[..n];
+        let opcode = match packet.get(..2) {
+            Some(x) => u16::from_be_bytes([x[0], x[1]]),
+            None => continue,
+        };
+        match opcode {
+            // OACK, server may reply with smaller sizes than requested
+            6 => {
+                for (name, value) in parse_oack(&packet[2..]) {
+                    match name.as_str() {
+                        "blksize" => download.block_size = value.parse()?,
+                        "windowsize" => download.window_size = value.parse()?,
+                        _ => (),
+                    }
+                }
+                last = Packet::ack(0).encode();
+                socket.send_to(&last, destination).await?;
+            }
+            3 if n >= 4 => {
+                let block = u16::from_be_bytes([packet[2], packet[3]]);
+                if block != expected {
+                    // last block received in order is acknowledged, so
+                    // server starts over from the one that went missing
+                    download.out_of_order += 1;
+                    in_window = 0;
+                    socket.send_to(&last, destination).await?;
+                    continue;
+                }
+
+                if download.blocks == 0 {
+                    download.first_block = start.elapsed();
+                }
+                download.blocks += 1;
+                download.bytes += (n - 4) as u64;
+                expected = expected.wrapping_add(1);
+                in_window += 1;
+                last = Packet::ack(block).encode();
+
+                let done = n - 4 < download.block_size as usize;
+                if done || in_window == download.window_size {
+                    in_window = 0;
+                    socket.send_to(&last, destination).await?;
+                }
+                if done {
+                    download.elapsed = start.elapsed();
+                    return Ok(download);
+                }
+            }
+            5 => match Packet::decode(packet) {
+                Ok(Packet::Error { tag, message: None }) => bail!("server error {:?}", tag),
+                Ok(Packet::Error {
+                    tag,
+                    message: Some(message),
+                }) => bail!("server error {:?}: {}", tag, message),
+                _ => bail!("invalid error packet"),
+            },
+            opcode => bail!("unexpected packet with opcode {}", opcode),
+        }
+    }
+}
+
+// option names are lowercased, values are kept as sent
+fn parse_oack(data: &[u8]) -> Vec<(String, String)> {
+    let mut fields = data
+        .split(|&x| x == 0)
+        .map(|x| String::from_utf8_lossy(x).to_string());
+    let mut options = Vec::new();
+    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+        if name.is_empty() {
+            break;
+        }
+        options.push((name.to_lowercase(), value));
+    }
+
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_oack() {
+        assert_eq!(
+            parse_oack(b"BLKSIZE\x001428\x00windowsize\x004\x00"),
+            vec![
+                ("blksize".to_string(), "1428".to_string()),
+                ("windowsize".to_string(), "4".to_string())
+            ]
+        );
+        assert!(parse_oack(b"\x00").is_empty());
+    }
+}
@@ -1,34 +1,81 @@
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fs::canonicalize;
 use std::io;
 use std::mem::MaybeUninit;
 use std::net::{Ipv4Addr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use anyhow::Context as _;
+use futures_util::future;
 use futures_util::task::{Context, Poll};
 use tokio::fs::{File, OpenOptions};
-use tokio::io::{AsyncReadExt, ReadBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
 use tokio::net::UdpSocket;
 use tokio_stream::{Stream, StreamExt};
-
+use tracing::Instrument;
+
+use crate::bootcfg::Generator;
+use crate::capture;
+use crate::config::Hooks;
+use crate::hooks;
+use crate::inventory::Inventory;
+use crate::iputil::{self, Ipv4AddrAndMask};
+use crate::sessions::{self, Sessions};
+use crate::signature::Verifier;
+use crate::sockutil;
+use crate::stats::{self, Stats};
 use error::{Error, Result};
 use packet::{Packet, TftpError, TftpOption};
 
+pub mod client;
 mod error;
 mod packet;
 // shared with HTTP server
 // TODO: move to common
 pub mod pathutils;
 
+pub const SERVER_PORT: u16 = 69;
 const MAX_PACKET_SIZE: usize = 1024;
 const TFTP_DEFAULT_BLOCK_SIZE: u32 = 512;
+// limits from RFC 2348
+pub const TFTP_MIN_BLOCK_SIZE: u64 = 8;
+pub const TFTP_MAX_BLOCK_SIZE: u64 = 65464;
+// IP, UDP and TFTP DATA headers preceding block payload
+const DATA_OVERHEAD: u32 = 20 + 8 + 4;
+
+// file from root or generated one held in memory
+type Source = Box<dyn AsyncRead + Send + Sync + Unpin>;
+
+// active transfers by transfer ID, shared with control socket
+pub type Transfers = Arc<Mutex<BTreeMap<u16, TransferInfo>>>;
+
+#[derive(Debug, Clone)]
+pub struct TransferInfo {
+    pub client: SocketAddr,
+    pub file: String,
+    pub size: Option<u64>,
+    pub sent: u64,
+    pub started: Instant,
+}
 
-pub async fn start(options: &super::Options) {
-    let socket = UdpSocket::bind((options.server_ip, 69)).await.unwrap();
-    socket.set_broadcast(true).unwrap();
+pub async fn start(
+    options: &super::Options,
+    transfers: &Transfers,
+    stats: &Arc<Stats>,
+    sessions: &Sessions,
+    inventory: &Inventory,
+) -> Result<()> {
+    let sockets = sockutil::bind_udp(
+        SocketAddr::from((options.server_ip(), SERVER_PORT)),
+        options.workers,
+    )?;
+    for socket in sockets.iter() {
+        socket.set_broadcast(true)?;
+    }
 
     debug!("server starting");
 
@@ -38,22 +85,58 @@ pub async fn start(options: &super::Options) {
         debug!("root: {}", root.display());
     }
 
-    let loader = canonicalize(options.loader.as_path()).unwrap();
-    let loader_relative = loader_path_to_relative(loader.as_path(), root.as_deref());
+    let loader = match options.loader.as_deref() {
+        Some(loader) => Some(canonicalize(loader)?),
+        None => None,
+    };
+    let loader_relative = loader
+        .as_deref()
+        .map(|loader| loader_path_to_relative(loader, root.as_deref()));
 
-    debug!("loader: {} ({})", loader.display(), loader_relative);
+    if let (Some(loader), Some(loader_relative)) = (loader.as_deref(), loader_relative.as_deref()) {
+        debug!("loader: {} ({})", loader.display(), loader_relative);
+    }
 
-    Server {
-        server_ip: options.server_ip,
+    // configured size is validated at startup, blocks larger than interface
+    // MTU would be fragmented which many PXE ROMs fail to reassemble
+    let mut max_block_size = options.tftp_max_block_size.get() as u32;
+    if let Some(mtu) = options.interface_mtu {
+        let limit = mtu
+            .saturating_sub(DATA_OVERHEAD)
+            .max(TFTP_MIN_BLOCK_SIZE as u32);
+        if limit < max_block_size {
+            debug!("max block size limited to {} by MTU {}", limit, mtu);
+            max_block_size = limit;
+        }
+    }
+
+    let server = Arc::new(Server {
+        server_ip: options.server_ip(),
         root,
         loader,
         loader_relative,
-        // TODO: allow setting these from command line
-        retries: 5,
-        timeout: Duration::from_secs(3),
+        retries: options.tftp_retries,
+        timeout: options.tftp_timeout.get(),
+        max_block_size,
+        max_window_size: options.tftp_max_window_size,
+        generator: Generator::new(options, inventory),
+        hooks: options.config.hooks.clone(),
+        client_subnets: options.client_subnet.clone(),
+        verifier: options.verify_key.map(Verifier::new),
+        transfers: Arc::clone(transfers),
+        stats: Arc::clone(stats),
+        sessions: Arc::clone(sessions),
+    });
+    // task per socket, so that workers run on separate threads
+    let workers = sockets.into_iter().map(|socket| {
+        let server = Arc::clone(&server);
+        tokio::spawn(async move { server.main(socket).await })
+    });
+    for worker in future::join_all(workers).await {
+        worker.map_err(io::Error::from)?;
     }
-    .main(socket)
-    .await
+
+    Ok(())
 }
 
 pub fn loader_path_to_relative(loader: &Path, root: Option<&Path>) -> String {
@@ -69,19 +152,40 @@ pub fn loader_path_to_relative(loader: &Path, root: Option<&Path>) -> String {
     }
 }
 
+// RFC 2348 allows replying with smaller size, invalid one is left out of
+// OACK so that transfer falls back to default
+fn negotiated_block_size(options: &HashMap<String, TftpOption>, max: u32) -> Option<u32> {
+    let TftpOption::U32(size) = options.get("blksize")?;
+    if u64::from(*size) < TFTP_MIN_BLOCK_SIZE {
+        warn!("ignoring invalid block size {}", size);
+        return None;
+    }
+    Some(cmp::min(*size, max))
+}
+
 struct Server {
     server_ip: Ipv4Addr,
     root: Option<PathBuf>,
-    loader: PathBuf,
+    loader: Option<PathBuf>,
     // path relative to root in URL format
     #[allow(dead_code)]
-    loader_relative: String,
+    loader_relative: Option<String>,
     retries: u32,
     timeout: Duration,
+    max_block_size: u32,
+    max_window_size: u16,
+    generator: Generator,
+    hooks: Hooks,
+    client_subnets: Vec<Ipv4AddrAndMask>,
+    // set when files have to match their signatures
+    verifier: Option<Arc<Verifier>>,
+    transfers: Transfers,
+    stats: Arc<Stats>,
+    sessions: Sessions,
 }
 
 impl Server {
-    async fn main(self, socket: UdpSocket) {
+    async fn main(&self, socket: UdpSocket) {
         let mut stream = PacketStream { socket: &socket };
 
         while let Some(r) = stream.next().await {
@@ -93,7 +197,9 @@ impl Server {
                             file,
                             options,
                         } => {
+                            let span = info_span!("tftp", client = %client_addr, file = %file);
                             self.handle_rw_request(client_addr, write, file, options)
+                                .instrument(span)
                                 .await;
                         }
                         // TODO: warn about ignored packets
@@ -118,7 +224,10 @@ impl Server {
     ) {
         match self.establish_connection(client_addr).await {
             Ok((tid, socket)) => {
-                if write {
+                if !iputil::allowed(&self.client_subnets, client_addr.ip()) {
+                    warn!("refused client outside of allowed subnets");
+                    Self::reply(&socket, &Packet::error(TftpError::AccessDenied, None)).await;
+                } else if write {
                     // we don't support write
                     Self::reply(
                         &socket,
@@ -129,30 +238,41 @@ impl Server {
                     )
                     .await;
                 } else {
-                    match self.open_file(file_name.as_str(), write).await {
-                        Ok(file) => {
+                    let file_name = match client_addr {
+                        SocketAddr::V4(client) => {
+                            hooks::tftp_path(&self.hooks, *client.ip(), file_name).await
+                        }
+                        SocketAddr::V6(_) => file_name,
+                    };
+                    match self.open_source(file_name.as_str()).await {
+                        Ok((file, file_len)) => {
                             info!("commencing {} transfer (ID {})", file_name, tid);
 
                             let can_negotiate = !options.is_empty();
                             let (block_size, can_negotiate_block_size) =
-                                if let Some(opt) = options.get("blksize") {
-                                    let TftpOption::U32(size) = opt;
-                                    (*size, true)
-                                } else {
-                                    (TFTP_DEFAULT_BLOCK_SIZE, false)
+                                match negotiated_block_size(&options, self.max_block_size) {
+                                    Some(size) => (size, true),
+                                    None => (TFTP_DEFAULT_BLOCK_SIZE, false),
                                 };
 
                             let send_tsize = options.get("tsize").is_some();
+                            // like block size, smaller window may be replied
+                            let window_size = options.get("windowsize").map(|opt| {
+                                let TftpOption::U32(size) = opt;
+                                cmp::min(*size, self.max_window_size as u32) as u16
+                            });
 
                             self.handle_read_request(
                                 tid,
                                 socket,
                                 file_name,
                                 file,
+                                file_len,
                                 block_size,
                                 can_negotiate,
                                 can_negotiate_block_size,
                                 send_tsize,
+                                window_size,
                             )
                             .await;
                         }
@@ -167,6 +287,26 @@ impl Server {
         }
     }
 
+    // files in root take precedence over generated ones
+    async fn open_source(&self, file_name: &str) -> Result<(Source, Option<u64>)> {
+        match self.open_file(file_name, false).await {
+            Ok(file) => {
+                let len = file.metadata().await.ok().map(|x| x.len());
+                #[cfg(all(feature = "uring", target_os = "linux"))]
+                let file = crate::uring::reader(file).await;
+                Ok((Box::new(file), len))
+            }
+            Err(e) => match self.generator.generate(file_name) {
+                Some(data) => {
+                    debug!("serving generated {}", file_name);
+                    let len = data.len() as u64;
+                    Ok((Box::new(io::Cursor::new(data.into_bytes())), Some(len)))
+                }
+                None => Err(e),
+            },
+        }
+    }
+
     async fn open_file(&self, file: &str, write: bool) -> Result<File> {
         // FIXME: workaround to prevent value from dropping
         let mut _t = None;
@@ -178,16 +318,21 @@ impl Server {
             );
             _t.as_deref().unwrap()
         } else {
-            if file == "PAYLOAD.BIN" {
-                self.loader.as_path()
-            } else {
-                return Err(Error::from(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "file not found",
-                )));
+            match self.loader.as_deref() {
+                Some(loader) if file == "PAYLOAD.BIN" => loader,
+                _ => {
+                    return Err(Error::from(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "file not found",
+                    )))
+                }
             }
         };
 
+        if let (Some(verifier), false) = (self.verifier.as_ref(), write) {
+            return Ok(File::from_std(verifier.open(path).await?));
+        }
+
         Ok(OpenOptions::new()
             .read(!write)
             .write(write)
@@ -200,14 +345,14 @@ impl Server {
         tid: u16,
         socket: UdpSocket,
         file_name: String,
-        file: File,
+        file: Source,
+        file_len: Option<u64>,
         block_size: u32,
         can_negotiate: bool,
         can_negotiate_block_size: bool,
         send_tsize: bool,
+        window_size: Option<u16>,
     ) {
-        let file_len = file.metadata().await.ok().map(|x| x.len());
-
         if can_negotiate {
             self.negotiate(
                 &socket,
@@ -217,6 +362,7 @@ impl Server {
                     None
                 },
                 if send_tsize { file_len } else { None },
+                window_size,
             )
             .await;
         }
@@ -226,14 +372,25 @@ impl Server {
             timeout: self.timeout,
             file_name,
             file,
+            file_len,
             socket,
             block_size: block_size as usize,
+            window_size: window_size.unwrap_or(1) as usize,
             tid,
+            transfers: Arc::clone(&self.transfers),
+            stats: Arc::clone(&self.stats),
+            sessions: Arc::clone(&self.sessions),
         }
         .spawn();
     }
 
-    async fn negotiate(&self, socket: &UdpSocket, block_size: Option<u32>, tsize: Option<u64>) {
+    async fn negotiate(
+        &self,
+        socket: &UdpSocket,
+        block_size: Option<u32>,
+        tsize: Option<u64>,
+        window_size: Option<u16>,
+    ) {
         let mut encoded: Vec<u8> = Vec::new();
         encoded.extend_from_slice(&6u16.to_be_bytes()[..]); // opcode
         if let Some(block_size) = block_size {
@@ -246,16 +403,24 @@ impl Server {
             encoded.extend_from_slice(tsize.to_string().as_bytes());
             encoded.push(0);
         }
+        if let Some(window_size) = window_size {
+            encoded.extend_from_slice(b"windowsize\x00");
+            encoded.extend_from_slice(window_size.to_string().as_bytes());
+            encoded.push(0);
+        }
 
         if encoded.len() == 2 {
             encoded.push(0);
         }
 
+        capture::udp_sent(socket, &encoded);
         socket.send(encoded.as_slice()).await.unwrap();
     }
 
     async fn reply(s: &UdpSocket, packet: &Packet) {
-        if let Err(e) = s.send(packet.encode().as_slice()).await {
+        let data = packet.encode();
+        capture::udp_sent(s, &data);
+        if let Err(e) = s.send(data.as_slice()).await {
             error!("send failed: {}", e)
         }
     }
@@ -286,10 +451,17 @@ impl<'a> Stream for PacketStream<'a> {
 
         match self.socket.poll_recv_from(cx, &mut rb) {
             Poll::Pending => Poll::Pending,
-            Poll::Ready(Ok(saddr)) => match Packet::decode(rb.filled()) {
-                Ok(packet) => Poll::Ready(Some(Ok((saddr, packet)))),
-                Err(e) => Poll::Ready(Some(Err(e))),
-            },
+            Poll::Ready(Ok(saddr)) => {
+                if capture::enabled() {
+                    if let Ok(local) = self.socket.local_addr() {
+                        capture::udp(saddr, local, rb.filled());
+                    }
+                }
+                match Packet::decode(rb.filled()) {
+                    Ok(packet) => Poll::Ready(Some(Ok((saddr, packet)))),
+                    Err(e) => Poll::Ready(Some(Err(e))),
+                }
+            }
             Poll::Ready(Err(e)) => Poll::Ready(Some(Err(Error::from(e)))),
         }
     }
@@ -299,117 +471,185 @@ struct TransferHandler {
     retries: u32,
     timeout: Duration,
     file_name: String,
-    file: File,
+    file: Source,
+    file_len: Option<u64>,
     socket: UdpSocket,
     block_size: usize,
+    // blocks sent before waiting for ACK (RFC 7440)
+    window_size: usize,
     tid: u16,
+    transfers: Transfers,
+    stats: Arc<Stats>,
+    sessions: Sessions,
 }
 
 impl TransferHandler {
     fn spawn(mut self) {
-        tokio::spawn(async move {
-            let start = Instant::now();
-            if let Err(e) = self.transfer_file().await {
-                error!(
-                    "transfer ID {} of {} failed: {}",
-                    self.tid, self.file_name, e
-                );
-            } else {
-                info!(
-                    "transfer ID {} of {} done after {} s",
-                    self.tid,
-                    self.file_name,
-                    Instant::now().duration_since(start).as_secs()
-                );
+        let span = info_span!("transfer", tid = self.tid);
+        tokio::spawn(
+            async move {
+                let start = Instant::now();
+                stats::incr(&self.stats.tftp.started);
+                if let Ok(client) = self.socket.peer_addr() {
+                    self.transfers.lock().unwrap().insert(
+                        self.tid,
+                        TransferInfo {
+                            client,
+                            file: self.file_name.clone(),
+                            size: self.file_len,
+                            sent: 0,
+                            started: start,
+                        },
+                    );
+                }
+
+                let result = self.transfer_file().await;
+                self.transfers.lock().unwrap().remove(&self.tid);
+
+                if let Err(e) = result {
+                    stats::incr(&self.stats.tftp.failed);
+                    error!(
+                        "transfer ID {} of {} failed: {}",
+                        self.tid, self.file_name, e
+                    );
+                } else {
+                    stats::incr(&self.stats.tftp.completed);
+                    info!(
+                        "transfer ID {} of {} done after {} s",
+                        self.tid,
+                        self.file_name,
+                        Instant::now().duration_since(start).as_secs()
+                    );
+                    if let Ok(SocketAddr::V4(client)) = self.socket.peer_addr() {
+                        sessions::fetched(&self.sessions, *client.ip(), &self.file_name);
+                    }
+                }
             }
-        });
+            .instrument(span),
+        );
     }
 
     async fn transfer_file(&mut self) -> anyhow::Result<()> {
-        //let mut header: [u8; 4] = [0, 6, 0, 0];
-
-        // buffer for outgoing data packet, size = data block size + header (opcode + block number)
-        let mut buffer_out: Vec<u8> = Vec::new();
-        buffer_out.resize(self.block_size + 4, 0);
-
-        // buffer for incoming packet, used by send_data
-        // declared here to prevent reinitializing array on every call to send_data
-        //
-        // seems like there is no simple way to read from socket into uninitialized array
-        // MaybeUninit::uninit() is not an option as recv() takes reference to u8 not MaybeUninit<u8>
-        // converting MaybeUninit<T> into T prior to initialization is instant UB.
-        // see
-        // https://doc.rust-lang.org/std/mem/union.MaybeUninit.html
-        // https://www.ralfj.de/blog/2019/07/14/uninit.html
+        // DATA packets sent but not acknowledged yet, oldest first, and
+        // number of them that went out since window last moved
+        let mut window: VecDeque<Vec<u8>> = VecDeque::with_capacity(self.window_size);
+        let mut sent = 0;
+        // block number of window front and of next block read from file
+        let mut first_block: u16 = 1;
+        let mut next_block: u16 = 1;
+        let mut read_all = false;
+
+        // buffer for incoming packet, ACK is 4 bytes and errors are short
         let mut buffer_in: [u8; 32] = [0; 32];
-
-        let mut current_block: u16 = 1;
+        let mut left_retries = self.retries;
 
         loop {
-            buffer_out[..2].copy_from_slice(&3u16.to_be_bytes()[..]);
-            buffer_out[2..4].copy_from_slice(&current_block.to_be_bytes()[..]);
-
-            let n = self
-                .file
-                .read(&mut buffer_out[4..])
-                .await
-                .context("failed to read from file")?;
-
-            self.send_data(current_block, &buffer_out[..n + 4], &mut buffer_in[..])
-                .await?;
-
-            if n != self.block_size {
-                // last block sent
+            while !read_all && window.len() < self.window_size {
+                // buffer for outgoing data packet, size = data block size + header (opcode + block number)
+                let mut data = vec![0u8; self.block_size + 4];
+                data[..2].copy_from_slice(&3u16.to_be_bytes()[..]);
+                data[2..4].copy_from_slice(&next_block.to_be_bytes()[..]);
+
+                let n = self
+                    .file
+                    .read(&mut data[4..])
+                    .await
+                    .context("failed to read from file")?;
+                data.truncate(n + 4);
+                // last block is shorter than block size, possibly empty
+                read_all = n != self.block_size;
+                window.push_back(data);
+                next_block = next_block.wrapping_add(1);
+            }
+            if window.is_empty() {
                 break;
             }
 
-            current_block += 1;
-        }
-
-        Ok(())
-    }
+            if sent < window.len() {
+                self.send_window(window.range(sent..)).await?;
+                sent = window.len();
+            }
 
-    async fn send_data(
-        &self,
-        current_block: u16,
-        data: &[u8],
-        in_buffer: &mut [u8],
-    ) -> anyhow::Result<()> {
-        let mut left_retries = self.retries;
+            // client acknowledges last block it got in order, blocks after
+            // it are sent again
+            match tokio::time::timeout(self.timeout, self.socket.recv(&mut buffer_in)).await {
+                Ok(result) => {
+                    let total_read = result?;
+                    capture::udp_received(&self.socket, &buffer_in[..total_read]);
+                    let acked = match Self::acked_block(&buffer_in[..total_read]) {
+                        Some(block) => block.wrapping_sub(first_block) as usize + 1,
+                        None => continue,
+                    };
+                    // duplicate ACK of block before window
+                    if acked > window.len() {
+                        continue;
+                    }
 
-        let mut interval = tokio::time::interval(self.timeout);
-        while left_retries > 0 {
-            tokio::select! {
-                _ = interval.tick() => {
-                    self.socket.send(data).await
-                        .context("failed to write to socket")?;
+                    let n: usize = window.drain(..acked).map(|x| x.len() - 4).sum();
+                    first_block = first_block.wrapping_add(acked as u16);
+                    sent = 0;
+                    left_retries = self.retries;
 
-                    left_retries -= 1;
+                    stats::add(&self.stats.tftp.bytes, n as u64);
+                    if let Some(transfer) = self.transfers.lock().unwrap().get_mut(&self.tid) {
+                        transfer.sent += n as u64;
+                    }
                 }
-                result = self.socket.recv(in_buffer) => {
-                    let total_read = result?;
-                    if Self::is_ack_packet(&in_buffer[..total_read], current_block) {
-                        return Ok(());
+                Err(_) => {
+                    left_retries = left_retries.saturating_sub(1);
+                    if left_retries == 0 {
+                        bail!("timed out");
                     }
+                    sent = 0;
                 }
-            };
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send_window(&self, packets: impl Iterator<Item = &Vec<u8>>) -> anyhow::Result<()> {
+        let packets: Vec<&[u8]> = packets.map(|x| x.as_slice()).collect();
+        for data in packets.iter() {
+            capture::udp_sent(&self.socket, data);
         }
+        let calls = sockutil::send_all(&self.socket, &packets)
+            .await
+            .context("failed to write to socket")?;
 
-        bail!("timed out");
+        stats::add(&self.stats.tftp_datagrams, packets.len() as u64);
+        stats::add(&self.stats.tftp_send_calls, calls as u64);
+        Ok(())
     }
 
-    fn is_ack_packet(buffer: &[u8], current_block: u16) -> bool {
+    fn acked_block(buffer: &[u8]) -> Option<u16> {
         match Packet::decode(buffer) {
-            Ok(packet) => {
-                if let Packet::Ack { block } = packet {
-                    if block == current_block {
-                        return true;
-                    }
-                }
+            Ok(Packet::Ack { block }) => Some(block),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("packet decode error during file transfer: {}", e);
+                None
             }
-            Err(e) => warn!("packet decode error during file transfer: {}", e),
         }
+    }
+}
 
-        false
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiated_block_size() {
+        let blksize = |size| {
+            let mut options = HashMap::new();
+            options.insert("blksize".to_string(), TftpOption::U32(size));
+            negotiated_block_size(&options, 1432)
+        };
+        assert_eq!(negotiated_block_size(&HashMap::new(), 1432), None);
+        assert_eq!(blksize(0), None);
+        assert_eq!(blksize(7), None);
+        assert_eq!(blksize(8), Some(8));
+        assert_eq!(blksize(1024), Some(1024));
+        assert_eq!(blksize(65464), Some(1432));
     }
 }
@@ -233,5 +233,16 @@ mod tests {
                         && matches!(options.get("blksize").unwrap(), TftpOption::U32(1432))
             )
         );
+
+        assert!(
+            matches!(Packet::decode(b"\x00\x01pxelinux.0\x00octet\x00windowsize\x0016\x00").unwrap(),
+                Packet::RwRequest { options, .. }
+                    if matches!(options.get("windowsize").unwrap(), TftpOption::U32(16))
+            )
+        );
+        assert!(matches!(
+            Packet::decode(b"\x00\x01pxelinux.0\x00octet\x00windowsize\x000\x00"),
+            Err(Error::InvalidOptionValue { .. })
+        ));
     }
 }
@@ -64,6 +64,14 @@ impl TftpOption {
                 })?;
                 Ok(Some(Self::U32(v)))
             }
+            // RFC 7440 allows 1 to 65535 blocks
+            "windowsize" => match u16::from_str_radix(option_value, 10) {
+                Ok(v) if v > 0 => Ok(Some(Self::U32(v as u32))),
+                _ => Err(Error::InvalidOptionValue {
+                    option: option_name.to_string(),
+                    value: option_value.to_string(),
+                }),
+            },
             _ => {
                 trace!(
                     "unhandled option \"{}\" value \"{}\"",
@@ -0,0 +1,251 @@
+// Live view of running server for terminals reached over SSH. Snapshot is
+// polled over control socket and whole screen redrawn with plain ANSI
+// escapes, no terminal library is needed. Lines not fitting terminal are
+// cut off, sections keep their order so counters stay on top.
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::control::{self, Snapshot};
+
+const ALTERNATE_SCREEN: &str = "\x1b[?1049h\x1b[?25l";
+const MAIN_SCREEN: &str = "\x1b[?25h\x1b[?1049l";
+const CLEAR: &str = "\x1b[H\x1b[2J";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+const PROGRESS_WIDTH: usize = 20;
+// sessions listed as recent events
+const MAX_EVENTS: usize = 10;
+
+// runs until interrupted
+pub async fn run(socket: &Path, interval: Duration) -> anyhow::Result<()> {
+    // fails early instead of on alternate screen
+    control::request(socket, "snapshot").await?;
+
+    print!("{}", ALTERNATE_SCREEN);
+    let result = tokio::select! {
+        result = refresh(socket, interval) => result,
+        result = tokio::signal::ctrl_c() => result.map_err(anyhow::Error::from),
+    };
+    print!("{}", MAIN_SCREEN);
+
+    result
+}
+
+async fn refresh(socket: &Path, interval: Duration) -> anyhow::Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+    let mut previous: Option<Snapshot> = None;
+    loop {
+        ticker.tick().await;
+        let snapshot: Snapshot =
+            serde_json::from_str(&control::request(socket, "snapshot").await?)?;
+        let (width, height) = terminal_size();
+        let screen = render(&snapshot, previous.as_ref(), interval);
+        let mut out = String::from(CLEAR);
+        for line in screen.lines().take(height.saturating_sub(1)) {
+            out += &truncate(line, width);
+            out += "\r\n";
+        }
+        print!("{}", out);
+        std::io::Write::flush(&mut std::io::stdout())?;
+        previous = Some(snapshot);
+    }
+}
+
+fn render(snapshot: &Snapshot, previous: Option<&Snapshot>, interval: Duration) -> String {
+    let mut out = String::new();
+    for (i, instance) in snapshot.instances.iter().enumerate() {
+        let before = previous
+            .and_then(|x| x.instances.get(i))
+            .map(|x| &x.counters);
+        let counters = &instance.counters;
+        let active = instance.leases.iter().filter(|x| x.expires_in.is_some());
+
+        writeln!(
+            out,
+            "{}{}{}",
+            BOLD,
+            instance.name.as_deref().unwrap_or("pxeserver"),
+            RESET
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "DHCP  {} leases of {}, {} offers, {} acks, {}, {}",
+            active.count(),
+            counters.dhcp_pool_size,
+            counters.dhcp_offers,
+            counters.dhcp_acks,
+            errors(counters.dhcp_naks, "NAKs"),
+            errors(counters.dhcp_declines, "declines")
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "TFTP  {} completed, {}, {}/s",
+            counters.tftp_completed,
+            errors(counters.tftp_failed, "failed"),
+            rate(counters.tftp_bytes, before.map(|x| x.tftp_bytes), interval)
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "HTTP  {} completed, {}, {}/s",
+            counters.http_completed,
+            errors(counters.http_failed, "failed"),
+            rate(counters.http_bytes, before.map(|x| x.http_bytes), interval)
+        )
+        .unwrap();
+
+        writeln!(out, "\n{}Transfers{}", BOLD, RESET).unwrap();
+        for transfer in instance.transfers.iter() {
+            writeln!(
+                out,
+                "{} {:>10} {:<21} {}",
+                progress(transfer.sent, transfer.size),
+                bytes(transfer.sent),
+                transfer.client,
+                transfer.file
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\n{}Recent events{}", BOLD, RESET).unwrap();
+        for session in instance.sessions.iter().take(MAX_EVENTS) {
+            writeln!(
+                out,
+                "{:>5} s ago  {} {:<15} {:<14} {}",
+                session.idle_secs,
+                session.mac,
+                session.ip.map(|x| x.to_string()).unwrap_or_default(),
+                session.stage,
+                session
+                    .last_log
+                    .as_deref()
+                    .or(session.last_file.as_deref())
+                    .unwrap_or_default()
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\n{}Leases{}", BOLD, RESET).unwrap();
+        for lease in instance.leases.iter() {
+            let state = match lease.expires_in {
+                Some(x) => format!("expires in {} s", x),
+                None => "offered".to_string(),
+            };
+            writeln!(
+                out,
+                "{:<15} {:<24} {:<16} {}",
+                lease.ip,
+                lease.client,
+                lease.hostname.as_deref().unwrap_or("-"),
+                state
+            )
+            .unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+
+    out
+}
+
+fn errors(count: u64, what: &str) -> String {
+    match count {
+        0 => format!("0 {}", what),
+        n => format!("{}{} {}{}", RED, n, what, RESET),
+    }
+}
+
+fn rate(bytes_now: u64, before: Option<u64>, interval: Duration) -> String {
+    let delta = bytes_now.saturating_sub(before.unwrap_or(bytes_now));
+    bytes((delta as f64 / interval.as_secs_f64().max(0.001)) as u64)
+}
+
+fn bytes(n: u64) -> String {
+    match n {
+        n if n >= 1 << 30 => format!("{:.1} GiB", n as f64 / (1u64 << 30) as f64),
+        n if n >= 1 << 20 => format!("{:.1} MiB", n as f64 / (1u64 << 20) as f64),
+        n if n >= 1 << 10 => format!("{:.1} KiB", n as f64 / (1u64 << 10) as f64),
+        n => format!("{} B", n),
+    }
+}
+
+// transfers of unknown size get empty bar
+fn progress(sent: u64, size: Option<u64>) -> String {
+    let (filled, percent) = match size {
+        Some(0) => (PROGRESS_WIDTH, "100%".to_string()),
+        Some(size) => {
+            let ratio = (sent as f64 / size as f64).min(1.0);
+            (
+                (ratio * PROGRESS_WIDTH as f64) as usize,
+                format!("{:>3}%", (ratio * 100.0) as u32),
+            )
+        }
+        None => (0, "  ?%".to_string()),
+    };
+    format!(
+        "[{}{}] {}",
+        "#".repeat(filled),
+        ".".repeat(PROGRESS_WIDTH - filled),
+        percent
+    )
+}
+
+// escape sequences take no columns
+fn truncate(line: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut columns = 0;
+    let mut escape = false;
+    for c in line.chars() {
+        if c == '\x1b' {
+            escape = true;
+        }
+        if !escape {
+            if columns == width {
+                continue;
+            }
+            columns += 1;
+        }
+        if escape && c.is_ascii_alphabetic() {
+            escape = false;
+        }
+        out.push(c);
+    }
+
+    out
+}
+
+#[cfg(unix)]
+fn terminal_size() -> (usize, usize) {
+    use nix::libc;
+
+    nix::ioctl_read_bad!(window_size, libc::TIOCGWINSZ, libc::winsize);
+
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    match unsafe { window_size(libc::STDOUT_FILENO, &mut size) } {
+        Ok(_) if size.ws_col > 0 && size.ws_row > 0 => (size.ws_col as usize, size.ws_row as usize),
+        _ => (80, 24),
+    }
+}
+
+#[cfg(not(unix))]
+fn terminal_size() -> (usize, usize) {
+    (80, 24)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        assert_eq!(progress(50, Some(100)), "[##########..........]  50%");
+        assert_eq!(progress(0, None), "[....................]   ?%");
+        assert_eq!(
+            truncate(&format!("{}abcdef{}", RED, RESET), 3),
+            format!("{}abc{}", RED, RESET)
+        );
+    }
+}
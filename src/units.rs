@@ -0,0 +1,111 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+// human friendly values accepted on command line and in configuration file
+// e.g. 250ms, 2h, 1h 30m for durations and 1432, 64KiB, 10MB for sizes
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HumanDuration(Duration);
+
+impl HumanDuration {
+    #[inline]
+    pub fn get(&self) -> Duration {
+        self.0
+    }
+}
+
+impl FromStr for HumanDuration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        humantime::parse_duration(s)
+            .map(Self)
+            .map_err(|e| anyhow!("invalid duration \"{}\": {}", s, e))
+    }
+}
+
+impl TryFrom<String> for HumanDuration {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        humantime::format_duration(self.0).fmt(f)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    #[inline]
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_size::parse_size(s)
+            .map(Self)
+            .map_err(|e| anyhow!("invalid size \"{}\": {}", s, e))
+    }
+}
+
+impl TryFrom<String> for ByteSize {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} B", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ByteSize, HumanDuration};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(
+            "250ms".parse::<HumanDuration>().unwrap().get(),
+            Duration::from_millis(250)
+        );
+        assert_eq!(
+            "2h".parse::<HumanDuration>().unwrap().get(),
+            Duration::from_secs(7200)
+        );
+        assert_eq!(
+            "1h 30m".parse::<HumanDuration>().unwrap().get(),
+            Duration::from_secs(5400)
+        );
+        assert!("3".parse::<HumanDuration>().is_err());
+        assert!("soon".parse::<HumanDuration>().is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!("1432".parse::<ByteSize>().unwrap().get(), 1432);
+        assert_eq!("64KiB".parse::<ByteSize>().unwrap().get(), 65536);
+        assert_eq!("10MB".parse::<ByteSize>().unwrap().get(), 10_000_000);
+        assert!("lots".parse::<ByteSize>().is_err());
+    }
+}
@@ -0,0 +1,187 @@
+// File reads of TFTP and HTTP transfers through io_uring. Reads are queued
+// to ring owned by dedicated thread, which submits everything queued in
+// single call and hands filled buffers back as completions arrive, so
+// reading block costs no blocking pool task. When ring cannot be set up,
+// e.g. on kernels older than 5.1, files are read through tokio as before.
+use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use futures_util::task::{Context, Poll};
+use io_uring::{opcode, types, IoUring};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::oneshot;
+
+const ENTRIES: u32 = 256;
+// largest single read, HTTP asks for more than any TFTP block
+const MAX_READ: usize = 64 * 1024;
+
+lazy_static::lazy_static! {
+    static ref RING: Mutex<Option<mpsc::Sender<Read>>> = Mutex::new(None);
+}
+
+pub type Reader = Box<dyn AsyncRead + Send + Sync + Unpin>;
+
+type Completion = (Vec<u8>, io::Result<usize>);
+
+struct Read {
+    // kept open until read completes
+    file: Arc<File>,
+    offset: u64,
+    buf: Vec<u8>,
+    done: oneshot::Sender<Completion>,
+}
+
+pub fn start() -> io::Result<()> {
+    let mut guard = RING.lock().unwrap();
+    if guard.is_some() {
+        return Ok(());
+    }
+
+    let ring = IoUring::new(ENTRIES)?;
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name("io-uring".to_string())
+        .spawn(move || run(ring, receiver))?;
+    *guard = Some(sender);
+    Ok(())
+}
+
+// file read through ring if it was started
+pub async fn reader(file: tokio::fs::File) -> Reader {
+    let started = RING.lock().unwrap().is_some();
+    match started {
+        true => Box::new(UringFile {
+            file: Arc::new(file.into_std().await),
+            offset: 0,
+            pending: None,
+        }),
+        false => Box::new(file),
+    }
+}
+
+fn run(mut ring: IoUring, receiver: mpsc::Receiver<Read>) {
+    let mut in_flight: HashMap<u64, Read> = HashMap::new();
+    let mut next_id: u64 = 0;
+
+    loop {
+        // blocks for new reads only while ring is idle, otherwise reads
+        // queued meanwhile go with next submission
+        let first = match in_flight.is_empty() {
+            true => match receiver.recv() {
+                Ok(read) => Some(read),
+                Err(_) => return,
+            },
+            false => None,
+        };
+        let free = ENTRIES as usize - in_flight.len();
+        for mut read in first.into_iter().chain(receiver.try_iter()).take(free) {
+            let entry = opcode::Read::new(
+                types::Fd(read.file.as_raw_fd()),
+                read.buf.as_mut_ptr(),
+                read.buf.len() as u32,
+            )
+            .offset64(read.offset as i64)
+            .build()
+            .user_data(next_id);
+            // buffer is owned by in_flight until completion, moving Vec
+            // leaves its heap allocation in place
+            match unsafe { ring.submission().push(&entry) } {
+                Ok(()) => {
+                    in_flight.insert(next_id, read);
+                    next_id = next_id.wrapping_add(1);
+                }
+                Err(_) => {
+                    let error = io::Error::new(io::ErrorKind::Other, "io_uring queue is full");
+                    let _ = read.done.send((read.buf, Err(error)));
+                }
+            }
+        }
+
+        if in_flight.is_empty() {
+            continue;
+        }
+        match ring.submit_and_wait(1) {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                error!("io_uring failed: {}", e);
+                *RING.lock().unwrap() = None;
+                for (_, read) in in_flight.drain() {
+                    let error = io::Error::new(e.kind(), e.to_string());
+                    let _ = read.done.send((read.buf, Err(error)));
+                }
+                return;
+            }
+        }
+
+        for entry in ring.completion() {
+            if let Some(read) = in_flight.remove(&entry.user_data()) {
+                let result = match entry.result() {
+                    n if n < 0 => Err(io::Error::from_raw_os_error(-n)),
+                    n => Ok(n as usize),
+                };
+                let _ = read.done.send((read.buf, result));
+            }
+        }
+    }
+}
+
+struct UringFile {
+    file: Arc<File>,
+    offset: u64,
+    pending: Option<oneshot::Receiver<Completion>>,
+}
+
+impl AsyncRead for UringFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.pending.is_none() {
+            let (done, receiver) = oneshot::channel();
+            let read = Read {
+                file: Arc::clone(&self.file),
+                offset: self.offset,
+                buf: vec![0; buf.remaining().min(MAX_READ)],
+                done,
+            };
+            let queued = match RING.lock().unwrap().as_ref() {
+                Some(sender) => sender.send(read).is_ok(),
+                None => false,
+            };
+            if !queued {
+                return Poll::Ready(Err(stopped()));
+            }
+            self.pending = Some(receiver);
+        }
+
+        let completion = match Pin::new(self.pending.as_mut().unwrap()).poll(cx) {
+            Poll::Ready(completion) => completion,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.pending = None;
+        match completion {
+            // caller may have passed smaller buffer since read was queued,
+            // rest is read again
+            Ok((data, Ok(n))) => {
+                let n = n.min(buf.remaining());
+                buf.put_slice(&data[..n]);
+                self.offset += n as u64;
+                Poll::Ready(Ok(()))
+            }
+            Ok((_, Err(e))) => Poll::Ready(Err(e)),
+            Err(_) => Poll::Ready(Err(stopped())),
+        }
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "io_uring thread stopped")
+}
@@ -0,0 +1,163 @@
+// Smoke test of whole boot chain with a throwaway QEMU VM. VM gets random
+// MAC marked in inventory for installation with tested profile, so it boots
+// it regardless of selectors, and its boot session is followed over control
+// socket until kernel is fetched. Serial console is watched as well, kernel
+// printing its banner there (console=ttyS0) counts as success too.
+//
+// On tap network VM talks to server like any other client. QEMU user-mode
+// network has DHCP of its own, there VM is pointed at iPXE script of profile
+// over HTTP and only console tells whether kernel was reached.
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+use crate::control::{self, Snapshot};
+use crate::dhcp::id::Mac;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const KERNEL_BANNER: &str = "Linux version";
+
+pub enum Network {
+    Tap(String),
+    // URL of boot file handed out by QEMU DHCP
+    User(String),
+}
+
+pub struct Settings {
+    pub profile: String,
+    pub network: Network,
+    pub qemu: PathBuf,
+    // UEFI firmware, BIOS when not given
+    pub ovmf: Option<PathBuf>,
+    pub memory: u32,
+    pub timeout: Duration,
+}
+
+pub async fn run(socket: &Path, settings: &Settings) -> anyhow::Result<()> {
+    let mac = random_mac()?;
+    let reply = control::request(
+        socket,
+        &format!("state {} installing {}", mac, settings.profile),
+    )
+    .await?;
+    print!("{}", reply);
+
+    let result = watch(socket, settings, mac).await;
+
+    // VM is gone, its MAC is not left marked for installation
+    if let Err(e) = control::request(socket, &format!("state {} discovered", mac)).await {
+        warn!("failed to reset state of {}: {:#}", mac, e);
+    }
+
+    result
+}
+
+async fn watch(socket: &Path, settings: &Settings, mac: Mac) -> anyhow::Result<()> {
+    let mut child = qemu(settings, mac)
+        .spawn()
+        .map_err(|e| anyhow!("failed to start {}: {}", settings.qemu.display(), e))?;
+    let mut console = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut ticker = tokio::time::interval(POLL_INTERVAL);
+    let deadline = tokio::time::sleep(settings.timeout);
+    tokio::pin!(deadline);
+    let mut stage = None;
+    let mut console_open = true;
+
+    println!("booting {} with profile {}", mac, settings.profile);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => {
+                bail!(
+                    "kernel not reached within {} s, boot stopped at {}",
+                    settings.timeout.as_secs(),
+                    stage.as_deref().unwrap_or("firmware (no DHCP request seen)")
+                );
+            }
+            status = child.wait() => bail!("QEMU exited early: {}", status?),
+            line = console.next_line(), if console_open => match line? {
+                Some(line) if line.contains(KERNEL_BANNER) => {
+                    println!("console: {}", line.trim());
+                    println!("{} reached kernel", mac);
+                    return Ok(());
+                }
+                Some(_) => (),
+                None => console_open = false,
+            },
+            _ = ticker.tick() => {
+                let snapshot: Snapshot =
+                    serde_json::from_str(&control::request(socket, "snapshot").await?)?;
+                let current = snapshot
+                    .instances
+                    .iter()
+                    .flat_map(|x| x.sessions.iter())
+                    .find(|x| x.mac.parse::<Mac>().ok() == Some(mac));
+                if let Some(session) = current {
+                    if stage.as_ref() != Some(&session.stage) {
+                        println!(
+                            "{}{}",
+                            session.stage,
+                            session
+                                .last_file
+                                .as_deref()
+                                .map(|x| format!(": {}", x))
+                                .unwrap_or_default()
+                        );
+                        stage = Some(session.stage.clone());
+                    }
+                    if session.stage == "kernel fetched" {
+                        println!("{} reached kernel", mac);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn qemu(settings: &Settings, mac: Mac) -> Command {
+    let netdev = match &settings.network {
+        Network::Tap(interface) => {
+            format!("tap,id=net0,ifname={},script=no,downscript=no", interface)
+        }
+        Network::User(url) => format!("user,id=net0,bootfile={}", url),
+    };
+
+    let mut command = Command::new(&settings.qemu);
+    command
+        .args(["-machine", "accel=kvm:tcg", "-display", "none"])
+        .args(["-serial", "stdio", "-monitor", "none", "-boot", "n"])
+        .arg("-m")
+        .arg(settings.memory.to_string())
+        .arg("-netdev")
+        .arg(netdev)
+        .arg("-device")
+        .arg(format!("virtio-net-pci,netdev=net0,mac={}", mac));
+    if let Some(ovmf) = settings.ovmf.as_deref() {
+        let mut drive = std::ffi::OsString::from("if=pflash,format=raw,readonly=on,file=");
+        drive.push(ovmf);
+        command.arg("-drive").arg(drive);
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true);
+
+    command
+}
+
+// in range QEMU uses for its NICs
+fn random_mac() -> anyhow::Result<Mac> {
+    let mut bytes = [0u8; 3];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow!("failed to generate MAC"))?;
+    format!(
+        "52:54:00:{:02x}:{:02x}:{:02x}",
+        bytes[0], bytes[1], bytes[2]
+    )
+    .parse()
+}